@@ -1,10 +1,14 @@
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader, IsTerminal, Write};
 use std::os::unix::net::UnixStream;
 use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Duration;
 
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use nicehist_core::protocol::{FrecentAddParams, Response};
 use serde::{Deserialize, Serialize};
 
 #[derive(Parser)]
@@ -13,107 +17,67 @@ use serde::{Deserialize, Serialize};
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Show timestamps as ISO-8601 (UTC) instead of relative ages
+    #[arg(long, global = true)]
+    absolute: bool,
+    /// Print search, predict, frecent, context and watch results as JSON
+    #[arg(long, global = true)]
+    json: bool,
+    /// Exit with status 1 when search, predict or frecent finds nothing
+    #[arg(long, global = true)]
+    exit_nonzero_on_empty: bool,
+}
+
+impl Cli {
+    /// Checks clap can't express: a global `--json` given before the
+    /// subcommand isn't seen by that subcommand's `conflicts_with`
+    fn validate(&self) -> Result<(), clap::Error> {
+        let plain = match &self.command {
+            Commands::Search(SearchArgs { plain, .. })
+            | Commands::Predict(PredictArgs { plain, .. })
+            | Commands::Frecent(FrecentArgs { plain, .. }) => *plain,
+            _ => false,
+        };
+        if self.json && plain {
+            return Err(Cli::command().error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "the argument '--plain' cannot be used with '--json'",
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Search command history
-    Search {
-        /// Search pattern
-        pattern: String,
-        /// Limit results
-        #[arg(short, long, default_value = "20")]
-        limit: usize,
-        /// Filter by directory
-        #[arg(short, long)]
-        dir: Option<String>,
-        /// Output commands only, one per line (for piping to fzf, etc.)
-        #[arg(long)]
-        plain: bool,
-        /// Most recent command (for n-gram context scoring)
-        #[arg(long)]
-        last_cmd: Option<String>,
-        /// Second most recent command (for trigram context scoring)
-        #[arg(long)]
-        prev_cmd: Option<String>,
-        /// Enable n-gram context boost in scoring
-        #[arg(long)]
-        ngram_boost: bool,
-        /// Last command's exit status (for exit-aware n-gram scoring)
-        #[arg(long)]
-        last_exit: Option<i32>,
-        /// Current working directory (for local file penalty scoring)
-        #[arg(long, default_value_t = default_cwd())]
-        cwd: String,
-    },
+    Search(SearchArgs),
     /// Store a command in history
-    Store {
-        /// Command string (use named arg to avoid clap treating -flags as options)
-        #[arg(long)]
-        cmd: String,
-        /// Working directory
-        #[arg(long, default_value_t = default_cwd())]
-        cwd: String,
-        /// Exit status of the command
-        #[arg(long)]
-        exit_status: Option<i64>,
-        /// Duration in milliseconds
-        #[arg(long)]
-        duration_ms: Option<i64>,
-        /// Start time (unix epoch seconds)
-        #[arg(long)]
-        start_time: Option<i64>,
-        /// Session ID
-        #[arg(long)]
-        session_id: Option<i64>,
-        /// Previous command (for n-gram context)
-        #[arg(long)]
-        prev_cmd: Option<String>,
-        /// Command before previous (for n-gram context)
-        #[arg(long)]
-        prev2_cmd: Option<String>,
-        /// Previous command's exit status (for exit-aware n-grams)
-        #[arg(long)]
-        prev_exit: Option<i32>,
-    },
+    Store(StoreArgs),
     /// Get command predictions
-    Predict {
-        /// Prefix to predict from (named arg to avoid clap treating -flags as options)
-        #[arg(long)]
+    Predict(PredictArgs),
+    /// Report which shown suggestion was accepted, so it ranks higher next time
+    Accept {
+        /// What had been typed when the suggestions were shown
+        #[arg(long, default_value = "")]
         prefix: String,
-        /// Working directory
-        #[arg(long, default_value_t = default_cwd())]
-        cwd: String,
-        /// Maximum number of predictions
-        #[arg(long, default_value = "5")]
-        limit: usize,
-        /// Last command (for n-gram context)
+        /// The accepted suggestion
         #[arg(long)]
-        last_cmd: Option<String>,
-        /// Previous command (for n-gram context)
-        #[arg(long)]
-        prev_cmd: Option<String>,
-        /// Socket read timeout in milliseconds
-        #[arg(long, default_value = "100")]
-        timeout_ms: u64,
-        /// Output one command per line, no scores (for widget consumption)
-        #[arg(long)]
-        plain: bool,
-        /// Last command's exit status (for exit-aware n-gram scoring)
-        #[arg(long)]
-        last_exit: Option<i32>,
-        /// Disable frecent directory boost
-        #[arg(long)]
-        no_frecent_boost: bool,
-        /// Ranking weights as JSON (e.g. '{"frequency":0.4,"recency":0.3}')
+        cmd: String,
+        /// A suggestion that was shown (repeatable); the ones not accepted count against them
         #[arg(long)]
-        weights: Option<String>,
+        shown: Vec<String>,
     },
+    /// Print each command as it is stored, like `tail -f` (stop with Ctrl-C)
+    Watch,
     /// Get current directory context
     Context {
         /// Working directory
         #[arg(long, default_value_t = default_cwd())]
         cwd: String,
+        /// Include command counts and the top commands run in this directory
+        #[arg(long)]
+        stats: bool,
     },
     /// Delete a command from history
     Delete {
@@ -121,47 +85,103 @@ enum Commands {
         #[arg(long)]
         cmd: String,
     },
+    /// Delete history older than a number of days, and commands no longer in any history
+    Prune {
+        /// Age in days past which history is deleted
+        #[arg(long, value_name = "DAYS")]
+        older_than: u32,
+    },
+    /// Delete all stored history, n-grams and frecent paths (the schema is kept)
+    Reset {
+        /// Really delete; without it, only show how much would be deleted
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Run the daemon (in the foreground unless --detach is given)
+    Daemon {
+        /// Stay attached to the terminal (default)
+        #[arg(long, conflicts_with = "detach")]
+        foreground: bool,
+        /// Fork into the background, returning once the daemon answers ping
+        #[arg(long)]
+        detach: bool,
+        /// Keep history in memory only, e.g. for CI or disposable shells
+        #[arg(long)]
+        in_memory: bool,
+    },
     /// Shut down the daemon
     Shutdown,
     /// Show history statistics
     Stats,
+    /// Compact the database (VACUUM, WAL checkpoint, ANALYZE), e.g. after deleting lots of history
+    Maintenance,
     /// Import history from zsh_history file
     Import {
         /// Path to zsh_history file
         #[arg(default_value_t = default_history_path())]
         path: String,
+        /// Ignore saved progress and import the whole file again
+        #[arg(long)]
+        restart: bool,
+        /// Skip commands already stored with the same timestamp and directory
+        /// (makes re-importing a file safe)
+        #[arg(long)]
+        dedup: bool,
+        /// Read the whole file and report what would be imported, without storing anything
+        #[arg(long)]
+        dry_run: bool,
     },
     /// Export history in zsh_history format
     Export {
         /// Maximum entries to export (0 = all)
         #[arg(short, long, default_value = "0")]
         limit: usize,
+        /// Only runs at or after this time (Unix seconds or YYYY-MM-DD[THH:MM[:SS]], UTC)
+        #[arg(long, value_parser = parse_since)]
+        since: Option<i64>,
+        /// Only runs at or before this time (a bare date includes that whole day)
+        #[arg(long, value_parser = parse_until)]
+        until: Option<i64>,
+        /// Output format: zsh extended history, or one JSON object per line
+        #[arg(long, default_value = "zsh", value_parser = ["zsh", "jsonl"])]
+        format: String,
+        /// Write the newest entries first instead of zsh's oldest-first order
+        #[arg(long, visible_alias = "reverse")]
+        newest_first: bool,
     },
     /// Benchmark RPC round-trip timing
     Bench {
         /// Number of iterations
         #[arg(short, long, default_value = "10")]
         iterations: usize,
+        /// Run against a scratch daemon seeded with this many synthetic commands
+        /// instead of your own history
+        #[arg(long, default_value = "0")]
+        seed: usize,
     },
     /// Ping the daemon
     Ping,
-    /// Query frecent paths (fasd-like frecency)
-    Frecent {
-        /// Search terms
-        terms: Vec<String>,
-        /// Directories only
-        #[arg(short = 'd', long)]
-        dirs: bool,
-        /// Files only
-        #[arg(short = 'f', long)]
-        files: bool,
-        /// Output one path per line (for piping)
-        #[arg(long)]
-        plain: bool,
-        /// Maximum results
+    /// Print the JSON Schema of every RPC method's params and result
+    Schema,
+    /// List directories commands have been run in, with counts
+    Places {
+        /// Maximum places to list
         #[arg(short, long, default_value = "20")]
         limit: usize,
+        /// Order by command count or by most recent use
+        #[arg(long, default_value = "count", value_parser = ["count", "recent"])]
+        sort: String,
+        /// Output directories only, one per line (for pickers)
+        #[arg(long)]
+        plain: bool,
     },
+    /// Inspect or clear the daemon's prediction cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Query frecent paths (fasd-like frecency)
+    Frecent(FrecentArgs),
     /// Bump a path's frecency
     FrecentAdd {
         /// Path to bump
@@ -170,12 +190,37 @@ enum Commands {
         #[arg(short = 't', long, default_value = "d")]
         path_type: String,
     },
+    /// Forget a frecent path (e.g. a directory that was removed)
+    FrecentDelete {
+        /// Path to forget
+        path: String,
+        /// Path type: d (directory) or f (file)
+        #[arg(short = 't', long, default_value = "d")]
+        path_type: String,
+    },
     /// Import fasd data file
     ImportFasd {
         /// Path to fasd data file
         #[arg(default_value_t = default_fasd_path())]
         path: String,
     },
+    /// Import history from a bash history file
+    ImportBash {
+        /// Path to bash_history
+        #[arg(default_value_t = default_bash_history_path())]
+        path: String,
+    },
+    /// Import history from atuin's database
+    ImportAtuin {
+        /// Path to atuin's history.db
+        #[arg(default_value_t = default_atuin_path())]
+        path: String,
+    },
+    /// Print a shell completion script (e.g. `nicehist completions zsh > _nicehist`)
+    Completions {
+        /// Shell to generate completions for
+        shell: clap_complete::Shell,
+    },
     /// Export frecent data in fasd format
     ExportFasd {
         /// Output file (default: stdout)
@@ -184,6 +229,233 @@ enum Commands {
     },
 }
 
+/// Arguments of `search`
+#[derive(Args)]
+struct SearchArgs {
+    /// Search pattern
+    pattern: String,
+    /// Limit results
+    #[arg(short, long, default_value = "20")]
+    limit: usize,
+    /// Filter by directory
+    #[arg(short, long)]
+    dir: Option<String>,
+    /// Output commands only, one per line (for piping to fzf, etc.)
+    #[arg(long, conflicts_with = "json")]
+    plain: bool,
+    /// Most recent command (for n-gram context scoring)
+    #[arg(long)]
+    last_cmd: Option<String>,
+    /// Second most recent command (for trigram context scoring)
+    #[arg(long)]
+    prev_cmd: Option<String>,
+    /// Third most recent command (for 4-gram context scoring)
+    #[arg(long)]
+    prev2_cmd: Option<String>,
+    /// Enable n-gram context boost in scoring
+    #[arg(long)]
+    ngram_boost: bool,
+    /// Last command's exit status (for exit-aware n-gram scoring)
+    #[arg(long)]
+    last_exit: Option<i32>,
+    /// Current working directory (for local file penalty scoring)
+    #[arg(long, default_value_t = default_cwd())]
+    cwd: String,
+    /// Show when each command was last run
+    #[arg(long)]
+    age: bool,
+    /// Treat the pattern as a regular expression (e.g. '^git (push|pull)')
+    #[arg(long)]
+    regex: bool,
+    /// Only runs that exited with this status (0 = successes only)
+    #[arg(long)]
+    exit_status: Option<i32>,
+    /// Only runs at or after this time (Unix seconds or YYYY-MM-DD[THH:MM[:SS]], UTC)
+    #[arg(long, value_parser = parse_since)]
+    since: Option<i64>,
+    /// Only runs at or before this time (a bare date includes that whole day)
+    #[arg(long, value_parser = parse_until)]
+    until: Option<i64>,
+    /// Skip this many results, to page past the first --limit
+    #[arg(long, default_value = "0")]
+    offset: usize,
+    /// Search history recorded on this host instead of this machine ('*' for every host)
+    #[arg(long)]
+    host: Option<String>,
+    /// Only runs from this terminal device (e.g. /dev/pts/3)
+    #[arg(long)]
+    tty: Option<String>,
+    /// Only runs inside projects of this type (e.g. rust, node)
+    #[arg(long)]
+    project_type: Option<String>,
+}
+
+/// Arguments of `store`
+#[derive(Args)]
+struct StoreArgs {
+    /// Command string (use named arg to avoid clap treating -flags as options)
+    #[arg(long)]
+    cmd: String,
+    /// Working directory
+    #[arg(long, default_value_t = default_cwd())]
+    cwd: String,
+    /// Exit status of the command
+    #[arg(long)]
+    exit_status: Option<i64>,
+    /// Duration in milliseconds
+    #[arg(long)]
+    duration_ms: Option<i64>,
+    /// Start time (unix epoch seconds)
+    #[arg(long)]
+    start_time: Option<i64>,
+    /// Session ID
+    #[arg(long)]
+    session_id: Option<i64>,
+    /// When the session's shell started (unix epoch seconds)
+    #[arg(long, requires = "session_id")]
+    session_start: Option<i64>,
+    /// Previous command (for n-gram context)
+    #[arg(long)]
+    prev_cmd: Option<String>,
+    /// Command before previous (for n-gram context)
+    #[arg(long)]
+    prev2_cmd: Option<String>,
+    /// Third previous command (for 4-gram context)
+    #[arg(long)]
+    prev3_cmd: Option<String>,
+    /// Previous command's exit status (for exit-aware n-grams)
+    #[arg(long)]
+    prev_exit: Option<i32>,
+    /// Link --prev-cmd into n-grams even if it came from another shell session
+    #[arg(long)]
+    cross_session_ngrams: bool,
+    /// Shell that ran the command (e.g. zsh, bash)
+    #[arg(long)]
+    shell: Option<String>,
+    /// Terminal the shell is running in (e.g. iTerm.app, tmux)
+    #[arg(long)]
+    terminal: Option<String>,
+    /// Terminal device the shell is running on (e.g. $TTY)
+    #[arg(long)]
+    tty: Option<String>,
+    /// Don't record anything when --cwd is at or below this directory (repeatable)
+    #[arg(long = "exclude-dir")]
+    exclude_dirs: Vec<String>,
+    /// Also learn sequences between the commands of a compound line (a && b | c), and each one's arguments
+    #[arg(long)]
+    split_pipelines: bool,
+}
+
+/// Arguments of `predict`
+#[derive(Args)]
+struct PredictArgs {
+    /// Prefix to predict from (named arg to avoid clap treating -flags as options)
+    #[arg(long)]
+    prefix: String,
+    /// Working directory
+    #[arg(long, default_value_t = default_cwd())]
+    cwd: String,
+    /// Maximum number of predictions
+    #[arg(long, default_value = "5")]
+    limit: usize,
+    /// Last command (for n-gram context)
+    #[arg(long)]
+    last_cmd: Option<String>,
+    /// Previous command (for n-gram context)
+    #[arg(long)]
+    prev_cmd: Option<String>,
+    /// Command before previous (for n-gram context)
+    #[arg(long)]
+    prev2_cmd: Option<String>,
+    /// Socket read timeout in milliseconds
+    #[arg(long, default_value = "100")]
+    timeout_ms: u64,
+    /// Output one command per line, no scores (for widget consumption)
+    #[arg(long, conflicts_with = "json")]
+    plain: bool,
+    /// Last command's exit status (for exit-aware n-gram scoring)
+    #[arg(long)]
+    last_exit: Option<i32>,
+    /// Disable frecent directory boost
+    #[arg(long)]
+    no_frecent_boost: bool,
+    /// Ranking weights as JSON (e.g. '{"frequency":0.4,"recency":0.3}')
+    #[arg(long)]
+    weights: Option<String>,
+    /// Favour commands usually run here that haven't been run lately
+    #[arg(long)]
+    since_last: bool,
+    /// Also match commands containing the prefix anywhere (prefix matches still rank first)
+    #[arg(long)]
+    substring: bool,
+    /// Leave out commands that have never exited successfully
+    #[arg(long)]
+    no_failures: bool,
+    /// Rank by overall frequency and recency, ignoring the directory and previous commands
+    #[arg(long)]
+    global: bool,
+    /// Daemon-side time budget: past it, return the best predictions found so far
+    #[arg(long)]
+    deadline_ms: Option<u64>,
+    /// Fill up to --limit with your most-used commands for the same program
+    #[arg(long)]
+    fallback_popular: bool,
+    /// Only commands run inside projects of this type (e.g. rust, node), wherever --cwd is
+    #[arg(long)]
+    project_type: Option<String>,
+    /// Full command line, for completing the word under --cursor mid-line
+    #[arg(long, requires = "cursor")]
+    buffer: Option<String>,
+    /// Cursor position (in characters) within --buffer
+    #[arg(long, requires = "buffer")]
+    cursor: Option<usize>,
+}
+
+/// Arguments of `frecent`
+#[derive(Args)]
+struct FrecentArgs {
+    /// Search terms
+    terms: Vec<String>,
+    /// Directories only
+    #[arg(short = 'd', long)]
+    dirs: bool,
+    /// Files only
+    #[arg(short = 'f', long)]
+    files: bool,
+    /// Only paths of this type, e.g. d or f (repeatable; combines with -d and -f)
+    #[arg(short = 't', long = "type", value_name = "TYPE")]
+    types: Vec<String>,
+    /// Output one path per line (for piping)
+    #[arg(long, conflicts_with = "json")]
+    plain: bool,
+    /// Show when each path was last accessed
+    #[arg(long)]
+    age: bool,
+    /// Skip paths that no longer exist
+    #[arg(long)]
+    exists_only: bool,
+    /// Maximum results
+    #[arg(short, long, default_value = "20")]
+    limit: usize,
+    /// Also list the current directory (left out by default)
+    #[arg(long)]
+    include_cwd: bool,
+    /// Multiply directories' scores by this, e.g. above 1 to favor them over files
+    #[arg(long)]
+    dir_weight: Option<f64>,
+    /// Multiply files' scores by this, e.g. below 1 to favor directories
+    #[arg(long)]
+    file_weight: Option<f64>,
+}
+
+#[derive(Subcommand)]
+enum CacheAction {
+    /// Show cached entries, hit/miss counts and size
+    Stats,
+    /// Drop all cached predictions
+    Clear,
+}
+
 fn default_cwd() -> String {
     std::env::current_dir()
         .map(|p| p.to_string_lossy().to_string())
@@ -195,6 +467,19 @@ fn default_fasd_path() -> String {
     format!("{}/.fasd", home)
 }
 
+fn default_bash_history_path() -> String {
+    let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+    format!("{}/.bash_history", home)
+}
+
+fn default_atuin_path() -> String {
+    let data_home = std::env::var("XDG_DATA_HOME").unwrap_or_else(|_| {
+        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+        format!("{}/.local/share", home)
+    });
+    format!("{}/atuin/history.db", data_home)
+}
+
 fn default_history_path() -> String {
     if let Ok(histfile) = std::env::var("HISTFILE") {
         return histfile;
@@ -203,7 +488,22 @@ fn default_history_path() -> String {
     format!("{}/.zsh_history", home)
 }
 
+/// The daemon's socket, resolved once per run
+static SOCKET: OnceLock<PathBuf> = OnceLock::new();
+
 fn socket_path() -> PathBuf {
+    SOCKET.get_or_init(resolve_socket_path).clone()
+}
+
+/// `NICEHIST_SOCKET` if set, then the `socket` from the daemon's config.toml,
+/// then the default location
+fn resolve_socket_path() -> PathBuf {
+    if let Some(socket) = std::env::var_os("NICEHIST_SOCKET") {
+        return PathBuf::from(socket);
+    }
+    if let Some(socket) = configured_socket() {
+        return socket;
+    }
     if let Ok(runtime_dir) = std::env::var("XDG_RUNTIME_DIR") {
         PathBuf::from(runtime_dir).join("nicehist.sock")
     } else {
@@ -214,6 +514,15 @@ fn socket_path() -> PathBuf {
     }
 }
 
+/// The `socket` override from the daemon's config.toml, if any
+fn configured_socket() -> Option<PathBuf> {
+    let proj_dirs = directories::ProjectDirs::from("", "", "nicehist")?;
+    let text = std::fs::read_to_string(proj_dirs.config_dir().join("config.toml")).ok()?;
+    let config: toml::Table = text.parse().ok()?;
+    let socket = config.get("socket")?.as_str()?;
+    Some(PathBuf::from(shellexpand::tilde(socket).as_ref()))
+}
+
 #[derive(Serialize)]
 struct RpcRequest {
     method: String,
@@ -221,45 +530,30 @@ struct RpcRequest {
     params: Option<serde_json::Value>,
 }
 
-#[derive(Deserialize)]
-struct RpcResponse {
-    result: Option<serde_json::Value>,
-    error: Option<RpcError>,
+/// A request as sent on the wire, with the JSON-RPC 2.0 envelope
+#[derive(Serialize)]
+struct RpcEnvelope<'a> {
+    jsonrpc: &'static str,
+    id: u64,
+    #[serde(flatten)]
+    request: &'a RpcRequest,
 }
 
-#[derive(Deserialize)]
-struct RpcError {
-    code: i32,
-    message: String,
-}
+/// Ids for outgoing requests, increasing for the life of the process
+static NEXT_RPC_ID: AtomicU64 = AtomicU64::new(1);
 
 fn send_rpc(request: &RpcRequest) -> Result<serde_json::Value> {
-    let socket = socket_path();
-
-    let mut stream = UnixStream::connect(&socket)
-        .with_context(|| format!("Failed to connect to daemon at {}", socket.display()))?;
-
-    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
-    stream.set_write_timeout(Some(Duration::from_secs(5)))?;
-
-    let request_json = serde_json::to_string(request)?;
-    writeln!(stream, "{}", request_json)?;
-    stream.flush()?;
-
-    let mut reader = BufReader::new(stream);
-    let mut response_line = String::new();
-    reader.read_line(&mut response_line)?;
-
-    let response: RpcResponse = serde_json::from_str(&response_line)?;
-
-    if let Some(error) = response.error {
-        anyhow::bail!("RPC error {}: {}", error.code, error.message);
-    }
-
-    response.result.context("No result in response")
+    send_rpc_with_timeout(request, Duration::from_secs(5))
 }
 
 fn send_rpc_with_timeout(request: &RpcRequest, timeout: Duration) -> Result<serde_json::Value> {
+    let (mut reader, id) = send_request(request, timeout)?;
+    read_result(&mut reader, id)
+}
+
+/// Connect to the daemon and send `request`, returning the stream to read the
+/// response from and the id it was sent with
+fn send_request(request: &RpcRequest, timeout: Duration) -> Result<(BufReader<UnixStream>, u64)> {
     let socket = socket_path();
 
     let mut stream = UnixStream::connect(&socket)
@@ -268,15 +562,21 @@ fn send_rpc_with_timeout(request: &RpcRequest, timeout: Duration) -> Result<serd
     stream.set_read_timeout(Some(timeout))?;
     stream.set_write_timeout(Some(timeout))?;
 
-    let request_json = serde_json::to_string(request)?;
+    let id = NEXT_RPC_ID.fetch_add(1, Ordering::Relaxed);
+    let request_json = serde_json::to_string(&RpcEnvelope { jsonrpc: "2.0", id, request })?;
     writeln!(stream, "{}", request_json)?;
     stream.flush()?;
+    Ok((BufReader::new(stream), id))
+}
 
-    let mut reader = BufReader::new(stream);
+fn read_result(reader: &mut BufReader<UnixStream>, id: u64) -> Result<serde_json::Value> {
     let mut response_line = String::new();
     reader.read_line(&mut response_line)?;
 
-    let response: RpcResponse = serde_json::from_str(&response_line)?;
+    let response: Response = serde_json::from_str(&response_line)?;
+    if response.id.as_ref().is_some_and(|r| *r != serde_json::json!(id)) {
+        anyhow::bail!("Response id {} does not match request id {}", response.id.unwrap(), id);
+    }
 
     if let Some(error) = response.error {
         anyhow::bail!("RPC error {}: {}", error.code, error.message);
@@ -285,26 +585,43 @@ fn send_rpc_with_timeout(request: &RpcRequest, timeout: Duration) -> Result<serd
     response.result.context("No result in response")
 }
 
-fn cmd_search(
-    pattern: &str,
-    limit: usize,
-    dir: Option<&str>,
-    plain: bool,
-    last_cmd: Option<&str>,
-    prev_cmd: Option<&str>,
-    ngram_boost: bool,
-    last_exit: Option<i32>,
-    cwd: &str,
-) -> Result<()> {
+fn cmd_search(args: &SearchArgs, times: Option<TimeStyle>, json: bool) -> Result<usize> {
+    let &SearchArgs {
+        ref pattern, limit, ref dir, plain, ref last_cmd, ref prev_cmd, ref prev2_cmd, ngram_boost, last_exit, ref cwd,
+        age: _, regex, exit_status, since, until, offset, ref host, ref tty, ref project_type,
+    } = args;
     let mut params = serde_json::json!({
         "pattern": pattern,
         "limit": limit,
         "ngram_boost": ngram_boost,
+        "offset": offset,
     });
 
+    if regex {
+        params["regex"] = serde_json::json!(true);
+    }
+    if let Some(status) = exit_status {
+        params["exit_status"] = serde_json::json!(status);
+    }
+    if let Some(ts) = since {
+        params["since"] = serde_json::json!(ts);
+    }
+    if let Some(ts) = until {
+        params["until"] = serde_json::json!(ts);
+    }
+
     if let Some(d) = dir {
         params["dir"] = serde_json::json!(d);
     }
+    if let Some(h) = host {
+        params["host"] = serde_json::json!(h);
+    }
+    if let Some(t) = tty {
+        params["tty"] = serde_json::json!(t);
+    }
+    if let Some(p) = project_type {
+        params["in_project"] = serde_json::json!(p);
+    }
 
     // Build last_cmds array from most recent first
     let mut last_cmds = Vec::new();
@@ -314,6 +631,9 @@ fn cmd_search(
     if let Some(cmd) = prev_cmd {
         last_cmds.push(cmd);
     }
+    if let Some(cmd) = prev2_cmd {
+        last_cmds.push(cmd);
+    }
     if !last_cmds.is_empty() {
         params["last_cmds"] = serde_json::json!(last_cmds);
     }
@@ -331,6 +651,12 @@ fn cmd_search(
     };
 
     let result = send_rpc(&request)?;
+    let found = result_count(result.get("results"));
+    if json {
+        print_json(result.get("results"))?;
+        return Ok(found);
+    }
+    let color = use_color();
 
     if let Some(results) = result.get("results").and_then(|r| r.as_array()) {
         if results.is_empty() && !plain {
@@ -356,16 +682,224 @@ fn cmd_search(
                             .get("score")
                             .and_then(|s| s.as_f64())
                             .unwrap_or(0.0);
-                        println!("{} ({:.3}){} @ {}", cmd, score, exit_str, cwd);
+                        let fail_str = match entry.get("failure_rate").and_then(|f| f.as_f64()) {
+                            Some(rate) if rate > 0.0 => format!(" ⚠ {:.0}% fail", rate * 100.0),
+                            _ => "".to_string(),
+                        };
+                        let span = entry
+                            .get("match_span")
+                            .and_then(|m| m.as_array())
+                            .and_then(|m| Some((m.first()?.as_u64()? as usize, m.get(1)?.as_u64()? as usize)));
+                        let shown = highlight_match(cmd, span, color);
+                        let age_str = match (times, entry.get("timestamp").and_then(|t| t.as_i64())) {
+                            (Some(style), Some(ts)) => format!(" · {}", format_timestamp(ts, style)),
+                            _ => "".to_string(),
+                        };
+                        println!("{} ({:.3}){}{} @ {}{}", shown, score, exit_str, fail_str, cwd, age_str);
                     }
                 }
             }
         }
     }
 
+    Ok(found)
+}
+
+/// How timestamps are shown in human-readable output
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TimeStyle {
+    /// "3m ago", "yesterday", "2w ago"
+    Relative,
+    /// ISO-8601 in UTC
+    Absolute,
+}
+
+/// Print an RPC result for --json (an empty array if the daemon sent nothing)
+fn print_json(value: Option<&serde_json::Value>) -> Result<()> {
+    let empty = serde_json::json!([]);
+    println!("{}", serde_json::to_string_pretty(value.unwrap_or(&empty))?);
     Ok(())
 }
 
+/// How many entries an RPC result array holds
+fn result_count(value: Option<&serde_json::Value>) -> usize {
+    value.and_then(|v| v.as_array()).map_or(0, Vec::len)
+}
+
+/// The process exit status: 2 for an error, and 1 for an empty search, predict
+/// or frecent result under --exit-nonzero-on-empty, so scripts can tell
+/// "nothing" from "done" and both from "failed"
+fn exit_status(outcome: &Result<Option<usize>>, nonzero_on_empty: bool) -> u8 {
+    match outcome {
+        Err(_) => 2,
+        Ok(Some(0)) if nonzero_on_empty => 1,
+        Ok(_) => 0,
+    }
+}
+
+/// Timestamps are shown when asked for with --age, or implied by --absolute
+fn time_style(age: bool, absolute: bool) -> Option<TimeStyle> {
+    if absolute {
+        Some(TimeStyle::Absolute)
+    } else if age {
+        Some(TimeStyle::Relative)
+    } else {
+        None
+    }
+}
+
+fn format_timestamp(ts: i64, style: TimeStyle) -> String {
+    match style {
+        TimeStyle::Relative => {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0);
+            format_relative_time(ts, now)
+        }
+        TimeStyle::Absolute => format_iso8601(ts),
+    }
+}
+
+/// Human-relative age of `ts` as seen at `now` (both Unix seconds)
+fn format_relative_time(ts: i64, now: i64) -> String {
+    const MINUTE: i64 = 60;
+    const HOUR: i64 = 60 * MINUTE;
+    const DAY: i64 = 24 * HOUR;
+    const WEEK: i64 = 7 * DAY;
+    const YEAR: i64 = 365 * DAY;
+
+    let age = now - ts;
+    if age <= 0 {
+        "just now".to_string()
+    } else if age < MINUTE {
+        format!("{}s ago", age)
+    } else if age < HOUR {
+        format!("{}m ago", age / MINUTE)
+    } else if age < DAY {
+        format!("{}h ago", age / HOUR)
+    } else if age < 2 * DAY {
+        "yesterday".to_string()
+    } else if age < WEEK {
+        format!("{}d ago", age / DAY)
+    } else if age < YEAR {
+        format!("{}w ago", age / WEEK)
+    } else {
+        format!("{}y ago", age / YEAR)
+    }
+}
+
+/// ISO-8601 UTC timestamp (e.g. 2023-11-14T22:13:20Z) without a date library
+fn format_iso8601(ts: i64) -> String {
+    let days = ts.div_euclid(86400);
+    let secs = ts.rem_euclid(86400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, secs / 3600, secs % 3600 / 60, secs % 60
+    )
+}
+
+/// Days since 1970-01-01 for a civil date (inverse of the conversion in format_iso8601)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Number of days in `month` (1-12) of `year`, in the proleptic Gregorian calendar
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Parse a time bound: Unix seconds, or a UTC date/time like 2024-01-01 or
+/// 2024-01-01T13:30[:00][Z]. A bare date ending a range covers that whole day.
+fn parse_time_bound(s: &str, end_of_day: bool) -> Result<i64, String> {
+    let s = s.trim();
+    if let Ok(ts) = s.parse::<i64>() {
+        return Ok(ts);
+    }
+
+    let invalid = || format!("invalid time '{}': expected Unix seconds or YYYY-MM-DD[THH:MM[:SS]]", s);
+    let (date, time) = match s.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time.trim_end_matches('Z'))),
+        None => (s, None),
+    };
+
+    let fields = |text: &str, sep: char| -> Option<Vec<i64>> {
+        text.split(sep).map(|f| f.parse::<i64>().ok()).collect()
+    };
+    let [year, month, day] = fields(date, '-').ok_or_else(invalid)?[..] else {
+        return Err(invalid());
+    };
+    if !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day) {
+        return Err(invalid());
+    }
+
+    let secs = match time {
+        None if end_of_day => 86399,
+        None => 0,
+        Some(time) => match fields(time, ':').ok_or_else(invalid)?[..] {
+            [h, m] if h < 24 && m < 60 => h * 3600 + m * 60,
+            [h, m, sec] if h < 24 && m < 60 && sec < 60 => h * 3600 + m * 60 + sec,
+            _ => return Err(invalid()),
+        },
+    };
+
+    Ok(days_from_civil(year, month, day) * 86400 + secs)
+}
+
+fn parse_since(s: &str) -> Result<i64, String> {
+    parse_time_bound(s, false)
+}
+
+fn parse_until(s: &str) -> Result<i64, String> {
+    parse_time_bound(s, true)
+}
+
+/// Whether to emit ANSI color: stdout is a terminal and NO_COLOR is unset
+fn use_color() -> bool {
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+/// Highlight the matched byte span of a command: bold red with color, «» markers without
+fn highlight_match(cmd: &str, span: Option<(usize, usize)>, color: bool) -> String {
+    let Some((start, end)) = span else {
+        return cmd.to_string();
+    };
+    let (Some(before), Some(matched), Some(after)) =
+        (cmd.get(..start), cmd.get(start..end), cmd.get(end..))
+    else {
+        return cmd.to_string();
+    };
+
+    if color {
+        format!("{}\x1b[1;31m{}\x1b[0m{}", before, matched, after)
+    } else {
+        format!("{}«{}»{}", before, matched, after)
+    }
+}
+
 fn cmd_stats() -> Result<()> {
     let request = RpcRequest {
         method: "ping".to_string(),
@@ -379,86 +913,617 @@ fn cmd_stats() -> Result<()> {
         }
         Err(e) => {
             println!("Daemon: not running ({})", e);
+            return Ok(());
+        }
+    }
+
+    let request = RpcRequest {
+        method: "stats".to_string(),
+        params: None,
+    };
+
+    match send_rpc(&request) {
+        Ok(stats) => {
+            let get = |key: &str| stats.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+            println!("Database: {}", format_bytes(get("db_size_bytes")));
+            println!();
+            let rows = [
+                ("History", "history"),
+                ("Commands", "commands"),
+                ("Places", "places"),
+                ("Bigrams", "bigrams"),
+                ("Trigrams", "trigrams"),
+                ("Frecent dirs", "frecent_dirs"),
+                ("Frecent files", "frecent_files"),
+            ];
+            for (label, key) in rows {
+                println!("  {:<14} {:>10}", label, get(key));
+            }
+            if let Some(shells) = stats["shells"].as_array().filter(|s| !s.is_empty()) {
+                let shares: Vec<String> = shells
+                    .iter()
+                    .map(|s| format!(
+                        "{:.0}% {}",
+                        s["fraction"].as_f64().unwrap_or(0.0) * 100.0,
+                        s["shell"].as_str().unwrap_or("?"),
+                    ))
+                    .collect();
+                println!("  {:<14} {}", "Shells", shares.join(", "));
+            }
+        }
+        Err(e) => {
+            println!("Stats unavailable ({})", e);
         }
     }
 
     Ok(())
 }
 
-fn cmd_import(path: &str) -> Result<()> {
+/// VACUUM rewrites the whole database, and pruning may delete most of it, which
+/// can take a while for a large history
+const MAINTENANCE_TIMEOUT: Duration = Duration::from_secs(600);
+
+fn cmd_maintenance() -> Result<()> {
+    let request = RpcRequest {
+        method: "maintenance".to_string(),
+        params: None,
+    };
+
+    let result = send_rpc_with_timeout(&request, MAINTENANCE_TIMEOUT)?;
+    let get = |key: &str| result.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+    let (before, after) = (get("size_before"), get("size_after"));
+    println!(
+        "Database: {} -> {} ({} freed)",
+        format_bytes(before),
+        format_bytes(after),
+        format_bytes(before.saturating_sub(after))
+    );
+
+    Ok(())
+}
+
+/// Human-readable size, e.g. "1.5 MiB"
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Number of history lines stored per transaction during import
+const IMPORT_BATCH_SIZE: usize = 500;
+
+/// How much of the start of a history file goes into its import fingerprint
+const FINGERPRINT_BYTES: u64 = 4096;
+
+/// FNV-1a, stable across builds unlike DefaultHasher
+fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Identifies the history file an import offset was saved against, so a file
+/// that was replaced or rewritten since is imported from the start again
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ImportFingerprint {
+    inode: u64,
+    /// Hash of the file's first `FINGERPRINT_BYTES`, or of its first `offset`
+    /// bytes when less than that was imported, since the rest may still grow
+    head: u64,
+}
+
+impl ImportFingerprint {
+    fn of(source: &std::path::Path, offset: u64) -> std::io::Result<Self> {
+        use std::io::Read;
+
+        let file = std::fs::File::open(source)?;
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(&file.metadata()?);
+        #[cfg(not(unix))]
+        let inode = 0;
+
+        let mut head = Vec::new();
+        file.take(offset.min(FINGERPRINT_BYTES)).read_to_end(&mut head)?;
+        Ok(Self { inode, head: fnv1a(head) })
+    }
+}
+
+/// Contents of an import state file: the byte offset reached and the fingerprint
+/// of the source at that point
+fn format_import_state(offset: u64, fingerprint: ImportFingerprint) -> String {
+    format!("{} {} {:016x}\n", offset, fingerprint.inode, fingerprint.head)
+}
+
+/// The saved offset, if the state file is readable and was saved against `source`
+/// as it is now
+fn resume_offset(state: &str, source: &std::path::Path) -> Option<u64> {
+    let mut fields = state.split_whitespace();
+    let offset: u64 = fields.next()?.parse().ok()?;
+    let saved = ImportFingerprint {
+        inode: fields.next()?.parse().ok()?,
+        head: u64::from_str_radix(fields.next()?, 16).ok()?,
+    };
+    (ImportFingerprint::of(source, offset).ok()? == saved).then_some(offset)
+}
+
+/// File recording how far an import of `source` has progressed,
+/// keyed by a hash of the source path so each history file resumes independently
+fn import_state_path(source: &std::path::Path) -> PathBuf {
+    let source = source.canonicalize().unwrap_or_else(|_| source.to_path_buf());
+    let hash = fnv1a(source.to_string_lossy().bytes());
+
+    let state_dir = directories::ProjectDirs::from("", "", "nicehist")
+        .map(|d| d.state_dir().unwrap_or(d.data_dir()).to_path_buf())
+        .unwrap_or_else(|| {
+            let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+            PathBuf::from(home).join(".local/state/nicehist")
+        });
+    state_dir.join("imports").join(format!("{:016x}", hash))
+}
+
+/// A command read from a zsh history file
+#[derive(Debug, Clone, PartialEq)]
+struct HistoryEntry {
+    cmd: String,
+    /// When it started (Unix seconds), from extended history
+    start_time: Option<i64>,
+    /// How long it ran in milliseconds, from extended history
+    duration_ms: Option<i64>,
+}
+
+/// Extract the command (and extended history metadata) from a zsh history line, if it holds one
+fn parse_history_line(line: &str) -> Option<HistoryEntry> {
+    // Format: : timestamp:duration;command
+    // Or just: command
+    let (cmd, start_time, duration_ms) = if let Some(rest) = line.strip_prefix(": ") {
+        // Extended history format; duration is in seconds
+        let (meta, cmd) = rest.split_once(';')?;
+        let (ts, dur) = meta.split_once(':').unwrap_or((meta, ""));
+        let start_time = ts.trim().parse().ok();
+        let duration_ms = dur.trim().parse::<i64>().ok().map(|secs| secs * 1000);
+        (cmd, start_time, duration_ms)
+    } else {
+        (line, None, None)
+    };
+
+    if cmd.trim().is_empty() {
+        return None;
+    }
+    Some(HistoryEntry { cmd: cmd.to_string(), start_time, duration_ms })
+}
+
+/// Whether a physical history line continues on the next one: zsh writes the
+/// newlines inside multi-line commands as a trailing backslash, while an escaped
+/// backslash (`\\`) at the end of a line is just a literal one
+fn continues_on_next_line(line: &str) -> bool {
+    line.bytes().rev().take_while(|&b| b == b'\\').count() % 2 == 1
+}
+
+/// What an import read from a history file
+#[derive(Debug, Default, PartialEq)]
+struct ImportCounts {
+    /// Commands handed to `store`
+    commands: usize,
+    /// How many of those were stored; the rest were duplicates or from excluded directories
+    stored: usize,
+    /// How many of those span several lines
+    multiline: usize,
+    /// Entries without a command, skipped
+    empty: usize,
+    /// Lines that couldn't be read (not UTF-8)
+    errors: usize,
+}
+
+/// Stream history lines from `reader`, which is positioned at byte `offset` of the
+/// source, handing each batch of commands to `store`. Continuation lines are joined
+/// into one command, and checkpoints only ever fall between whole commands. After every stored batch
+/// `checkpoint` receives the offset just past its last line, so an interrupted
+/// import can resume there.
+fn import_history<R: BufRead>(
+    mut reader: R,
+    mut offset: u64,
+    batch_size: usize,
+    mut store: impl FnMut(&[HistoryEntry]) -> Result<usize>,
+    mut checkpoint: impl FnMut(u64, usize) -> Result<()>,
+) -> Result<ImportCounts> {
+    let mut counts = ImportCounts::default();
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut buf = Vec::new();
+    // Lines of a multi-line command read so far (only the first has the `: ts:dur;` prefix)
+    let mut pending = String::new();
+
+    loop {
+        buf.clear();
+        let read = reader.read_until(b'\n', &mut buf)?;
+        if read > 0 {
+            match std::str::from_utf8(&buf) {
+                Ok(line) => {
+                    let line = line.trim_end_matches(['\n', '\r']);
+                    if continues_on_next_line(line) {
+                        pending.push_str(&line[..line.len() - 1]);
+                        pending.push('\n');
+                    } else {
+                        pending.push_str(line);
+                        match parse_history_line(&pending) {
+                            Some(entry) => batch.push(entry),
+                            None => counts.empty += 1,
+                        }
+                        pending.clear();
+                    }
+                }
+                Err(_) => {
+                    counts.errors += 1;
+                    pending.clear();
+                }
+            }
+            offset += read as u64;
+        }
+
+        let done = read == 0;
+        if done && !pending.is_empty() {
+            // File ends mid-command
+            match parse_history_line(pending.trim_end_matches('\n')) {
+                Some(entry) => batch.push(entry),
+                None => counts.empty += 1,
+            }
+            pending.clear();
+        }
+        if batch.len() >= batch_size || (done && !batch.is_empty()) {
+            counts.stored += store(&batch)?;
+            counts.commands += batch.len();
+            counts.multiline += batch.iter().filter(|e| e.cmd.contains('\n')).count();
+            batch.clear();
+            checkpoint(offset, counts.stored)?;
+        }
+
+        if done {
+            break;
+        }
+    }
+
+    Ok(counts)
+}
+
+/// Store imported history entries in one batch, all run from `cwd`
+fn store_history_entries(batch: &[HistoryEntry], cwd: &str, dedup: bool) -> Result<usize> {
+    let commands: Vec<serde_json::Value> = batch
+        .iter()
+        .map(|entry| serde_json::json!({
+            "cmd": entry.cmd,
+            "cwd": cwd,
+            "start_time": entry.start_time,
+            "duration_ms": entry.duration_ms,
+            "dedup_window": dedup.then_some(0),
+        }))
+        .collect();
+
+    let request = RpcRequest {
+        method: "store_batch".to_string(),
+        params: Some(serde_json::json!({"commands": commands})),
+    };
+    let result = send_rpc(&request)?;
+    Ok(result.get("stored").and_then(|n| n.as_u64()).unwrap_or(0) as usize)
+}
+
+fn cmd_import(path: &str, restart: bool, dedup: bool, dry_run: bool) -> Result<()> {
     use std::fs::File;
-    use std::io::BufReader as FileBufReader;
+    use std::io::{BufReader as FileBufReader, Seek, SeekFrom};
 
     let path = shellexpand::tilde(path);
-    let file = File::open(path.as_ref())
+    let mut file = File::open(path.as_ref())
         .with_context(|| format!("Failed to open {}", path))?;
 
-    let reader = FileBufReader::new(file);
-    let mut count = 0;
-    let mut errors = 0;
+    if dry_run {
+        // The whole file, however far an earlier import got, and no progress saved
+        let counts = import_history(FileBufReader::new(file), 0, IMPORT_BATCH_SIZE, |_| Ok(0), |_, _| Ok(()))?;
+        println!("Dry run of {} (nothing stored):", path);
+        print!("{}", format_import_counts(&counts));
+        return Ok(());
+    }
+
+    let len = file.metadata()?.len();
+
+    let source = std::path::Path::new(path.as_ref());
+    let state_path = import_state_path(source);
+    if restart {
+        std::fs::remove_file(&state_path).ok();
+    }
+
+    // Start over if the file was truncated, replaced or rewritten since the last import
+    let offset = std::fs::read_to_string(&state_path)
+        .ok()
+        .and_then(|state| resume_offset(&state, source))
+        .filter(|&offset| offset <= len)
+        .unwrap_or(0);
+
+    if offset == len && len > 0 {
+        println!("Nothing new to import from {} (use --restart to import it again)", path);
+        return Ok(());
+    }
 
     let cwd = std::env::current_dir()
         .map(|p| p.to_string_lossy().to_string())
         .unwrap_or_else(|_| "/".to_string());
 
-    println!("Importing from {}...", path);
+    if offset > 0 {
+        println!("Resuming import from {} at byte {}...", path, offset);
+    } else {
+        println!("Importing from {}...", path);
+    }
 
-    for line in reader.lines() {
-        let line = match line {
-            Ok(l) => l,
-            Err(_) => {
-                errors += 1;
-                continue;
-            }
-        };
+    file.seek(SeekFrom::Start(offset))?;
+    if let Some(dir) = state_path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let store = |batch: &[HistoryEntry]| store_history_entries(batch, &cwd, dedup);
+
+    let checkpoint = |offset: u64, count: usize| -> Result<()> {
+        let fingerprint = ImportFingerprint::of(source, offset)
+            .with_context(|| format!("Failed to read {}", source.display()))?;
+        std::fs::write(&state_path, format_import_state(offset, fingerprint))
+            .with_context(|| format!("Failed to save import progress to {}", state_path.display()))?;
+        print!("\rImported {} commands...", count);
+        std::io::stdout().flush().ok();
+        Ok(())
+    };
+
+    let counts = import_history(FileBufReader::new(file), offset, IMPORT_BATCH_SIZE, store, checkpoint)
+        .context("Import interrupted; re-run to resume")?;
+
+    println!(
+        "\rImported {} commands ({} skipped, {} errors)",
+        counts.stored,
+        counts.commands - counts.stored,
+        counts.errors
+    );
+
+    Ok(())
+}
+
+/// A dry run's report, one count per line
+fn format_import_counts(counts: &ImportCounts) -> String {
+    format!(
+        "  {} commands ({} multi-line)\n  {} empty entries skipped\n  {} unreadable lines\n",
+        counts.commands, counts.multiline, counts.empty, counts.errors
+    )
+}
 
-        // Skip empty lines
+/// Read bash history: one command per line, each optionally preceded by a
+/// `#<epoch>` line (written when `HISTTIMEFORMAT` is set). Other lines starting
+/// with `#` are commands. Returns the entries and how many lines couldn't be read.
+fn parse_bash_history<R: BufRead>(mut reader: R) -> Result<(Vec<HistoryEntry>, usize)> {
+    let mut entries = Vec::new();
+    let mut errors = 0;
+    let mut timestamp = None;
+    let mut buf = Vec::new();
+
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+        let Ok(line) = std::str::from_utf8(&buf) else {
+            errors += 1;
+            timestamp = None;
+            continue;
+        };
+        let line = line.trim_end_matches(['\n', '\r']);
+        if let Some(epoch) = line.strip_prefix('#').and_then(|s| s.parse::<i64>().ok()) {
+            timestamp = Some(epoch);
+            continue;
+        }
         if line.trim().is_empty() {
             continue;
         }
+        entries.push(HistoryEntry {
+            cmd: line.to_string(),
+            start_time: timestamp.take(),
+            duration_ms: None,
+        });
+    }
 
-        // Parse zsh history format
-        // Format: : timestamp:0;command
-        // Or just: command
-        let cmd = if line.starts_with(": ") {
-            // Extended history format
-            if let Some(pos) = line.find(';') {
-                line[pos + 1..].to_string()
-            } else {
-                continue;
-            }
-        } else {
-            line
-        };
+    Ok((entries, errors))
+}
 
-        if cmd.trim().is_empty() {
-            continue;
+fn cmd_import_bash(path: &str) -> Result<()> {
+    use std::fs::File;
+    use std::io::BufReader as FileBufReader;
+
+    let path = shellexpand::tilde(path);
+    let file = File::open(path.as_ref())
+        .with_context(|| format!("Failed to open {}", path))?;
+    println!("Importing bash history from {}...", path);
+
+    let cwd = default_cwd();
+    let (entries, errors) = parse_bash_history(FileBufReader::new(file))?;
+    let mut count = 0;
+    for batch in entries.chunks(IMPORT_BATCH_SIZE) {
+        count += store_history_entries(batch, &cwd, false).context("Import interrupted")?;
+        print!("\rImported {} commands...", count);
+        std::io::stdout().flush().ok();
+    }
+
+    println!("\rImported {} commands ({} errors)", count, errors);
+
+    Ok(())
+}
+
+/// A command read from atuin's history database
+#[derive(Debug, Clone, PartialEq)]
+struct AtuinEntry {
+    cmd: String,
+    cwd: String,
+    /// When it started (Unix seconds; atuin stores nanoseconds)
+    start_time: i64,
+    /// How long it ran in milliseconds, if atuin knew
+    duration_ms: Option<i64>,
+    /// Exit status, if atuin knew
+    exit_status: Option<i64>,
+    /// atuin's session id
+    session: String,
+}
+
+/// Read atuin's history oldest first, skipping soft-deleted rows. Returns the
+/// entries and how many rows couldn't be read.
+fn read_atuin_history(path: &std::path::Path) -> Result<(Vec<AtuinEntry>, usize)> {
+    let conn = rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Failed to open atuin database: {}", path.display()))?;
+
+    // Older atuin versions have no deleted_at column
+    let filter = if conn.prepare("SELECT deleted_at FROM history LIMIT 0").is_ok() {
+        "WHERE deleted_at IS NULL"
+    } else {
+        ""
+    };
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT command, cwd, exit, duration, timestamp, session FROM history {} ORDER BY timestamp",
+            filter
+        ))
+        .context("Not an atuin history database")?;
+
+    let mut entries = Vec::new();
+    let mut errors = 0;
+    let rows = stmt.query_map([], |row| {
+        let exit: i64 = row.get(2)?;
+        let duration: i64 = row.get(3)?;
+        let timestamp: i64 = row.get(4)?;
+        // atuin records -1 for an unknown exit status or duration
+        Ok(AtuinEntry {
+            cmd: row.get(0)?,
+            cwd: row.get(1)?,
+            start_time: timestamp / 1_000_000_000,
+            duration_ms: (duration >= 0).then_some(duration / 1_000_000),
+            exit_status: (exit >= 0).then_some(exit),
+            session: row.get(5)?,
+        })
+    })?;
+    for row in rows {
+        match row {
+            Ok(entry) => entries.push(entry),
+            Err(_) => errors += 1,
         }
+    }
+    Ok((entries, errors))
+}
 
-        // Store via RPC
-        let params = serde_json::json!({
-            "cmd": cmd,
-            "cwd": cwd,
-            "exit_status": 0,
-        });
+/// Store params for atuin entries, linking each command to the ones before it
+/// in the same atuin session so n-grams are learned
+fn atuin_store_params(entries: &[AtuinEntry]) -> Vec<serde_json::Value> {
+    use std::collections::HashMap;
+
+    // The last three entries seen in each session, most recent first
+    let mut sessions: HashMap<&str, [Option<&AtuinEntry>; 3]> = HashMap::new();
+    entries
+        .iter()
+        .map(|entry| {
+            let [prev, prev2, prev3] = sessions.get(entry.session.as_str()).copied().unwrap_or_default();
+            sessions.insert(&entry.session, [Some(entry), prev, prev2]);
+            serde_json::json!({
+                "cmd": entry.cmd,
+                "cwd": entry.cwd,
+                "exit_status": entry.exit_status,
+                "start_time": entry.start_time,
+                "duration_ms": entry.duration_ms,
+                "prev_cmd": prev.map(|e| &e.cmd),
+                "prev2_cmd": prev2.map(|e| &e.cmd),
+                "prev3_cmd": prev3.map(|e| &e.cmd),
+                "prev_exit": prev.and_then(|e| e.exit_status),
+            })
+        })
+        .collect()
+}
 
+fn cmd_import_atuin(path: &str) -> Result<()> {
+    let path = shellexpand::tilde(path);
+    println!("Importing atuin history from {}...", path);
+
+    let (entries, errors) = read_atuin_history(std::path::Path::new(path.as_ref()))?;
+    let mut count = 0;
+    for batch in atuin_store_params(&entries).chunks(IMPORT_BATCH_SIZE) {
         let request = RpcRequest {
-            method: "store".to_string(),
-            params: Some(params),
+            method: "store_batch".to_string(),
+            params: Some(serde_json::json!({"commands": batch})),
         };
+        send_rpc(&request).context("Import interrupted")?;
+        count += batch.len();
+        print!("\rImported {} commands...", count);
+        std::io::stdout().flush().ok();
+    }
 
-        match send_rpc(&request) {
-            Ok(_) => count += 1,
-            Err(_) => errors += 1,
-        }
+    println!("\rImported {} commands ({} errors)", count, errors);
+
+    Ok(())
+}
 
-        if count % 100 == 0 {
-            print!("\rImported {} commands...", count);
-            std::io::stdout().flush().ok();
+fn cmd_places(limit: usize, sort: &str, plain: bool, absolute: bool) -> Result<()> {
+    let request = RpcRequest {
+        method: "places".to_string(),
+        params: Some(serde_json::json!({ "limit": limit, "sort": sort })),
+    };
+
+    let result = send_rpc(&request)?;
+    let style = if absolute { TimeStyle::Absolute } else { TimeStyle::Relative };
+
+    if let Some(places) = result.get("places").and_then(|p| p.as_array()) {
+        if places.is_empty() && !plain {
+            println!("No places found");
+        }
+        for place in places {
+            let dir = place.get("dir").and_then(|d| d.as_str()).unwrap_or("?");
+            if plain {
+                println!("{}", dir);
+                continue;
+            }
+            let count = place.get("count").and_then(|c| c.as_i64()).unwrap_or(0);
+            let last_used = place.get("last_used").and_then(|t| t.as_i64()).unwrap_or(0);
+            println!("{}\t{}\t{}", count, format_timestamp(last_used, style), dir);
         }
     }
 
-    println!("\rImported {} commands ({} errors)", count, errors);
+    Ok(())
+}
+
+fn cmd_cache(action: &CacheAction) -> Result<()> {
+    let method = match action {
+        CacheAction::Stats => "cache_stats",
+        CacheAction::Clear => "cache_clear",
+    };
+    let request = RpcRequest {
+        method: method.to_string(),
+        params: None,
+    };
+
+    let result = send_rpc(&request)?;
+
+    match action {
+        CacheAction::Stats => {
+            let get = |key: &str| result.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+            let (hits, misses) = (get("hits"), get("misses"));
+            let hit_rate = if hits + misses > 0 {
+                hits as f64 / (hits + misses) as f64 * 100.0
+            } else {
+                0.0
+            };
+            println!("Entries: {} / {}", get("entries"), get("capacity"));
+            println!("Hits: {} ({:.0}%)", hits, hit_rate);
+            println!("Misses: {}", misses);
+            println!("Size: {} bytes", get("size_bytes"));
+        }
+        CacheAction::Clear => println!("Prediction cache cleared"),
+    }
 
     Ok(())
 }
@@ -479,7 +1544,56 @@ fn cmd_ping() -> Result<()> {
     }
 }
 
-fn cmd_export(limit: usize) -> Result<()> {
+fn cmd_schema() -> Result<()> {
+    let request = RpcRequest {
+        method: "schema".to_string(),
+        params: None,
+    };
+
+    let result = send_rpc(&request)?;
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
+
+/// A history entry as written by `export --format jsonl`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ExportRecord {
+    cmd: String,
+    cwd: String,
+    /// When it was run (Unix seconds)
+    timestamp: i64,
+    exit_status: Option<i32>,
+    duration_ms: Option<i64>,
+}
+
+/// Write search results (newest first, as the daemon returns them) in `format`,
+/// oldest first unless `newest_first`; returns how many were written
+fn write_export(results: &[serde_json::Value], format: &str, newest_first: bool, out: &mut dyn Write) -> Result<usize> {
+    let entries: Vec<&serde_json::Value> = if newest_first {
+        results.iter().collect()
+    } else {
+        results.iter().rev().collect()
+    };
+    for entry in entries {
+        if format == "jsonl" {
+            let record: ExportRecord = serde_json::from_value(entry.clone())?;
+            writeln!(out, "{}", serde_json::to_string(&record)?)?;
+            continue;
+        }
+
+        let cmd = entry.get("cmd").and_then(|c| c.as_str()).unwrap_or("");
+        let timestamp = entry.get("timestamp").and_then(|t| t.as_i64()).unwrap_or(0);
+        let duration_ms = entry.get("duration_ms").and_then(|d| d.as_i64()).unwrap_or(0);
+        let duration_secs = duration_ms / 1000;
+
+        // Extended zsh history format: : timestamp:duration;command, with the
+        // newlines of multi-line commands written as zsh does (backslash-newline)
+        writeln!(out, ": {}:{};{}", timestamp, duration_secs, cmd.replace('\n', "\\\n"))?;
+    }
+    Ok(results.len())
+}
+
+fn cmd_export(limit: usize, since: Option<i64>, until: Option<i64>, format: &str, newest_first: bool) -> Result<()> {
     let effective_limit = if limit == 0 { 100_000 } else { limit };
 
     let request = RpcRequest {
@@ -487,91 +1601,143 @@ fn cmd_export(limit: usize) -> Result<()> {
         params: Some(serde_json::json!({
             "pattern": "",
             "limit": effective_limit,
+            "since": since,
+            "until": until,
         })),
     };
 
     let result = send_rpc(&request)?;
 
     if let Some(results) = result.get("results").and_then(|r| r.as_array()) {
-        // Collect and reverse so oldest is first (search returns newest first)
-        let entries: Vec<_> = results.iter().rev().collect();
-        for entry in &entries {
-            let cmd = entry.get("cmd").and_then(|c| c.as_str()).unwrap_or("");
-            let timestamp = entry.get("timestamp").and_then(|t| t.as_i64()).unwrap_or(0);
-            let duration_ms = entry.get("duration_ms").and_then(|d| d.as_i64()).unwrap_or(0);
-            let duration_secs = duration_ms / 1000;
-
-            // Extended zsh history format: : timestamp:duration;command
-            println!(": {}:{};{}", timestamp, duration_secs, cmd);
-        }
-        eprintln!("Exported {} entries", entries.len());
+        let exported = write_export(results, format, newest_first, &mut std::io::stdout().lock())?;
+        eprintln!("Exported {} entries", exported);
     }
 
     Ok(())
 }
 
-fn cmd_bench(iterations: usize) -> Result<()> {
+/// Time `iterations` round trips of one request and print avg/min/max; returns the average
+fn bench_rpc(label: &str, iterations: usize, method: &str, params: Option<serde_json::Value>) -> Result<Duration> {
     use std::time::Instant;
 
-    eprintln!("Benchmarking {} iterations...\n", iterations);
-
-    // Benchmark ping
-    let mut ping_times = Vec::new();
+    let request = RpcRequest {
+        method: method.to_string(),
+        params,
+    };
+    let mut times = Vec::new();
     for _ in 0..iterations {
         let start = Instant::now();
-        let request = RpcRequest {
-            method: "ping".to_string(),
-            params: None,
-        };
         send_rpc(&request)?;
-        ping_times.push(start.elapsed());
+        times.push(start.elapsed());
     }
 
-    let avg_ping = ping_times.iter().sum::<Duration>() / iterations as u32;
-    let min_ping = ping_times.iter().min().unwrap();
-    let max_ping = ping_times.iter().max().unwrap();
-    eprintln!("ping:   avg={:?}  min={:?}  max={:?}", avg_ping, min_ping, max_ping);
+    let avg = times.iter().sum::<Duration>() / iterations as u32;
+    let min = times.iter().min().unwrap();
+    let max = times.iter().max().unwrap();
+    eprintln!("{:<14} avg={:?}  min={:?}  max={:?}", format!("{}:", label), avg, min, max);
+    Ok(avg)
+}
 
-    // Benchmark search (empty pattern, limit 1000)
-    let mut search_times = Vec::new();
-    for _ in 0..iterations {
-        let start = Instant::now();
-        let request = RpcRequest {
-            method: "search".to_string(),
-            params: Some(serde_json::json!({
-                "pattern": "",
-                "limit": 1000,
-            })),
-        };
-        send_rpc(&request)?;
-        search_times.push(start.elapsed());
+/// A throwaway daemon with its own socket and database, stopped and removed on drop
+struct ScratchDaemon {
+    dir: PathBuf,
+}
+
+impl ScratchDaemon {
+    fn start() -> Result<Self> {
+        let dir = std::env::temp_dir().join(format!("nicehist-bench-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+
+        // Every later RPC goes to the scratch socket, whatever config.toml says
+        let socket = dir.join("nicehist.sock");
+        if SOCKET.set(socket.clone()).is_err() {
+            anyhow::bail!("scratch daemon must start before any request is sent");
+        }
+
+        // An empty config keeps the user's socket and database settings out of it
+        let config = dir.join("config.toml");
+        std::fs::write(&config, "")?;
+
+        let daemon = daemon_binary();
+        let status = std::process::Command::new(&daemon)
+            .arg("--detach")
+            .arg("--config")
+            .arg(&config)
+            .env("NICEHIST_SOCKET", &socket)
+            .env("XDG_DATA_HOME", &dir)
+            .status()
+            .with_context(|| format!("Failed to run {}", daemon.display()))?;
+        if !status.success() {
+            anyhow::bail!("scratch daemon failed to start ({})", status);
+        }
+        Ok(Self { dir })
     }
 
-    let avg_search = search_times.iter().sum::<Duration>() / iterations as u32;
-    let min_search = search_times.iter().min().unwrap();
-    let max_search = search_times.iter().max().unwrap();
-    eprintln!("search: avg={:?}  min={:?}  max={:?}", avg_search, min_search, max_search);
+    /// Store `count` distinct synthetic commands
+    fn seed(&self, count: usize) -> Result<()> {
+        const PROGRAMS: &[&str] = &["git checkout", "cargo test", "docker run", "kubectl get", "npm run", "make"];
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+
+        for chunk_start in (0..count).step_by(IMPORT_BATCH_SIZE) {
+            let commands: Vec<serde_json::Value> = (chunk_start..count.min(chunk_start + IMPORT_BATCH_SIZE))
+                .map(|i| serde_json::json!({
+                    "cmd": format!("{} target-{}", PROGRAMS[i % PROGRAMS.len()], i),
+                    "cwd": format!("/src/project-{}", i % 50),
+                    "start_time": now - i as i64,
+                }))
+                .collect();
+            send_rpc(&RpcRequest {
+                method: "store_batch".to_string(),
+                params: Some(serde_json::json!({ "commands": commands })),
+            })?;
+        }
+        Ok(())
+    }
+}
 
-    // Benchmark predict
-    let mut predict_times = Vec::new();
-    for _ in 0..iterations {
-        let start = Instant::now();
-        let request = RpcRequest {
-            method: "predict".to_string(),
-            params: Some(serde_json::json!({
-                "prefix": "git",
-                "cwd": "/tmp",
-                "limit": 5,
-            })),
-        };
-        send_rpc(&request)?;
-        predict_times.push(start.elapsed());
+impl Drop for ScratchDaemon {
+    fn drop(&mut self) {
+        let _ = cmd_shutdown();
+        std::fs::remove_dir_all(&self.dir).ok();
     }
+}
+
+fn cmd_bench(iterations: usize, seed: usize) -> Result<()> {
+    let _scratch = if seed > 0 {
+        let scratch = ScratchDaemon::start()?;
+        eprintln!("Seeding a scratch daemon with {} commands...", seed);
+        scratch.seed(seed)?;
+        Some(scratch)
+    } else {
+        None
+    };
+
+    eprintln!("Benchmarking {} iterations...\n", iterations);
 
-    let avg_predict = predict_times.iter().sum::<Duration>() / iterations as u32;
-    let min_predict = predict_times.iter().min().unwrap();
-    let max_predict = predict_times.iter().max().unwrap();
-    eprintln!("predict: avg={:?}  min={:?}  max={:?}", avg_predict, min_predict, max_predict);
+    bench_rpc("ping", iterations, "ping", None)?;
+    bench_rpc("search", iterations, "search", Some(serde_json::json!({
+        "pattern": "",
+        "limit": 1000,
+    })))?;
+    bench_rpc("predict", iterations, "predict", Some(serde_json::json!({
+        "prefix": "git",
+        "cwd": "/tmp",
+        "limit": 5,
+    })))?;
+
+    // Substring search through the full-text index vs a LIKE scan of every command
+    let fts = bench_rpc("search (fts)", iterations, "search", Some(serde_json::json!({
+        "pattern": "target-1234",
+        "use_fts": true,
+    })))?;
+    let like = bench_rpc("search (like)", iterations, "search", Some(serde_json::json!({
+        "pattern": "target-1234",
+        "use_fts": false,
+    })))?;
+    eprintln!("fts speedup:   {:.1}x", like.as_secs_f64() / fts.as_secs_f64().max(f64::EPSILON));
 
     Ok(())
 }
@@ -587,17 +1753,50 @@ fn cmd_delete(cmd: &str) -> Result<()> {
     Ok(())
 }
 
-fn cmd_store(
-    cmd: &str,
-    cwd: &str,
-    exit_status: Option<i64>,
-    duration_ms: Option<i64>,
-    start_time: Option<i64>,
-    session_id: Option<i64>,
-    prev_cmd: Option<&str>,
-    prev2_cmd: Option<&str>,
-    prev_exit: Option<i32>,
-) -> Result<()> {
+fn cmd_prune(older_than_days: u32) -> Result<()> {
+    let request = RpcRequest {
+        method: "prune_history".to_string(),
+        params: Some(serde_json::json!({ "older_than_days": older_than_days })),
+    };
+
+    let result = send_rpc_with_timeout(&request, MAINTENANCE_TIMEOUT)?;
+    let get = |key: &str| result.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+    println!(
+        "Pruned {} history entries older than {} days ({} commands no longer used)",
+        get("history"),
+        older_than_days,
+        get("commands")
+    );
+    Ok(())
+}
+
+fn cmd_reset(yes: bool) -> Result<()> {
+    let request = RpcRequest {
+        method: "reset".to_string(),
+        params: Some(serde_json::json!({ "dry_run": !yes })),
+    };
+
+    let result = send_rpc_with_timeout(&request, MAINTENANCE_TIMEOUT)?;
+    let rows = result.get("rows").and_then(|v| v.as_u64()).unwrap_or(0);
+    if yes {
+        println!("Deleted {} rows", rows);
+        return Ok(());
+    }
+
+    println!("This would delete {} rows:", rows);
+    if let Some(tables) = result.get("tables").and_then(|v| v.as_object()) {
+        for (table, count) in tables {
+            println!("  {:<18} {}", table, count);
+        }
+    }
+    anyhow::bail!("Not resetting without --yes")
+}
+
+fn cmd_store(args: &StoreArgs) -> Result<()> {
+    let &StoreArgs {
+        ref cmd, ref cwd, exit_status, duration_ms, start_time, session_id, session_start, ref prev_cmd, ref prev2_cmd,
+        ref prev3_cmd, prev_exit, cross_session_ngrams, ref shell, ref terminal, ref tty, ref exclude_dirs, split_pipelines,
+    } = args;
     let mut params = serde_json::json!({
         "cmd": cmd,
         "cwd": cwd,
@@ -615,15 +1814,39 @@ fn cmd_store(
     if let Some(v) = session_id {
         params["session_id"] = serde_json::json!(v);
     }
+    if let Some(v) = session_start {
+        params["session_start"] = serde_json::json!(v);
+    }
     if let Some(v) = prev_cmd {
         params["prev_cmd"] = serde_json::json!(v);
     }
     if let Some(v) = prev2_cmd {
         params["prev2_cmd"] = serde_json::json!(v);
     }
+    if let Some(v) = prev3_cmd {
+        params["prev3_cmd"] = serde_json::json!(v);
+    }
     if let Some(v) = prev_exit {
         params["prev_exit"] = serde_json::json!(v);
     }
+    if cross_session_ngrams {
+        params["cross_session_ngrams"] = serde_json::json!(true);
+    }
+    if let Some(v) = shell {
+        params["shell"] = serde_json::json!(v);
+    }
+    if let Some(v) = terminal {
+        params["terminal"] = serde_json::json!(v);
+    }
+    if let Some(v) = tty {
+        params["tty"] = serde_json::json!(v);
+    }
+    if !exclude_dirs.is_empty() {
+        params["exclude_dirs"] = serde_json::json!(exclude_dirs);
+    }
+    if split_pipelines {
+        params["split_pipelines"] = serde_json::json!(true);
+    }
 
     let request = RpcRequest {
         method: "store".to_string(),
@@ -634,26 +1857,20 @@ fn cmd_store(
     Ok(())
 }
 
-fn cmd_predict(
-    prefix: &str,
-    cwd: &str,
-    limit: usize,
-    last_cmd: Option<&str>,
-    prev_cmd: Option<&str>,
-    timeout_ms: u64,
-    plain: bool,
-    frecent_boost: bool,
-    weights_json: Option<&str>,
-    last_exit: Option<i32>,
-) -> Result<()> {
+fn cmd_predict(args: &PredictArgs, json: bool) -> Result<usize> {
+    let &PredictArgs {
+        ref prefix, ref cwd, limit, ref last_cmd, ref prev_cmd, ref prev2_cmd, timeout_ms, plain, last_exit,
+        no_frecent_boost, ref weights, since_last, substring, no_failures, global, deadline_ms, fallback_popular,
+        ref project_type, ref buffer, cursor,
+    } = args;
     let mut params = serde_json::json!({
         "prefix": prefix,
         "cwd": cwd,
         "limit": limit,
-        "frecent_boost": frecent_boost,
+        "frecent_boost": !no_frecent_boost,
     });
 
-    if let Some(wj) = weights_json {
+    if let Some(wj) = weights {
         let weights: serde_json::Value = serde_json::from_str(wj)
             .with_context(|| format!("Invalid weights JSON: {}", wj))?;
         params["weights"] = weights;
@@ -666,12 +1883,40 @@ fn cmd_predict(
     if let Some(c) = prev_cmd {
         last_cmds.push(serde_json::json!(c));
     }
+    if let Some(c) = prev2_cmd {
+        last_cmds.push(serde_json::json!(c));
+    }
     if !last_cmds.is_empty() {
         params["last_cmds"] = serde_json::json!(last_cmds);
     }
     if let Some(exit) = last_exit {
         params["last_exit"] = serde_json::json!(exit);
     }
+    if since_last {
+        params["since_last"] = serde_json::json!(true);
+    }
+    if substring {
+        params["match_mode"] = serde_json::json!("substring");
+    }
+    if no_failures {
+        params["exclude_failed"] = serde_json::json!(true);
+    }
+    if global {
+        params["global"] = serde_json::json!(true);
+    }
+    if let Some(ms) = deadline_ms {
+        params["deadline_ms"] = serde_json::json!(ms);
+    }
+    if fallback_popular {
+        params["fallback_popular"] = serde_json::json!(true);
+    }
+    if let Some(p) = project_type {
+        params["in_project"] = serde_json::json!(p);
+    }
+    if let (Some(buffer), Some(cursor)) = (buffer, cursor) {
+        params["buffer"] = serde_json::json!(buffer);
+        params["cursor_pos"] = serde_json::json!(cursor);
+    }
 
     let request = RpcRequest {
         method: "predict".to_string(),
@@ -680,6 +1925,11 @@ fn cmd_predict(
 
     let timeout = Duration::from_millis(timeout_ms);
     let result = send_rpc_with_timeout(&request, timeout)?;
+    let found = result_count(result.get("suggestions"));
+    if json {
+        print_json(result.get("suggestions"))?;
+        return Ok(found);
+    }
 
     if let Some(suggestions) = result.get("suggestions").and_then(|s| s.as_array()) {
         for (i, entry) in suggestions.iter().enumerate() {
@@ -697,23 +1947,76 @@ fn cmd_predict(
         }
     }
 
+    Ok(found)
+}
+
+fn cmd_accept(prefix: &str, cmd: &str, shown: &[String]) -> Result<()> {
+    let request = RpcRequest {
+        method: "accept".to_string(),
+        params: Some(serde_json::json!({
+            "prefix": prefix,
+            "cmd": cmd,
+            "shown": shown,
+        })),
+    };
+
+    send_rpc(&request)?;
+    Ok(())
+}
+
+fn cmd_watch(json: bool) -> Result<()> {
+    let request = RpcRequest {
+        method: "subscribe".to_string(),
+        params: None,
+    };
+
+    let (mut reader, id) = send_request(&request, Duration::from_secs(5))?;
+    read_result(&mut reader, id)?;
+    // Commands arrive whenever they're run, so wait for them indefinitely
+    reader.get_ref().set_read_timeout(None)?;
+
+    for line in reader.lines() {
+        let line = line?;
+        if json {
+            println!("{}", line);
+            continue;
+        }
+        let stored: serde_json::Value = serde_json::from_str(&line)?;
+        if let Some(cmd) = stored.get("cmd").and_then(|c| c.as_str()) {
+            println!("{}", cmd);
+        }
+    }
     Ok(())
 }
 
-fn cmd_context(cwd: &str) -> Result<()> {
+fn cmd_context(cwd: &str, stats: bool, json: bool) -> Result<()> {
     let request = RpcRequest {
         method: "context".to_string(),
-        params: Some(serde_json::json!({ "cwd": cwd })),
+        params: Some(serde_json::json!({ "cwd": cwd, "stats": stats })),
     };
 
     let result = send_rpc(&request)?;
+    if json {
+        return print_json(Some(&result));
+    }
 
     if let Some(obj) = result.as_object() {
         for (key, value) in obj {
-            if let Some(s) = value.as_str() {
-                if !s.is_empty() {
-                    println!("{}={}", key, s);
-                }
+            if let Some(s) = value.as_str()
+                && !s.is_empty()
+            {
+                println!("{}={}", key, s);
+            }
+        }
+
+        if let Some(n) = obj.get("dir_commands").and_then(|n| n.as_i64()) {
+            println!("dir_commands={}", n);
+        }
+        if let Some(top) = obj.get("top_commands").and_then(|t| t.as_array()) {
+            for (i, entry) in top.iter().enumerate() {
+                let cmd = entry.get("cmd").and_then(|c| c.as_str()).unwrap_or("");
+                let count = entry.get("count").and_then(|c| c.as_i64()).unwrap_or(0);
+                println!("top_command_{}={} ({})", i + 1, cmd, count);
             }
         }
     }
@@ -721,6 +2024,43 @@ fn cmd_context(cwd: &str) -> Result<()> {
     Ok(())
 }
 
+/// Locate the daemon binary: next to this executable, then on $PATH
+fn daemon_binary() -> PathBuf {
+    if let Ok(exe) = std::env::current_exe() {
+        let sibling = exe.with_file_name("nicehist-daemon");
+        if sibling.is_file() {
+            return sibling;
+        }
+    }
+
+    if let Some(path) = std::env::var_os("PATH") {
+        for dir in std::env::split_paths(&path) {
+            let candidate = dir.join("nicehist-daemon");
+            if candidate.is_file() {
+                return candidate;
+            }
+        }
+    }
+
+    PathBuf::from("nicehist-daemon")
+}
+
+fn cmd_daemon(detach: bool, in_memory: bool) -> Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    let daemon = daemon_binary();
+    let mode = if detach { "--detach" } else { "--foreground" };
+    let mut command = std::process::Command::new(&daemon);
+    command.arg(mode);
+    if in_memory {
+        command.arg("--in-memory");
+    }
+
+    // exec only returns on failure
+    let err = command.exec();
+    Err(err).with_context(|| format!("Failed to exec {}", daemon.display()))
+}
+
 fn cmd_shutdown() -> Result<()> {
     let request = RpcRequest {
         method: "shutdown".to_string(),
@@ -732,14 +2072,39 @@ fn cmd_shutdown() -> Result<()> {
     Ok(())
 }
 
-fn cmd_frecent(terms: &[String], path_type: Option<&str>, plain: bool, limit: usize) -> Result<()> {
+fn cmd_frecent(args: &FrecentArgs, times: Option<TimeStyle>, json: bool) -> Result<usize> {
+    let &FrecentArgs {
+        ref terms, dirs, files, ref types, plain, age: _, exists_only, limit, include_cwd, dir_weight, file_weight,
+    } = args;
+    let mut path_types = types.clone();
+    if dirs {
+        path_types.push("d".to_string());
+    }
+    if files {
+        path_types.push("f".to_string());
+    }
+    // The shell records its logical $PWD, which may differ from the resolved cwd
+    let mut exclude = Vec::new();
+    if !include_cwd {
+        exclude.push(default_cwd());
+        exclude.extend(std::env::var("PWD").ok().filter(|pwd| *pwd != exclude[0]));
+    }
+    let type_weights = (dir_weight.is_some() || file_weight.is_some())
+        .then(|| (dir_weight.unwrap_or(1.0), file_weight.unwrap_or(1.0)));
+
     let mut params = serde_json::json!({
         "terms": terms,
         "limit": limit,
+        "raw": json || times.is_some(),
+        "exists_only": exists_only,
+        "exclude": exclude,
     });
 
-    if let Some(pt) = path_type {
-        params["path_type"] = serde_json::json!(pt);
+    if !path_types.is_empty() {
+        params["path_types"] = serde_json::json!(path_types);
+    }
+    if let Some((d, f)) = type_weights {
+        params["type_weights"] = serde_json::json!({"d": d, "f": f});
     }
 
     let request = RpcRequest {
@@ -748,6 +2113,13 @@ fn cmd_frecent(terms: &[String], path_type: Option<&str>, plain: bool, limit: us
     };
 
     let result = send_rpc(&request)?;
+    let found = result_count(result.get("results"));
+
+    if json {
+        // Includes rank and last_access
+        print_json(result.get("results"))?;
+        return Ok(found);
+    }
 
     if let Some(results) = result.get("results").and_then(|r| r.as_array()) {
         if results.is_empty() && !plain {
@@ -766,14 +2138,19 @@ fn cmd_frecent(terms: &[String], path_type: Option<&str>, plain: bool, limit: us
                             .get("path_type")
                             .and_then(|t| t.as_str())
                             .unwrap_or("?");
-                        println!("{:.1}\t{}\t{}", score, pt, path);
+                        match (times, entry.get("last_access").and_then(|t| t.as_i64())) {
+                            (Some(style), Some(ts)) => {
+                                println!("{:.1}\t{}\t{}\t{}", score, pt, format_timestamp(ts, style), path)
+                            }
+                            _ => println!("{:.1}\t{}\t{}", score, pt, path),
+                        }
                     }
                 }
             }
         }
     }
 
-    Ok(())
+    Ok(found)
 }
 
 fn cmd_frecent_add(path: &str, path_type: &str) -> Result<()> {
@@ -789,99 +2166,114 @@ fn cmd_frecent_add(path: &str, path_type: &str) -> Result<()> {
     Ok(())
 }
 
-fn cmd_import_fasd(path: &str) -> Result<()> {
-    use std::fs::File;
-    use std::io::BufReader as FileBufReader;
+fn cmd_frecent_delete(path: &str, path_type: &str) -> Result<()> {
+    let request = RpcRequest {
+        method: "frecent_delete".to_string(),
+        params: Some(serde_json::json!({
+            "path": path,
+            "path_type": path_type,
+        })),
+    };
 
-    let path = shellexpand::tilde(path);
-    let file = File::open(path.as_ref())
-        .with_context(|| format!("Failed to open fasd data file: {}", path))?;
+    let result = send_rpc(&request)?;
+    if result.get("deleted").and_then(|d| d.as_bool()).unwrap_or(false) {
+        println!("Deleted: {}", path);
+    } else {
+        println!("Not tracked: {}", path);
+    }
+    Ok(())
+}
 
-    let reader = FileBufReader::new(file);
-    let mut count = 0;
-    let mut errors = 0;
+/// Write a completion script for `shell`; needs no daemon
+fn cmd_completions(shell: clap_complete::Shell, out: &mut dyn Write) {
+    clap_complete::generate(shell, &mut Cli::command(), "nicehist", out);
+}
 
-    println!("Importing fasd data from {}...", path);
+/// Read fasd's data file: `path|rank|timestamp` lines, each typed as a directory or
+/// file by what's on disk. Returns the entries and how many lines couldn't be read.
+fn parse_fasd<R: BufRead>(reader: R) -> (Vec<FrecentAddParams>, usize) {
+    let mut entries = Vec::new();
+    let mut errors = 0;
 
     for line in reader.lines() {
-        let line = match line {
-            Ok(l) => l,
-            Err(_) => {
-                errors += 1;
-                continue;
-            }
+        let Ok(line) = line else {
+            errors += 1;
+            continue;
         };
-
-        let line = line.trim().to_string();
+        let line = line.trim();
         if line.is_empty() {
             continue;
         }
 
-        // fasd format: path|rank|timestamp
-        let parts: Vec<&str> = line.rsplitn(3, '|').collect();
-        if parts.len() < 3 {
+        // rsplitn reverses: timestamp, rank, then the path, which may itself contain '|'
+        let mut parts = line.rsplitn(3, '|');
+        let (Some(timestamp), Some(rank), Some(path)) = (parts.next(), parts.next(), parts.next()) else {
             errors += 1;
             continue;
-        }
-
-        // rsplitn reverses: [timestamp, rank, path]
-        let timestamp: i64 = match parts[0].parse() {
-            Ok(t) => t,
-            Err(_) => {
-                errors += 1;
-                continue;
-            }
         };
-        let rank: f64 = match parts[1].parse() {
-            Ok(r) => r,
-            Err(_) => {
-                errors += 1;
-                continue;
-            }
+        let (Ok(timestamp), Ok(rank)) = (timestamp.parse::<i64>(), rank.parse::<f64>()) else {
+            errors += 1;
+            continue;
         };
-        let entry_path = parts[2];
 
-        // Determine type from filesystem
-        let path_type = if std::path::Path::new(entry_path).is_dir() {
-            "d"
-        } else {
-            "f"
-        };
+        let path_type = if std::path::Path::new(path).is_dir() { "d" } else { "f" };
+        entries.push(FrecentAddParams {
+            path: path.to_string(),
+            path_type: path_type.to_string(),
+            rank: Some(rank),
+            timestamp: Some(timestamp),
+        });
+    }
 
-        let request = RpcRequest {
-            method: "frecent_add".to_string(),
-            params: Some(serde_json::json!({
-                "path": entry_path,
-                "path_type": path_type,
-                "rank": rank,
-                "timestamp": timestamp,
-            })),
-        };
+    (entries, errors)
+}
 
-        match send_rpc(&request) {
-            Ok(_) => count += 1,
-            Err(_) => errors += 1,
-        }
+fn cmd_import_fasd(path: &str) -> Result<()> {
+    use std::fs::File;
+    use std::io::BufReader as FileBufReader;
 
-        if count % 50 == 0 && count > 0 {
-            eprint!("\rImported {} entries...", count);
-            std::io::stderr().flush().ok();
-        }
-    }
+    let path = shellexpand::tilde(path);
+    let file = File::open(path.as_ref())
+        .with_context(|| format!("Failed to open fasd data file: {}", path))?;
+
+    println!("Importing fasd data from {}...", path);
+    let (entries, errors) = parse_fasd(FileBufReader::new(file));
+
+    // One transaction for the whole file, so it's fast and imported ranks aren't aged mid-import
+    let request = RpcRequest {
+        method: "frecent_add_batch".to_string(),
+        params: Some(serde_json::json!({"entries": entries})),
+    };
+    let count = send_rpc(&request)?
+        .get("added")
+        .and_then(|n| n.as_u64())
+        .unwrap_or(0);
 
     eprintln!("\rImported {} fasd entries ({} errors)", count, errors);
 
     Ok(())
 }
 
+/// Write `frecent_export` results as fasd's `path|rank|timestamp` lines; returns how many
+fn write_fasd(results: &[serde_json::Value], out: &mut dyn Write) -> Result<usize> {
+    let mut count = 0;
+    for entry in results {
+        let path = entry.get("path").and_then(|p| p.as_str()).unwrap_or("");
+        let rank = entry.get("rank").and_then(|r| r.as_f64()).unwrap_or(0.0);
+        let last_access = entry.get("last_access").and_then(|t| t.as_i64()).unwrap_or(0);
+
+        if !path.is_empty() {
+            writeln!(out, "{}|{}|{}", path, rank, last_access)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
 fn cmd_export_fasd(output: Option<&str>) -> Result<()> {
     let request = RpcRequest {
-        method: "frecent_query".to_string(),
-        params: Some(serde_json::json!({
-            "terms": [],
-            "limit": 100000,
-            "raw": true,
-        })),
+        method: "frecent_export".to_string(),
+        params: None,
     };
 
     let result = send_rpc(&request)?;
@@ -893,93 +2285,650 @@ fn cmd_export_fasd(output: Option<&str>) -> Result<()> {
         Box::new(std::io::stdout())
     };
 
-    let mut count = 0;
-    if let Some(results) = result.get("results").and_then(|r| r.as_array()) {
-        for entry in results {
-            let path = entry.get("path").and_then(|p| p.as_str()).unwrap_or("");
-            let rank = entry.get("rank").and_then(|r| r.as_f64()).unwrap_or(0.0);
-            let last_access = entry.get("last_access").and_then(|t| t.as_i64()).unwrap_or(0);
-
-            if !path.is_empty() {
-                writeln!(writer, "{}|{}|{}", path, rank, last_access)?;
-                count += 1;
-            }
-        }
-    }
+    let results = result.get("results").and_then(|r| r.as_array()).map(Vec::as_slice).unwrap_or_default();
+    let count = write_fasd(results, &mut writer)?;
 
-    if output.is_some() {
-        eprintln!("Exported {} entries to {}", count, output.unwrap());
+    if let Some(path) = output {
+        eprintln!("Exported {} entries to {}", count, path);
     }
 
     Ok(())
 }
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
     let cli = Cli::parse();
+    if let Err(e) = cli.validate() {
+        e.exit();
+    }
+
+    let nonzero_on_empty = cli.exit_nonzero_on_empty;
+    let outcome = run(cli);
+    if let Err(e) = &outcome {
+        eprintln!("Error: {:?}", e);
+    }
+    ExitCode::from(exit_status(&outcome, nonzero_on_empty))
+}
 
+/// Run a command, returning how many results it found if it has any to count
+fn run(cli: Cli) -> Result<Option<usize>> {
+    // How many results search, predict or frecent found
+    let mut found = None;
     match cli.command {
-        Commands::Search { pattern, limit, dir, plain, last_cmd, prev_cmd, ngram_boost, last_exit, cwd } => {
-            cmd_search(&pattern, limit, dir.as_deref(), plain, last_cmd.as_deref(), prev_cmd.as_deref(), ngram_boost, last_exit, &cwd)?;
-        }
-        Commands::Store {
-            cmd, cwd, exit_status, duration_ms, start_time,
-            session_id, prev_cmd, prev2_cmd, prev_exit,
-        } => {
-            cmd_store(&cmd, &cwd, exit_status, duration_ms, start_time,
-                      session_id, prev_cmd.as_deref(), prev2_cmd.as_deref(), prev_exit)?;
-        }
-        Commands::Predict {
-            prefix, cwd, limit, last_cmd, prev_cmd, timeout_ms, plain,
-            last_exit, no_frecent_boost, weights,
-        } => {
-            cmd_predict(&prefix, &cwd, limit, last_cmd.as_deref(),
-                        prev_cmd.as_deref(), timeout_ms, plain, !no_frecent_boost,
-                        weights.as_deref(), last_exit)?;
-        }
-        Commands::Context { cwd } => {
-            cmd_context(&cwd)?;
+        Commands::Search(args) => {
+            found = Some(cmd_search(&args, time_style(args.age, cli.absolute), cli.json)?);
+        }
+        Commands::Store(args) => {
+            cmd_store(&args)?;
+        }
+        Commands::Predict(args) => {
+            found = Some(cmd_predict(&args, cli.json)?);
+        }
+        Commands::Accept { prefix, cmd, shown } => {
+            cmd_accept(&prefix, &cmd, &shown)?;
+        }
+        Commands::Watch => {
+            cmd_watch(cli.json)?;
+        }
+        Commands::Context { cwd, stats } => {
+            cmd_context(&cwd, stats, cli.json)?;
         }
         Commands::Delete { cmd } => {
             cmd_delete(&cmd)?;
         }
+        Commands::Prune { older_than } => {
+            cmd_prune(older_than)?;
+        }
+        Commands::Reset { yes } => {
+            cmd_reset(yes)?;
+        }
+        Commands::Daemon { foreground: _, detach, in_memory } => {
+            cmd_daemon(detach, in_memory)?;
+        }
         Commands::Shutdown => {
             cmd_shutdown()?;
         }
         Commands::Stats => {
             cmd_stats()?;
         }
-        Commands::Import { path } => {
-            cmd_import(&path)?;
+        Commands::Maintenance => {
+            cmd_maintenance()?;
+        }
+        Commands::Import { path, restart, dedup, dry_run } => {
+            cmd_import(&path, restart, dedup, dry_run)?;
         }
-        Commands::Export { limit } => {
-            cmd_export(limit)?;
+        Commands::Export { limit, since, until, format, newest_first } => {
+            cmd_export(limit, since, until, &format, newest_first)?;
         }
-        Commands::Bench { iterations } => {
-            cmd_bench(iterations)?;
+        Commands::Bench { iterations, seed } => {
+            cmd_bench(iterations, seed)?;
         }
         Commands::Ping => {
             cmd_ping()?;
         }
-        Commands::Frecent { terms, dirs, files, plain, limit } => {
-            let path_type = if dirs {
-                Some("d")
-            } else if files {
-                Some("f")
-            } else {
-                None
-            };
-            cmd_frecent(&terms, path_type, plain, limit)?;
+        Commands::Schema => {
+            cmd_schema()?;
+        }
+        Commands::Places { limit, sort, plain } => {
+            cmd_places(limit, &sort, plain, cli.absolute)?;
+        }
+        Commands::Cache { action } => {
+            cmd_cache(&action)?;
+        }
+        Commands::Frecent(args) => {
+            found = Some(cmd_frecent(&args, time_style(args.age, cli.absolute), cli.json)?);
         }
         Commands::FrecentAdd { path, path_type } => {
             cmd_frecent_add(&path, &path_type)?;
         }
+        Commands::FrecentDelete { path, path_type } => {
+            cmd_frecent_delete(&path, &path_type)?;
+        }
         Commands::ImportFasd { path } => {
             cmd_import_fasd(&path)?;
         }
+        Commands::ImportBash { path } => {
+            cmd_import_bash(&path)?;
+        }
+        Commands::ImportAtuin { path } => {
+            cmd_import_atuin(&path)?;
+        }
         Commands::ExportFasd { output } => {
             cmd_export_fasd(output.as_deref())?;
         }
+        Commands::Completions { shell } => {
+            cmd_completions(shell, &mut std::io::stdout());
+        }
     }
 
-    Ok(())
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rpc_envelope() {
+        let request = RpcRequest { method: "ping".to_string(), params: None };
+        let json = serde_json::to_value(RpcEnvelope { jsonrpc: "2.0", id: 3, request: &request }).unwrap();
+        assert_eq!(json, serde_json::json!({"jsonrpc": "2.0", "id": 3, "method": "ping"}));
+    }
+
+    #[test]
+    fn test_highlight_match_markers() {
+        assert_eq!(highlight_match("git commit -m x", Some((4, 10)), false), "git «commit» -m x");
+        assert_eq!(highlight_match("git status", Some((0, 3)), true), "\x1b[1;31mgit\x1b[0m status");
+    }
+
+    #[test]
+    fn test_highlight_match_invalid_span() {
+        assert_eq!(highlight_match("ls", None, false), "ls");
+        assert_eq!(highlight_match("ls", Some((1, 9)), false), "ls");
+        // Not on a char boundary
+        assert_eq!(highlight_match("é", Some((1, 2)), false), "é");
+    }
+
+    #[test]
+    fn test_json_conflicts_with_plain() {
+        for args in [
+            &["nicehist", "search", "git", "--plain", "--json"][..],
+            &["nicehist", "--json", "predict", "--prefix", "git", "--plain"],
+            &["nicehist", "frecent", "--plain", "--json"],
+        ] {
+            let err = Cli::try_parse_from(args).and_then(|cli| cli.validate()).expect_err("should conflict");
+            assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+        }
+
+        let cli = Cli::try_parse_from(["nicehist", "context", "--json"]).unwrap();
+        assert!(cli.json);
+    }
+
+    #[test]
+    fn test_exit_nonzero_on_empty() {
+        // Off by default, and global like --json
+        assert!(!Cli::try_parse_from(["nicehist", "search", "git"]).unwrap().exit_nonzero_on_empty);
+        for args in [
+            &["nicehist", "--exit-nonzero-on-empty", "predict", "--prefix", "git"][..],
+            &["nicehist", "frecent", "--exit-nonzero-on-empty"],
+        ] {
+            assert!(Cli::try_parse_from(args).unwrap().exit_nonzero_on_empty);
+        }
+
+        let results = serde_json::json!({"results": [{"cmd": "git status"}]});
+        let empty = serde_json::json!({"results": []});
+        assert_eq!(exit_status(&Ok(Some(result_count(results.get("results")))), true), 0);
+        assert_eq!(exit_status(&Ok(Some(result_count(empty.get("results")))), true), 1);
+        // A missing result array is empty too
+        assert_eq!(exit_status(&Ok(Some(result_count(None))), true), 1);
+        assert_eq!(exit_status(&Ok(Some(0)), false), 0);
+        // Other commands have no results to count
+        assert_eq!(exit_status(&Ok(None), true), 0);
+        // Errors are told apart from finding nothing, with or without the flag
+        for nonzero_on_empty in [false, true] {
+            assert_eq!(exit_status(&Err(anyhow::anyhow!("daemon not running")), nonzero_on_empty), 2);
+        }
+    }
+
+    #[test]
+    fn test_zsh_completions() {
+        let mut out = Vec::new();
+        cmd_completions(clap_complete::Shell::Zsh, &mut out);
+        let script = String::from_utf8(out).unwrap();
+
+        assert!(script.starts_with("#compdef nicehist"));
+        for needle in ["(frecent)", "(predict)", "(search)", "--exists-only", "--substring", "--regex"] {
+            assert!(script.contains(needle), "missing {}", needle);
+        }
+    }
+
+    #[test]
+    fn test_parse_history_line() {
+        let cmd = |line: &str| parse_history_line(line).map(|e| e.cmd);
+        assert_eq!(cmd(": 1700000000:0;git status").as_deref(), Some("git status"));
+        assert_eq!(cmd("ls -la").as_deref(), Some("ls -la"));
+        assert_eq!(cmd(": 1700000000:0;"), None);
+        assert_eq!(cmd(": malformed"), None);
+
+        assert_eq!(parse_history_line(": 1700000000:12;make; make test"), Some(HistoryEntry {
+            cmd: "make; make test".to_string(),
+            start_time: Some(1700000000),
+            duration_ms: Some(12000),
+        }));
+        let plain = parse_history_line("ls -la").unwrap();
+        assert_eq!((plain.start_time, plain.duration_ms), (None, None));
+    }
+
+    #[test]
+    fn test_import_joins_continuation_lines() {
+        let history = concat!(
+            ": 1700000000:0;for f in *; do\\\n",
+            "  echo $f\\\n",
+            "done\n",
+            ": 1700000001:0;echo trailing\\\\\n",
+            ": 1700000002:0;ls\n",
+        );
+        let mut stored: Vec<HistoryEntry> = Vec::new();
+        let mut saved = Vec::new();
+        let counts = import_history(
+            std::io::Cursor::new(history.as_bytes()),
+            0,
+            1,
+            |batch| {
+                stored.extend_from_slice(batch);
+                Ok(batch.len())
+            },
+            |offset, _| {
+                saved.push(offset);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!((counts.commands, counts.errors), (3, 0));
+        let cmds: Vec<&str> = stored.iter().map(|e| e.cmd.as_str()).collect();
+        assert_eq!(cmds, vec!["for f in *; do\n  echo $f\ndone", "echo trailing\\\\", "ls"]);
+        assert_eq!(stored[0].start_time, Some(1700000000));
+        // The first checkpoint lands after the whole loop, not inside it
+        assert_eq!(saved[0], history.find(": 1700000001").unwrap() as u64);
+    }
+
+    #[test]
+    fn test_import_dry_run_counts() {
+        let dir = tempfile::tempdir().unwrap();
+        let fixture = dir.path().join("zsh_history");
+        let mut history = Vec::new();
+        history.extend_from_slice(b": 1700000000:0;cargo build\n");
+        history.extend_from_slice(b": 1700000001:0;for f in *; do\\\n  echo $f\\\ndone\n");
+        history.extend_from_slice(b": 1700000002:0;\n");
+        history.extend_from_slice(b"\n");
+        history.extend_from_slice(b": 1700000003:0;echo \xff\xfe\n");
+        history.extend_from_slice(b"ls\n");
+        std::fs::write(&fixture, &history).unwrap();
+
+        let file = std::fs::File::open(&fixture).unwrap();
+        let counts = import_history(std::io::BufReader::new(file), 0, 2, |_| Ok(0), |_, _| Ok(())).unwrap();
+        assert_eq!(counts, ImportCounts { commands: 3, stored: 0, multiline: 1, empty: 2, errors: 1 });
+        assert_eq!(
+            format_import_counts(&counts),
+            "  3 commands (1 multi-line)\n  2 empty entries skipped\n  1 unreadable lines\n"
+        );
+    }
+
+    #[test]
+    fn test_import_keeps_extended_timestamps() {
+        let history = ": 1700000000:3;cargo build\n: 1700000100:0;cargo test\nls\n";
+        let mut stored: Vec<HistoryEntry> = Vec::new();
+        import_history(
+            std::io::Cursor::new(history.as_bytes()),
+            0,
+            10,
+            |batch| {
+                stored.extend_from_slice(batch);
+                Ok(batch.len())
+            },
+            |_, _| Ok(()),
+        )
+        .unwrap();
+
+        let times: Vec<_> = stored.iter().map(|e| (e.cmd.as_str(), e.start_time, e.duration_ms)).collect();
+        assert_eq!(times, vec![
+            ("cargo build", Some(1700000000), Some(3000)),
+            ("cargo test", Some(1700000100), Some(0)),
+            ("ls", None, None),
+        ]);
+    }
+
+    #[test]
+    fn test_export_formats() {
+        // Newest first, as search returns them
+        let results = vec![
+            serde_json::json!({"cmd": "cargo test", "cwd": "/src", "timestamp": 1700000100, "exit_status": 101, "duration_ms": 2500, "score": 0.9}),
+            serde_json::json!({"cmd": "ls", "cwd": "/tmp", "timestamp": 1700000000}),
+        ];
+
+        let mut out = Vec::new();
+        assert_eq!(write_export(&results, "zsh", false, &mut out).unwrap(), 2);
+        assert_eq!(String::from_utf8(out).unwrap(), ": 1700000000:0;ls\n: 1700000100:2;cargo test\n");
+
+        let mut out = Vec::new();
+        write_export(&results, "jsonl", false, &mut out).unwrap();
+        let records: Vec<ExportRecord> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records, vec![
+            ExportRecord { cmd: "ls".into(), cwd: "/tmp".into(), timestamp: 1700000000, exit_status: None, duration_ms: None },
+            ExportRecord {
+                cmd: "cargo test".into(),
+                cwd: "/src".into(),
+                timestamp: 1700000100,
+                exit_status: Some(101),
+                duration_ms: Some(2500),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_export_newest_first() {
+        let results: Vec<serde_json::Value> = (0..4)
+            .map(|i| serde_json::json!({"cmd": format!("cmd{}", i), "cwd": "/src", "timestamp": 1700000100 - i}))
+            .collect();
+        for format in ["zsh", "jsonl"] {
+            let export = |newest_first: bool| -> Vec<String> {
+                let mut out = Vec::new();
+                assert_eq!(write_export(&results, format, newest_first, &mut out).unwrap(), 4);
+                String::from_utf8(out).unwrap().lines().map(str::to_string).collect()
+            };
+            let oldest_first = export(false);
+            let mut newest_first = export(true);
+            assert!(newest_first[0].contains("cmd0"));
+            newest_first.reverse();
+            assert_eq!(newest_first, oldest_first);
+        }
+
+        let cli = Cli::try_parse_from(["nicehist", "export", "--reverse"]).unwrap();
+        assert!(matches!(cli.command, Commands::Export { newest_first: true, .. }));
+    }
+
+    #[test]
+    fn test_export_import_round_trips_multiline_commands() {
+        use nicehist_core::Database;
+        use nicehist_core::protocol::{SearchParams, StoreParams};
+
+        let cmds = ["for f in *.log; do\n  gzip \"$f\"\ndone", "echo 'a\n\nb'", "ls"];
+        let source = Database::open_in_memory().unwrap();
+        for (i, cmd) in cmds.iter().enumerate() {
+            source.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/src".to_string(),
+                start_time: Some(1700000000 + i as i64),
+                ..Default::default()
+            }).unwrap();
+        }
+        let all = SearchParams { pattern: String::new(), host: Some("*".to_string()), ..Default::default() };
+        let results: Vec<serde_json::Value> = source.search(&all).unwrap()
+            .iter()
+            .map(|r| serde_json::to_value(r).unwrap())
+            .collect();
+
+        let mut out = Vec::new();
+        write_export(&results, "zsh", false, &mut out).unwrap();
+        assert_eq!(String::from_utf8_lossy(&out).lines().count(), 7);
+
+        let imported = Database::open_in_memory().unwrap();
+        import_history(
+            std::io::Cursor::new(out),
+            0,
+            10,
+            |batch| {
+                let params: Vec<StoreParams> = batch
+                    .iter()
+                    .map(|entry| StoreParams {
+                        cmd: entry.cmd.clone(),
+                        cwd: "/src".to_string(),
+                        start_time: entry.start_time,
+                        ..Default::default()
+                    })
+                    .collect();
+                imported.store_batch(&params)
+            },
+            |_, _| Ok(()),
+        )
+        .unwrap();
+
+        let mut round_tripped: Vec<(String, i64)> = imported.search(&all).unwrap().into_iter().map(|r| (r.cmd, r.timestamp)).collect();
+        round_tripped.sort_by_key(|(_, timestamp)| *timestamp);
+        let expected: Vec<(String, i64)> = cmds.iter().enumerate().map(|(i, cmd)| (cmd.to_string(), 1700000000 + i as i64)).collect();
+        assert_eq!(round_tripped, expected);
+    }
+
+    #[test]
+    fn test_parse_bash_history() {
+        let history = b"ls -la\n#1700000000\ngit status\n# not a timestamp\n\n#1700000100\n#1700000200\nmake\n#12ab\n\xff\necho done\n";
+        let (entries, errors) = parse_bash_history(&history[..]).unwrap();
+        assert_eq!(errors, 1);
+        let parsed: Vec<_> = entries.iter().map(|e| (e.cmd.as_str(), e.start_time)).collect();
+        assert_eq!(parsed, vec![
+            ("ls -la", None),
+            ("git status", Some(1700000000)),
+            ("# not a timestamp", None),
+            ("make", Some(1700000200)),
+            ("#12ab", None),
+            ("echo done", None),
+        ]);
+    }
+
+    #[test]
+    fn test_import_atuin() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.db");
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE history (
+                 id TEXT PRIMARY KEY, timestamp INTEGER NOT NULL, duration INTEGER NOT NULL, exit INTEGER NOT NULL,
+                 command TEXT NOT NULL, cwd TEXT NOT NULL, session TEXT NOT NULL, hostname TEXT NOT NULL,
+                 deleted_at INTEGER
+             );
+             INSERT INTO history VALUES
+                 ('a', 1700000000000000000, 2500000000, 0, 'cargo build', '/src', 's1', 'host', NULL),
+                 ('b', 1700000001000000000, 1000000, 0, 'ls', '/tmp', 's2', 'host', NULL),
+                 ('c', 1700000002000000000, 3000000000, 101, 'cargo test', '/src', 's1', 'host', NULL),
+                 ('d', 1700000003000000000, -1, -1, 'secret stuff', '/src', 's1', 'host', 1700000004000000000),
+                 ('e', 1700000005000000000, -1, -1, 'cargo fix', '/src', 's1', 'host', NULL);",
+        )
+        .unwrap();
+        drop(conn);
+
+        let (entries, errors) = read_atuin_history(&path).unwrap();
+        assert_eq!(errors, 0);
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0], AtuinEntry {
+            cmd: "cargo build".to_string(),
+            cwd: "/src".to_string(),
+            start_time: 1700000000,
+            duration_ms: Some(2500),
+            exit_status: Some(0),
+            session: "s1".to_string(),
+        });
+        assert_eq!((entries[3].cmd.as_str(), entries[3].duration_ms, entries[3].exit_status), ("cargo fix", None, None));
+
+        let params = atuin_store_params(&entries);
+        // "ls" ran in another session, so cargo test follows cargo build
+        assert_eq!(params[1]["prev_cmd"], serde_json::Value::Null);
+        assert_eq!(params[2]["prev_cmd"], "cargo build");
+        assert_eq!(params[2]["prev_exit"], 0);
+        assert_eq!(params[3]["prev_cmd"], "cargo test");
+        assert_eq!(params[3]["prev2_cmd"], "cargo build");
+        assert_eq!(params[3]["prev_exit"], 101);
+
+        assert!(read_atuin_history(&dir.path().join("missing.db")).is_err());
+    }
+
+    #[test]
+    fn test_fasd_round_trip() {
+        use nicehist_core::Database;
+
+        // Ranks sum past the aging threshold, which must not decay imported data
+        let home = tempfile::tempdir().unwrap();
+        let project = home.path().join("project").to_string_lossy().into_owned();
+        let old = home.path().join("old|archive").to_string_lossy().into_owned();
+        let notes = home.path().join("notes.md").to_string_lossy().into_owned();
+        std::fs::create_dir(&project).unwrap();
+        std::fs::create_dir(&old).unwrap();
+        let fasd = format!(
+            "{}|1500.25|1700000300\n{}|2.5|1700000100\n\n{}|650|1600000000\nnot fasd\n/x|high|1700000000\n",
+            project, notes, old
+        );
+
+        let (entries, errors) = parse_fasd(fasd.as_bytes());
+        assert_eq!(errors, 2);
+        let types: Vec<&str> = entries.iter().map(|e| e.path_type.as_str()).collect();
+        assert_eq!(types, ["d", "f", "d"]);
+
+        let export = |db: &Database| {
+            let results: Vec<serde_json::Value> = db.frecent_export().unwrap()
+                .iter()
+                .map(|r| serde_json::to_value(r).unwrap())
+                .collect();
+            let mut out = Vec::new();
+            assert_eq!(write_fasd(&results, &mut out).unwrap(), 3);
+            String::from_utf8(out).unwrap()
+        };
+
+        let first = Database::open_in_memory().unwrap();
+        first.frecent_add_batch(&entries).unwrap();
+        let exported = export(&first);
+        assert_eq!(exported, format!(
+            "{}|1500.25|1700000300\n{}|650|1600000000\n{}|2.5|1700000100\n",
+            project, old, notes
+        ));
+
+        let second = Database::open_in_memory().unwrap();
+        second.frecent_add_batch(&parse_fasd(exported.as_bytes()).0).unwrap();
+        assert_eq!(export(&second), exported);
+    }
+
+    #[test]
+    fn test_import_resume_stores_each_line_once() {
+        let history = ": 1:0;one\n: 2:0;two\nthree\n\n: 4:0;four\nfive\n";
+        let mut stored: Vec<String> = Vec::new();
+        let mut saved: u64 = 0;
+
+        // First run fails on the second batch, after "one" and "two" were committed
+        let mut calls = 0;
+        let result = import_history(
+            std::io::Cursor::new(history.as_bytes()),
+            0,
+            2,
+            |batch| {
+                calls += 1;
+                if calls == 2 {
+                    anyhow::bail!("daemon went away");
+                }
+                stored.extend(batch.iter().map(|e| e.cmd.clone()));
+                Ok(batch.len())
+            },
+            |offset, _| {
+                saved = offset;
+                Ok(())
+            },
+        );
+        assert!(result.is_err());
+        assert_eq!(stored, vec!["one", "two"]);
+
+        // Resume from the saved offset
+        let rest = &history.as_bytes()[saved as usize..];
+        let counts = import_history(
+            std::io::Cursor::new(rest),
+            saved,
+            2,
+            |batch| {
+                stored.extend(batch.iter().map(|e| e.cmd.clone()));
+                Ok(batch.len())
+            },
+            |offset, _| {
+                saved = offset;
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert_eq!((counts.commands, counts.errors), (3, 0));
+        assert_eq!(stored, vec!["one", "two", "three", "four", "five"]);
+        assert_eq!(saved, history.len() as u64);
+    }
+
+    #[test]
+    fn test_import_resume_needs_same_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("zsh_history");
+        std::fs::write(&source, "ls\ncd /tmp\n").unwrap();
+        let state = format_import_state(3, ImportFingerprint::of(&source, 3).unwrap());
+        assert_eq!(resume_offset(&state, &source), Some(3));
+
+        // Appending keeps the offset
+        std::fs::write(&source, "ls\ncd /tmp\nmake\n").unwrap();
+        assert_eq!(resume_offset(&state, &source), Some(3));
+
+        // Rewritten in place to the same length, or replaced by another file
+        std::fs::write(&source, "pwd\ncd /tmp\nmake\n").unwrap();
+        assert_eq!(resume_offset(&state, &source), None);
+        let replacement = dir.path().join("new_history");
+        std::fs::write(&replacement, "ls\ncd /tmp\nmake\n").unwrap();
+        std::fs::rename(&replacement, &source).unwrap();
+        #[cfg(unix)]
+        assert_eq!(resume_offset(&state, &source), None);
+
+        // Offsets saved without a fingerprint
+        assert_eq!(resume_offset("3", &source), None);
+    }
+
+    #[test]
+    fn test_format_relative_time_boundaries() {
+        let now = 1_700_000_000;
+        let ago = |secs: i64| format_relative_time(now - secs, now);
+
+        assert_eq!(ago(0), "just now");
+        assert_eq!(ago(-30), "just now");
+        assert_eq!(ago(1), "1s ago");
+        assert_eq!(ago(59), "59s ago");
+        assert_eq!(ago(60), "1m ago");
+        assert_eq!(ago(3599), "59m ago");
+        assert_eq!(ago(3600), "1h ago");
+        assert_eq!(ago(86399), "23h ago");
+        assert_eq!(ago(86400), "yesterday");
+        assert_eq!(ago(2 * 86400 - 1), "yesterday");
+        assert_eq!(ago(2 * 86400), "2d ago");
+        assert_eq!(ago(7 * 86400 - 1), "6d ago");
+        assert_eq!(ago(7 * 86400), "1w ago");
+        assert_eq!(ago(364 * 86400), "52w ago");
+        assert_eq!(ago(365 * 86400), "1y ago");
+    }
+
+    #[test]
+    fn test_format_iso8601() {
+        assert_eq!(format_iso8601(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_iso8601(1_700_000_000), "2023-11-14T22:13:20Z");
+        assert_eq!(format_iso8601(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_iso8601(-1), "1969-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn test_parse_time_bound() {
+        assert_eq!(parse_time_bound("1700000000", false), Ok(1_700_000_000));
+        assert_eq!(parse_time_bound("2023-11-14T22:13:20Z", false), Ok(1_700_000_000));
+        assert_eq!(parse_time_bound("2023-11-14 22:13", false), Ok(1_699_999_980));
+        assert_eq!(parse_time_bound("2000-02-29", false), Ok(951_782_400));
+        assert_eq!(parse_time_bound("2000-02-29", true), Ok(951_782_400 + 86399));
+        assert_eq!(parse_time_bound("1970-01-01", false), Ok(0));
+        for bad in ["yesterday", "2024-13-01", "2024-01", "2024-01-01T25:00", "2024-01-01Tnoon"] {
+            assert!(parse_time_bound(bad, false).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_parse_time_bound_rejects_impossible_dates() {
+        for bad in ["2024-02-30", "2024-02-31", "2023-02-29", "1900-02-29", "2024-04-31", "2024-11-31", "2024-01-32", "2024-01-00"] {
+            assert!(parse_time_bound(bad, false).is_err(), "{}", bad);
+        }
+        for good in ["2024-02-29", "2000-02-29", "2024-04-30", "2024-12-31"] {
+            assert!(parse_time_bound(good, false).is_ok(), "{}", good);
+        }
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024), "5.0 MiB");
+    }
+
+    #[test]
+    fn test_time_style() {
+        assert_eq!(time_style(false, false), None);
+        assert_eq!(time_style(true, false), Some(TimeStyle::Relative));
+        assert_eq!(time_style(false, true), Some(TimeStyle::Absolute));
+    }
 }
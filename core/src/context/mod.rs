@@ -0,0 +1,241 @@
+//! Context collection for directories.
+//!
+//! Detects VCS (git, hg, jj, svn) and project type (rust, node, python, etc.)
+//! for context-aware command predictions.
+
+mod project;
+mod vcs;
+
+use project::detect_project_types;
+use vcs::{detect_vcs, list_git_branches};
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::protocol::ContextInfo;
+
+/// Most directories cached at once; a long-lived daemon sees many
+const CACHE_CAPACITY: usize = 1024;
+
+/// Context cache entry
+struct CacheEntry<T> {
+    value: T,
+    timestamp: Instant,
+}
+
+/// Per-directory cache
+type Cache<T> = Arc<Mutex<HashMap<String, CacheEntry<T>>>>;
+
+/// Context collector with caching
+pub struct ContextCollector {
+    cache: Cache<ContextInfo>,
+    branches: Cache<Option<Vec<String>>>,
+    cache_ttl: Duration,
+    capacity: usize,
+}
+
+impl Default for ContextCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ContextCollector {
+    /// Create a new context collector with default TTL (5 seconds)
+    pub fn new() -> Self {
+        Self {
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            branches: Arc::new(Mutex::new(HashMap::new())),
+            cache_ttl: Duration::from_secs(5),
+            capacity: CACHE_CAPACITY,
+        }
+    }
+
+    /// Create with custom cache TTL
+    #[allow(dead_code)]
+    pub fn with_ttl(ttl: Duration) -> Self {
+        Self {
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            branches: Arc::new(Mutex::new(HashMap::new())),
+            cache_ttl: ttl,
+            capacity: CACHE_CAPACITY,
+        }
+    }
+
+    /// Get context for a directory (cached)
+    pub fn get_context(&self, dir: &str) -> ContextInfo {
+        self.cached(&self.cache, dir, || self.compute_context(dir))
+    }
+
+    /// A directory's value from `cache`, computed afresh once it's older than the TTL
+    fn cached<T: Clone>(&self, cache: &Mutex<HashMap<String, CacheEntry<T>>>, dir: &str, compute: impl FnOnce() -> T) -> T {
+        // Check cache first
+        {
+            let cache = cache.lock().unwrap();
+            if let Some(entry) = cache.get(dir)
+                && entry.timestamp.elapsed() < self.cache_ttl
+            {
+                return entry.value.clone();
+            }
+        }
+
+        // Compute fresh value
+        let value = compute();
+
+        // Update cache, dropping expired entries (or else the oldest) when full
+        {
+            let mut cache = cache.lock().unwrap();
+            if cache.len() >= self.capacity && !cache.contains_key(dir) {
+                cache.retain(|_, entry| entry.timestamp.elapsed() < self.cache_ttl);
+                if cache.len() >= self.capacity
+                    && let Some(oldest) = cache.iter().min_by_key(|(_, entry)| entry.timestamp).map(|(dir, _)| dir.clone())
+                {
+                    cache.remove(&oldest);
+                }
+            }
+            cache.insert(
+                dir.to_string(),
+                CacheEntry {
+                    value: value.clone(),
+                    timestamp: Instant::now(),
+                },
+            );
+        }
+
+        value
+    }
+
+    /// Invalidate cache for a directory
+    #[allow(dead_code)]
+    pub fn invalidate(&self, dir: &str) {
+        self.cache.lock().unwrap().remove(dir);
+        self.branches.lock().unwrap().remove(dir);
+    }
+
+    /// Local branches of the Git repository containing a directory, cached
+    /// like contexts (a deleted branch is gone once the entry expires)
+    pub fn git_branches(&self, dir: &str) -> Option<Vec<String>> {
+        self.cached(&self.branches, dir, || list_git_branches(Path::new(dir)))
+    }
+
+    /// Compute context without caching
+    fn compute_context(&self, dir: &str) -> ContextInfo {
+        let path = Path::new(dir);
+
+        // Detect VCS
+        let vcs_info = detect_vcs(path);
+
+        // Detect project types; the first match stays the primary one
+        let projects: Vec<String> = detect_project_types(path).iter().map(|p| p.to_string()).collect();
+
+        ContextInfo {
+            vcs: vcs_info.as_ref().map(|v| v.vcs_type.to_string()),
+            branch: vcs_info.as_ref().and_then(|v| v.branch.clone()),
+            remote: vcs_info.as_ref().and_then(|v| v.remote.clone()),
+            vcs_root: vcs_info.map(|v| v.root.to_string_lossy().to_string()),
+            project: projects.first().cloned(),
+            projects,
+            dir_commands: None,
+            top_commands: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_context_collector_caching() {
+        let collector = ContextCollector::new();
+
+        // Get context twice - second should be cached
+        let ctx1 = collector.get_context("/tmp");
+        let ctx2 = collector.get_context("/tmp");
+
+        // Both should return same result
+        assert_eq!(ctx1.vcs, ctx2.vcs);
+        assert_eq!(ctx1.project, ctx2.project);
+    }
+
+    #[test]
+    fn test_context_collector_invalidate() {
+        let collector = ContextCollector::new();
+
+        // Get context
+        let _ctx1 = collector.get_context("/tmp");
+
+        // Invalidate
+        collector.invalidate("/tmp");
+
+        // Cache should be empty for that dir
+        let cache = collector.cache.lock().unwrap();
+        assert!(!cache.contains_key("/tmp"));
+    }
+
+    #[test]
+    fn test_context_cache_is_bounded() {
+        let collector = ContextCollector { capacity: 2, ..ContextCollector::new() };
+        for dir in ["/tmp", "/", "/nonexistent", "/tmp"] {
+            collector.get_context(dir);
+        }
+
+        let cache = collector.cache.lock().unwrap();
+        assert_eq!(cache.len(), 2);
+        // The oldest entry made room
+        assert!(!cache.contains_key("/"));
+        assert!(cache.contains_key("/tmp") && cache.contains_key("/nonexistent"));
+    }
+
+    #[test]
+    fn test_git_branches_are_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let heads = dir.path().join(".git/refs/heads");
+        std::fs::create_dir_all(&heads).unwrap();
+        std::fs::write(dir.path().join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        for branch in ["main", "old"] {
+            std::fs::write(heads.join(branch), "0123456789abcdef0123456789abcdef01234567\n").unwrap();
+        }
+        let repo = dir.path().to_string_lossy();
+
+        let collector = ContextCollector::new();
+        assert_eq!(collector.git_branches(&repo).unwrap(), ["main", "old"]);
+
+        // A deleted branch is still listed until the entry expires
+        std::fs::remove_file(heads.join("old")).unwrap();
+        assert_eq!(collector.git_branches(&repo).unwrap(), ["main", "old"]);
+        collector.invalidate(&repo);
+        assert_eq!(collector.git_branches(&repo).unwrap(), ["main"]);
+    }
+
+    #[test]
+    fn test_context_for_current_dir() {
+        let collector = ContextCollector::new();
+        let cwd = env::current_dir().unwrap();
+        let ctx = collector.get_context(cwd.to_str().unwrap());
+
+        // Current directory (nicehist) should be detected as a git repo
+        // and a Rust project
+        if cwd.join(".git").exists() || cwd.join("Cargo.toml").exists() {
+            // We expect at least one of these to be detected
+            assert!(ctx.vcs.is_some() || ctx.project.is_some());
+        }
+    }
+
+    #[test]
+    fn test_context_lists_every_project_type() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("Cargo.toml"), "").unwrap();
+        std::fs::write(dir.path().join("package.json"), "").unwrap();
+
+        let ctx = ContextCollector::new().get_context(dir.path().to_str().unwrap());
+        assert_eq!(ctx.project.as_deref(), Some("rust"));
+        assert_eq!(ctx.projects, ["rust", "node"]);
+
+        let json = serde_json::to_value(&ctx).unwrap();
+        assert_eq!(json["projects"], serde_json::json!(["rust", "node"]));
+    }
+}
@@ -0,0 +1,264 @@
+//! Project type detection.
+//!
+//! Detects project type based on manifest files (Cargo.toml, package.json, etc.)
+
+use std::fmt;
+use std::path::Path;
+
+/// Detected project type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProjectType {
+    Rust,
+    Node,
+    Python,
+    Go,
+    Ruby,
+    Java,
+    CSharp,
+    Cpp,
+    C,
+    Php,
+    Elixir,
+    Haskell,
+    Scala,
+    Kotlin,
+    Swift,
+    Zig,
+    Nix,
+    Bazel,
+    Deno,
+    Bun,
+    Gleam,
+}
+
+impl fmt::Display for ProjectType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ProjectType::Rust => "rust",
+            ProjectType::Node => "node",
+            ProjectType::Python => "python",
+            ProjectType::Go => "go",
+            ProjectType::Ruby => "ruby",
+            ProjectType::Java => "java",
+            ProjectType::CSharp => "csharp",
+            ProjectType::Cpp => "cpp",
+            ProjectType::C => "c",
+            ProjectType::Php => "php",
+            ProjectType::Elixir => "elixir",
+            ProjectType::Haskell => "haskell",
+            ProjectType::Scala => "scala",
+            ProjectType::Kotlin => "kotlin",
+            ProjectType::Swift => "swift",
+            ProjectType::Zig => "zig",
+            ProjectType::Nix => "nix",
+            ProjectType::Bazel => "bazel",
+            ProjectType::Deno => "deno",
+            ProjectType::Bun => "bun",
+            ProjectType::Gleam => "gleam",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Manifest files and their associated project types, most specific first
+const PROJECT_MARKERS: &[(&str, ProjectType)] = &[
+    // Bazel (drives the build whatever the languages)
+    ("MODULE.bazel", ProjectType::Bazel),
+    ("WORKSPACE", ProjectType::Bazel),
+    ("WORKSPACE.bazel", ProjectType::Bazel),
+    ("BUILD.bazel", ProjectType::Bazel),
+    // Rust
+    ("Cargo.toml", ProjectType::Rust),
+    // Deno and Bun (before Node: they often have a package.json too)
+    ("deno.json", ProjectType::Deno),
+    ("deno.jsonc", ProjectType::Deno),
+    ("bun.lockb", ProjectType::Bun),
+    ("bun.lock", ProjectType::Bun),
+    // Node.js
+    ("package.json", ProjectType::Node),
+    // Python
+    ("pyproject.toml", ProjectType::Python),
+    ("setup.py", ProjectType::Python),
+    ("setup.cfg", ProjectType::Python),
+    ("requirements.txt", ProjectType::Python),
+    ("Pipfile", ProjectType::Python),
+    // Go
+    ("go.mod", ProjectType::Go),
+    ("go.sum", ProjectType::Go),
+    // Ruby
+    ("Gemfile", ProjectType::Ruby),
+    ("Rakefile", ProjectType::Ruby),
+    // Java
+    ("pom.xml", ProjectType::Java),
+    ("build.gradle", ProjectType::Java),
+    ("build.gradle.kts", ProjectType::Kotlin),
+    // C#
+    ("*.csproj", ProjectType::CSharp),
+    ("*.sln", ProjectType::CSharp),
+    // C/C++
+    ("CMakeLists.txt", ProjectType::Cpp),
+    ("Makefile", ProjectType::C),
+    ("meson.build", ProjectType::Cpp),
+    // PHP
+    ("composer.json", ProjectType::Php),
+    // Elixir
+    ("mix.exs", ProjectType::Elixir),
+    // Haskell
+    ("stack.yaml", ProjectType::Haskell),
+    ("*.cabal", ProjectType::Haskell),
+    // Scala
+    ("build.sbt", ProjectType::Scala),
+    // Kotlin
+    ("build.gradle.kts", ProjectType::Kotlin),
+    // Swift
+    ("Package.swift", ProjectType::Swift),
+    // Zig
+    ("build.zig", ProjectType::Zig),
+    // Gleam
+    ("gleam.toml", ProjectType::Gleam),
+    // Nix (last: a flake is often just the dev shell of a project in another language)
+    ("flake.nix", ProjectType::Nix),
+    ("default.nix", ProjectType::Nix),
+    ("shell.nix", ProjectType::Nix),
+];
+
+/// Detect every project type in the project root for a directory
+///
+/// Looks for manifest files in the directory and walks up to the first
+/// directory with any, then returns all types found there in marker order, so
+/// a polyglot repo (e.g. `Cargo.toml` next to `package.json`) reports both.
+pub fn detect_project_types(path: &Path) -> Vec<ProjectType> {
+    let mut current = if path.is_file() {
+        match path.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => return Vec::new(),
+        }
+    } else {
+        path.to_path_buf()
+    };
+
+    // Try current directory first, then walk up to find project root (max 10 levels)
+    for _ in 0..=10 {
+        let found = detect_all_in_dir(&current);
+        if !found.is_empty() {
+            return found;
+        }
+        if !current.pop() {
+            break;
+        }
+    }
+
+    Vec::new()
+}
+
+/// Check for project markers in a specific directory, returning every type in marker order
+fn detect_all_in_dir(dir: &Path) -> Vec<ProjectType> {
+    let mut found = Vec::new();
+    for (marker, project_type) in PROJECT_MARKERS {
+        if found.contains(project_type) {
+            continue;
+        }
+        let present = if let Some(ext) = marker.strip_prefix('*') {
+            // Glob pattern - check for any matching file (ext e.g. ".csproj")
+            std::fs::read_dir(dir).is_ok_and(|entries| {
+                entries.flatten().any(|entry| entry.file_name().to_str().is_some_and(|name| name.ends_with(ext)))
+            })
+        } else {
+            // Exact file name
+            dir.join(marker).exists()
+        };
+        if present {
+            found.push(*project_type);
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_project_type_display() {
+        assert_eq!(ProjectType::Rust.to_string(), "rust");
+        assert_eq!(ProjectType::Node.to_string(), "node");
+        assert_eq!(ProjectType::Python.to_string(), "python");
+    }
+
+    #[test]
+    fn test_detect_rust_project() {
+        let cwd = env::current_dir().unwrap();
+        if cwd.join("Cargo.toml").exists() {
+            let pt = detect_project_types(&cwd).first().copied();
+            assert_eq!(pt, Some(ProjectType::Rust));
+        }
+    }
+
+    #[test]
+    fn test_detect_from_subdir() {
+        let cwd = env::current_dir().unwrap();
+        if cwd.join("Cargo.toml").exists() {
+            let subdir = cwd.join("daemon/src");
+            if subdir.exists() {
+                let pt = detect_project_types(&subdir).first().copied();
+                assert_eq!(pt, Some(ProjectType::Rust));
+            }
+        }
+    }
+
+    #[test]
+    fn test_detect_all_in_dir() {
+        let cwd = env::current_dir().unwrap();
+        if cwd.join("Cargo.toml").exists() {
+            let pt = detect_all_in_dir(&cwd).first().copied();
+            assert_eq!(pt, Some(ProjectType::Rust));
+        }
+    }
+
+    #[test]
+    fn test_detect_new_markers() {
+        let cases: &[(&[&str], ProjectType)] = &[
+            (&["flake.nix"], ProjectType::Nix),
+            (&["default.nix"], ProjectType::Nix),
+            (&["WORKSPACE"], ProjectType::Bazel),
+            (&["BUILD.bazel"], ProjectType::Bazel),
+            (&["deno.json"], ProjectType::Deno),
+            (&["deno.jsonc", "package.json"], ProjectType::Deno),
+            (&["bun.lockb", "package.json"], ProjectType::Bun),
+            (&["gleam.toml"], ProjectType::Gleam),
+            // A flake next to a manifest is the project's dev shell
+            (&["flake.nix", "Cargo.toml"], ProjectType::Rust),
+        ];
+        for (markers, expected) in cases {
+            let dir = tempfile::tempdir().unwrap();
+            for marker in *markers {
+                std::fs::write(dir.path().join(marker), "").unwrap();
+            }
+            assert_eq!(detect_all_in_dir(dir.path()).first(), Some(expected), "{:?}", markers);
+        }
+        assert_eq!(ProjectType::Bazel.to_string(), "bazel");
+        assert_eq!(ProjectType::Gleam.to_string(), "gleam");
+    }
+
+    #[test]
+    fn test_detect_all_in_polyglot_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("Cargo.toml"), "").unwrap();
+        std::fs::write(dir.path().join("package.json"), "").unwrap();
+        let sub = dir.path().join("src-tauri/src");
+        std::fs::create_dir_all(&sub).unwrap();
+
+        assert_eq!(detect_all_in_dir(dir.path()), vec![ProjectType::Rust, ProjectType::Node]);
+        assert_eq!(detect_project_types(&sub), vec![ProjectType::Rust, ProjectType::Node]);
+    }
+
+    #[test]
+    fn test_detect_nonexistent() {
+        // /tmp is unlikely to have project markers
+        let pt = detect_project_types(Path::new("/tmp"));
+        // Could be None or could detect something if /tmp is in a project
+        // Just make sure it doesn't crash
+        let _ = pt;
+    }
+}
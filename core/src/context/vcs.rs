@@ -0,0 +1,570 @@
+//! VCS (Version Control System) detection.
+//!
+//! Detects Git, Mercurial, Jujutsu and Subversion repositories and extracts
+//! branch information (and, for Git, the origin remote).
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// VCS information for a directory
+#[derive(Debug, Clone)]
+pub struct VcsInfo {
+    /// Type of VCS ("git", "hg", "jj" or "svn")
+    pub vcs_type: &'static str,
+    /// Repository root directory
+    pub root: PathBuf,
+    /// Current branch name (if available)
+    pub branch: Option<String>,
+    /// URL of the `origin` remote (Git only), the same across clones
+    pub remote: Option<String>,
+}
+
+/// Detect VCS for a directory
+///
+/// Walks up the directory tree looking for .jj, .git, .hg or .svn directories.
+/// Returns VCS info with branch name if found.
+pub fn detect_vcs(path: &Path) -> Option<VcsInfo> {
+    // A jj repo usually has a colocated .git; jj is what's driving it
+    if let Some(info) = detect_jj(path) {
+        return Some(info);
+    }
+
+    // Try git next (most common)
+    if let Some(info) = detect_git(path) {
+        return Some(info);
+    }
+
+    // Try mercurial
+    if let Some(info) = detect_hg(path) {
+        return Some(info);
+    }
+
+    // Try subversion
+    if let Some(info) = detect_svn(path) {
+        return Some(info);
+    }
+
+    None
+}
+
+/// Detect Git repository
+fn detect_git(path: &Path) -> Option<VcsInfo> {
+    let root = find_repo_root(path, ".git")?;
+
+    // Get branch name and origin
+    let branch = get_git_branch(&root);
+    let remote = get_git_remote(&root);
+
+    Some(VcsInfo {
+        vcs_type: "git",
+        root,
+        branch,
+        remote,
+    })
+}
+
+/// Detect Mercurial repository
+fn detect_hg(path: &Path) -> Option<VcsInfo> {
+    let root = find_repo_root(path, ".hg")?;
+
+    // Get branch name
+    let branch = get_hg_branch(&root);
+
+    Some(VcsInfo {
+        vcs_type: "hg",
+        root,
+        branch,
+        remote: None,
+    })
+}
+
+/// Detect Jujutsu repository
+fn detect_jj(path: &Path) -> Option<VcsInfo> {
+    let root = find_repo_root(path, ".jj")?;
+
+    // Get bookmark name
+    let branch = get_jj_bookmark(&root);
+
+    Some(VcsInfo {
+        vcs_type: "jj",
+        root,
+        branch,
+        remote: None,
+    })
+}
+
+/// Detect Subversion working copy
+fn detect_svn(path: &Path) -> Option<VcsInfo> {
+    // Since svn 1.7 only the working copy root has a .svn directory
+    let root = find_repo_root(path, ".svn")?;
+
+    // Get branch name
+    let branch = get_svn_branch(&root);
+
+    Some(VcsInfo {
+        vcs_type: "svn",
+        root,
+        branch,
+        remote: None,
+    })
+}
+
+/// Find repository root by walking up the directory tree
+fn find_repo_root(start: &Path, marker: &str) -> Option<PathBuf> {
+    let mut current = if start.is_file() {
+        start.parent()?.to_path_buf()
+    } else {
+        start.to_path_buf()
+    };
+
+    loop {
+        if current.join(marker).exists() {
+            return Some(current);
+        }
+
+        if !current.pop() {
+            return None;
+        }
+    }
+}
+
+/// The git directory for a work tree. In worktrees and submodules `.git` is a
+/// file holding `gitdir: <path>` (relative to the work tree, or absolute).
+fn git_dir(repo_root: &Path) -> Option<PathBuf> {
+    let dot_git = repo_root.join(".git");
+    if dot_git.is_dir() {
+        return Some(dot_git);
+    }
+    let content = std::fs::read_to_string(&dot_git).ok()?;
+    let gitdir = content.lines().find_map(|line| line.strip_prefix("gitdir:"))?.trim();
+    Some(repo_root.join(gitdir))
+}
+
+/// Get current Git branch name
+fn get_git_branch(repo_root: &Path) -> Option<String> {
+    // Try reading HEAD directly (faster than shelling out)
+    let head_path = git_dir(repo_root).map(|dir| dir.join("HEAD"));
+    if let Some(content) = head_path.and_then(|path| std::fs::read_to_string(path).ok()) {
+        let content = content.trim();
+        if let Some(branch) = content.strip_prefix("ref: refs/heads/") {
+            return Some(branch.to_string());
+        }
+        // Detached HEAD - return short hash
+        if content.chars().count() >= 7 {
+            return Some(content.chars().take(7).collect());
+        }
+    }
+
+    // Fallback to git command
+    let output = Command::new("git")
+        .args(["rev-parse", "--abbrev-ref", "HEAD"])
+        .current_dir(repo_root)
+        .output()
+        .ok()?;
+
+    if output.status.success() {
+        let branch = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !branch.is_empty() && branch != "HEAD" {
+            return Some(branch);
+        }
+    }
+
+    None
+}
+
+/// The git directory holding a work tree's config and refs. Worktrees share the
+/// main repository's through their `commondir`.
+fn git_common_dir(repo_root: &Path) -> Option<PathBuf> {
+    let dir = git_dir(repo_root)?;
+    match std::fs::read_to_string(dir.join("commondir")) {
+        Ok(common) => Some(dir.join(common.trim())),
+        Err(_) => Some(dir),
+    }
+}
+
+/// URL of the Git `origin` remote, read from the repository config
+fn get_git_remote(repo_root: &Path) -> Option<String> {
+    let config = std::fs::read_to_string(git_common_dir(repo_root)?.join("config")).ok()?;
+    git_config_value(&config, "remote \"origin\"", "url")
+}
+
+/// Local branches of the Git repository containing `path`, sorted
+pub fn list_git_branches(path: &Path) -> Option<Vec<String>> {
+    let root = find_repo_root(path, ".git")?;
+    let common = git_common_dir(&root)?;
+    let heads = common.join("refs/heads");
+    let packed = std::fs::read_to_string(common.join("packed-refs")).ok();
+
+    let mut branches = Vec::new();
+    if heads.is_dir() || packed.is_some() {
+        // Try reading loose and packed refs directly (faster than shelling out)
+        collect_loose_refs(&heads, "", &mut branches);
+        for line in packed.iter().flat_map(|packed| packed.lines()) {
+            if let Some(branch) = line.split_once(' ').and_then(|(_, name)| name.strip_prefix("refs/heads/")) {
+                branches.push(branch.to_string());
+            }
+        }
+    } else {
+        // Fallback to git command, e.g. for the reftable backend
+        let output = Command::new("git")
+            .args(["branch", "--list", "--format=%(refname:short)"])
+            .current_dir(&root)
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        branches.extend(String::from_utf8_lossy(&output.stdout).lines().map(str::to_string));
+    }
+
+    branches.sort();
+    branches.dedup();
+    Some(branches)
+}
+
+/// Branch names under a `refs/heads` directory; a name can hold slashes
+fn collect_loose_refs(dir: &Path, prefix: &str, branches: &mut Vec<String>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if entry.path().is_dir() {
+            collect_loose_refs(&entry.path(), &format!("{}/", name), branches);
+        } else {
+            branches.push(name);
+        }
+    }
+}
+
+/// A value from git-config INI text: `key` in the `[section]` header given
+/// exactly as written in the file (e.g. `remote "origin"`). Section and key
+/// names are case-insensitive; the quoted subsection is not.
+fn git_config_value(config: &str, section: &str, key: &str) -> Option<String> {
+    let (name, subsection) = section.split_once(' ').unwrap_or((section, ""));
+    let mut in_section = false;
+    for line in config.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.split_once(']')).map(|(h, _)| h.trim()) {
+            let (header_name, header_sub) = header.split_once(' ').unwrap_or((header, ""));
+            in_section = header_name.eq_ignore_ascii_case(name) && header_sub.trim() == subsection;
+            continue;
+        }
+        if !in_section {
+            continue;
+        }
+        if let Some((k, v)) = line.split_once('=')
+            && k.trim().eq_ignore_ascii_case(key)
+        {
+            let v = v.trim();
+            return Some(v.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(v).to_string());
+        }
+    }
+    None
+}
+
+/// Get current Mercurial branch name
+fn get_hg_branch(repo_root: &Path) -> Option<String> {
+    // Try reading .hg/branch directly
+    let branch_path = repo_root.join(".hg/branch");
+    if let Ok(content) = std::fs::read_to_string(&branch_path) {
+        let branch = content.trim().to_string();
+        if !branch.is_empty() {
+            return Some(branch);
+        }
+    }
+
+    // Fallback to hg command
+    let output = Command::new("hg")
+        .args(["branch"])
+        .current_dir(repo_root)
+        .output()
+        .ok()?;
+
+    if output.status.success() {
+        let branch = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !branch.is_empty() {
+            return Some(branch);
+        }
+    }
+
+    // Default branch for hg
+    Some("default".to_string())
+}
+
+/// Get the bookmark nearest the Jujutsu working-copy commit
+fn get_jj_bookmark(repo_root: &Path) -> Option<String> {
+    // jj's store isn't readable without jj; don't let it snapshot the working copy
+    let output = Command::new("jj")
+        .args([
+            "log",
+            "--no-graph",
+            "--ignore-working-copy",
+            "--color=never",
+            "-r",
+            "latest(::@ & bookmarks())",
+            "-T",
+            "bookmarks.map(|b| b.name()).join(\"\\n\")",
+        ])
+        .current_dir(repo_root)
+        .output()
+        .ok()?;
+
+    if output.status.success() {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        if let Some(bookmark) = stdout.lines().map(str::trim).find(|b| !b.is_empty()) {
+            return Some(bookmark.to_string());
+        }
+    }
+
+    None
+}
+
+/// Get the current Subversion branch (`trunk`, or the name under `branches/` or `tags/`)
+fn get_svn_branch(repo_root: &Path) -> Option<String> {
+    // Try reading the working copy database directly
+    if let Some(repos_path) = read_svn_repos_path(&repo_root.join(".svn/wc.db")) {
+        return svn_branch_from_path(&repos_path);
+    }
+
+    // Fallback to svn command
+    let output = Command::new("svn")
+        .args(["info", "--show-item", "relative-url"])
+        .current_dir(repo_root)
+        .output()
+        .ok()?;
+
+    if output.status.success() {
+        let url = String::from_utf8_lossy(&output.stdout);
+        return svn_branch_from_path(url.trim().trim_start_matches("^/"));
+    }
+
+    None
+}
+
+/// Repository path of the working copy root, from `.svn/wc.db`
+fn read_svn_repos_path(wc_db: &Path) -> Option<String> {
+    let conn = rusqlite::Connection::open_with_flags(wc_db, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY).ok()?;
+    conn.query_row(
+        "SELECT repos_path FROM nodes WHERE local_relpath = '' ORDER BY op_depth LIMIT 1",
+        [],
+        |row| row.get(0),
+    )
+    .ok()
+}
+
+/// Branch name for a path in the conventional trunk/branches/tags layout,
+/// e.g. `project/branches/feature-x/src` -> `feature-x`
+fn svn_branch_from_path(repos_path: &str) -> Option<String> {
+    let mut parts = repos_path.split('/').filter(|p| !p.is_empty());
+    while let Some(part) = parts.next() {
+        match part {
+            "trunk" => return Some("trunk".to_string()),
+            "branches" | "tags" => return parts.next().map(str::to_string),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_find_repo_root_git() {
+        // This test assumes we're running in the nicehist repo
+        let cwd = env::current_dir().unwrap();
+        if cwd.join(".git").exists() {
+            let root = find_repo_root(&cwd, ".git");
+            assert!(root.is_some());
+            assert!(root.unwrap().join(".git").exists());
+        }
+    }
+
+    #[test]
+    fn test_detect_vcs_git() {
+        let cwd = env::current_dir().unwrap();
+        if cwd.join(".git").exists() {
+            let info = detect_vcs(&cwd);
+            assert!(info.is_some());
+            let info = info.unwrap();
+            assert_eq!(info.vcs_type, "git");
+            assert!(info.branch.is_some());
+        }
+    }
+
+    #[test]
+    fn test_detect_vcs_nonexistent() {
+        let info = detect_vcs(Path::new("/tmp"));
+        // /tmp is unlikely to be a git/hg repo
+        // (this might fail if /tmp is in a repo, but unlikely)
+        match info {
+            None => {} // Expected
+            Some(ref i) => {
+                assert!(i.vcs_type == "git" || i.vcs_type == "hg");
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_git_branch() {
+        let cwd = env::current_dir().unwrap();
+        if cwd.join(".git").exists() {
+            let branch = get_git_branch(&cwd);
+            assert!(branch.is_some());
+            // Branch name should be non-empty
+            assert!(!branch.unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_git_worktree_and_submodule() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // Main repo with a worktree gitdir, as `git worktree add ../wt -b feature` leaves it
+        let main_git = dir.path().join("main/.git");
+        std::fs::create_dir_all(main_git.join("worktrees/wt")).unwrap();
+        std::fs::write(main_git.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        std::fs::write(main_git.join("worktrees/wt/HEAD"), "ref: refs/heads/feature\n").unwrap();
+
+        let worktree = dir.path().join("wt");
+        std::fs::create_dir_all(worktree.join("src")).unwrap();
+        std::fs::write(worktree.join(".git"), format!("gitdir: {}\n", main_git.join("worktrees/wt").display())).unwrap();
+
+        assert_eq!(find_repo_root(&worktree.join("src"), ".git"), Some(worktree.clone()));
+        let info = detect_vcs(&worktree.join("src")).unwrap();
+        assert_eq!(info.vcs_type, "git");
+        assert_eq!(info.root, worktree);
+        assert_eq!(info.branch.as_deref(), Some("feature"));
+
+        // Submodule with a relative gitdir and a detached HEAD
+        let submodule = dir.path().join("main/vendor/lib");
+        std::fs::create_dir_all(&submodule).unwrap();
+        std::fs::create_dir_all(main_git.join("modules/lib")).unwrap();
+        std::fs::write(main_git.join("modules/lib/HEAD"), "0123456789abcdef0123456789abcdef01234567\n").unwrap();
+        std::fs::write(submodule.join(".git"), "gitdir: ../../.git/modules/lib\n").unwrap();
+
+        let info = detect_vcs(&submodule).unwrap();
+        assert_eq!(info.root, submodule);
+        assert_eq!(info.branch.as_deref(), Some("0123456"));
+
+        // The worktree lists the main repository's branches
+        std::fs::write(main_git.join("worktrees/wt/commondir"), "../..\n").unwrap();
+        std::fs::create_dir_all(main_git.join("refs/heads/feature")).unwrap();
+        std::fs::write(main_git.join("refs/heads/main"), "0123456\n").unwrap();
+        std::fs::write(main_git.join("refs/heads/feature/x"), "0123456\n").unwrap();
+        std::fs::write(
+            main_git.join("packed-refs"),
+            "# pack-refs with: peeled fully-peeled sorted\n0123456 refs/heads/main\n0123456 refs/heads/old\n0123456 refs/tags/v1\n^abcdef0\n",
+        )
+        .unwrap();
+        assert_eq!(list_git_branches(&worktree.join("src")).unwrap(), ["feature/x", "main", "old"]);
+    }
+
+    #[test]
+    fn test_git_remote_from_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("clone");
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::write(root.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        // No config yet, so no remote
+        assert_eq!(detect_vcs(&root).unwrap().remote, None);
+
+        std::fs::write(
+            root.join(".git/config"),
+            "[core]\n\
+             \trepositoryformatversion = 0\n\
+             [remote \"upstream\"]\n\
+             \turl = https://example.com/upstream/nicehist.git\n\
+             [Remote \"origin\"]\n\
+             \t# where we push\n\
+             \tURL = git@github.com:akatrevorjay/nicehist.git\n\
+             \tfetch = +refs/heads/*:refs/remotes/origin/*\n\
+             [branch \"main\"]\n\
+             \tremote = origin\n",
+        )
+        .unwrap();
+        let info = detect_vcs(&root).unwrap();
+        assert_eq!(info.remote.as_deref(), Some("git@github.com:akatrevorjay/nicehist.git"));
+
+        // A worktree reads the main repository's config through commondir
+        let gitdir = root.join(".git/worktrees/wt");
+        std::fs::create_dir_all(&gitdir).unwrap();
+        std::fs::write(gitdir.join("HEAD"), "ref: refs/heads/feature\n").unwrap();
+        std::fs::write(gitdir.join("commondir"), "../..\n").unwrap();
+        let worktree = dir.path().join("wt");
+        std::fs::create_dir_all(&worktree).unwrap();
+        std::fs::write(worktree.join(".git"), format!("gitdir: {}\n", gitdir.display())).unwrap();
+        assert_eq!(detect_vcs(&worktree).unwrap().remote, info.remote);
+    }
+
+    #[test]
+    fn test_detect_svn() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("checkout");
+        let subdir = root.join("src/lib");
+        std::fs::create_dir_all(&subdir).unwrap();
+        std::fs::create_dir(root.join(".svn")).unwrap();
+
+        // Without a readable wc.db (and possibly no svn binary) there's no branch
+        let info = detect_vcs(&subdir).unwrap();
+        assert_eq!(info.vcs_type, "svn");
+        assert_eq!(info.root, root);
+
+        let conn = rusqlite::Connection::open(root.join(".svn/wc.db")).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE nodes (wc_id INTEGER, local_relpath TEXT, op_depth INTEGER, repos_path TEXT);
+             INSERT INTO nodes VALUES (1, '', 0, 'project/branches/feature-x');
+             INSERT INTO nodes VALUES (1, 'src', 0, 'project/branches/feature-x/src');",
+        )
+        .unwrap();
+        drop(conn);
+        assert_eq!(detect_vcs(&subdir).unwrap().branch.as_deref(), Some("feature-x"));
+    }
+
+    #[test]
+    fn test_svn_branch_from_path() {
+        assert_eq!(svn_branch_from_path("trunk").as_deref(), Some("trunk"));
+        assert_eq!(svn_branch_from_path("project/trunk/src").as_deref(), Some("trunk"));
+        assert_eq!(svn_branch_from_path("project/branches/feature-x").as_deref(), Some("feature-x"));
+        assert_eq!(svn_branch_from_path("tags/v1.0").as_deref(), Some("v1.0"));
+        assert_eq!(svn_branch_from_path("project/branches"), None);
+        assert_eq!(svn_branch_from_path("project/src"), None);
+    }
+
+    #[test]
+    fn test_detect_jj_before_colocated_git() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("repo");
+        let subdir = root.join("src");
+        std::fs::create_dir_all(&subdir).unwrap();
+        std::fs::create_dir(root.join(".git")).unwrap();
+        std::fs::write(root.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        assert_eq!(detect_vcs(&subdir).unwrap().vcs_type, "git");
+
+        std::fs::create_dir(root.join(".jj")).unwrap();
+        let info = detect_vcs(&subdir).unwrap();
+        assert_eq!(info.vcs_type, "jj");
+        assert_eq!(info.root, root);
+    }
+
+    #[test]
+    fn test_find_repo_root_from_subdir() {
+        let cwd = env::current_dir().unwrap();
+        if cwd.join(".git").exists() {
+            // Test from a subdirectory
+            let subdir = cwd.join("daemon/src");
+            if subdir.exists() {
+                let root = find_repo_root(&subdir, ".git");
+                assert!(root.is_some());
+                assert_eq!(root.unwrap(), cwd);
+            }
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! In-memory index of the most used commands.
+//!
+//! Most keystrokes land on a handful of short prefixes (`g`, `gi`, `ca`, ...),
+//! and every cold prediction for those re-aggregates the whole history table.
+//! The index keeps the per-directory aggregates for the commands under the
+//! busiest prefixes so `predict` can rank them without touching SQLite, and
+//! falls back to the query for anything it doesn't cover.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use rusqlite::Connection;
+
+use super::FREQUENCY_CAP_WINDOW;
+
+/// Maximum number of commands held in memory
+const HOT_CAPACITY: usize = 2000;
+
+/// Longest prefix (in characters) considered when picking hot prefixes
+const MAX_PREFIX_CHARS: usize = 4;
+
+/// Rebuild from SQLite at least this often (seconds), so the set of hot
+/// prefixes follows changing habits
+const REFRESH_INTERVAL: i64 = 3600;
+
+/// Aggregated history for one command on this host
+#[derive(Debug, Clone)]
+struct HotCommand {
+    id: i64,
+    argv: String,
+    /// `argv` lowercased the way SQLite's LIKE folds case (ASCII only)
+    folded: String,
+    freq: i64,
+    failures: i64,
+    /// Runs with a known exit status, which the failure rate is taken over
+    known: i64,
+    /// Distinct `FREQUENCY_CAP_WINDOW`s it was run in
+    active_windows: i64,
+    last_used: i64,
+    has_local_files: bool,
+    /// Run count per directory
+    dirs: HashMap<String, i64>,
+}
+
+/// A prediction candidate with the aggregates the ranking needs
+#[derive(Debug, Clone)]
+pub(super) struct Candidate {
+    pub id: i64,
+    pub cmd: String,
+    pub freq: i64,
+    pub last_used: i64,
+    pub exact_dir_freq: i64,
+    pub hierarchy_score: f64,
+    pub failure_rate: f64,
+    pub has_local_files: bool,
+    pub project_freq: i64,
+    pub active_windows: i64,
+}
+
+/// A newly stored run, folded into the index once it is committed
+#[derive(Debug, Clone)]
+pub(super) struct Run {
+    pub host: String,
+    pub id: i64,
+    pub argv: String,
+    pub dir: String,
+    pub start_time: i64,
+    pub failed: bool,
+    /// Whether the exit status is known; imported history often lacks it
+    pub has_exit: bool,
+    pub has_local_files: bool,
+}
+
+/// Hot commands grouped under the prefixes they are most often typed with
+#[derive(Debug)]
+pub(super) struct HotIndex {
+    capacity: usize,
+    host: String,
+    /// When the index was last rebuilt; None until the first build or after invalidation
+    built_at: Option<i64>,
+    /// Every command on the host fits, so any prefix can be answered
+    complete: bool,
+    prefixes: HashSet<String>,
+    commands: HashMap<i64, HotCommand>,
+    #[cfg(test)]
+    disabled: bool,
+}
+
+impl Default for HotIndex {
+    fn default() -> Self {
+        Self {
+            capacity: HOT_CAPACITY,
+            host: String::new(),
+            built_at: None,
+            complete: false,
+            prefixes: HashSet::new(),
+            commands: HashMap::new(),
+            #[cfg(test)]
+            disabled: false,
+        }
+    }
+}
+
+impl HotIndex {
+    /// Drop the index; it is rebuilt on the next prediction
+    pub fn invalidate(&mut self) {
+        self.built_at = None;
+    }
+
+    #[cfg(test)]
+    pub fn set_disabled(&mut self, disabled: bool) {
+        self.disabled = disabled;
+    }
+
+    /// Rebuild from SQLite if the index is missing, stale, or for another host.
+    /// `failed_sql` is 1 for a failed history row `h`.
+    pub fn refresh(&mut self, conn: &Connection, host: &str, failed_sql: &str, now: i64) -> Result<()> {
+        let fresh = self.built_at.is_some_and(|t| now - t < REFRESH_INTERVAL);
+        if fresh && self.host == host {
+            return Ok(());
+        }
+        self.rebuild(conn, host, failed_sql, now)
+    }
+
+    fn rebuild(&mut self, conn: &Connection, host: &str, failed_sql: &str, now: i64) -> Result<()> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT c.id, c.argv, p.dir, COUNT(*), MAX(h.start_time),
+                    SUM({}), COUNT(h.exit_status),
+                    MAX(h.has_local_file_args)
+             FROM history h
+             JOIN commands c ON c.id = h.command_id
+             JOIN places p ON p.id = h.place_id
+             WHERE p.host = ?1
+             GROUP BY c.id, p.id",
+            failed_sql
+        ))?;
+        let rows = stmt.query_map([host], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, i64>(5)?,
+                row.get::<_, i64>(6)?,
+                row.get::<_, i32>(7).unwrap_or(0) != 0,
+            ))
+        })?;
+
+        let mut all: HashMap<i64, HotCommand> = HashMap::new();
+        // An interrupted scan must fail rather than leave a truncated index marked fresh
+        for row in rows {
+            let (id, argv, dir, count, last_used, failures, known, has_local_files) = row?;
+            let entry = all.entry(id).or_insert_with(|| HotCommand {
+                id,
+                folded: argv.to_ascii_lowercase(),
+                argv,
+                freq: 0,
+                failures: 0,
+                known: 0,
+                active_windows: 0,
+                last_used: 0,
+                has_local_files: false,
+                dirs: HashMap::new(),
+            });
+            entry.freq += count;
+            entry.failures += failures;
+            entry.known += known;
+            entry.last_used = entry.last_used.max(last_used);
+            entry.has_local_files |= has_local_files;
+            entry.dirs.insert(dir, count);
+        }
+
+        // Counted per command, since the same window can span several directories
+        let mut stmt = conn.prepare(&format!(
+            "SELECT h.command_id, COUNT(DISTINCT h.start_time / {})
+             FROM history h
+             JOIN places p ON p.id = h.place_id
+             WHERE p.host = ?1
+             GROUP BY h.command_id",
+            FREQUENCY_CAP_WINDOW
+        ))?;
+        for row in stmt.query_map([host], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))? {
+            let (id, windows) = row?;
+            if let Some(entry) = all.get_mut(&id) {
+                entry.active_windows = windows;
+            }
+        }
+
+        self.host = host.to_string();
+        self.built_at = Some(now);
+        self.prefixes.clear();
+        self.complete = all.len() <= self.capacity;
+        if self.complete {
+            self.commands = all;
+            return Ok(());
+        }
+
+        // Usage and members of every short prefix
+        let mut by_prefix: HashMap<String, (i64, Vec<i64>)> = HashMap::new();
+        for command in all.values() {
+            for prefix in short_prefixes(&command.folded) {
+                let slot = by_prefix.entry(prefix).or_default();
+                slot.0 += command.freq;
+                slot.1.push(command.id);
+            }
+        }
+        let mut ranked: Vec<(String, (i64, Vec<i64>))> = by_prefix.into_iter().collect();
+        ranked.sort_by(|(a, (a_usage, _)), (b, (b_usage, _))| b_usage.cmp(a_usage).then_with(|| a.cmp(b)));
+
+        // Greedily take the busiest prefixes whose commands still fit
+        let mut kept: HashSet<i64> = HashSet::new();
+        for (prefix, (_, ids)) in ranked {
+            if self.covers(&prefix) {
+                continue;
+            }
+            let added = ids.iter().filter(|id| !kept.contains(id)).count();
+            if kept.len() + added > self.capacity {
+                continue;
+            }
+            kept.extend(ids);
+            self.prefixes.insert(prefix);
+        }
+
+        self.commands = all.into_iter().filter(|(id, _)| kept.contains(id)).collect();
+        Ok(())
+    }
+
+    /// Whether every command starting with `folded` is held in memory
+    fn covers(&self, folded: &str) -> bool {
+        self.complete || short_prefixes(folded).any(|p| self.prefixes.contains(&p))
+    }
+
+    /// Fold a typed prefix for lookup, or None if the index can't answer it
+    fn lookup_key(&self, prefix: &str) -> Option<String> {
+        #[cfg(test)]
+        if self.disabled {
+            return None;
+        }
+        self.built_at?;
+        let folded = prefix.to_ascii_lowercase();
+        self.covers(&folded).then_some(folded)
+    }
+
+    /// Prediction candidates for `prefix`, in the same order as the SQL path,
+    /// or None when the prefix is cold
+    pub fn candidates(
+        &self,
+        prefix: &str,
+        cwd: &str,
+        dir_list: &[String],
+        project_root: Option<&str>,
+        exclude_failed: bool,
+        limit: usize,
+    ) -> Option<Vec<Candidate>> {
+        let folded = self.lookup_key(prefix)?;
+        let cwd_len = cwd.chars().count() as f64;
+
+        let mut candidates: Vec<Candidate> = self
+            .commands
+            .values()
+            .filter(|c| c.folded.starts_with(&folded))
+            .filter(|c| !exclude_failed || c.failures < c.known.max(1))
+            .map(|c| {
+                let mut exact_dir_freq = 0;
+                let mut hierarchy_score = 0.0;
+                let mut project_freq = 0;
+                for (dir, &count) in &c.dirs {
+                    if dir == cwd {
+                        exact_dir_freq += count;
+                    }
+                    if dir_list.contains(dir) {
+                        let depth = cwd_len - dir.chars().count() as f64;
+                        hierarchy_score += count as f64 / (1.0 + depth / 10.0);
+                    }
+                    if let Some(root) = project_root
+                        && (dir == root || dir.strip_prefix(root).is_some_and(|rest| rest.starts_with('/')))
+                    {
+                        project_freq += count;
+                    }
+                }
+                Candidate {
+                    id: c.id,
+                    cmd: c.argv.clone(),
+                    freq: c.freq,
+                    last_used: c.last_used,
+                    exact_dir_freq,
+                    hierarchy_score,
+                    failure_rate: c.failures as f64 / c.known.max(1) as f64,
+                    has_local_files: c.has_local_files,
+                    project_freq,
+                    active_windows: c.active_windows,
+                }
+            })
+            .collect();
+
+        // Mirrors the SQL ORDER BY so the same window survives the LIMIT
+        candidates.sort_by(|a, b| {
+            b.exact_dir_freq
+                .cmp(&a.exact_dir_freq)
+                .then_with(|| (b.project_freq > 0).cmp(&(a.project_freq > 0)))
+                .then_with(|| b.hierarchy_score.total_cmp(&a.hierarchy_score))
+                .then_with(|| b.last_used.cmp(&a.last_used))
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        candidates.truncate(limit);
+        Some(candidates)
+    }
+
+    /// Fold a committed run into the index if its command falls under a hot prefix
+    pub fn record(&mut self, run: &Run) {
+        if self.built_at.is_none() || run.host != self.host {
+            return;
+        }
+        let folded = run.argv.to_ascii_lowercase();
+        if !self.covers(&folded) {
+            return;
+        }
+        if !self.commands.contains_key(&run.id) && self.commands.len() >= self.capacity {
+            // Its prefixes can't be answered in full any more; pick them again
+            self.invalidate();
+            return;
+        }
+        let entry = self.commands.entry(run.id).or_insert_with(|| HotCommand {
+            id: run.id,
+            argv: run.argv.clone(),
+            folded,
+            freq: 0,
+            failures: 0,
+            known: 0,
+            active_windows: 0,
+            last_used: run.start_time,
+            has_local_files: false,
+            dirs: HashMap::new(),
+        });
+        // Runs arrive in order, so only one later than the last can open a window
+        if entry.freq == 0 || run.start_time / FREQUENCY_CAP_WINDOW > entry.last_used / FREQUENCY_CAP_WINDOW {
+            entry.active_windows += 1;
+        }
+        entry.freq += 1;
+        entry.failures += run.failed as i64;
+        entry.known += run.has_exit as i64;
+        entry.last_used = entry.last_used.max(run.start_time);
+        entry.has_local_files |= run.has_local_files;
+        *entry.dirs.entry(run.dir.clone()).or_insert(0) += 1;
+    }
+}
+
+/// The 1..=MAX_PREFIX_CHARS character prefixes of `s`
+fn short_prefixes(s: &str) -> impl Iterator<Item = String> + '_ {
+    s.char_indices()
+        .skip(1)
+        .map(|(i, _)| i)
+        .chain(std::iter::once(s.len()))
+        .take(MAX_PREFIX_CHARS)
+        .filter(|&end| end > 0)
+        .map(|end| s[..end].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::IgnoredExits;
+
+    #[test]
+    fn test_short_prefixes() {
+        let prefixes: Vec<String> = short_prefixes("gït status").collect();
+        assert_eq!(prefixes, vec!["g", "gï", "gït", "gït "]);
+        assert_eq!(short_prefixes("").count(), 0);
+    }
+
+    #[test]
+    fn test_rebuild_keeps_busiest_prefixes_within_capacity() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::migrations::run_migrations(&conn).unwrap();
+        conn.execute("INSERT INTO places (id, host, dir) VALUES (1, 'h', '/')", []).unwrap();
+        for (id, argv, runs) in [(1, "git status", 5), (2, "git push", 3), (3, "ls", 1), (4, "make", 1)] {
+            conn.execute("INSERT INTO commands (id, argv) VALUES (?1, ?2)", rusqlite::params![id, argv]).unwrap();
+            for t in 0..runs {
+                conn.execute(
+                    "INSERT INTO history (command_id, place_id, start_time) VALUES (?1, 1, ?2)",
+                    rusqlite::params![id, t],
+                ).unwrap();
+            }
+        }
+
+        let mut index = HotIndex { capacity: 2, ..Default::default() };
+        index.refresh(&conn, "h", &IgnoredExits::default().failed_sql(), 100).unwrap();
+
+        assert!(!index.complete);
+        assert_eq!(index.commands.len(), 2);
+        assert!(index.candidates("Git", "/", &[], None, false, 10).is_some());
+        assert!(index.candidates("l", "/", &[], None, false, 10).is_none());
+        assert!(index.candidates("", "/", &[], None, false, 10).is_none());
+
+        let hits = index.candidates("git", "/", &[], None, false, 10).unwrap();
+        assert_eq!(hits.iter().map(|c| c.cmd.as_str()).collect::<Vec<_>>(), vec!["git status", "git push"]);
+
+        // Another run of a held command is folded in
+        let run = |id: i64, argv: &str| Run {
+            host: "h".to_string(),
+            id,
+            argv: argv.to_string(),
+            dir: "/".to_string(),
+            start_time: 50,
+            failed: false,
+            has_exit: true,
+            has_local_files: false,
+        };
+        index.record(&run(2, "git push"));
+        let hits = index.candidates("git push", "/", &[], None, false, 10).unwrap();
+        assert_eq!((hits[0].freq, hits[0].last_used), (4, 50));
+
+        // A new command under a hot prefix doesn't fit, so the index is dropped
+        index.record(&run(5, "git pull"));
+        assert_eq!(index.commands.len(), 2);
+        assert!(index.candidates("git", "/", &[], None, false, 10).is_none());
+    }
+}
@@ -0,0 +1,654 @@
+//! Database migrations for schema versioning.
+
+use anyhow::{Context, Result};
+use rusqlite::Connection;
+use tracing::{info, warn};
+
+use super::schema::SCHEMA_V1;
+
+/// Current schema version
+const CURRENT_VERSION: i32 = 16;
+
+/// Run all pending migrations
+pub fn run_migrations(conn: &Connection) -> Result<()> {
+    let current = get_schema_version(conn)?;
+
+    if current == 0 {
+        // Fresh database, apply initial schema (includes all tables up to current version)
+        info!("Applying initial schema (version {})", CURRENT_VERSION);
+        conn.execute_batch(SCHEMA_V1)
+            .context("Failed to apply initial schema")?;
+        set_schema_version(conn, CURRENT_VERSION)?;
+    } else if current < CURRENT_VERSION {
+        // Apply incremental migrations
+        for version in (current + 1)..=CURRENT_VERSION {
+            info!("Applying migration to version {}", version);
+            apply_migration(conn, version)?;
+            set_schema_version(conn, version)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Get current schema version (0 if not initialized)
+fn get_schema_version(conn: &Connection) -> Result<i32> {
+    // Check if schema_version table exists
+    let table_exists: bool = conn
+        .query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type='table' AND name='schema_version'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap_or(false);
+
+    if !table_exists {
+        return Ok(0);
+    }
+
+    let version: i32 = conn
+        .query_row(
+            "SELECT COALESCE(MAX(version), 0) FROM schema_version",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap_or(0);
+
+    Ok(version)
+}
+
+/// Set schema version
+fn set_schema_version(conn: &Connection, version: i32) -> Result<()> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+
+    conn.execute(
+        "INSERT OR REPLACE INTO schema_version (version, applied_at) VALUES (?1, ?2)",
+        [version as i64, now],
+    )?;
+
+    Ok(())
+}
+
+/// Apply a specific migration
+fn apply_migration(conn: &Connection, version: i32) -> Result<()> {
+    match version {
+        2 => apply_migration_v2(conn),
+        3 => apply_migration_v3(conn),
+        4 => apply_migration_v4(conn),
+        5 => apply_migration_v5(conn),
+        6 => apply_migration_v6(conn),
+        7 => apply_migration_v7(conn),
+        8 => apply_migration_v8(conn),
+        9 => apply_migration_v9(conn),
+        10 => apply_migration_v10(conn),
+        11 => apply_migration_v11(conn),
+        12 => apply_migration_v12(conn),
+        13 => apply_migration_v13(conn),
+        14 => apply_migration_v14(conn),
+        15 => apply_migration_v15(conn),
+        16 => apply_migration_v16(conn),
+        _ => Ok(()), // No migration needed
+    }
+}
+
+/// Migration v2: Add parsed_commands and arg_patterns tables for argument-aware suggestions
+fn apply_migration_v2(conn: &Connection) -> Result<()> {
+    conn.execute_batch(r#"
+        -- Parsed commands for argument-aware suggestions
+        CREATE TABLE IF NOT EXISTS parsed_commands (
+            command_id INTEGER PRIMARY KEY REFERENCES commands(id),
+            program TEXT NOT NULL,
+            subcommand TEXT,
+            args_hash TEXT
+        );
+
+        -- Argument patterns: what arguments follow a given program+subcommand
+        CREATE TABLE IF NOT EXISTS arg_patterns (
+            id INTEGER PRIMARY KEY,
+            program TEXT NOT NULL,
+            subcommand TEXT,
+            arg_value TEXT NOT NULL,
+            frequency INTEGER NOT NULL DEFAULT 1,
+            last_used INTEGER NOT NULL,
+            place_id INTEGER REFERENCES places(id),
+            UNIQUE(program, subcommand, arg_value, place_id)
+        );
+
+        -- Indexes for fast queries
+        CREATE INDEX IF NOT EXISTS idx_parsed_commands_program ON parsed_commands(program);
+        CREATE INDEX IF NOT EXISTS idx_parsed_commands_subcommand ON parsed_commands(program, subcommand);
+        CREATE INDEX IF NOT EXISTS idx_arg_patterns_lookup ON arg_patterns(program, subcommand);
+        CREATE INDEX IF NOT EXISTS idx_arg_patterns_place ON arg_patterns(place_id);
+    "#).context("Failed to apply migration v2")?;
+
+    Ok(())
+}
+
+/// Migration v3: Add frecent_paths table for fasd-like frecency tracking
+fn apply_migration_v3(conn: &Connection) -> Result<()> {
+    conn.execute_batch(r#"
+        -- Frecent paths (fasd-like frecency tracking)
+        CREATE TABLE IF NOT EXISTS frecent_paths (
+            id INTEGER PRIMARY KEY,
+            path TEXT NOT NULL,
+            path_type TEXT NOT NULL DEFAULT 'd',
+            rank REAL NOT NULL DEFAULT 1.0,
+            last_access INTEGER NOT NULL,
+            access_count INTEGER NOT NULL DEFAULT 1,
+            UNIQUE(path, path_type)
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_frecent_paths_type ON frecent_paths(path_type);
+        CREATE INDEX IF NOT EXISTS idx_frecent_paths_rank ON frecent_paths(rank DESC);
+        CREATE INDEX IF NOT EXISTS idx_frecent_paths_path ON frecent_paths(path);
+    "#).context("Failed to create frecent_paths table")?;
+
+    // Bootstrap from existing history: populate frecent directories from places table
+    conn.execute_batch(r#"
+        INSERT OR IGNORE INTO frecent_paths (path, path_type, rank, last_access, access_count)
+        SELECT p.dir, 'd', COUNT(*) * 1.0, MAX(h.start_time), COUNT(*)
+        FROM history h JOIN places p ON p.id = h.place_id
+        GROUP BY p.dir;
+    "#).context("Failed to bootstrap frecent_paths from history")?;
+
+    info!("Migration v3: created frecent_paths table and bootstrapped from history");
+    Ok(())
+}
+
+/// Migration v4: Add exit-aware bigram table for exit-status-aware n-grams
+fn apply_migration_v4(conn: &Connection) -> Result<()> {
+    conn.execute_batch(r#"
+        -- Exit-aware bigram: P(command | prev_command, prev_exit_ok)
+        CREATE TABLE IF NOT EXISTS ngrams_2_exit (
+            prev_command_id INTEGER NOT NULL REFERENCES commands(id),
+            command_id INTEGER NOT NULL REFERENCES commands(id),
+            prev_exit_ok INTEGER NOT NULL,  -- 1 = prev exited 0, 0 = prev failed
+            frequency INTEGER NOT NULL DEFAULT 1,
+            last_used INTEGER NOT NULL,
+            PRIMARY KEY (prev_command_id, command_id, prev_exit_ok)
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_ngrams_2_exit_prev ON ngrams_2_exit(prev_command_id, prev_exit_ok);
+    "#).context("Failed to apply migration v4")?;
+
+    info!("Migration v4: created ngrams_2_exit table for exit-aware n-grams");
+    Ok(())
+}
+
+/// Migration v5: Add has_local_file_args column to history table
+fn apply_migration_v5(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE history ADD COLUMN has_local_file_args INTEGER NOT NULL DEFAULT 0",
+    )
+    .context("Failed to apply migration v5")?;
+
+    info!("Migration v5: added has_local_file_args column to history table");
+    Ok(())
+}
+
+/// Migration v6: Add shell and terminal columns to history table
+fn apply_migration_v6(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE history ADD COLUMN shell TEXT;
+         ALTER TABLE history ADD COLUMN terminal TEXT;",
+    )
+    .context("Failed to apply migration v6")?;
+
+    info!("Migration v6: added shell and terminal columns to history table");
+    Ok(())
+}
+
+/// Migration v7: Add commands_fts full-text index for substring search
+fn apply_migration_v7(conn: &Connection) -> Result<()> {
+    conn.execute_batch(r#"
+        -- Full-text index over command text; the trigram tokenizer answers substring queries
+        CREATE VIRTUAL TABLE IF NOT EXISTS commands_fts USING fts5(
+            argv,
+            content='commands',
+            content_rowid='id',
+            tokenize='trigram'
+        );
+
+        -- Keep commands_fts in step with commands
+        CREATE TRIGGER IF NOT EXISTS commands_fts_insert AFTER INSERT ON commands BEGIN
+            INSERT INTO commands_fts(rowid, argv) VALUES (new.id, new.argv);
+        END;
+        CREATE TRIGGER IF NOT EXISTS commands_fts_delete AFTER DELETE ON commands BEGIN
+            INSERT INTO commands_fts(commands_fts, rowid, argv) VALUES ('delete', old.id, old.argv);
+        END;
+        CREATE TRIGGER IF NOT EXISTS commands_fts_update AFTER UPDATE OF argv ON commands BEGIN
+            INSERT INTO commands_fts(commands_fts, rowid, argv) VALUES ('delete', old.id, old.argv);
+            INSERT INTO commands_fts(rowid, argv) VALUES (new.id, new.argv);
+        END;
+
+        -- Index the commands stored before the table existed
+        INSERT INTO commands_fts(commands_fts) VALUES ('rebuild');
+    "#).context("Failed to apply migration v7")?;
+
+    info!("Migration v7: created commands_fts full-text index");
+    Ok(())
+}
+
+/// Migration v8: Add ngrams_4 table for longer command context
+fn apply_migration_v8(conn: &Connection) -> Result<()> {
+    conn.execute_batch(r#"
+        CREATE TABLE IF NOT EXISTS ngrams_4 (
+            prev3_command_id INTEGER NOT NULL REFERENCES commands(id),
+            prev2_command_id INTEGER NOT NULL REFERENCES commands(id),
+            prev1_command_id INTEGER NOT NULL REFERENCES commands(id),
+            command_id INTEGER NOT NULL REFERENCES commands(id),
+            frequency INTEGER NOT NULL DEFAULT 1,
+            last_used INTEGER NOT NULL,
+            PRIMARY KEY (prev3_command_id, prev2_command_id, prev1_command_id, command_id)
+        );
+        CREATE INDEX IF NOT EXISTS idx_ngrams_4_prev ON ngrams_4(prev3_command_id, prev2_command_id, prev1_command_id);
+    "#).context("Failed to apply migration v8")?;
+
+    info!("Migration v8: created ngrams_4 table");
+    Ok(())
+}
+
+/// Migration v9: Index history by session for same-session n-gram linking
+fn apply_migration_v9(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_history_session_id ON history(session_id);",
+    )
+    .context("Failed to apply migration v9")?;
+
+    info!("Migration v9: added history session_id index");
+    Ok(())
+}
+
+/// Migration v10: Add tty column to history table
+fn apply_migration_v10(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE history ADD COLUMN tty TEXT;")
+        .context("Failed to apply migration v10")?;
+
+    info!("Migration v10: added tty column to history table");
+    Ok(())
+}
+
+/// Migration v11: Add vcs_remote column to contexts table
+fn apply_migration_v11(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE contexts ADD COLUMN vcs_remote TEXT;")
+        .context("Failed to apply migration v11")?;
+
+    info!("Migration v11: added vcs_remote column to contexts table");
+    Ok(())
+}
+
+/// Migration v12: Add accepts table for prediction feedback
+fn apply_migration_v12(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS accepts (
+            command_id INTEGER PRIMARY KEY REFERENCES commands(id),
+            accepted INTEGER NOT NULL DEFAULT 0,
+            rejected INTEGER NOT NULL DEFAULT 0,
+            last_accepted INTEGER
+        );",
+    )
+    .context("Failed to apply migration v12")?;
+
+    info!("Migration v12: added accepts table");
+    Ok(())
+}
+
+/// Migration v13: Key sessions by host and shell pid, as history's session_id
+/// holds the pid. Sessions are recorded from here on; older ones aren't recent
+fn apply_migration_v13(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS sessions (
+            id INTEGER PRIMARY KEY,
+            host TEXT NOT NULL,
+            pid INTEGER,
+            start_time INTEGER NOT NULL,
+            end_time INTEGER
+        );
+        CREATE UNIQUE INDEX IF NOT EXISTS idx_sessions_host_pid ON sessions(host, pid);",
+    )
+    .context("Failed to apply migration v13")?;
+
+    info!("Migration v13: added sessions host/pid index");
+    Ok(())
+}
+
+/// Migration v14: Store durations as integer milliseconds, so they read back
+/// exactly; the float `duration` column is still written for older readers
+fn apply_migration_v14(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE history ADD COLUMN duration_ms INTEGER;")
+        .context("Failed to apply migration v14")?;
+    if conn.prepare("SELECT duration FROM history LIMIT 0").is_ok() {
+        conn.execute(
+            "UPDATE history SET duration_ms = CAST(ROUND(duration * 1000) AS INTEGER) WHERE duration IS NOT NULL",
+            [],
+        )
+        .context("Failed to backfill history.duration_ms")?;
+    }
+
+    info!("Migration v14: added duration_ms column to history table");
+    Ok(())
+}
+
+/// Migration v15: Scope learned branch names to their repository. Branches
+/// learned before this take the repository last recorded for the directory
+/// they were checked out in; any left unscoped are only suggested outside
+/// repositories
+fn apply_migration_v15(conn: &Connection) -> Result<()> {
+    if conn.prepare("SELECT id FROM arg_patterns LIMIT 0").is_ok() {
+        conn.execute_batch("ALTER TABLE arg_patterns ADD COLUMN vcs_root TEXT;")
+            .context("Failed to apply migration v15")?;
+    }
+    if conn.prepare("SELECT place_id FROM arg_patterns LIMIT 0").is_ok()
+        && conn.prepare("SELECT place_id, context_id FROM history LIMIT 0").is_ok()
+        && conn.prepare("SELECT vcs_root FROM contexts LIMIT 0").is_ok()
+    {
+        conn.execute(
+            "UPDATE arg_patterns SET vcs_root = (
+                 SELECT x.vcs_root FROM history h
+                 JOIN contexts x ON x.id = h.context_id
+                 WHERE h.place_id = arg_patterns.place_id AND x.vcs_root IS NOT NULL
+                 ORDER BY h.id DESC
+                 LIMIT 1
+             )
+             WHERE vcs_root IS NULL AND program = 'git' AND subcommand IN ('checkout', 'switch')",
+            [],
+        )
+        .context("Failed to backfill arg_patterns.vcs_root")?;
+    }
+
+    info!("Migration v15: added vcs_root column to arg_patterns table");
+    Ok(())
+}
+
+/// Migration v16: Link commands learned from a compound line to that line,
+/// so pruning its history doesn't treat them as orphans while it's kept
+fn apply_migration_v16(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS command_segments (
+            command_id INTEGER NOT NULL REFERENCES commands(id),
+            parent_id INTEGER NOT NULL REFERENCES commands(id),
+            PRIMARY KEY (command_id, parent_id)
+        );",
+    )
+    .context("Failed to apply migration v16")?;
+
+    info!("Migration v16: added command_segments table");
+    Ok(())
+}
+
+/// Repair tables and columns that have gone missing since the schema was
+/// applied, e.g. dropped by hand in the sqlite3 shell.
+///
+/// The database is compared with a scratch copy of the current schema. Missing
+/// tables are recreated (every statement in it is `IF NOT EXISTS`) and missing
+/// columns added back where SQLite allows it. Returns what was repaired.
+pub fn heal_schema(conn: &Connection) -> Result<Vec<String>> {
+    let reference = Connection::open_in_memory()?;
+    reference.execute_batch(SCHEMA_V1)?;
+
+    // Leave out FTS5's own shadow tables; they come and go with commands_fts
+    let tables = |conn: &Connection| -> Result<Vec<String>> {
+        let mut stmt = conn.prepare(
+            "SELECT name FROM sqlite_master
+             WHERE type = 'table' AND name NOT LIKE 'sqlite\\_%' ESCAPE '\\' AND name NOT LIKE 'commands\\_fts\\_%' ESCAPE '\\'",
+        )?;
+        let names = stmt.query_map([], |row| row.get(0))?.collect::<rusqlite::Result<_>>()?;
+        Ok(names)
+    };
+    let existing = tables(conn)?;
+    let mut repairs = Vec::new();
+
+    let missing: Vec<String> = tables(&reference)?.into_iter().filter(|t| !existing.contains(t)).collect();
+    if !missing.is_empty() {
+        conn.execute_batch(SCHEMA_V1).context("Failed to recreate missing tables")?;
+        if missing.iter().any(|t| t == "commands_fts") {
+            conn.execute_batch("INSERT INTO commands_fts(commands_fts) VALUES ('rebuild')")?;
+        }
+        repairs.extend(missing.iter().map(|t| format!("recreated table {}", t)));
+    }
+
+    // (name, type, not null, default) of each column
+    type Column = (String, String, bool, Option<String>);
+    for table in existing.iter().filter(|t| *t != "commands_fts") {
+        let columns = |conn: &Connection| -> Result<Vec<Column>> {
+            let mut stmt = conn.prepare("SELECT name, type, \"notnull\", dflt_value FROM pragma_table_info(?1)")?;
+            let columns = stmt
+                .query_map([table], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?
+                .collect::<rusqlite::Result<_>>()?;
+            Ok(columns)
+        };
+        let have: Vec<String> = columns(conn)?.into_iter().map(|(name, ..)| name).collect();
+        for (name, ty, not_null, default) in columns(&reference)? {
+            if have.contains(&name) {
+                continue;
+            }
+            let mut sql = format!("ALTER TABLE {} ADD COLUMN {} {}", table, name, ty);
+            if let Some(default) = default {
+                if not_null {
+                    sql.push_str(" NOT NULL");
+                }
+                sql.push_str(&format!(" DEFAULT {}", default));
+            }
+            match conn.execute_batch(&sql) {
+                Ok(()) => repairs.push(format!("added column {}.{}", table, name)),
+                Err(e) => warn!("Column {}.{} is missing and can't be added back: {}", table, name, e),
+            }
+        }
+    }
+
+    for repair in &repairs {
+        warn!("Schema drift: {}", repair);
+    }
+    Ok(repairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fresh_database_migration() {
+        let conn = Connection::open_in_memory().unwrap();
+
+        // Should start at version 0
+        assert_eq!(get_schema_version(&conn).unwrap(), 0);
+
+        // Run migrations
+        run_migrations(&conn).unwrap();
+
+        // Should now be at current version
+        assert_eq!(get_schema_version(&conn).unwrap(), CURRENT_VERSION);
+
+        // Tables should exist
+        let count: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='history'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_idempotent_migrations() {
+        let conn = Connection::open_in_memory().unwrap();
+
+        // Run migrations twice
+        run_migrations(&conn).unwrap();
+        run_migrations(&conn).unwrap();
+
+        // Should still be at current version
+        assert_eq!(get_schema_version(&conn).unwrap(), CURRENT_VERSION);
+    }
+
+    #[test]
+    fn test_migration_v6_adds_shell_columns() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE history (id INTEGER PRIMARY KEY, session_id INTEGER, has_local_file_args INTEGER NOT NULL DEFAULT 0);
+             CREATE TABLE commands (id INTEGER PRIMARY KEY, argv TEXT NOT NULL UNIQUE);
+             CREATE TABLE contexts (id INTEGER PRIMARY KEY);
+             CREATE TABLE schema_version (version INTEGER PRIMARY KEY, applied_at INTEGER NOT NULL);",
+        )
+        .unwrap();
+        set_schema_version(&conn, 5).unwrap();
+
+        run_migrations(&conn).unwrap();
+
+        let columns: Vec<String> = conn
+            .prepare("SELECT name FROM pragma_table_info('history')")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect();
+        assert!(columns.contains(&"shell".to_string()));
+        assert!(columns.contains(&"terminal".to_string()));
+    }
+
+    #[test]
+    fn test_migration_v7_indexes_existing_commands() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE history (id INTEGER PRIMARY KEY, session_id INTEGER);
+             CREATE TABLE commands (id INTEGER PRIMARY KEY, argv TEXT NOT NULL UNIQUE);
+             CREATE TABLE contexts (id INTEGER PRIMARY KEY);
+             CREATE TABLE schema_version (version INTEGER PRIMARY KEY, applied_at INTEGER NOT NULL);
+             INSERT INTO commands (argv) VALUES ('git checkout main'), ('cargo build');",
+        )
+        .unwrap();
+        set_schema_version(&conn, 6).unwrap();
+
+        run_migrations(&conn).unwrap();
+        conn.execute("INSERT INTO commands (argv) VALUES ('git checkout -b topic')", []).unwrap();
+        conn.execute("DELETE FROM commands WHERE argv = 'git checkout main'", []).unwrap();
+
+        let matches: Vec<String> = conn
+            .prepare("SELECT argv FROM commands_fts WHERE commands_fts MATCH '\"checkout\"'")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .filter_map(|r| r.ok())
+            .collect();
+        assert_eq!(matches, vec!["git checkout -b topic".to_string()]);
+    }
+
+    #[test]
+    fn test_migration_v8_creates_ngrams_4() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE history (id INTEGER PRIMARY KEY, session_id INTEGER);
+             CREATE TABLE commands (id INTEGER PRIMARY KEY, argv TEXT NOT NULL UNIQUE);
+             CREATE TABLE contexts (id INTEGER PRIMARY KEY);
+             CREATE TABLE schema_version (version INTEGER PRIMARY KEY, applied_at INTEGER NOT NULL);",
+        )
+        .unwrap();
+        set_schema_version(&conn, 7).unwrap();
+
+        run_migrations(&conn).unwrap();
+
+        let count: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type='table' AND name='ngrams_4'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 1);
+        assert!(get_schema_version(&conn).unwrap() >= 8);
+    }
+
+    #[test]
+    fn test_migration_v14_backfills_duration_ms() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE history (id INTEGER PRIMARY KEY, session_id INTEGER, duration REAL);
+             CREATE TABLE commands (id INTEGER PRIMARY KEY, argv TEXT NOT NULL UNIQUE);
+             CREATE TABLE contexts (id INTEGER PRIMARY KEY);
+             CREATE TABLE schema_version (version INTEGER PRIMARY KEY, applied_at INTEGER NOT NULL);
+             INSERT INTO history (duration) VALUES (0.001), (1.234), (NULL);",
+        )
+        .unwrap();
+        set_schema_version(&conn, 13).unwrap();
+
+        run_migrations(&conn).unwrap();
+
+        let durations: Vec<Option<i64>> = conn
+            .prepare("SELECT duration_ms FROM history ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(durations, [Some(1), Some(1234), None]);
+    }
+
+    #[test]
+    fn test_migration_v15_scopes_existing_branches() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE history (id INTEGER PRIMARY KEY, session_id INTEGER, place_id INTEGER, context_id INTEGER, duration_ms INTEGER);
+             CREATE TABLE commands (id INTEGER PRIMARY KEY, argv TEXT NOT NULL UNIQUE);
+             CREATE TABLE contexts (id INTEGER PRIMARY KEY, vcs_root TEXT);
+             CREATE TABLE arg_patterns (id INTEGER PRIMARY KEY, program TEXT NOT NULL, subcommand TEXT,
+                                        arg_value TEXT NOT NULL, place_id INTEGER);
+             CREATE TABLE schema_version (version INTEGER PRIMARY KEY, applied_at INTEGER NOT NULL);
+             INSERT INTO contexts (id, vcs_root) VALUES (1, '/src/api'), (2, NULL);
+             INSERT INTO history (place_id, context_id) VALUES (1, 1), (2, 2), (3, NULL);
+             INSERT INTO arg_patterns (program, subcommand, arg_value, place_id) VALUES
+                 ('git', 'checkout', 'fix-login', 1),
+                 ('git', 'switch', 'main', 1),
+                 ('git', 'checkout', 'scratch', 2),
+                 ('git', 'checkout', 'orphan', 3),
+                 ('cargo', 'test', 'parser', 1);",
+        )
+        .unwrap();
+        set_schema_version(&conn, 14).unwrap();
+
+        run_migrations(&conn).unwrap();
+
+        let roots: Vec<(String, Option<String>)> = conn
+            .prepare("SELECT arg_value, vcs_root FROM arg_patterns ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        let api = Some("/src/api".to_string());
+        assert_eq!(roots, [
+            ("fix-login".to_string(), api.clone()),
+            ("main".to_string(), api),
+            // Outside any repository, or never seen with one
+            ("scratch".to_string(), None),
+            ("orphan".to_string(), None),
+            // Not a branch
+            ("parser".to_string(), None),
+        ]);
+    }
+
+    #[test]
+    fn test_heal_schema_restores_tables_and_columns() {
+        let conn = Connection::open_in_memory().unwrap();
+        run_migrations(&conn).unwrap();
+        assert!(heal_schema(&conn).unwrap().is_empty());
+
+        conn.execute("INSERT INTO commands (argv) VALUES ('cargo build')", []).unwrap();
+        conn.execute_batch(
+            "ALTER TABLE history DROP COLUMN shell;
+             DROP TABLE commands_fts;",
+        )
+        .unwrap();
+
+        let repairs = heal_schema(&conn).unwrap();
+        assert_eq!(repairs, ["recreated table commands_fts", "added column history.shell"]);
+        let matches: i64 = conn
+            .query_row("SELECT COUNT(*) FROM commands_fts WHERE commands_fts MATCH 'build'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(matches, 1);
+        assert!(heal_schema(&conn).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,6400 @@
+//! SQLite database layer for nicehist.
+
+mod hot;
+mod migrations;
+mod schema;
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use rusqlite::{Connection, OptionalExtension};
+use tracing::debug;
+
+use crate::context::ContextCollector;
+use crate::prediction::ContextRanker;
+use crate::prediction::parser::{
+    extract_learnable_args, is_branch_command, parse_command, parse_statements, split_pipeline, ParsedCommand,
+};
+use crate::protocol::{
+    AcceptParams, CommandCount, ContextInfo, MaintenanceResult, MatchMode, PlaceInfo, PlaceSort, PlacesParams, FrecentAddParams, FrecentQueryParams, FrecencyResult, PredictParams, PruneResult,
+    ResetResult, SearchParams, SearchResult, ShellShare, Stats, StoreParams, StoreResult, StoredCommand, Suggestion,
+};
+use crate::redact::{Redaction, Redactor};
+
+/// Thread-safe database handle
+#[derive(Clone)]
+pub struct Database {
+    conn: Arc<Mutex<Connection>>,
+    hot: Arc<Mutex<hot::HotIndex>>,
+    redactor: Arc<Redactor>,
+    contexts: Arc<ContextCollector>,
+    /// Store commands verbatim, control characters included
+    raw_commands: bool,
+    ignored_exits: Arc<IgnoredExits>,
+}
+
+/// What became of a command passed to [`Database::store_command`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreOutcome {
+    /// Stored as the history entry with this id
+    Stored(i64),
+    /// Not stored: "dir_excluded", "redacted" or "duplicate"
+    Skipped(&'static str),
+}
+
+impl StoreOutcome {
+    /// The new history entry's id, if the command was stored
+    pub fn id(self) -> Option<i64> {
+        match self {
+            StoreOutcome::Stored(id) => Some(id),
+            StoreOutcome::Skipped(_) => None,
+        }
+    }
+}
+
+impl From<StoreOutcome> for StoreResult {
+    fn from(outcome: StoreOutcome) -> Self {
+        match outcome {
+            StoreOutcome::Stored(id) => StoreResult { stored: true, id: Some(id), reason: None },
+            StoreOutcome::Skipped(reason) => StoreResult { stored: false, id: None, reason: Some(reason.to_string()) },
+        }
+    }
+}
+
+/// Exit statuses that don't count toward a command's failure rate: the command
+/// was cancelled rather than broken
+#[derive(Debug, Clone, PartialEq)]
+pub struct IgnoredExits {
+    /// Exact statuses, by default 130 (interrupted with Ctrl-C)
+    pub codes: Vec<i32>,
+    /// Also 129-192, the 128+n a shell reports for a command killed by signal n
+    pub signals: bool,
+}
+
+impl Default for IgnoredExits {
+    fn default() -> Self {
+        Self { codes: vec![130], signals: false }
+    }
+}
+
+impl IgnoredExits {
+    /// Whether a run with this exit status counts as failed
+    pub fn is_failure(&self, status: i32) -> bool {
+        status != 0 && !self.codes.contains(&status) && !(self.signals && (129..=192).contains(&status))
+    }
+
+    /// SQL that is 1 for a failed history row `h` and 0 otherwise
+    fn failed_sql(&self) -> String {
+        let mut failed = "h.exit_status != 0 AND h.exit_status IS NOT NULL".to_string();
+        if !self.codes.is_empty() {
+            let codes: Vec<String> = self.codes.iter().map(i32::to_string).collect();
+            failed.push_str(&format!(" AND h.exit_status NOT IN ({})", codes.join(", ")));
+        }
+        if self.signals {
+            failed.push_str(" AND h.exit_status NOT BETWEEN 129 AND 192");
+        }
+        format!("CASE WHEN {} THEN 1 ELSE 0 END", failed)
+    }
+}
+
+impl Database {
+    /// Open or create a database at the given path
+    pub fn open(path: &Path) -> Result<Self> {
+        // Ensure parent directory exists
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open database: {}", path.display()))?;
+
+        // Enable WAL mode for concurrent access
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+
+        let db = Self {
+            conn: Arc::new(Mutex::new(conn)),
+            hot: Arc::default(),
+            redactor: Arc::default(),
+            contexts: Arc::default(),
+            raw_commands: false,
+            ignored_exits: Arc::default(),
+        };
+
+        // Run migrations
+        db.migrate()?;
+
+        Ok(db)
+    }
+
+    /// Open an in-memory database, for tests and throwaway sessions; nothing is saved
+    pub fn open_in_memory() -> Result<Self> {
+        let conn = Connection::open_in_memory()?;
+        conn.pragma_update(None, "foreign_keys", "ON")?;
+
+        let db = Self {
+            conn: Arc::new(Mutex::new(conn)),
+            hot: Arc::default(),
+            redactor: Arc::default(),
+            contexts: Arc::default(),
+            raw_commands: false,
+            ignored_exits: Arc::default(),
+        };
+
+        db.migrate()?;
+
+        Ok(db)
+    }
+
+    /// Run database migrations, then repair any tables or columns removed by hand
+    fn migrate(&self) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        migrations::run_migrations(&conn)?;
+        migrations::heal_schema(&conn)?;
+        Ok(())
+    }
+
+    /// Use `redactor` to mask or skip secrets in stored commands
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = Arc::new(redactor);
+        self
+    }
+
+    /// Store commands exactly as sent, instead of stripping escape sequences
+    /// and control characters that would garble a terminal when shown
+    pub fn with_raw_commands(mut self, raw: bool) -> Self {
+        self.raw_commands = raw;
+        self
+    }
+
+    /// Don't count runs with these exit statuses as failures
+    pub fn with_ignored_exits(mut self, ignored: IgnoredExits) -> Self {
+        self.ignored_exits = Arc::new(ignored);
+        self.hot.lock().unwrap().invalidate();
+        self
+    }
+
+    /// The text a command is stored as: masked by the redactor, and without
+    /// control characters unless storing raw; None if it shouldn't be stored
+    fn stored_text<'a>(&self, cmd: &'a str) -> Option<Cow<'a, str>> {
+        let cmd = if self.raw_commands { Cow::Borrowed(cmd) } else { strip_control_chars(cmd) };
+        match self.redactor.redact(&cmd) {
+            Redaction::Store(Cow::Owned(masked)) => Some(Cow::Owned(masked)),
+            Redaction::Store(Cow::Borrowed(_)) => Some(cmd),
+            Redaction::Skip => None,
+        }
+    }
+
+    /// Store a command in the database, reporting why it was skipped if it wasn't
+    pub fn store_command(&self, params: &StoreParams) -> Result<StoreOutcome> {
+        let ctx = self.store_context(&params.cwd, &params.exclude_dirs);
+        let conn = self.conn.lock().unwrap();
+        let mut runs = Vec::new();
+        match self.store_with_conn(&conn, params, ctx.as_ref(), &mut runs) {
+            Ok(outcome) => {
+                let mut hot = self.hot.lock().unwrap();
+                for run in &runs {
+                    hot.record(run);
+                }
+                Ok(outcome)
+            }
+            Err(e) => {
+                // Whatever was written before the error isn't in the hot index
+                self.hot.lock().unwrap().invalidate();
+                Err(e)
+            }
+        }
+    }
+
+    /// The context a command is stored with; None if its directory is excluded.
+    /// Detection can run a VCS binary, so it happens before taking the lock.
+    fn store_context(&self, cwd: &str, exclude_dirs: &[String]) -> Option<ContextInfo> {
+        (!dir_excluded(cwd, exclude_dirs)).then(|| self.contexts.get_context(cwd))
+    }
+
+    /// A stored history entry as it is published to subscribers
+    pub fn stored_command(&self, id: i64) -> Result<Option<StoredCommand>> {
+        let conn = self.conn.lock().unwrap();
+        let stored = conn
+            .query_row(
+                "SELECT c.argv, p.dir, h.start_time, h.exit_status, h.duration_ms
+                 FROM history h
+                 JOIN commands c ON h.command_id = c.id
+                 JOIN places p ON h.place_id = p.id
+                 WHERE h.id = ?1",
+                [id],
+                |row| {
+                    Ok(StoredCommand {
+                        id,
+                        cmd: row.get(0)?,
+                        cwd: row.get(1)?,
+                        timestamp: row.get(2)?,
+                        exit_status: row.get(3)?,
+                        duration_ms: row.get(4)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(stored)
+    }
+
+    /// Store several commands in a single transaction; either all are stored or none.
+    /// Returns how many were stored, skipping those from excluded directories.
+    pub fn store_batch(&self, batch: &[StoreParams]) -> Result<usize> {
+        // Detection can run a VCS binary, so it happens before taking the lock
+        let mut contexts: HashMap<&str, ContextInfo> = HashMap::new();
+        for params in batch.iter().filter(|p| !dir_excluded(&p.cwd, &p.exclude_dirs)) {
+            contexts.entry(&params.cwd).or_insert_with(|| self.contexts.get_context(&params.cwd));
+        }
+
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let mut runs = Vec::new();
+        for params in batch {
+            let ctx = contexts.get(params.cwd.as_str()).filter(|_| !dir_excluded(&params.cwd, &params.exclude_dirs));
+            self.store_with_conn(&tx, params, ctx, &mut runs)?;
+        }
+        tx.commit()?;
+
+        // Only what was committed reaches the hot index
+        let mut hot = self.hot.lock().unwrap();
+        for run in &runs {
+            hot.record(run);
+        }
+        Ok(runs.len())
+    }
+
+    /// Store one command, adding the run to fold into the hot index once
+    /// it's committed to `runs`
+    fn store_with_conn(
+        &self,
+        conn: &Connection,
+        params: &StoreParams,
+        ctx: Option<&ContextInfo>,
+        runs: &mut Vec<hot::Run>,
+    ) -> Result<StoreOutcome> {
+        // Excluded directories record neither history nor frecency
+        let Some(ctx) = ctx else {
+            debug!("Not storing command from excluded directory {}", params.cwd);
+            return Ok(StoreOutcome::Skipped("dir_excluded"));
+        };
+
+        // Secrets are masked before anything derived from the command is written;
+        // a skipped previous command just doesn't link n-grams
+        let redact = |cmd: &str| self.stored_text(cmd).map(Cow::into_owned);
+        let Some(cmd) = redact(&params.cmd) else {
+            debug!("Not storing command matching a skip rule");
+            return Ok(StoreOutcome::Skipped("redacted"));
+        };
+        let prev_cmd = params.prev_cmd.as_deref().and_then(redact);
+        let prev2_cmd = params.prev2_cmd.as_deref().and_then(redact);
+        let prev3_cmd = params.prev3_cmd.as_deref().and_then(redact);
+        // A duplicate doesn't count towards n-grams either: re-importing a
+        // history file would otherwise learn every sequence in it twice
+        if self.is_duplicate(conn, &cmd, params) {
+            debug!("Not storing duplicate of {}", cmd);
+            return Ok(StoreOutcome::Skipped("duplicate"));
+        }
+        let [prev_cmd, prev2_cmd, prev3_cmd] =
+            self.same_session_prev_cmds(conn, params, [prev_cmd, prev2_cmd, prev3_cmd])?;
+        let params = &StoreParams {
+            cmd,
+            prev_cmd,
+            prev2_cmd,
+            prev3_cmd,
+            ..params.clone()
+        };
+
+        // Get or create command ID
+        let command_id = self.get_or_create_command(conn, &params.cmd)?;
+
+        // Get or create place ID
+        let hostname = resolve_hostname();
+        let place_id = self.get_or_create_place(conn, &hostname, &params.cwd)?;
+
+        // Get or create context ID
+        let context_id = self.get_or_create_context(conn, ctx)?;
+
+        // Calculate time bucket (hour of day)
+        let start_time = params
+            .start_time
+            .unwrap_or_else(chrono_lite_timestamp);
+        let time_bucket = ((start_time % 86400) / 3600) as i32;
+
+        // Detect if command references local file arguments
+        let has_local_file_args = Self::detect_local_file_args(&params.cmd, &params.cwd);
+
+        // Insert history entry
+        conn.execute(
+            "INSERT INTO history (session_id, command_id, place_id, context_id, start_time, duration, exit_status, time_bucket, has_local_file_args, shell, terminal, tty, duration_ms)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            rusqlite::params![
+                params.session_id,
+                command_id,
+                place_id,
+                context_id,
+                start_time,
+                params.duration_ms.map(|d| d as f64 / 1000.0),
+                params.exit_status,
+                time_bucket,
+                has_local_file_args as i32,
+                params.shell,
+                params.terminal,
+                params.tty,
+                params.duration_ms,
+            ],
+        )?;
+
+        let history_id = conn.last_insert_rowid();
+
+        // A session starts when its shell does, or else with the first command it
+        // stores. A later shell start for the same PID is a new shell reusing it.
+        if let Some(session_id) = params.session_id {
+            conn.prepare_cached(
+                "INSERT INTO sessions (host, pid, start_time) VALUES (?1, ?2, ?3)
+                 ON CONFLICT(host, pid) DO UPDATE SET start_time = excluded.start_time
+                 WHERE ?4 AND excluded.start_time > sessions.start_time",
+            )?
+            .execute(rusqlite::params![
+                hostname,
+                session_id,
+                params.session_start.unwrap_or(start_time),
+                params.session_start.is_some(),
+            ])?;
+        }
+
+        runs.push(hot::Run {
+            host: hostname.clone(),
+            id: command_id,
+            argv: params.cmd.clone(),
+            dir: params.cwd.clone(),
+            start_time,
+            failed: params.exit_status.is_some_and(|s| self.ignored_exits.is_failure(s)),
+            has_exit: params.exit_status.is_some(),
+            has_local_files: has_local_file_args,
+        });
+
+        // Update n-grams if previous command provided
+        if let Some(ref prev_cmd) = params.prev_cmd {
+            let prev_id = self.get_or_create_command(conn, prev_cmd)?;
+            self.update_bigram(conn, prev_id, command_id)?;
+
+            // Update exit-aware bigram if previous exit status provided
+            if let Some(prev_exit) = params.prev_exit {
+                let prev_exit_ok = if prev_exit == 0 { 1i32 } else { 0 };
+                self.update_bigram_exit(conn, prev_id, command_id, prev_exit_ok)?;
+            }
+
+            if let Some(ref prev2_cmd) = params.prev2_cmd {
+                let prev2_id = self.get_or_create_command(conn, prev2_cmd)?;
+                self.update_trigram(conn, prev2_id, prev_id, command_id)?;
+
+                if let Some(ref prev3_cmd) = params.prev3_cmd {
+                    let prev3_id = self.get_or_create_command(conn, prev3_cmd)?;
+                    self.update_fourgram(conn, prev3_id, prev2_id, prev_id, command_id)?;
+                }
+            }
+        }
+
+        // Learn the sequence inside a compound line (`make build && make test`)
+        if params.split_pipelines {
+            let segments = split_pipeline(&params.cmd);
+            if segments.len() > 1 {
+                let ids = segments
+                    .iter()
+                    .map(|segment| self.get_or_create_command(conn, segment))
+                    .collect::<Result<Vec<_>>>()?;
+                let mut link = conn.prepare_cached(
+                    "INSERT OR IGNORE INTO command_segments (command_id, parent_id) VALUES (?1, ?2)",
+                )?;
+                for &id in &ids {
+                    link.execute([id, command_id])?;
+                }
+                for pair in ids.windows(2) {
+                    self.update_bigram(conn, pair[0], pair[1])?;
+                }
+            }
+        }
+
+        // Store parsed command for argument suggestions
+        self.store_parsed_command(conn, command_id, &params.cmd)?;
+
+        // Store argument patterns, per statement when splitting compound lines
+        if params.split_pipelines {
+            for statement in parse_statements(&params.cmd) {
+                self.store_arg_patterns(conn, &statement, Some(place_id), ctx)?;
+            }
+        } else {
+            self.store_arg_patterns(conn, &parse_command(&params.cmd), Some(place_id), ctx)?;
+        }
+
+        // Extract frecent paths from command arguments
+        self.extract_frecent_paths(conn, &params.cmd, &params.cwd)?;
+
+        debug!("Stored command {} with history_id {}", params.cmd, history_id);
+        Ok(StoreOutcome::Stored(history_id))
+    }
+
+    /// Keep the leading previous commands that could have come from this session.
+    ///
+    /// A client may hand over a `prev_cmd` from another terminal; linking it
+    /// would teach a transition that never happened. Stores arrive out of order
+    /// or get skipped (duplicates, excluded directories), so a previous command
+    /// is kept if the session ran it recently or if it hasn't been seen at all,
+    /// and is only dropped when it was last run in some other session. That
+    /// drops every older command too.
+    fn same_session_prev_cmds(
+        &self,
+        conn: &Connection,
+        params: &StoreParams,
+        prev_cmds: [Option<String>; 3],
+    ) -> Result<[Option<String>; 3]> {
+        let Some(session_id) = params.session_id.filter(|_| !params.cross_session_ngrams) else {
+            return Ok(prev_cmds);
+        };
+        let recent: HashSet<String> = conn
+            .prepare_cached(
+                "SELECT c.argv FROM history h
+                 JOIN commands c ON c.id = h.command_id
+                 WHERE h.session_id = ?1
+                 ORDER BY h.id DESC
+                 LIMIT ?2",
+            )?
+            .query_map([session_id, SESSION_PREV_WINDOW], |row| row.get(0))?
+            .collect::<rusqlite::Result<_>>()?;
+        let mut last_session = conn.prepare_cached(
+            "SELECT h.session_id FROM history h
+             JOIN commands c ON c.id = h.command_id
+             WHERE c.argv = ?1
+             ORDER BY h.id DESC
+             LIMIT 1",
+        )?;
+
+        let mut linked = true;
+        let mut kept = [None, None, None];
+        for (slot, cmd) in kept.iter_mut().zip(prev_cmds) {
+            linked = linked
+                && match cmd {
+                    Some(ref cmd) if recent.contains(cmd) => true,
+                    Some(ref cmd) => {
+                        let last: Option<Option<i64>> = last_session.query_row([cmd], |row| row.get(0)).optional()?;
+                        !matches!(last, Some(Some(other)) if other != session_id)
+                    }
+                    None => false,
+                };
+            *slot = cmd.filter(|_| linked);
+        }
+        Ok(kept)
+    }
+
+    /// Whether `cmd` was already stored in the same place within the request's dedup
+    /// window. Only a command with its own start time can be a duplicate: stamped
+    /// with "now", every repeat in a plain history file would look like one.
+    fn is_duplicate(&self, conn: &Connection, cmd: &str, params: &StoreParams) -> bool {
+        let (Some(window), Some(start_time)) = (params.dedup_window, params.start_time) else {
+            return false;
+        };
+        conn.query_row(
+            "SELECT 1 FROM history h
+             JOIN commands c ON c.id = h.command_id
+             JOIN places p ON p.id = h.place_id
+             WHERE c.argv = ?1 AND p.host = ?2 AND p.dir = ?3 AND ABS(h.start_time - ?4) <= ?5
+             LIMIT 1",
+            rusqlite::params![cmd, query_host(None), params.cwd, start_time, window],
+            |_| Ok(()),
+        )
+        .is_ok()
+    }
+
+    fn get_or_create_command(&self, conn: &Connection, argv: &str) -> Result<i64> {
+        // Try to find existing
+        let mut stmt = conn.prepare_cached("SELECT id FROM commands WHERE argv = ?1")?;
+        let result: Option<i64> = stmt.query_row([argv], |row| row.get(0)).ok();
+
+        if let Some(id) = result {
+            return Ok(id);
+        }
+
+        // Create new
+        conn.execute("INSERT INTO commands (argv) VALUES (?1)", [argv])?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    fn get_or_create_place(&self, conn: &Connection, host: &str, dir: &str) -> Result<i64> {
+        let mut stmt =
+            conn.prepare_cached("SELECT id FROM places WHERE host = ?1 AND dir = ?2")?;
+        let result: Option<i64> = stmt.query_row([host, dir], |row| row.get(0)).ok();
+
+        if let Some(id) = result {
+            return Ok(id);
+        }
+
+        conn.execute(
+            "INSERT INTO places (host, dir) VALUES (?1, ?2)",
+            [host, dir],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// The contexts row for a directory's VCS and project, if it has either
+    fn get_or_create_context(&self, conn: &Connection, ctx: &ContextInfo) -> Result<Option<i64>> {
+        if ctx.vcs.is_none() && ctx.project.is_none() {
+            return Ok(None);
+        }
+        let values = rusqlite::params![ctx.vcs, ctx.vcs_root, ctx.branch, ctx.project, ctx.remote];
+
+        let existing = conn
+            .prepare_cached(
+                "SELECT id FROM contexts WHERE vcs_type IS ?1 AND vcs_root IS ?2 AND vcs_branch IS ?3
+                    AND project_type IS ?4 AND vcs_remote IS ?5",
+            )?
+            .query_row(values, |row| row.get(0))
+            .optional()?;
+        if existing.is_some() {
+            return Ok(existing);
+        }
+
+        conn.execute(
+            "INSERT INTO contexts (vcs_type, vcs_root, vcs_branch, project_type, vcs_remote) VALUES (?1, ?2, ?3, ?4, ?5)",
+            values,
+        )?;
+        Ok(Some(conn.last_insert_rowid()))
+    }
+
+    fn update_bigram(&self, conn: &Connection, prev_id: i64, cmd_id: i64) -> Result<()> {
+        let now = chrono_lite_timestamp();
+        conn.execute(
+            "INSERT INTO ngrams_2 (prev_command_id, command_id, frequency, last_used)
+             VALUES (?1, ?2, 1, ?3)
+             ON CONFLICT(prev_command_id, command_id) DO UPDATE SET
+                frequency = frequency + 1,
+                last_used = ?3",
+            rusqlite::params![prev_id, cmd_id, now],
+        )?;
+        Ok(())
+    }
+
+    fn update_trigram(
+        &self,
+        conn: &Connection,
+        prev2_id: i64,
+        prev1_id: i64,
+        cmd_id: i64,
+    ) -> Result<()> {
+        let now = chrono_lite_timestamp();
+        conn.execute(
+            "INSERT INTO ngrams_3 (prev2_command_id, prev1_command_id, command_id, frequency, last_used)
+             VALUES (?1, ?2, ?3, 1, ?4)
+             ON CONFLICT(prev2_command_id, prev1_command_id, command_id) DO UPDATE SET
+                frequency = frequency + 1,
+                last_used = ?4",
+            rusqlite::params![prev2_id, prev1_id, cmd_id, now],
+        )?;
+        Ok(())
+    }
+
+    fn update_fourgram(
+        &self,
+        conn: &Connection,
+        prev3_id: i64,
+        prev2_id: i64,
+        prev1_id: i64,
+        cmd_id: i64,
+    ) -> Result<()> {
+        let now = chrono_lite_timestamp();
+        conn.execute(
+            "INSERT INTO ngrams_4 (prev3_command_id, prev2_command_id, prev1_command_id, command_id, frequency, last_used)
+             VALUES (?1, ?2, ?3, ?4, 1, ?5)
+             ON CONFLICT(prev3_command_id, prev2_command_id, prev1_command_id, command_id) DO UPDATE SET
+                frequency = frequency + 1,
+                last_used = ?5",
+            rusqlite::params![prev3_id, prev2_id, prev1_id, cmd_id, now],
+        )?;
+        Ok(())
+    }
+
+    fn update_bigram_exit(
+        &self,
+        conn: &Connection,
+        prev_id: i64,
+        cmd_id: i64,
+        prev_exit_ok: i32,
+    ) -> Result<()> {
+        let now = chrono_lite_timestamp();
+        conn.execute(
+            "INSERT INTO ngrams_2_exit (prev_command_id, command_id, prev_exit_ok, frequency, last_used)
+             VALUES (?1, ?2, ?3, 1, ?4)
+             ON CONFLICT(prev_command_id, command_id, prev_exit_ok) DO UPDATE SET
+                frequency = frequency + 1,
+                last_used = ?4",
+            rusqlite::params![prev_id, cmd_id, prev_exit_ok, now],
+        )?;
+        Ok(())
+    }
+
+    /// Store parsed command for argument-aware suggestions
+    fn store_parsed_command(&self, conn: &Connection, command_id: i64, cmd: &str) -> Result<()> {
+        let parsed = parse_command(cmd);
+
+        if parsed.program.is_empty() {
+            return Ok(());
+        }
+
+        // Store parsed command
+        conn.execute(
+            "INSERT OR REPLACE INTO parsed_commands (command_id, program, subcommand, args_hash)
+             VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![
+                command_id,
+                &parsed.program,
+                &parsed.subcommand,
+                truncate_at_char_boundary(&parsed.args.join(" "), 50), // Truncate args hash
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Store argument patterns for argument-aware suggestions. Branch names
+    /// are kept with the repository they were checked out in.
+    fn store_arg_patterns(
+        &self,
+        conn: &Connection,
+        parsed: &ParsedCommand,
+        place_id: Option<i64>,
+        ctx: &ContextInfo,
+    ) -> Result<()> {
+        let learnable = extract_learnable_args(parsed);
+        let now = chrono_lite_timestamp();
+        let vcs_root = if is_branch_command(parsed) { ctx.vcs_root.as_deref() } else { None };
+
+        for arg in learnable {
+            // Skip very short or very long args
+            if arg.len() < 2 || arg.len() > 100 {
+                continue;
+            }
+
+            conn.execute(
+                "INSERT INTO arg_patterns (program, subcommand, arg_value, frequency, last_used, place_id, vcs_root)
+                 VALUES (?1, ?2, ?3, 1, ?4, ?5, ?6)
+                 ON CONFLICT(program, subcommand, arg_value, place_id) DO UPDATE SET
+                    frequency = frequency + 1,
+                    last_used = ?4,
+                    vcs_root = ?6",
+                rusqlite::params![
+                    &parsed.program,
+                    &parsed.subcommand,
+                    &arg,
+                    now,
+                    place_id,
+                    vcs_root,
+                ],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Get argument suggestions for a partial command
+    pub fn get_arg_suggestions(
+        &self,
+        prefix: &str,
+        cwd: &str,
+        limit: usize,
+    ) -> Result<Vec<Suggestion>> {
+        let parsed = parse_command(prefix);
+        let branch_scope = self.branch_scope(&parsed, cwd);
+        let conn = self.conn.lock().unwrap();
+        self.arg_suggestions_with_conn(&conn, prefix, &parsed, cwd, limit, branch_scope.as_ref())
+    }
+
+    /// Where a branch-taking command's arguments come from. Detecting it can
+    /// run git, so it's resolved before taking the lock.
+    fn branch_scope(&self, parsed: &ParsedCommand, cwd: &str) -> Option<BranchScope> {
+        is_branch_command(parsed).then(|| BranchScope {
+            vcs_root: self.contexts.get_context(cwd).vcs_root,
+            branches: self.contexts.git_branches(cwd),
+        })
+    }
+
+    fn arg_suggestions_with_conn(
+        &self,
+        conn: &Connection,
+        prefix: &str,
+        parsed: &ParsedCommand,
+        cwd: &str,
+        limit: usize,
+        branch_scope: Option<&BranchScope>,
+    ) -> Result<Vec<Suggestion>> {
+        // Only suggest args if command ends with space (expecting argument)
+        if !parsed.is_partial() {
+            return Ok(vec![]);
+        }
+
+        let hostname = resolve_hostname();
+
+        // Get place_id for directory-specific args
+        let place_id: Option<i64> = conn
+            .query_row(
+                "SELECT id FROM places WHERE host = ?1 AND dir = ?2",
+                [&hostname, cwd],
+                |row| row.get(0),
+            )
+            .ok();
+
+        // Branch names only come from this repository, and the ones it still
+        // has go first (all of them are ranked when its branches are known)
+        let branch_args = branch_scope.is_some();
+        let vcs_root = branch_scope.and_then(|scope| scope.vcs_root.as_deref());
+        let branches = branch_scope.and_then(|scope| scope.branches.as_ref());
+        let query_limit = if branches.is_some() { -1 } else { limit as i64 };
+
+        // Query for argument patterns
+        let mut stmt = conn.prepare_cached(
+            "SELECT arg_value, SUM(frequency) as total_freq,
+                    SUM(CASE WHEN place_id = ?4 THEN frequency ELSE 0 END) as dir_freq
+             FROM arg_patterns
+             WHERE program = ?1 AND (subcommand = ?2 OR (subcommand IS NULL AND ?2 IS NULL))
+               AND (NOT ?5 OR vcs_root IS ?6)
+             GROUP BY arg_value
+             ORDER BY dir_freq DESC, total_freq DESC
+             LIMIT ?3",
+        )?;
+
+        let rows = stmt.query_map(
+            rusqlite::params![&parsed.program, &parsed.subcommand, query_limit, place_id, branch_args, vcs_root],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            },
+        )?;
+
+        let mut scored = Vec::new();
+        for (arg, total_freq, dir_freq) in rows.flatten() {
+            let base_score = (total_freq as f64).ln().max(0.0) / 10.0;
+            let dir_bonus = if dir_freq > 0 { 0.3 } else { 0.0 };
+            let mut score = (base_score + dir_bonus).min(1.0);
+            let stale = branches.is_some_and(|branches| !branches.contains(&arg));
+            if stale {
+                score *= 0.5;
+            }
+            scored.push((stale, arg, score));
+        }
+        scored.sort_by_key(|&(stale, ..)| stale);
+        scored.truncate(limit);
+
+        let suggestions = scored
+            .into_iter()
+            .map(|(_, arg, score)| Suggestion {
+                // Build full command with this argument
+                cmd: format!("{}{}", prefix, arg),
+                score,
+            })
+            .collect();
+
+        Ok(suggestions)
+    }
+
+    /// Get predictions based on prefix and context
+    pub fn predict(&self, params: &PredictParams) -> Result<Vec<Suggestion>> {
+        // Cursor in the middle of the line: predict from the text before it, then
+        // complete only the token under the cursor and keep the rest of the line
+        if let Some((head, tail)) = split_at_cursor(params) {
+            let head_params = PredictParams {
+                prefix: head.to_string(),
+                limit: params.limit * 2,
+                buffer: None,
+                cursor_pos: None,
+                ..params.clone()
+            };
+            let mut suggestions = splice_at_cursor(self.predict_prefix(&head_params)?, head, tail);
+            suggestions.truncate(params.limit);
+            return Ok(suggestions);
+        }
+
+        self.predict_prefix(params)
+    }
+
+    fn predict_prefix(&self, params: &PredictParams) -> Result<Vec<Suggestion>> {
+        let parsed = parse_command(&params.prefix);
+        let expecting_arg = parsed.is_partial() && !parsed.program.is_empty();
+        let branch_scope = expecting_arg.then(|| self.branch_scope(&parsed, &params.cwd)).flatten();
+        let conn = self.conn.lock().unwrap();
+
+        // Get hostname for place matching
+        let hostname = query_host(params.host.as_deref());
+
+        // Strategy 0: a command followed by a space is expecting an argument, so
+        // suggest the ones learned for it. Until any are, whole commands are
+        // ranked as usual, n-grams and all, under the same lock
+        if expecting_arg {
+            let arg_suggestions = self.arg_suggestions_with_conn(
+                &conn,
+                &params.prefix,
+                &parsed,
+                &params.cwd,
+                params.limit,
+                branch_scope.as_ref(),
+            )?;
+            if !arg_suggestions.is_empty() {
+                return Ok(arg_suggestions);
+            }
+        }
+
+        self.predict_with_conn(&conn, params, &hostname)
+    }
+
+    fn predict_with_conn(
+        &self,
+        conn: &Connection,
+        params: &PredictParams,
+        hostname: &str,
+    ) -> Result<Vec<Suggestion>> {
+        // Past the deadline SQLite interrupts the running query, and ranking
+        // falls back to whatever it has
+        let deadline = params.deadline_ms.map(|ms| Instant::now() + Duration::from_millis(ms));
+        if let Some(deadline) = deadline {
+            conn.progress_handler(DEADLINE_CHECK_OPS, Some(move || Instant::now() >= deadline));
+        }
+        let result = self.rank_predictions(conn, params, hostname, deadline);
+        if deadline.is_some() {
+            conn.progress_handler(0, None::<fn() -> bool>);
+        }
+        result
+    }
+
+    fn rank_predictions(
+        &self,
+        conn: &Connection,
+        params: &PredictParams,
+        hostname: &str,
+        deadline: Option<Instant>,
+    ) -> Result<Vec<Suggestion>> {
+        // Scored suggestions paired with last-used time for tiebreaking
+        let mut scored: Vec<(Suggestion, i64)> = Vec::new();
+
+        // Strategy 1: Compute n-gram bonus scores (additive, applied in strategy 2)
+        let mut w = params.weights.clone().unwrap_or_default();
+        if params.global {
+            // Rank on frequency and recency alone, wherever the commands were run
+            w.use_ngram = false;
+            w.use_frecent = false;
+            w.use_directory = false;
+        }
+        let ngram_bonus = if w.use_ngram {
+            self.compute_ngram_bonus(conn, &params.last_cmds, &params.prefix, params.limit, params.last_exit, &w)?
+        } else {
+            std::collections::HashMap::new()
+        };
+
+        // Strategy 2: Prefix match with recency, directory, and parent directory weighting
+        // Build list of directories to check (current + ancestors)
+        let (dir_list, project_root) = if w.use_directory {
+            (get_directory_hierarchy(&params.cwd, 3), params.project_root.as_deref())
+        } else {
+            (Vec::new(), None)
+        };
+        let window = params.limit.saturating_mul(w.candidate_factor.max(1));
+
+        // Hot prefixes are ranked from memory; cold ones aggregate the history table.
+        // The index only holds this machine's history
+        let mut hot = self.hot.lock().unwrap();
+        let substring = params.match_mode == MatchMode::Substring;
+        let candidates = (|| {
+            let hot_candidates = if substring || params.global || params.host.is_some() || params.in_project.is_some() {
+                None
+            } else {
+                hot.refresh(conn, hostname, &self.ignored_exits.failed_sql(), chrono_lite_timestamp())?;
+                hot.candidates(&params.prefix, &params.cwd, &dir_list, project_root, params.exclude_failed, window)
+            };
+            match hot_candidates {
+                Some(candidates) => Ok(candidates),
+                None => self.query_candidates(conn, params, hostname, &dir_list, project_root, window),
+            }
+        })();
+        drop(hot);
+        let candidates = match candidates {
+            Ok(candidates) => candidates,
+            Err(e) if deadline.is_some_and(|d| Instant::now() >= d) => {
+                // Out of time: answer with the n-gram successors found so far
+                debug!("Predict deadline hit while ranking candidates: {}", e);
+                return Ok(rank_ngram_successors(ngram_bonus, &w, params.limit));
+            }
+            Err(e) => return Err(e),
+        };
+
+        let now = chrono_lite_timestamp();
+
+        // Cross-pollination: boost predictions in frecent directories
+        let frecent_boost = if params.frecent_boost && w.use_frecent {
+            let frecent_rank: f64 = conn
+                .query_row(
+                    "SELECT rank FROM frecent_paths WHERE path = ?1 AND path_type = 'd'",
+                    [&params.cwd],
+                    |row| row.get(0),
+                )
+                .unwrap_or(0.0);
+            // Normalize: log(rank+1) / 100, capped at configured max
+            (frecent_rank.ln_1p() / 100.0).min(w.frecent_boost_max)
+        } else {
+            0.0
+        };
+
+        // Click-through: suggestions you've taken when shown before
+        let accept_rates = if w.accept > 0.0 {
+            let ids: Vec<i64> = candidates.iter().map(|c| c.id).collect();
+            self.accept_rates(conn, &ids).unwrap_or_default()
+        } else {
+            std::collections::HashMap::new()
+        };
+
+        // Commands from the shell sessions started most recently
+        let session_starts = if w.session_recency > 0.0 {
+            let ids: Vec<i64> = candidates.iter().map(|c| c.id).collect();
+            self.session_starts(conn, &ids, now).unwrap_or_default()
+        } else {
+            std::collections::HashMap::new()
+        };
+
+        for hot::Candidate { id, cmd, freq, last_used, exact_dir_freq, hierarchy_score, failure_rate, has_local_files, project_freq, active_windows } in candidates {
+            // Calculate score based on frequency, recency, and directory match
+            let age_days = (now - last_used) as f64 / 86400.0;
+            let recency_score = if params.since_last {
+                // Grows back toward 1 over about a day since the last run
+                1.0 - (-age_days / SINCE_LAST_DAYS).exp()
+            } else {
+                ContextRanker::recency_decay(last_used, w.recency_half_life_days)
+            };
+            let freq_score = frequency_score(freq, active_windows, &w);
+
+            // Directory scoring: exact match > same project > parent match
+            let dir_score = if !w.use_directory {
+                0.0
+            } else if exact_dir_freq > 0 {
+                w.dir_exact
+            } else if project_freq > 0 {
+                w.dir_project
+            } else if hierarchy_score > 0.0 {
+                w.dir_hierarchy * hierarchy_score.min(1.0)
+            } else {
+                0.0
+            };
+
+            // N-gram bonus: commands that follow the previous command get a boost
+            let ngram_score = ngram_bonus.get(&cmd).copied().unwrap_or(0.0) * w.ngram;
+
+            // Project bonus: commands typical of any of the cwd's project types (cargo in a Rust project)
+            let typical = params.project_type.iter().chain(&params.project_types).any(|p| ContextRanker::matches_project_type(&cmd, p));
+            let project_score = if w.use_directory && typical { w.project_boost } else { 0.0 };
+
+            let accept_score = accept_rates.get(&id).copied().unwrap_or(0.0) * w.accept;
+
+            let session_score = session_starts
+                .get(&id)
+                .map_or(0.0, |&started| ContextRanker::recency_decay(started, SESSION_HALF_LIFE_DAYS) * w.session_recency);
+
+            // Penalize commands that frequently fail. Ranking uses the uncapped score,
+            // so strong suggestions past 1.0 keep their order; it's capped for display
+            let failure_penalty = 1.0 - (failure_rate * w.failure_penalty);
+            let mut score = (freq_score * w.frequency + recency_score * w.recency + dir_score + frecent_boost + ngram_score + project_score + accept_score + session_score) * failure_penalty;
+
+            // Penalize commands with local file args when predicting from a different directory
+            if has_local_files && exact_dir_freq == 0 && !params.global {
+                score *= 1.0 - w.local_file_penalty;
+            }
+
+            scored.push((Suggestion { cmd, score }, last_used));
+        }
+
+        // Sort by score, breaking ties deterministically, and limit
+        scored.sort_by(|(a, a_used), (b, b_used)| {
+            rank_order(a.score, b.score, *a_used, *b_used, &a.cmd, &b.cmd)
+        });
+        if w.diversity > 0.0 {
+            scored = diversify(scored, w.diversity);
+        }
+        if substring {
+            // Mid-string hits only follow the commands that start with what was typed
+            let prefix = params.prefix.to_ascii_lowercase();
+            scored.sort_by_key(|(s, _)| !s.cmd.to_ascii_lowercase().starts_with(&prefix));
+        }
+        scored.truncate(params.limit);
+
+        let mut suggestions: Vec<Suggestion> = scored
+            .into_iter()
+            .map(|(s, _)| Suggestion { score: s.score.min(1.0), ..s })
+            .collect();
+        if params.fallback_popular && suggestions.len() < params.limit {
+            self.fill_with_popular(conn, params, &mut suggestions)?;
+        }
+        Ok(suggestions)
+    }
+
+    /// Top up short predictions with the most-used commands for the prefix's
+    /// program on any host, in any directory. Their scores stay below every
+    /// primary suggestion so they always rank last.
+    fn fill_with_popular(&self, conn: &Connection, params: &PredictParams, suggestions: &mut Vec<Suggestion>) -> Result<()> {
+        // A finished first word matches as a whole program; a partial one as a prefix
+        let program = params.prefix.split_whitespace().next().unwrap_or("");
+        let pattern = if params.prefix.trim_start().len() > program.len() {
+            format!("{} ", program)
+        } else {
+            program.to_string()
+        };
+        let having = if params.exclude_failed {
+            format!("HAVING SUM({}) < MAX(COUNT(h.exit_status), 1)", self.ignored_exits.failed_sql())
+        } else {
+            String::new()
+        };
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT c.argv, COUNT(*) as freq
+             FROM history h
+             JOIN commands c ON c.id = h.command_id
+             WHERE (c.argv LIKE ?1 || '%' ESCAPE '\\' OR c.argv = ?2)
+               AND (?4 IS NULL OR h.context_id IN (SELECT id FROM contexts WHERE project_type = ?4))
+             GROUP BY c.id
+             {}
+             ORDER BY freq DESC, MAX(h.start_time) DESC, c.id
+             LIMIT ?3",
+            having
+        ))?;
+        let seen: HashSet<String> = suggestions.iter().map(|s| s.cmd.clone()).collect();
+        let rows = stmt
+            .query_map(rusqlite::params![escape_like(&pattern), program, params.limit + seen.len(), params.in_project], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?;
+        let mut popular = Vec::new();
+        for row in rows {
+            match row {
+                Ok(row) => popular.push(row),
+                // Past the predict deadline: what was ranked stands, filled or not
+                Err(e) if e.sqlite_error_code() == Some(rusqlite::ErrorCode::OperationInterrupted) => {
+                    debug!("Predict deadline hit while filling with popular commands");
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let ceiling = suggestions.last().map_or(1.0, |s| s.score) * POPULAR_FALLBACK_SCALE;
+        let max_freq = popular.first().map_or(1, |(_, freq)| *freq) as f64;
+        for (cmd, freq) in popular {
+            if suggestions.len() >= params.limit {
+                break;
+            }
+            if !seen.contains(&cmd) {
+                suggestions.push(Suggestion { cmd, score: ceiling * freq as f64 / max_freq });
+            }
+        }
+        Ok(())
+    }
+
+    /// Aggregate prediction candidates for a prefix from the history table
+    fn query_candidates(
+        &self,
+        conn: &Connection,
+        params: &PredictParams,
+        hostname: &str,
+        dir_list: &[String],
+        project_root: Option<&str>,
+        window: usize,
+    ) -> Result<Vec<hot::Candidate>> {
+        let dir_placeholders: Vec<String> = dir_list.iter().enumerate().map(|(i, _)| format!("?{}", i + 7)).collect();
+        let dir_case = if !dir_placeholders.is_empty() {
+            format!(
+                "SUM(CASE WHEN p.dir IN ({}) THEN 1.0 / (1 + (LENGTH(?2) - LENGTH(p.dir)) / 10.0) ELSE 0 END)",
+                dir_placeholders.join(", ")
+            )
+        } else {
+            "0".to_string()
+        };
+
+        // Substring mode fills the window with prefix hits first
+        let (match_clause, match_order) = match params.match_mode {
+            MatchMode::Prefix => ("c.argv LIKE ?1 || '%' ESCAPE '\\'", ""),
+            MatchMode::Substring => ("c.argv LIKE '%' || ?1 || '%' ESCAPE '\\'", "c.argv LIKE ?1 || '%' ESCAPE '\\' DESC, "),
+        };
+
+        let having = if params.exclude_failed { "HAVING failure_rate < 1.0" } else { "" };
+
+        // Global predictions skip the per-directory aggregates and fill the window by frequency
+        let (exact_dir_case, project_case, dir_order) = if params.global {
+            ("0", "0", "freq DESC, ")
+        } else {
+            (
+                "SUM(CASE WHEN p.dir = ?2 THEN 1 ELSE 0 END)",
+                "SUM(CASE WHEN p.dir = ?5 OR SUBSTR(p.dir, 1, LENGTH(?5) + 1) = ?5 || '/' THEN 1 ELSE 0 END)",
+                "exact_dir_freq DESC, project_freq > 0 DESC, hierarchy_score DESC, ",
+            )
+        };
+
+        let query = format!(
+            "SELECT c.argv, COUNT(*) as freq, MAX(h.start_time) as last_used,
+                    {} as exact_dir_freq,
+                    {} as hierarchy_score,
+                    CAST(SUM({}) AS REAL) / MAX(COUNT(h.exit_status), 1) as failure_rate,
+                    MAX(h.has_local_file_args) as has_local_files,
+                    {} as project_freq,
+                    c.id,
+                    COUNT(DISTINCT h.start_time / {window}) as active_windows
+             FROM history h
+             JOIN commands c ON c.id = h.command_id
+             JOIN places p ON p.id = h.place_id
+             WHERE {} AND (?3 = '*' OR p.host = ?3)
+               AND (?6 IS NULL OR h.context_id IN (SELECT id FROM contexts WHERE project_type = ?6))
+             GROUP BY c.id
+             {}
+             ORDER BY {}{}last_used DESC, c.id
+             LIMIT ?4",
+            exact_dir_case, dir_case, self.ignored_exits.failed_sql(), project_case, match_clause, having, match_order, dir_order,
+            window = FREQUENCY_CAP_WINDOW,
+        );
+
+        let mut stmt = conn.prepare(&query)?;
+
+        // Build params array
+        let mut query_params: Vec<Box<dyn rusqlite::ToSql>> = vec![
+            Box::new(escape_like(&params.prefix)),
+            Box::new(params.cwd.clone()),
+            Box::new(hostname.to_string()),
+            Box::new(window),
+        ];
+        query_params.push(Box::new(project_root.map(String::from)));
+        query_params.push(Box::new(params.in_project.clone()));
+        for dir in dir_list {
+            query_params.push(Box::new(dir.clone()));
+        }
+
+        let params_refs: Vec<&dyn rusqlite::ToSql> = query_params.iter().map(|p| p.as_ref()).collect();
+
+        let rows = stmt.query_map(params_refs.as_slice(), |row| {
+            Ok(hot::Candidate {
+                cmd: row.get(0)?,
+                freq: row.get(1)?,
+                last_used: row.get(2)?,
+                exact_dir_freq: row.get(3)?,
+                hierarchy_score: row.get::<_, f64>(4).unwrap_or(0.0),
+                failure_rate: row.get::<_, f64>(5).unwrap_or(0.0),
+                has_local_files: row.get::<_, i32>(6).unwrap_or(0) != 0,
+                project_freq: row.get::<_, i64>(7).unwrap_or(0),
+                id: row.get(8)?,
+                active_windows: row.get(9)?,
+            })
+        })?;
+
+        // Errors (an interrupt past the predict deadline) must not pass as a short window
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Compute n-gram bonus scores for commands following the given previous commands.
+    /// Returns a HashMap of command → bonus score (0.0-1.0).
+    ///
+    /// Uses conditional probability (freq/total_successors) instead of raw ln(freq),
+    /// applies recency decay based on last_used timestamp, and optionally boosts
+    /// exit-aware bigrams when last_exit is provided.
+    fn compute_ngram_bonus(
+        &self,
+        conn: &Connection,
+        last_cmds: &[String],
+        prefix: &str,
+        limit: usize,
+        last_exit: Option<i32>,
+        w: &crate::protocol::RankingWeights,
+    ) -> Result<std::collections::HashMap<String, f64>> {
+        let mut ngram_bonus: std::collections::HashMap<String, f64> = std::collections::HashMap::new();
+
+        if last_cmds.is_empty() {
+            return Ok(ngram_bonus);
+        }
+        let last_cmds = &last_cmds[..last_cmds.len().min(NGRAM_CONTEXT)];
+        let prefix = escape_like(prefix);
+
+        let now = chrono_lite_timestamp();
+        let halflife = w.ngram_recency_halflife;
+
+        let prev1_cmd = &last_cmds[0];
+        if let Ok(prev1_id) = self.get_command_id(conn, prev1_cmd) {
+            // Trigram lookup: if we have two previous commands
+            if last_cmds.len() >= 2 {
+                let prev2_cmd = &last_cmds[1];
+                if let Ok(prev2_id) = self.get_command_id(conn, prev2_cmd) {
+                    // 4-gram lookup: the longest context wins when it has been seen
+                    if last_cmds.len() >= 3
+                        && let Ok(prev3_id) = self.get_command_id(conn, &last_cmds[2])
+                    {
+                        let total_fourgram: f64 = conn.query_row(
+                            "SELECT COALESCE(SUM(frequency), 0) FROM ngrams_4
+                             WHERE prev3_command_id = ?1 AND prev2_command_id = ?2 AND prev1_command_id = ?3",
+                            rusqlite::params![prev3_id, prev2_id, prev1_id],
+                            |row| row.get(0),
+                        ).unwrap_or(0.0);
+
+                        if total_fourgram > 0.0 {
+                            let mut stmt = conn.prepare_cached(
+                                "SELECT c.argv, n.frequency, n.last_used
+                                 FROM ngrams_4 n
+                                 JOIN commands c ON c.id = n.command_id
+                                 WHERE n.prev3_command_id = ?1 AND n.prev2_command_id = ?2 AND n.prev1_command_id = ?3
+                                   AND c.argv LIKE ?4 || '%' ESCAPE '\\'
+                                 ORDER BY n.frequency DESC
+                                 LIMIT ?5",
+                            )?;
+
+                            let rows = stmt.query_map(
+                                rusqlite::params![prev3_id, prev2_id, prev1_id, prefix, limit],
+                                |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?)),
+                            )?;
+
+                            for (cmd, freq, last_used) in rows.flatten() {
+                                let cond_prob = freq as f64 / total_fourgram;
+                                let age_days = (now - last_used) as f64 / 86400.0;
+                                let recency = (-age_days / halflife).exp();
+                                let bonus = (cond_prob * recency * w.ngram_fourgram_boost).min(1.0);
+                                ngram_bonus.insert(cmd, bonus);
+                            }
+                        }
+                    }
+
+                    // Fetch total trigram successor frequency for conditional probability
+                    let total_trigram: f64 = conn.query_row(
+                        "SELECT COALESCE(SUM(frequency), 0) FROM ngrams_3 WHERE prev2_command_id = ?1 AND prev1_command_id = ?2",
+                        rusqlite::params![prev2_id, prev1_id],
+                        |row| row.get(0),
+                    ).unwrap_or(0.0);
+
+                    if total_trigram > 0.0 {
+                        let mut stmt = conn.prepare_cached(
+                            "SELECT c.argv, n.frequency, n.last_used
+                             FROM ngrams_3 n
+                             JOIN commands c ON c.id = n.command_id
+                             WHERE n.prev2_command_id = ?1 AND n.prev1_command_id = ?2
+                               AND c.argv LIKE ?3 || '%' ESCAPE '\\'
+                             ORDER BY n.frequency DESC
+                             LIMIT ?4",
+                        )?;
+
+                        let rows = stmt.query_map(
+                            rusqlite::params![prev2_id, prev1_id, prefix, limit],
+                            |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?)),
+                        )?;
+
+                        for (cmd, freq, last_used) in rows.flatten() {
+                            let cond_prob = freq as f64 / total_trigram;
+                            let age_days = (now - last_used) as f64 / 86400.0;
+                            let recency = (-age_days / halflife).exp();
+                            let bonus = (cond_prob * recency * w.ngram_trigram_boost).min(1.0);
+                            let entry = ngram_bonus.entry(cmd).or_insert(0.0);
+                            *entry = entry.max(bonus);
+                        }
+                    }
+                }
+            }
+
+            // Exit-aware bigram lookup (if last_exit provided)
+            // Track which commands got exit-aware scores so we can skip them in general bigram
+            let mut exit_scored: std::collections::HashSet<String> = std::collections::HashSet::new();
+
+            if let Some(exit_code) = last_exit {
+                let prev_exit_ok = if exit_code == 0 { 1i32 } else { 0 };
+
+                let total_exit: f64 = conn.query_row(
+                    "SELECT COALESCE(SUM(frequency), 0) FROM ngrams_2_exit WHERE prev_command_id = ?1 AND prev_exit_ok = ?2",
+                    rusqlite::params![prev1_id, prev_exit_ok],
+                    |row| row.get(0),
+                ).unwrap_or(0.0);
+
+                if total_exit > 0.0 {
+                    let mut stmt = conn.prepare_cached(
+                        "SELECT c.argv, n.frequency, n.last_used
+                         FROM ngrams_2_exit n
+                         JOIN commands c ON c.id = n.command_id
+                         WHERE n.prev_command_id = ?1 AND n.prev_exit_ok = ?2
+                           AND c.argv LIKE ?3 || '%' ESCAPE '\\'
+                         ORDER BY n.frequency DESC
+                         LIMIT ?4",
+                    )?;
+
+                    let rows = stmt.query_map(
+                        rusqlite::params![prev1_id, prev_exit_ok, prefix, limit],
+                        |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?)),
+                    )?;
+
+                    for (cmd, freq, last_used) in rows.flatten() {
+                        let cond_prob = freq as f64 / total_exit;
+                        let age_days = (now - last_used) as f64 / 86400.0;
+                        let recency = (-age_days / halflife).exp();
+                        let bonus = (cond_prob * recency * w.ngram_exit_boost).min(1.0);
+                        // Only use exit-aware score if trigram didn't already provide a higher one
+                        let current = ngram_bonus.get(&cmd).copied().unwrap_or(0.0);
+                        if bonus > current {
+                            ngram_bonus.insert(cmd.clone(), bonus);
+                        }
+                        exit_scored.insert(cmd);
+                    }
+                }
+            }
+
+            // General bigram lookup: prev1 → ?
+            // Fetch total bigram successor frequency for conditional probability
+            let total_bigram: f64 = conn.query_row(
+                "SELECT COALESCE(SUM(frequency), 0) FROM ngrams_2 WHERE prev_command_id = ?1",
+                rusqlite::params![prev1_id],
+                |row| row.get(0),
+            ).unwrap_or(0.0);
+
+            if total_bigram > 0.0 {
+                let mut stmt = conn.prepare_cached(
+                    "SELECT c.argv, n.frequency, n.last_used
+                     FROM ngrams_2 n
+                     JOIN commands c ON c.id = n.command_id
+                     WHERE n.prev_command_id = ?1 AND c.argv LIKE ?2 || '%' ESCAPE '\\'
+                     ORDER BY n.frequency DESC
+                     LIMIT ?3",
+                )?;
+
+                let rows = stmt.query_map(
+                    rusqlite::params![prev1_id, prefix, limit],
+                    |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, i64>(2)?)),
+                )?;
+
+                for (cmd, freq, last_used) in rows.flatten() {
+                    // Skip if exit-aware data already scored this command
+                    if exit_scored.contains(&cmd) {
+                        continue;
+                    }
+                    let cond_prob = freq as f64 / total_bigram;
+                    let age_days = (now - last_used) as f64 / 86400.0;
+                    let recency = (-age_days / halflife).exp();
+                    let bonus = (cond_prob * recency).min(1.0);
+                    // Only insert if trigram didn't already provide a higher bonus
+                    ngram_bonus.entry(cmd).or_insert(bonus);
+                }
+            }
+        }
+
+        Ok(ngram_bonus)
+    }
+
+    /// Smoothed share of the times each command was shown that it was accepted,
+    /// `accepted / (accepted + rejected + 1)`; commands never accepted are left out
+    fn accept_rates(&self, conn: &Connection, ids: &[i64]) -> Result<std::collections::HashMap<i64, f64>> {
+        if ids.is_empty() {
+            return Ok(std::collections::HashMap::new());
+        }
+        let mut stmt = conn.prepare(&format!(
+            "SELECT command_id, CAST(accepted AS REAL) / (accepted + rejected + 1)
+             FROM accepts WHERE accepted > 0 AND command_id IN ({})",
+            vec!["?"; ids.len()].join(", ")
+        ))?;
+        let rates = stmt
+            .query_map(rusqlite::params_from_iter(ids), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(rates)
+    }
+
+    /// Start time of the newest session each command was run in, among sessions
+    /// recent enough to still earn a session bonus. History from before a session
+    /// started belongs to an earlier shell with the same PID.
+    fn session_starts(&self, conn: &Connection, ids: &[i64], now: i64) -> Result<std::collections::HashMap<i64, i64>> {
+        if ids.is_empty() {
+            return Ok(std::collections::HashMap::new());
+        }
+        let cutoff = now - (SESSION_HALF_LIFE_DAYS * 10.0 * 86400.0) as i64;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT h.command_id, MAX(s.start_time)
+             FROM sessions s
+             JOIN history h ON h.session_id = s.pid AND h.start_time >= s.start_time
+             JOIN places p ON p.id = h.place_id AND p.host = s.host
+             WHERE s.start_time >= ? AND h.command_id IN ({})
+             GROUP BY h.command_id",
+            vec!["?"; ids.len()].join(", ")
+        ))?;
+        let query_params = std::iter::once(&cutoff).chain(ids);
+        let starts = stmt
+            .query_map(rusqlite::params_from_iter(query_params), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(starts)
+    }
+
+    fn get_command_id(&self, conn: &Connection, argv: &str) -> Result<i64> {
+        let mut stmt = conn.prepare_cached("SELECT id FROM commands WHERE argv = ?1")?;
+        let id: i64 = stmt.query_row([argv], |row| row.get(0))?;
+        Ok(id)
+    }
+
+    /// Record which shown suggestion the user took. The others shown count as
+    /// rejected; suggestions that aren't known commands are ignored.
+    pub fn accept(&self, params: &AcceptParams) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let now = chrono_lite_timestamp();
+
+        if let Ok(id) = self.get_command_id(&tx, &params.cmd) {
+            tx.execute(
+                "INSERT INTO accepts (command_id, accepted, last_accepted) VALUES (?1, 1, ?2)
+                 ON CONFLICT(command_id) DO UPDATE SET accepted = accepted + 1, last_accepted = ?2",
+                rusqlite::params![id, now],
+            )?;
+        }
+        let passed_over: HashSet<&String> = params.shown.iter().filter(|cmd| **cmd != params.cmd).collect();
+        for cmd in passed_over {
+            if let Ok(id) = self.get_command_id(&tx, cmd) {
+                tx.execute(
+                    "INSERT INTO accepts (command_id, rejected) VALUES (?1, 1)
+                     ON CONFLICT(command_id) DO UPDATE SET rejected = rejected + 1",
+                    [id],
+                )?;
+            }
+        }
+
+        tx.commit()?;
+        debug!("Accepted {:?} for prefix {:?} over {} others", params.cmd, params.prefix, params.shown.len().saturating_sub(1));
+        Ok(())
+    }
+
+    /// Add or bump a path's frecency (fasd-like ranking)
+    pub fn frecent_add(&self, params: &FrecentAddParams) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        self.frecent_add_with_conn(&conn, &params.path, &params.path_type, params.rank, params.timestamp)
+    }
+
+    /// Forget a frecent path; returns whether it was tracked
+    pub fn frecent_delete(&self, path: &str, path_type: &str) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        let removed = conn.execute(
+            "DELETE FROM frecent_paths WHERE path = ?1 AND path_type = ?2",
+            rusqlite::params![path, path_type],
+        )?;
+        Ok(removed > 0)
+    }
+
+    /// Add or bump many paths in one transaction, aging each path type at most once at the end
+    pub fn frecent_add_batch(&self, entries: &[FrecentAddParams]) -> Result<usize> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        let mut bumped_types = HashSet::new();
+        for e in entries {
+            self.frecent_upsert(&tx, &e.path, &e.path_type, e.rank, e.timestamp)?;
+            if e.rank.is_none() {
+                bumped_types.insert(e.path_type.as_str());
+            }
+        }
+        for path_type in bumped_types {
+            self.frecent_age(&tx, path_type)?;
+        }
+
+        tx.commit()?;
+        Ok(entries.len())
+    }
+
+    fn frecent_add_with_conn(
+        &self,
+        conn: &Connection,
+        path: &str,
+        path_type: &str,
+        rank_override: Option<f64>,
+        timestamp_override: Option<i64>,
+    ) -> Result<()> {
+        self.frecent_upsert(conn, path, path_type, rank_override, timestamp_override)?;
+
+        // Imported ranks are kept as-is; aging them here would make import lossy
+        if rank_override.is_some() {
+            return Ok(());
+        }
+
+        self.frecent_age(conn, path_type)
+    }
+
+    fn frecent_upsert(
+        &self,
+        conn: &Connection,
+        path: &str,
+        path_type: &str,
+        rank_override: Option<f64>,
+        timestamp_override: Option<i64>,
+    ) -> Result<()> {
+        let now = timestamp_override.unwrap_or_else(chrono_lite_timestamp);
+
+        if let Some(rank) = rank_override {
+            // Import mode: use provided rank/timestamp directly
+            conn.execute(
+                "INSERT INTO frecent_paths (path, path_type, rank, last_access, access_count)
+                 VALUES (?1, ?2, ?3, ?4, 1)
+                 ON CONFLICT(path, path_type) DO UPDATE SET
+                    rank = MAX(rank, ?3),
+                    last_access = MAX(last_access, ?4),
+                    access_count = access_count + 1",
+                rusqlite::params![path, path_type, rank, now],
+            )?;
+        } else {
+            // Normal mode: fasd rank formula
+            // new_rank = old_rank + 1/old_rank (or 1.0 for new entries)
+            conn.execute(
+                "INSERT INTO frecent_paths (path, path_type, rank, last_access, access_count)
+                 VALUES (?1, ?2, 1.0, ?3, 1)
+                 ON CONFLICT(path, path_type) DO UPDATE SET
+                    rank = rank + 1.0 / MAX(rank, 0.01),
+                    last_access = ?3,
+                    access_count = access_count + 1",
+                rusqlite::params![path, path_type, now],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Aging: if total rank for this path_type exceeds 2000, decay all by 0.9
+    fn frecent_age(&self, conn: &Connection, path_type: &str) -> Result<()> {
+        let total_rank: f64 = conn
+            .query_row(
+                "SELECT COALESCE(SUM(rank), 0.0) FROM frecent_paths WHERE path_type = ?1",
+                [path_type],
+                |row| row.get(0),
+            )
+            .unwrap_or(0.0);
+
+        if total_rank > 2000.0 {
+            conn.execute(
+                "UPDATE frecent_paths SET rank = rank * 0.9 WHERE path_type = ?1",
+                [path_type],
+            )?;
+            // Prune entries with rank < 1.0
+            conn.execute(
+                "DELETE FROM frecent_paths WHERE path_type = ?1 AND rank < 1.0",
+                [path_type],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// All frecent paths with their raw rank and last access, in fasd's rank order
+    pub fn frecent_export(&self) -> Result<Vec<FrecencyResult>> {
+        let conn = self.conn.lock().unwrap();
+        let now = chrono_lite_timestamp();
+
+        let mut stmt = conn.prepare(
+            "SELECT path, path_type, rank, last_access, access_count FROM frecent_paths ORDER BY rank DESC, path",
+        )?;
+        let results = stmt
+            .query_map([], |row| {
+                let rank: f64 = row.get(2)?;
+                let last_access: i64 = row.get(3)?;
+                Ok(FrecencyResult {
+                    path: row.get(0)?,
+                    path_type: row.get(1)?,
+                    score: frecency_score(rank, row.get(4)?, last_access, now),
+                    rank: Some(rank),
+                    last_access: Some(last_access),
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(results)
+    }
+
+    /// Query frecent paths with fasd-compatible matching and scoring
+    pub fn frecent_query(&self, params: &FrecentQueryParams) -> Result<Vec<FrecencyResult>> {
+        let conn = self.conn.lock().unwrap();
+        let now = chrono_lite_timestamp();
+
+        // Fetch all candidate paths (filtered by type)
+        let map_row = |row: &rusqlite::Row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, f64>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, i64>(4)?,
+            ))
+        };
+        let type_filter = if params.path_types.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE path_type IN ({})", vec!["?"; params.path_types.len()].join(", "))
+        };
+        let mut stmt = conn.prepare(&format!(
+            "SELECT path, path_type, rank, last_access, access_count FROM frecent_paths {} ORDER BY rank DESC",
+            type_filter
+        ))?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(&params.path_types), map_row)?;
+
+        let mut candidates: Vec<(String, String, f64, i64, i64)> = Vec::new();
+        for r in rows.flatten() {
+            if !params.exclude.contains(&r.0) {
+                candidates.push(r);
+            }
+        }
+
+        let raw = params.raw;
+        let type_weight = |path_type: &str| params.type_weights.as_ref().map_or(1.0, |w| w.weight(path_type));
+
+        // If no search terms, return all by frecency score
+        if params.terms.is_empty() {
+            let mut results: Vec<FrecencyResult> = candidates
+                .iter()
+                .map(|(path, path_type, rank, last_access, access_count)| FrecencyResult {
+                    path: path.clone(),
+                    path_type: path_type.clone(),
+                    score: frecency_score(*rank, *access_count, *last_access, now) * type_weight(path_type),
+                    rank: if raw { Some(*rank) } else { None },
+                    last_access: if raw { Some(*last_access) } else { None },
+                })
+                .collect();
+            results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+            return Ok(take_frecent_results(results, params));
+        }
+
+        // Three-tier matching
+        let mut results: Vec<FrecencyResult> = Vec::new();
+
+        // Tier 1: Ordered substring match (case-sensitive)
+        for (path, path_type, rank, last_access, access_count) in &candidates {
+            if matches_ordered_substring(path, &params.terms, false) {
+                results.push(FrecencyResult {
+                    path: path.clone(),
+                    path_type: path_type.clone(),
+                    score: frecency_score(*rank, *access_count, *last_access, now) * type_weight(path_type),
+                    rank: if raw { Some(*rank) } else { None },
+                    last_access: if raw { Some(*last_access) } else { None },
+                });
+            }
+        }
+
+        // Tier 2: Case-insensitive ordered substring
+        if results.is_empty() {
+            for (path, path_type, rank, last_access, access_count) in &candidates {
+                if matches_ordered_substring(path, &params.terms, true) {
+                    results.push(FrecencyResult {
+                        path: path.clone(),
+                        path_type: path_type.clone(),
+                        score: frecency_score(*rank, *access_count, *last_access, now) * type_weight(path_type),
+                        rank: if raw { Some(*rank) } else { None },
+                        last_access: if raw { Some(*last_access) } else { None },
+                    });
+                }
+            }
+        }
+
+        // Tier 3: Fuzzy match (each char of each term in order)
+        if results.is_empty() {
+            for (path, path_type, rank, last_access, access_count) in &candidates {
+                if matches_fuzzy(path, &params.terms) {
+                    results.push(FrecencyResult {
+                        path: path.clone(),
+                        path_type: path_type.clone(),
+                        score: frecency_score(*rank, *access_count, *last_access, now) * type_weight(path_type),
+                        rank: if raw { Some(*rank) } else { None },
+                        last_access: if raw { Some(*last_access) } else { None },
+                    });
+                }
+            }
+        }
+
+        results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+        Ok(take_frecent_results(results, params))
+    }
+
+    /// Detect whether a command references local (relative) file paths that exist in cwd.
+    /// Only relative paths trigger the flag — absolute/~ paths work from anywhere.
+    fn detect_local_file_args(cmd: &str, cwd: &str) -> bool {
+        use std::path::PathBuf;
+
+        let parsed = parse_command(cmd);
+        let mut checked = 0;
+
+        for arg in &parsed.args {
+            if checked >= 5 {
+                break;
+            }
+
+            // Skip flags
+            if arg.starts_with('-') {
+                continue;
+            }
+
+            // Skip absolute paths and home-relative paths (they work from anywhere)
+            if arg.starts_with('/') || arg.starts_with('~') {
+                continue;
+            }
+
+            // Skip args that don't look like paths
+            if !arg.contains('/') && !arg.contains('.') && arg.len() > 50 {
+                continue;
+            }
+
+            checked += 1;
+
+            if PathBuf::from(cwd).join(arg).exists() {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Extract frecent paths from a command's arguments and bump their frecency
+    fn extract_frecent_paths(&self, conn: &Connection, cmd: &str, cwd: &str) -> Result<()> {
+        use std::path::PathBuf;
+
+        // Always bump the cwd as a directory
+        self.frecent_add_with_conn(conn, cwd, "d", None, None)?;
+
+        let parsed = parse_command(cmd);
+        let mut count = 0;
+
+        for arg in &parsed.args {
+            if count >= 5 {
+                break;
+            }
+
+            // Skip flags
+            if arg.starts_with('-') {
+                continue;
+            }
+
+            // Skip args that don't look like paths
+            if !arg.contains('/') && !arg.contains('.') && arg.len() > 50 {
+                continue;
+            }
+
+            // Resolve relative paths against cwd
+            let path = if arg.starts_with('/') || arg.starts_with('~') {
+                let expanded = if arg.starts_with('~') {
+                    if let Ok(home) = std::env::var("HOME") {
+                        arg.replacen('~', &home, 1)
+                    } else {
+                        continue;
+                    }
+                } else {
+                    arg.to_string()
+                };
+                PathBuf::from(expanded)
+            } else {
+                PathBuf::from(cwd).join(arg)
+            };
+
+            // Check if path exists and categorize
+            if let Ok(meta) = std::fs::metadata(&path) {
+                let path_str = path.to_string_lossy().to_string();
+                if meta.is_dir() {
+                    self.frecent_add_with_conn(conn, &path_str, "d", None, None)?;
+                } else if meta.is_file() {
+                    self.frecent_add_with_conn(conn, &path_str, "f", None, None)?;
+                }
+                count += 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get context information for a directory
+    #[allow(dead_code)]
+    pub fn get_context(&self, _cwd: &str) -> Result<ContextInfo> {
+        // For now, return empty context - will be filled by context module
+        Ok(ContextInfo {
+            vcs: None,
+            branch: None,
+            vcs_root: None,
+            remote: None,
+            project: None,
+            projects: Vec::new(),
+            dir_commands: None,
+            top_commands: None,
+        })
+    }
+
+    /// Number of commands run in exactly `cwd` on this host, and the `top` most frequent of them
+    pub fn dir_stats(&self, cwd: &str, top: usize) -> Result<(i64, Vec<CommandCount>)> {
+        let conn = self.conn.lock().unwrap();
+
+        let hostname = resolve_hostname();
+
+        let total: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM history h
+             JOIN places p ON p.id = h.place_id
+             WHERE p.host = ?1 AND p.dir = ?2",
+            rusqlite::params![hostname, cwd],
+            |row| row.get(0),
+        )?;
+
+        let mut stmt = conn.prepare(
+            "SELECT c.argv, COUNT(*) as n FROM history h
+             JOIN places p ON p.id = h.place_id
+             JOIN commands c ON c.id = h.command_id
+             WHERE p.host = ?1 AND p.dir = ?2
+             GROUP BY c.id
+             ORDER BY n DESC, MAX(h.start_time) DESC, c.argv
+             LIMIT ?3",
+        )?;
+        let top_commands = stmt
+            .query_map(rusqlite::params![hostname, cwd, top], |row| {
+                Ok(CommandCount { cmd: row.get(0)?, count: row.get(1)? })
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok((total, top_commands))
+    }
+
+    /// Every directory with recorded history, with command counts and last use
+    pub fn places(&self, params: &PlacesParams) -> Result<Vec<PlaceInfo>> {
+        let conn = self.conn.lock().unwrap();
+
+        let order = match params.sort {
+            PlaceSort::Count => "count DESC, last_used DESC",
+            PlaceSort::Recent => "last_used DESC, count DESC",
+        };
+        let mut stmt = conn.prepare(&format!(
+            "SELECT p.dir, p.host, COUNT(*) as count, MAX(h.start_time) as last_used
+             FROM history h
+             JOIN places p ON p.id = h.place_id
+             GROUP BY p.id
+             ORDER BY {}, p.dir
+             LIMIT ?1",
+            order
+        ))?;
+
+        let places = stmt
+            .query_map([params.limit], |row| {
+                Ok(PlaceInfo {
+                    dir: row.get(0)?,
+                    host: row.get(1)?,
+                    count: row.get(2)?,
+                    last_used: row.get(3)?,
+                })
+            })?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        Ok(places)
+    }
+
+    /// Delete a command and all its references from the database
+    pub fn delete_command(&self, cmd: &str) -> Result<u64> {
+        let conn = self.conn.lock().unwrap();
+
+        // Look up command_id
+        let command_id: i64 = conn
+            .query_row("SELECT id FROM commands WHERE argv = ?1", [cmd], |row| {
+                row.get(0)
+            })
+            .with_context(|| format!("Command not found: {}", cmd))?;
+
+        // Delete from all referencing tables
+        conn.execute("DELETE FROM history WHERE command_id = ?1", [command_id])?;
+        conn.execute(
+            "DELETE FROM ngrams_2 WHERE command_id = ?1 OR prev_command_id = ?1",
+            [command_id],
+        )?;
+        conn.execute(
+            "DELETE FROM ngrams_3 WHERE command_id = ?1 OR prev1_command_id = ?1 OR prev2_command_id = ?1",
+            [command_id],
+        )?;
+        conn.execute(
+            "DELETE FROM ngrams_4 WHERE command_id = ?1 OR prev1_command_id = ?1 OR prev2_command_id = ?1 OR prev3_command_id = ?1",
+            [command_id],
+        )?;
+        conn.execute(
+            "DELETE FROM ngrams_2_exit WHERE command_id = ?1 OR prev_command_id = ?1",
+            [command_id],
+        )?;
+        conn.execute(
+            "DELETE FROM dir_command_freq WHERE command_id = ?1",
+            [command_id],
+        )?;
+        conn.execute(
+            "DELETE FROM parsed_commands WHERE command_id = ?1",
+            [command_id],
+        )?;
+        conn.execute(
+            "DELETE FROM command_segments WHERE command_id = ?1 OR parent_id = ?1",
+            [command_id],
+        )?;
+        conn.execute(
+            "DELETE FROM accepts WHERE command_id = ?1",
+            [command_id],
+        )?;
+
+        // Clean up arg_patterns from this command's program/subcommand
+        let parsed = parse_command(cmd);
+        if !parsed.program.is_empty()
+            && let Some(ref sub) = parsed.subcommand
+        {
+            conn.execute(
+                "DELETE FROM arg_patterns WHERE program = ?1 AND subcommand = ?2",
+                rusqlite::params![parsed.program, sub],
+            )?;
+        }
+
+        // Delete the command itself
+        let deleted = conn.execute("DELETE FROM commands WHERE id = ?1", [command_id])?;
+        self.hot.lock().unwrap().invalidate();
+
+        Ok(deleted as u64)
+    }
+
+    /// Delete history recorded before `cutoff` (epoch seconds), then the commands
+    /// no history refers to any more along with their n-grams and parse data.
+    /// Commands are shared between history rows, so one still run since the
+    /// cutoff (anywhere) is kept, as is one learned from a compound line that is.
+    pub fn prune_history(&self, cutoff: i64) -> Result<PruneResult> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        let history = tx.execute("DELETE FROM history WHERE start_time < ?1", [cutoff])?;
+
+        tx.execute_batch(
+            "CREATE TEMP TABLE orphaned_commands AS
+                 SELECT id FROM commands c WHERE NOT EXISTS (SELECT 1 FROM history h WHERE h.command_id = c.id)
+                     AND NOT EXISTS (SELECT 1 FROM command_segments s JOIN history h ON h.command_id = s.parent_id
+                                     WHERE s.command_id = c.id);
+             DELETE FROM ngrams_2 WHERE command_id IN orphaned_commands OR prev_command_id IN orphaned_commands;
+             DELETE FROM ngrams_3 WHERE command_id IN orphaned_commands
+                 OR prev1_command_id IN orphaned_commands OR prev2_command_id IN orphaned_commands;
+             DELETE FROM ngrams_4 WHERE command_id IN orphaned_commands OR prev1_command_id IN orphaned_commands
+                 OR prev2_command_id IN orphaned_commands OR prev3_command_id IN orphaned_commands;
+             DELETE FROM ngrams_2_exit WHERE command_id IN orphaned_commands OR prev_command_id IN orphaned_commands;
+             DELETE FROM dir_command_freq WHERE command_id IN orphaned_commands;
+             DELETE FROM parsed_commands WHERE command_id IN orphaned_commands;
+             DELETE FROM command_segments WHERE command_id IN orphaned_commands OR parent_id IN orphaned_commands;
+             DELETE FROM accepts WHERE command_id IN orphaned_commands;",
+        )?;
+        let commands = tx.execute("DELETE FROM commands WHERE id IN orphaned_commands", [])?;
+        tx.execute_batch("DROP TABLE orphaned_commands")?;
+
+        tx.commit()?;
+        self.hot.lock().unwrap().invalidate();
+
+        Ok(PruneResult { history, commands })
+    }
+
+    /// Delete all recorded data, keeping the schema and its version.
+    ///
+    /// With `dry_run` nothing is deleted; the result counts what would be.
+    pub fn reset(&self, dry_run: bool) -> Result<ResetResult> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        let mut tables = std::collections::BTreeMap::new();
+        for table in DATA_TABLES {
+            let rows = if dry_run {
+                tx.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get::<_, i64>(0))? as usize
+            } else {
+                tx.execute(&format!("DELETE FROM {}", table), [])?
+            };
+            tables.insert(table.to_string(), rows);
+        }
+
+        if !dry_run {
+            tx.commit()?;
+            self.hot.lock().unwrap().invalidate();
+        }
+
+        Ok(ResetResult { rows: tables.values().sum(), tables })
+    }
+
+    /// Aggregate statistics about the stored history
+    pub fn stats(&self) -> Result<Stats> {
+        let conn = self.conn.lock().unwrap();
+
+        let count = |table: &str| -> Result<i64> {
+            Ok(conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0))?)
+        };
+        let history = count("history")?;
+        // Commands only learned from inside compound lines were never run as such
+        let commands: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM commands c
+             WHERE EXISTS (SELECT 1 FROM history h WHERE h.command_id = c.id)
+                OR NOT EXISTS (SELECT 1 FROM command_segments s WHERE s.command_id = c.id)",
+            [],
+            |row| row.get(0),
+        )?;
+        let places = count("places")?;
+        let bigrams = count("ngrams_2")?;
+        let trigrams = count("ngrams_3")?;
+        let frecent_count = |path_type: &str| -> Result<i64> {
+            Ok(conn.query_row("SELECT COUNT(*) FROM frecent_paths WHERE path_type = ?1", [path_type], |row| row.get(0))?)
+        };
+        let frecent_dirs = frecent_count("d")?;
+        let frecent_files = frecent_count("f")?;
+        let db_size_bytes = Self::size_on_disk(&conn)?;
+
+        let mut stmt = conn.prepare(
+            "SELECT shell, COUNT(*) as n FROM history
+             WHERE shell IS NOT NULL
+             GROUP BY shell
+             ORDER BY n DESC, shell",
+        )?;
+        let counts: Vec<(String, i64)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        let known: i64 = counts.iter().map(|(_, n)| n).sum();
+        let shells = counts
+            .into_iter()
+            .map(|(shell, count)| ShellShare {
+                shell,
+                count,
+                fraction: count as f64 / known.max(1) as f64,
+            })
+            .collect();
+
+        Ok(Stats {
+            history,
+            commands,
+            places,
+            bigrams,
+            trigrams,
+            frecent_dirs,
+            frecent_files,
+            db_size_bytes,
+            shells,
+        })
+    }
+
+    /// Refresh query planner statistics, rebuild the file to drop free pages and
+    /// truncate the WAL. VACUUM can't run inside a transaction and needs the
+    /// database to itself, so the connection is held throughout.
+    pub fn maintenance(&self) -> Result<MaintenanceResult> {
+        let conn = self.conn.lock().unwrap();
+        let size_before = Self::size_on_disk(&conn)?;
+
+        conn.execute_batch("ANALYZE; VACUUM;")?;
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+
+        Ok(MaintenanceResult {
+            size_before,
+            size_after: Self::size_on_disk(&conn)?,
+        })
+    }
+
+    /// Size of the database file plus its WAL, or of its pages when in memory
+    fn size_on_disk(conn: &Connection) -> Result<u64> {
+        match conn.path().filter(|p| !p.is_empty()) {
+            Some(path) => {
+                let file_size = |path: &str| std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+                Ok(file_size(path) + file_size(&format!("{}-wal", path)))
+            }
+            None => {
+                let pages: i64 = conn.query_row("PRAGMA page_count", [], |row| row.get(0))?;
+                let page_size: i64 = conn.query_row("PRAGMA page_size", [], |row| row.get(0))?;
+                Ok((pages * page_size) as u64)
+            }
+        }
+    }
+
+    /// Search history
+    pub fn search(&self, params: &SearchParams) -> Result<Vec<SearchResult>> {
+        let conn = self.conn.lock().unwrap();
+
+        let hostname = query_host(params.host.as_deref());
+
+        // Compute n-gram bonuses if enabled
+        let w = params.weights.clone().unwrap_or_default();
+        let ngram_bonus = if params.ngram_boost && !params.last_cmds.is_empty() {
+            self.compute_ngram_bonus(&conn, &params.last_cmds, "", params.limit, params.last_exit, &w)?
+        } else {
+            std::collections::HashMap::new()
+        };
+
+        let cwd_for_query = params.cwd.clone().unwrap_or_default();
+        let has_cwd = params.cwd.is_some();
+
+        // Regex patterns are matched in Rust; an empty LIKE pattern lets every row through
+        let regex = if params.regex {
+            Some(regex::Regex::new(&params.pattern)?)
+        } else {
+            None
+        };
+        let like_pattern = if regex.is_some() { String::new() } else { escape_like(&params.pattern) };
+
+        // The trigram index narrows plain substrings of 3+ characters to candidate
+        // commands; LIKE still runs on those so matching is unchanged
+        let fts_query = (params.use_fts && regex.is_none() && fts_searchable(&params.pattern))
+            .then(|| format!("\"{}\"", params.pattern.replace('"', "\"\"")));
+        // With the index, CROSS JOIN makes the matched commands drive the query
+        // instead of a scan of this host's whole history
+        let (from_clause, fts_clause) = if fts_query.is_some() {
+            (
+                "commands c CROSS JOIN history h ON h.command_id = c.id",
+                "AND c.id IN (SELECT rowid FROM commands_fts WHERE commands_fts MATCH ?10)",
+            )
+        } else {
+            ("history h JOIN commands c ON c.id = h.command_id", "")
+        };
+
+        // No SQL LIMIT — we need all matching commands to score-sort properly.
+        // GROUP BY c.id bounds results to unique commands (typically a few thousand),
+        // and the aggregates (COUNT, SUM, MAX) require scanning all rows anyway.
+        // Rust handles truncation to params.limit after score-sorting.
+        // Optional filters are NULL when unset.
+        let query = format!("SELECT c.argv, p.dir, MAX(h.start_time) as last_used,
+                    (SELECT h2.exit_status FROM history h2
+                     JOIN places p2 ON p2.id = h2.place_id
+                     WHERE h2.command_id = c.id AND (?2 = '*' OR p2.host = ?2)
+                       AND (?3 IS NULL OR p2.dir = ?3)
+                       AND (?5 IS NULL OR h2.exit_status = ?5)
+                       AND (?6 IS NULL OR h2.start_time >= ?6)
+                       AND (?7 IS NULL OR h2.start_time <= ?7)
+                       AND (?8 IS NULL OR h2.tty = ?8)
+                       AND (?9 IS NULL OR h2.context_id IN (SELECT id FROM contexts WHERE project_type = ?9))
+                     ORDER BY h2.start_time DESC, h2.id DESC LIMIT 1) as last_exit,
+                    h.duration_ms,
+                    COUNT(*) as cmd_freq,
+                    CAST(SUM({}) AS REAL)
+                        / MAX(COUNT(h.exit_status), 1) as failure_rate,
+                    MAX(h.has_local_file_args) as has_local_files,
+                    SUM(CASE WHEN p.dir = ?4 THEN 1 ELSE 0 END) as cwd_freq,
+                    MIN(h.start_time) as first_used,
+                    COUNT(DISTINCT h.start_time / {window}) as active_windows
+             FROM {}
+             JOIN places p ON p.id = h.place_id
+             WHERE c.argv LIKE '%' || ?1 || '%' ESCAPE '\\'
+               AND (?2 = '*' OR p.host = ?2)
+               AND (?3 IS NULL OR p.dir = ?3)
+               AND (?5 IS NULL OR h.exit_status = ?5)
+               AND (?6 IS NULL OR h.start_time >= ?6)
+               AND (?7 IS NULL OR h.start_time <= ?7)
+               AND (?8 IS NULL OR h.tty = ?8)
+               AND (?9 IS NULL OR h.context_id IN (SELECT id FROM contexts WHERE project_type = ?9))
+               {}
+             GROUP BY c.id", self.ignored_exits.failed_sql(), from_clause, fts_clause, window = FREQUENCY_CAP_WINDOW);
+
+        let mut stmt = conn.prepare(&query)?;
+
+        let map_row = |row: &rusqlite::Row| {
+            let cmd: String = row.get(0)?;
+            let timestamp: i64 = row.get(2)?;
+            let exit_status: Option<i32> = row.get(3)?;
+            let cmd_freq: i64 = row.get(5)?;
+            let failure_rate: f64 = row.get::<_, f64>(6).unwrap_or(0.0);
+            let has_local_files: bool = row.get::<_, i32>(7).unwrap_or(0) != 0;
+            let cwd_freq: i64 = row.get::<_, i64>(8).unwrap_or(0);
+            let active_windows: i64 = row.get(10)?;
+
+            let recency_score = ContextRanker::recency_decay(timestamp, w.recency_half_life_days);
+            let freq_score = frequency_score(cmd_freq, active_windows, &w);
+            let failure_penalty = 1.0 - (failure_rate * w.failure_penalty);
+
+            // Apply n-gram bonus if available (the same bonus predict uses)
+            let ngram_score = ngram_bonus.get(&cmd).copied().unwrap_or(0.0) * w.ngram;
+            // Directory affinity: the share of this command's runs made in the cwd
+            let dir_score = if has_cwd && cmd_freq > 0 {
+                w.dir_exact * cwd_freq as f64 / cmd_freq as f64
+            } else {
+                0.0
+            };
+            let mut score = (freq_score * w.frequency + recency_score * w.recency + ngram_score + dir_score).min(1.0) * failure_penalty;
+
+            // Penalize commands with local file args when searching from a different directory
+            if has_local_files && cwd_freq == 0 && has_cwd {
+                score *= 1.0 - w.local_file_penalty;
+            }
+
+            let span = match regex {
+                Some(ref re) => re.find(&cmd).filter(|m| !m.is_empty()).map(|m| [m.start(), m.end()]),
+                None => match_span(&cmd, &params.pattern),
+            };
+
+            Ok(SearchResult {
+                cmd,
+                cwd: row.get(1)?,
+                timestamp,
+                first_used: row.get(9)?,
+                exit_status,
+                duration_ms: row.get(4)?,
+                score: Some(score),
+                failure_rate: Some(failure_rate),
+                match_span: span,
+            })
+        };
+
+        let mut query_params: Vec<&dyn rusqlite::ToSql> = vec![
+            &like_pattern,
+            &hostname,
+            &params.dir,
+            &cwd_for_query,
+            &params.exit_status,
+            &params.since,
+            &params.until,
+            &params.tty,
+            &params.in_project,
+        ];
+        if let Some(ref fts_query) = fts_query {
+            query_params.push(fts_query);
+        }
+
+        let mut results: Vec<SearchResult> = stmt
+            .query_map(query_params.as_slice(), map_row)?
+            .filter_map(|r| r.ok())
+            .collect();
+
+        if let Some(ref re) = regex {
+            results.retain(|r| re.is_match(&r.cmd));
+        }
+
+        // Sort by score descending (ties broken deterministically), then cut out the
+        // requested page; paging after the sort keeps pages in displayed order
+        results.sort_by(|a, b| {
+            rank_order(a.score.unwrap_or(0.0), b.score.unwrap_or(0.0), a.timestamp, b.timestamp, &a.cmd, &b.cmd)
+        });
+
+        Ok(results.into_iter().skip(params.offset).take(params.limit).collect())
+    }
+}
+
+/// Calculate frecency score using fasd's time-weighted formula, plus a
+/// logarithmic bonus for how many times the path was actually visited
+fn frecency_score(rank: f64, access_count: i64, last_access: i64, now: i64) -> f64 {
+    let age = (now - last_access).max(0) as f64;
+    let weight = if age < 3600.0 {
+        6.0 // Within 1 hour
+    } else if age < 86400.0 {
+        4.0 // Within 1 day
+    } else if age < 604800.0 {
+        2.0 // Within 1 week
+    } else {
+        1.0 // Older
+    };
+    (rank + (access_count.max(0) as f64).ln_1p()) * weight
+}
+
+/// Locate the search pattern in a command, mirroring SQLite LIKE's ASCII case folding.
+/// Returns byte offsets [start, end), or None for an empty pattern.
+fn match_span(cmd: &str, pattern: &str) -> Option<[usize; 2]> {
+    if pattern.is_empty() {
+        return None;
+    }
+    let start = cmd
+        .to_ascii_lowercase()
+        .find(&pattern.to_ascii_lowercase())?;
+    Some([start, start + pattern.len()])
+}
+
+/// Length (seconds) of the windows `frequency_cap` limits runs within
+const FREQUENCY_CAP_WINDOW: i64 = 3600;
+
+/// Log-scaled frequency score. With `frequency_cap` set, each window of
+/// `FREQUENCY_CAP_WINDOW` the command ran in counts at most that many runs, so a
+/// burst in a tight loop can't outweigh steady use over many days
+fn frequency_score(freq: i64, active_windows: i64, w: &crate::protocol::RankingWeights) -> f64 {
+    let mut freq = freq as f64;
+    if w.frequency_cap > 0.0 {
+        freq = freq.min(w.frequency_cap * active_windows.max(1) as f64);
+    }
+    freq.ln().max(0.0) / 10.0
+}
+
+/// Previous commands n-gram scoring conditions on (the 4-gram's three); later
+/// entries of `last_cmds` are ignored
+pub const NGRAM_CONTEXT: usize = 3;
+
+/// Time scale (days) over which a command counts as "not run lately" in since-last predictions
+const SINCE_LAST_DAYS: f64 = 1.0;
+
+/// How many of a session's latest commands a previous command is looked for in
+const SESSION_PREV_WINDOW: i64 = 10;
+
+/// Half-life (days) of the bonus for commands from recently started sessions
+const SESSION_HALF_LIFE_DAYS: f64 = 1.0;
+
+/// Popularity fallbacks score at most this fraction of the lowest primary suggestion
+const POPULAR_FALLBACK_SCALE: f64 = 0.5;
+
+/// SQLite VM instructions between checks of a predict deadline
+const DEADLINE_CHECK_OPS: i32 = 1000;
+
+/// Best-effort ranking from n-gram bonuses alone, for predictions out of time
+fn rank_ngram_successors(ngram_bonus: std::collections::HashMap<String, f64>, w: &crate::protocol::RankingWeights, limit: usize) -> Vec<Suggestion> {
+    let mut suggestions: Vec<Suggestion> = ngram_bonus
+        .into_iter()
+        .map(|(cmd, bonus)| Suggestion { cmd, score: (bonus * w.ngram).min(1.0) })
+        .collect();
+    suggestions.sort_by(|a, b| rank_order(a.score, b.score, 0, 0, &a.cmd, &b.cmd));
+    suggestions.truncate(limit);
+    suggestions
+}
+
+/// Keep the best `limit` of score-sorted frecent matches. With `exists_only`,
+/// paths are checked on disk in order, stopping once enough live ones are found.
+fn take_frecent_results(results: Vec<FrecencyResult>, params: &FrecentQueryParams) -> Vec<FrecencyResult> {
+    results
+        .into_iter()
+        .filter(|r| !params.exists_only || Path::new(&r.path).exists())
+        .take(params.limit)
+        .collect()
+}
+
+/// Split the predict buffer at the cursor, if the cursor is before the end of it
+fn split_at_cursor(params: &PredictParams) -> Option<(&str, &str)> {
+    let buffer = params.buffer.as_deref()?;
+    let (at, _) = buffer.char_indices().nth(params.cursor_pos?)?;
+    Some(buffer.split_at(at))
+}
+
+/// Turn whole-command predictions for `head` into edits of the line `head + tail`:
+/// each completes the token under the cursor and keeps the text after that token.
+/// Duplicates and no-op completions are dropped, keeping the higher-ranked one.
+fn splice_at_cursor(suggestions: Vec<Suggestion>, head: &str, tail: &str) -> Vec<Suggestion> {
+    let rest_of_line = tail.trim_start_matches(|c: char| !c.is_whitespace());
+    let buffer = format!("{}{}", head, tail);
+    let mut seen = HashSet::new();
+
+    suggestions
+        .into_iter()
+        .filter_map(|s| {
+            let completion = s.cmd.strip_prefix(head)?;
+            let token_end = completion.find(char::is_whitespace).unwrap_or(completion.len());
+            let token = &completion[..token_end];
+            if token.is_empty() {
+                return None;
+            }
+            let cmd = format!("{}{}{}", head, token, rest_of_line);
+            if cmd == buffer || !seen.insert(cmd.clone()) {
+                return None;
+            }
+            Some(Suggestion { cmd, score: s.score })
+        })
+        .collect()
+}
+
+/// Ordering for ranked results: higher score first, then most recently used,
+/// then command text, so equal scores always sort the same way
+fn rank_order(a_score: f64, b_score: f64, a_used: i64, b_used: i64, a_cmd: &str, b_cmd: &str) -> std::cmp::Ordering {
+    b_score
+        .total_cmp(&a_score)
+        .then(b_used.cmp(&a_used))
+        .then_with(|| a_cmd.cmp(b_cmd))
+}
+
+/// Maximal-marginal-relevance rerank of score-sorted suggestions.
+///
+/// Greedily picks the candidate whose score, minus `diversity` times its highest
+/// token similarity to anything already picked, is largest. Picked suggestions
+/// carry that adjusted score.
+fn diversify(mut candidates: Vec<(Suggestion, i64)>, diversity: f64) -> Vec<(Suggestion, i64)> {
+    let mut selected: Vec<(Suggestion, i64)> = Vec::with_capacity(candidates.len());
+
+    while !candidates.is_empty() {
+        let adjusted: Vec<f64> = candidates
+            .iter()
+            .map(|(c, _)| {
+                let max_sim = selected
+                    .iter()
+                    .map(|(s, _)| token_similarity(&c.cmd, &s.cmd))
+                    .fold(0.0, f64::max);
+                (c.score - diversity * max_sim).max(0.0)
+            })
+            .collect();
+
+        // Candidates are already in rank order, so the first maximum wins ties
+        let best = (0..candidates.len())
+            .reduce(|best, i| if adjusted[i] > adjusted[best] { i } else { best })
+            .unwrap();
+
+        let (mut suggestion, last_used) = candidates.remove(best);
+        suggestion.score = adjusted[best];
+        selected.push((suggestion, last_used));
+    }
+
+    selected
+}
+
+/// Jaccard overlap of whitespace-separated tokens (0.0 to 1.0)
+fn token_similarity(a: &str, b: &str) -> f64 {
+    let a: HashSet<&str> = a.split_whitespace().collect();
+    let b: HashSet<&str> = b.split_whitespace().collect();
+    let union = a.union(&b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / union as f64
+}
+
+/// Check if all terms match as ordered substrings in the path
+fn matches_ordered_substring(path: &str, terms: &[String], case_insensitive: bool) -> bool {
+    let haystack = if case_insensitive {
+        path.to_lowercase()
+    } else {
+        path.to_string()
+    };
+
+    // Consume the haystack term by term; split_once never cuts inside a character
+    let mut rest = haystack.as_str();
+    for term in terms {
+        let needle = if case_insensitive {
+            term.to_lowercase()
+        } else {
+            term.to_string()
+        };
+        match rest.split_once(needle.as_str()) {
+            Some((_, after)) => rest = after,
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Drop ANSI escape sequences and other control characters, keeping tabs and
+/// the newlines of multi-line commands
+fn strip_control_chars(cmd: &str) -> Cow<'_, str> {
+    if !cmd.chars().any(|c| c.is_control() && c != '\t' && c != '\n') {
+        return Cow::Borrowed(cmd);
+    }
+    let mut out = String::with_capacity(cmd.len());
+    let mut chars = cmd.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\t' | '\n' => out.push(c),
+            // CSI (`ESC [ ... final`) and OSC (`ESC ] ... BEL` or `ESC ] ... ESC \\`)
+            // sequences go whole; any other escape takes one character with it
+            '\x1b' => match chars.next() {
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('\x40'..='\x7e').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// Whether `cwd` is at or below one of `exclude_dirs` (a leading `~` means $HOME),
+/// or it or an ancestor contains a `.nicehistignore` file
+fn dir_excluded(cwd: &str, exclude_dirs: &[String]) -> bool {
+    let home = std::env::var("HOME").unwrap_or_default();
+    let listed = exclude_dirs.iter().any(|dir| {
+        let dir = match dir.strip_prefix('~') {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("{}{}", home, rest),
+            _ => dir.to_string(),
+        };
+        let dir = dir.trim_end_matches('/');
+        !dir.is_empty() && (cwd == dir || cwd.strip_prefix(dir).is_some_and(|rest| rest.starts_with('/')))
+    });
+    listed || Path::new(cwd).ancestors().any(|dir| dir.join(".nicehistignore").exists())
+}
+
+/// Whether the full-text index can answer a substring pattern: the trigram
+/// tokenizer needs 3+ characters
+fn fts_searchable(pattern: &str) -> bool {
+    pattern.chars().count() >= 3
+}
+
+/// Escape LIKE metacharacters so `pattern` matches literally under `ESCAPE '\'`
+fn escape_like(pattern: &str) -> String {
+    let mut escaped = String::with_capacity(pattern.len());
+    for c in pattern.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Truncate to at most `max_bytes`, backing off to the previous char boundary
+fn truncate_at_char_boundary(s: &str, max_bytes: usize) -> &str {
+    &s[..s.floor_char_boundary(max_bytes)]
+}
+
+/// Fuzzy match: each character of each term appears in order in the path
+fn matches_fuzzy(path: &str, terms: &[String]) -> bool {
+    let path_lower = path.to_lowercase();
+    let mut path_chars = path_lower.chars().peekable();
+
+    for term in terms {
+        let term_lower = term.to_lowercase();
+        for tc in term_lower.chars() {
+            loop {
+                match path_chars.next() {
+                    Some(pc) if pc == tc => break,
+                    Some(_) => continue,
+                    None => return false,
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Host whose history a query covers: the one asked for (`*` = every host), or this machine
+fn query_host(host: Option<&str>) -> String {
+    match host {
+        Some(host) => host.to_string(),
+        None => resolve_hostname(),
+    }
+}
+
+/// This machine's name as history records it: `$NICEHIST_HOSTNAME` if set,
+/// e.g. to keep a host's history together after a rename or to pin it in tests
+pub fn resolve_hostname() -> String {
+    match std::env::var("NICEHIST_HOSTNAME") {
+        Ok(host) if !host.is_empty() => host,
+        _ => hostname::get()
+            .map(|h| h.to_string_lossy().to_string())
+            .unwrap_or_else(|_| "unknown".to_string()),
+    }
+}
+
+/// The repository branch names are suggested from, and the branches it still has
+struct BranchScope {
+    vcs_root: Option<String>,
+    branches: Option<Vec<String>>,
+}
+
+/// Tables of recorded data, each listed before the tables it references
+const DATA_TABLES: &[&str] = &[
+    "history",
+    "ngrams_2",
+    "ngrams_3",
+    "ngrams_4",
+    "ngrams_2_exit",
+    "dir_command_freq",
+    "accepts",
+    "parsed_commands",
+    "command_segments",
+    "arg_patterns",
+    "frecent_paths",
+    "commands",
+    "places",
+    "contexts",
+    "sessions",
+];
+
+/// Get current Unix timestamp (simple implementation without chrono dependency)
+pub fn chrono_lite_timestamp() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Get directory and its ancestors up to max_depth
+fn get_directory_hierarchy(dir: &str, max_depth: usize) -> Vec<String> {
+    use std::path::Path;
+
+    let mut dirs = vec![dir.to_string()];
+    let mut current = Path::new(dir);
+
+    for _ in 0..max_depth {
+        if let Some(parent) = current.parent() {
+            let parent_str = parent.to_string_lossy().to_string();
+            if parent_str.is_empty() || parent_str == "/" {
+                break;
+            }
+            dirs.push(parent_str);
+            current = parent;
+        } else {
+            break;
+        }
+    }
+
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_database_open_in_memory() {
+        let db = Database::open_in_memory().unwrap();
+        assert!(db.conn.lock().is_ok());
+    }
+
+    #[test]
+    fn test_store_and_retrieve_command() {
+        let db = Database::open_in_memory().unwrap();
+
+        let params = StoreParams {
+            cmd: "git status".to_string(),
+            cwd: "/home/user/project".to_string(),
+            exit_status: Some(0),
+            duration_ms: Some(100),
+            start_time: Some(1700000000),
+            session_id: Some(12345),
+            prev_cmd: None,
+            prev2_cmd: None,
+            prev_exit: None,
+            ..Default::default()
+        };
+
+        let id = db.store_command(&params).unwrap().id().unwrap();
+        assert!(id > 0);
+
+        // Search for the command
+        let search_params = SearchParams {
+            pattern: "git".to_string(),
+            limit: 10,
+            dir: None,
+            exit_status: None,
+            last_cmds: vec![],
+            cwd: None,
+            ngram_boost: false,
+            last_exit: None,
+            ..Default::default()
+        };
+
+        let results = db.search(&search_params).unwrap();
+        assert!(!results.is_empty());
+        assert_eq!(results[0].cmd, "git status");
+    }
+
+    #[test]
+    fn test_search_reports_match_span() {
+        let db = Database::open_in_memory().unwrap();
+        db.store_command(&StoreParams {
+            cmd: "git status".to_string(),
+            cwd: "/home/user/project".to_string(),
+            ..Default::default()
+        }).unwrap();
+
+        let span = |pattern: &str| {
+            db.search(&SearchParams { pattern: pattern.to_string(), ..Default::default() }).unwrap()[0].match_span
+        };
+        assert_eq!(span("git"), Some([0, 3]));
+        assert_eq!(span("STAT"), Some([4, 8]));
+        assert_eq!(span(""), None);
+    }
+
+    #[test]
+    fn test_ngram_updates() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Store first command
+        let params1 = StoreParams {
+            cmd: "git add -A".to_string(),
+            cwd: "/home/user/project".to_string(),
+            exit_status: Some(0),
+            duration_ms: Some(50),
+            start_time: Some(1700000000),
+            session_id: Some(12345),
+            prev_cmd: None,
+            prev2_cmd: None,
+            prev_exit: None,
+            ..Default::default()
+        };
+        db.store_command(&params1).unwrap();
+
+        // Store second command with previous
+        let params2 = StoreParams {
+            cmd: "git commit -m 'test'".to_string(),
+            cwd: "/home/user/project".to_string(),
+            exit_status: Some(0),
+            duration_ms: Some(200),
+            start_time: Some(1700000001),
+            session_id: Some(12345),
+            prev_cmd: Some("git add -A".to_string()),
+            prev2_cmd: None,
+            prev_exit: None,
+            ..Default::default()
+        };
+        db.store_command(&params2).unwrap();
+
+        // Check that bigram was created
+        let conn = db.conn.lock().unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM ngrams_2", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_predictions() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Store some commands
+        for cmd in &["git status", "git add -A", "git commit -m 'test'", "git push"] {
+            let params = StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/home/user/project".to_string(),
+                exit_status: Some(0),
+                duration_ms: Some(100),
+                start_time: None,
+                session_id: None,
+                prev_cmd: None,
+                prev2_cmd: None,
+                prev_exit: None,
+                ..Default::default()
+            };
+            db.store_command(&params).unwrap();
+        }
+
+        // Get predictions for "git "
+        let predict_params = PredictParams {
+            prefix: "git".to_string(),
+            cwd: "/home/user/project".to_string(),
+            last_cmds: vec![],
+            limit: 5,
+            frecent_boost: true,
+            weights: None,
+            last_exit: None,
+            ..Default::default()
+        };
+
+        let suggestions = db.predict(&predict_params).unwrap();
+        assert!(!suggestions.is_empty());
+        assert!(suggestions.iter().all(|s| s.cmd.starts_with("git")));
+    }
+
+    #[test]
+    fn test_frecent_add_and_query() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Add some directories
+        for _ in 0..5 {
+            db.frecent_add(&crate::protocol::FrecentAddParams {
+                path: "/home/user/project".to_string(),
+                path_type: "d".to_string(),
+                rank: None,
+                timestamp: None,
+            }).unwrap();
+        }
+
+        db.frecent_add(&crate::protocol::FrecentAddParams {
+            path: "/home/user/other".to_string(),
+            path_type: "d".to_string(),
+            rank: None,
+            timestamp: None,
+        }).unwrap();
+
+        // Query without terms should return all sorted by score
+        let results = db.frecent_query(&crate::protocol::FrecentQueryParams {
+            path_types: vec!["d".to_string()],
+            limit: 10,
+            ..Default::default()
+        }).unwrap();
+
+        assert!(!results.is_empty());
+        assert_eq!(results[0].path, "/home/user/project");
+    }
+
+    #[test]
+    fn test_frecent_query_path_type_filter() {
+        let db = Database::open_in_memory().unwrap();
+        let entries = [("/home/user/notes.md", "f"), ("/home/user", "d"), ("/etc/hosts", "f"), ("/run/app.sock", "s")];
+        for (path, path_type) in entries {
+            db.frecent_add(&crate::protocol::FrecentAddParams {
+                path: path.to_string(),
+                path_type: path_type.to_string(),
+                rank: None,
+                timestamp: None,
+            }).unwrap();
+        }
+
+        let query = |path_types: &[&str]| -> Vec<(String, String)> {
+            let mut results: Vec<(String, String)> = db.frecent_query(&crate::protocol::FrecentQueryParams {
+                path_types: path_types.iter().map(|t| t.to_string()).collect(),
+                limit: 10,
+                ..Default::default()
+            }).unwrap().into_iter().map(|r| (r.path, r.path_type)).collect();
+            results.sort();
+            results
+        };
+        let files = query(&["f"]);
+        assert_eq!(files.iter().map(|(p, _)| p.as_str()).collect::<Vec<_>>(), ["/etc/hosts", "/home/user/notes.md"]);
+        assert!(files.iter().all(|(_, t)| t == "f"));
+        assert_eq!(query(&["d"]), [("/home/user".to_string(), "d".to_string())]);
+        assert_eq!(query(&[]).len(), 4);
+        // Dirs and files together, but not the socket
+        let mixed = query(&["d", "f"]);
+        assert_eq!(mixed.len(), 3);
+        assert!(mixed.iter().any(|(_, t)| t == "d") && mixed.iter().any(|(_, t)| t == "f"));
+        // Quotes are bound, not spliced into the SQL
+        assert!(query(&["d' OR '1'='1"]).is_empty());
+    }
+
+    #[test]
+    fn test_frecent_type_weights_reorder_mixed_results() {
+        let db = Database::open_in_memory().unwrap();
+        for (path, path_type, visits) in [("/src/notes.md", "f", 4), ("/src", "d", 2)] {
+            for _ in 0..visits {
+                db.frecent_add(&crate::protocol::FrecentAddParams {
+                    path: path.to_string(),
+                    path_type: path_type.to_string(),
+                    rank: None,
+                    timestamp: None,
+                }).unwrap();
+            }
+        }
+
+        let query = |type_weights: Option<crate::protocol::FrecentTypeWeights>| -> Vec<String> {
+            db.frecent_query(&crate::protocol::FrecentQueryParams {
+                terms: vec!["src".to_string()],
+                limit: 10,
+                type_weights,
+                ..Default::default()
+            }).unwrap().into_iter().map(|r| r.path).collect()
+        };
+        assert_eq!(query(None), ["/src/notes.md", "/src"]);
+        let for_cd = crate::protocol::FrecentTypeWeights { d: 1.0, f: 0.25 };
+        assert_eq!(query(Some(for_cd)), ["/src", "/src/notes.md"]);
+    }
+
+    #[test]
+    fn test_frecent_query_exclude() {
+        let db = Database::open_in_memory().unwrap();
+        for (path, visits) in [("/home/user/project", 5), ("/home/user/docs", 2), ("/home/user/project/src", 1)] {
+            for _ in 0..visits {
+                db.frecent_add(&crate::protocol::FrecentAddParams {
+                    path: path.to_string(),
+                    path_type: "d".to_string(),
+                    rank: None,
+                    timestamp: None,
+                }).unwrap();
+            }
+        }
+
+        let query = |terms: &[&str], exclude: &[&str]| -> Vec<String> {
+            db.frecent_query(&crate::protocol::FrecentQueryParams {
+                terms: terms.iter().map(|t| t.to_string()).collect(),
+                limit: 10,
+                exclude: exclude.iter().map(|p| p.to_string()).collect(),
+                ..Default::default()
+            }).unwrap().into_iter().map(|r| r.path).collect()
+        };
+
+        // The top-scoring path is the one excluded
+        assert_eq!(query(&[], &[])[0], "/home/user/project");
+        assert_eq!(query(&[], &["/home/user/project"]), ["/home/user/docs", "/home/user/project/src"]);
+        assert_eq!(query(&["project"], &["/home/user/project"]), ["/home/user/project/src"]);
+        // Exact matches only: a trailing slash names a different path
+        assert_eq!(query(&[], &["/home/user/project/"]).len(), 3);
+    }
+
+    #[test]
+    fn test_store_split_pipelines_learns_bigrams() {
+        let db = Database::open_in_memory().unwrap();
+        let bigram = |prev: &str, cmd: &str| -> Option<i64> {
+            db.conn.lock().unwrap().query_row(
+                "SELECT n.frequency FROM ngrams_2 n
+                 JOIN commands p ON p.id = n.prev_command_id
+                 JOIN commands c ON c.id = n.command_id
+                 WHERE p.argv = ?1 AND c.argv = ?2",
+                [prev, cmd],
+                |row| row.get(0),
+            ).ok()
+        };
+        let store = |split_pipelines: bool| {
+            db.store_command(&StoreParams {
+                cmd: "make build && make test | tee log".to_string(),
+                cwd: "/project".to_string(),
+                split_pipelines,
+                ..Default::default()
+            }).unwrap();
+        };
+
+        store(false);
+        assert_eq!(bigram("make build", "make test"), None);
+
+        store(true);
+        assert_eq!(bigram("make build", "make test"), Some(1));
+        assert_eq!(bigram("make test", "tee log"), Some(1));
+    }
+
+    #[test]
+    fn test_store_split_pipelines_learns_args_per_statement() {
+        let db = Database::open_in_memory().unwrap();
+        let args = |program: &str| -> Vec<String> {
+            let conn = db.conn.lock().unwrap();
+            let mut stmt = conn
+                .prepare("SELECT arg_value FROM arg_patterns WHERE program = ?1 ORDER BY arg_value")
+                .unwrap();
+            stmt.query_map([program], |row| row.get(0)).unwrap().collect::<rusqlite::Result<_>>().unwrap()
+        };
+
+        db.store_command(&StoreParams {
+            cmd: "cd src; grep -rn TODO $(git diff --name-only main)".to_string(),
+            cwd: "/project".to_string(),
+            split_pipelines: true,
+            ..Default::default()
+        }).unwrap();
+
+        // `cd` learns its own argument rather than everything after it
+        assert_eq!(args("cd"), vec!["src"]);
+        assert_eq!(args("grep"), vec!["$(git diff --name-only main)", "TODO"]);
+        // The substitution is learned as git's, not as part of grep's
+        assert_eq!(args("git"), vec!["main"]);
+    }
+
+    #[test]
+    fn test_store_dedup_window() {
+        let db = Database::open_in_memory().unwrap();
+        let history_rows = || -> i64 {
+            db.conn.lock().unwrap().query_row("SELECT COUNT(*) FROM history", [], |row| row.get(0)).unwrap()
+        };
+        // The same imported file: a repeated command, and one run in two places
+        let import = |dedup_window: Option<i64>| {
+            let entries: Vec<StoreParams> = [("make", "/src", 1700000000), ("make", "/src", 1700000100), ("ls", "/src", 1700000200), ("ls", "/tmp", 1700000200)]
+                .into_iter()
+                .map(|(cmd, cwd, start_time)| StoreParams {
+                    cmd: cmd.to_string(),
+                    cwd: cwd.to_string(),
+                    start_time: Some(start_time),
+                    dedup_window,
+                    ..Default::default()
+                })
+                .collect();
+            db.store_batch(&entries).unwrap()
+        };
+
+        assert_eq!(import(Some(0)), 4);
+        assert_eq!(history_rows(), 4);
+        assert_eq!(import(Some(0)), 0);
+        assert_eq!(history_rows(), 4);
+        // Without a window every import adds rows
+        assert_eq!(import(None), 4);
+        assert_eq!(history_rows(), 8);
+
+        let again = StoreParams {
+            cmd: "make".to_string(),
+            cwd: "/src".to_string(),
+            start_time: Some(1700000030),
+            dedup_window: Some(60),
+            ..Default::default()
+        };
+        assert_eq!(db.store_command(&again).unwrap(), StoreOutcome::Skipped("duplicate"));
+        assert!(db.store_command(&StoreParams { dedup_window: Some(10), ..again }).unwrap().id().is_some());
+    }
+
+    #[test]
+    fn test_store_dedup_needs_start_time() {
+        let db = Database::open_in_memory().unwrap();
+        // A plain history file has no timestamps: each repeat is its own run
+        let untimed = StoreParams {
+            cmd: "make".to_string(),
+            cwd: "/src".to_string(),
+            dedup_window: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(db.store_batch(&[untimed.clone(), untimed.clone(), untimed]).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_store_masks_secrets() {
+        let db = Database::open_in_memory().unwrap();
+        db.store_command(&StoreParams {
+            cmd: "curl --token=abc123 https://api".to_string(),
+            cwd: "/tmp".to_string(),
+            prev_cmd: Some("export API_TOKEN=s3cr3t".to_string()),
+            ..Default::default()
+        }).unwrap();
+
+        let conn = db.conn.lock().unwrap();
+        let stored: Vec<String> = conn
+            .prepare("SELECT argv FROM commands ORDER BY argv").unwrap()
+            .query_map([], |row| row.get(0)).unwrap()
+            .collect::<rusqlite::Result<_>>().unwrap();
+        assert_eq!(stored, vec!["curl --token=*** https://api", "export API_TOKEN=***"]);
+        drop(conn);
+
+        let db = db.with_redactor(crate::redact::Redactor::parse("skip *--token*").unwrap());
+        let params = StoreParams {
+            cmd: "curl --token=abc123 https://api".to_string(),
+            cwd: "/tmp".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(db.store_command(&params).unwrap(), StoreOutcome::Skipped("redacted"));
+    }
+
+    #[test]
+    fn test_store_strips_control_chars() {
+        let db = Database::open_in_memory().unwrap();
+        let params = StoreParams {
+            cmd: "echo \x1b[31mred\x1b[0m\x07 \x1b]0;title\x07done\tok\nls".to_string(),
+            cwd: "/tmp".to_string(),
+            ..Default::default()
+        };
+        let id = db.store_command(&params).unwrap().id().unwrap();
+        assert_eq!(db.stored_command(id).unwrap().unwrap().cmd, "echo red done\tok\nls");
+
+        let db = db.with_raw_commands(true);
+        let id = db.store_command(&params).unwrap().id().unwrap();
+        assert_eq!(db.stored_command(id).unwrap().unwrap().cmd, params.cmd);
+    }
+
+    #[test]
+    fn test_frecent_delete() {
+        let db = Database::open_in_memory().unwrap();
+        for path in ["/home/user/gone", "/home/user/kept"] {
+            db.frecent_add(&crate::protocol::FrecentAddParams {
+                path: path.to_string(),
+                path_type: "d".to_string(),
+                rank: None,
+                timestamp: None,
+            }).unwrap();
+        }
+
+        assert!(!db.frecent_delete("/home/user/gone", "f").unwrap());
+        assert!(db.frecent_delete("/home/user/gone", "d").unwrap());
+        assert!(!db.frecent_delete("/home/user/gone", "d").unwrap());
+
+        let results = db.frecent_query(&crate::protocol::FrecentQueryParams {
+            terms: vec!["user".to_string()],
+            limit: 10,
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(results.iter().map(|r| r.path.as_str()).collect::<Vec<_>>(), vec!["/home/user/kept"]);
+    }
+
+    #[test]
+    fn test_frecent_query_exists_only() {
+        let db = Database::open_in_memory().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let live = dir.path().join("live-proj");
+        std::fs::create_dir(&live).unwrap();
+        let dead = dir.path().join("dead-proj");
+
+        // The dead path ranks highest
+        for (path, rank) in [(&dead, 10.0), (&live, 5.0)] {
+            db.frecent_add(&crate::protocol::FrecentAddParams {
+                path: path.to_string_lossy().to_string(),
+                path_type: "d".to_string(),
+                rank: Some(rank),
+                timestamp: None,
+            }).unwrap();
+        }
+
+        let query = |terms: Vec<String>, exists_only: bool| {
+            db.frecent_query(&crate::protocol::FrecentQueryParams {
+                terms,
+                path_types: vec!["d".to_string()],
+                limit: 1,
+                exists_only,
+                ..Default::default()
+            }).unwrap().into_iter().map(|r| r.path).collect::<Vec<_>>()
+        };
+        let live = live.to_string_lossy().to_string();
+        let dead = dead.to_string_lossy().to_string();
+
+        assert_eq!(query(vec!["proj".to_string()], false), vec![dead.clone()]);
+        assert_eq!(query(vec!["proj".to_string()], true), vec![live.clone()]);
+        assert_eq!(query(vec![], true), vec![live]);
+        assert!(query(vec!["dead".to_string()], true).is_empty());
+    }
+
+    #[test]
+    fn test_frecent_query_matching() {
+        let db = Database::open_in_memory().unwrap();
+
+        db.frecent_add(&crate::protocol::FrecentAddParams {
+            path: "/home/user/project/src".to_string(),
+            path_type: "d".to_string(),
+            rank: None,
+            timestamp: None,
+        }).unwrap();
+
+        db.frecent_add(&crate::protocol::FrecentAddParams {
+            path: "/home/user/other".to_string(),
+            path_type: "d".to_string(),
+            rank: None,
+            timestamp: None,
+        }).unwrap();
+
+        // Substring match
+        let results = db.frecent_query(&crate::protocol::FrecentQueryParams {
+            terms: vec!["proj".to_string(), "src".to_string()],
+            path_types: vec!["d".to_string()],
+            limit: 10,
+            ..Default::default()
+        }).unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, "/home/user/project/src");
+    }
+
+    #[test]
+    fn test_frecent_import_mode() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Import with explicit rank/timestamp
+        db.frecent_add(&crate::protocol::FrecentAddParams {
+            path: "/imported/path".to_string(),
+            path_type: "d".to_string(),
+            rank: Some(42.5),
+            timestamp: Some(1700000000),
+        }).unwrap();
+
+        let results = db.frecent_query(&crate::protocol::FrecentQueryParams {
+            terms: vec!["imported".to_string()],
+            limit: 10,
+            ..Default::default()
+        }).unwrap();
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].path, "/imported/path");
+    }
+
+    #[test]
+    fn test_frecent_raw_json_round_trip() {
+        let db = Database::open_in_memory().unwrap();
+
+        db.frecent_add(&crate::protocol::FrecentAddParams {
+            path: "/home/user/project".to_string(),
+            path_type: "d".to_string(),
+            rank: Some(12.5),
+            timestamp: Some(1700000000),
+        }).unwrap();
+
+        // `frecent --json` requests raw results and prints the array as-is
+        let results = db.frecent_query(&crate::protocol::FrecentQueryParams {
+            limit: 10,
+            raw: true,
+            ..Default::default()
+        }).unwrap();
+        let json = serde_json::to_string(&results).unwrap();
+
+        let parsed: Vec<FrecencyResult> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].path, "/home/user/project");
+        assert_eq!(parsed[0].path_type, "d");
+        assert_eq!(parsed[0].rank, Some(12.5));
+        assert_eq!(parsed[0].last_access, Some(1700000000));
+        assert!(parsed[0].score > 0.0);
+    }
+
+    #[test]
+    fn test_frecency_score_function() {
+        let now = 1700000000i64;
+
+        // Recent access (within hour) should score higher
+        let recent_score = frecency_score(10.0, 1, now - 100, now);
+        let day_old_score = frecency_score(10.0, 1, now - 50000, now);
+        let week_old_score = frecency_score(10.0, 1, now - 400000, now);
+        let old_score = frecency_score(10.0, 1, now - 1000000, now);
+
+        assert!(recent_score > day_old_score);
+        assert!(day_old_score > week_old_score);
+        assert!(week_old_score > old_score);
+
+        // Same rank and recency: the path visited more often wins
+        assert!(frecency_score(10.0, 500, now - 100, now) > frecency_score(10.0, 1, now - 100, now));
+    }
+
+    #[test]
+    fn test_frecent_query_prefers_visited_paths() {
+        let db = Database::open_in_memory().unwrap();
+        let now = chrono_lite_timestamp();
+        for path in ["/home/user/imported", "/home/user/visited"] {
+            db.frecent_add(&crate::protocol::FrecentAddParams {
+                path: path.to_string(),
+                path_type: "d".to_string(),
+                rank: Some(10.0),
+                timestamp: Some(now),
+            }).unwrap();
+        }
+        db.conn.lock().unwrap()
+            .execute("UPDATE frecent_paths SET access_count = 500 WHERE path = '/home/user/visited'", [])
+            .unwrap();
+
+        let results = db.frecent_query(&crate::protocol::FrecentQueryParams {
+            terms: vec!["user".to_string()],
+            path_types: vec!["d".to_string()],
+            limit: 10,
+            raw: true,
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(results.iter().map(|r| r.path.as_str()).collect::<Vec<_>>(), ["/home/user/visited", "/home/user/imported"]);
+        assert_eq!(results[0].rank, results[1].rank);
+    }
+
+    #[test]
+    fn test_matches_ordered_substring_fn() {
+        assert!(matches_ordered_substring("/home/user/project/src", &["proj".to_string(), "src".to_string()], false));
+        assert!(!matches_ordered_substring("/home/user/project/src", &["src".to_string(), "proj".to_string()], false));
+        // Case insensitive
+        assert!(matches_ordered_substring("/Home/User/Project", &["project".to_string()], true));
+        assert!(!matches_ordered_substring("/Home/User/Project", &["project".to_string()], false));
+    }
+
+    #[test]
+    fn test_multibyte_input_never_panics() {
+        let db = Database::open_in_memory().unwrap();
+
+        // 'é' straddles byte 50 of the joined args, where args_hash is cut
+        let long_args = format!("echo {}é tail", "a".repeat(44));
+        let cmds = [
+            "ls 日本語",
+            "cd /tmp/🦀 && echo 'ß'",
+            "vim Ünïcödé.txt",
+            "git commit -m \"e\u{301}té\"",
+            long_args.as_str(),
+        ];
+        let dirs = ["/tmp/日本語", "/home/zoë/🦀 proj", "/ü"];
+
+        let mut prev: Option<&str> = None;
+        for cwd in dirs {
+            for cmd in cmds {
+                db.store_command(&StoreParams {
+                    cmd: cmd.to_string(),
+                    cwd: cwd.to_string(),
+                    prev_cmd: prev.map(String::from),
+                    ..Default::default()
+                }).unwrap();
+                prev = Some(cmd);
+            }
+            db.frecent_add(&FrecentAddParams {
+                path: cwd.to_string(),
+                path_type: "d".to_string(),
+                rank: None,
+                timestamp: None,
+            }).unwrap();
+        }
+
+        for cmd in cmds {
+            let char_ends = cmd.char_indices().map(|(i, _)| i).chain(std::iter::once(cmd.len()));
+            for (pos, end) in char_ends.enumerate() {
+                let head = &cmd[..end];
+                db.predict(&PredictParams {
+                    prefix: head.to_string(),
+                    cwd: dirs[1].to_string(),
+                    last_cmds: vec![cmds[0].to_string()],
+                    ..Default::default()
+                }).unwrap();
+                db.predict(&PredictParams {
+                    buffer: Some(cmd.to_string()),
+                    cursor_pos: Some(pos),
+                    cwd: dirs[0].to_string(),
+                    ..Default::default()
+                }).unwrap();
+                db.search(&SearchParams {
+                    pattern: head.to_string(),
+                    limit: 10,
+                    dir: None, exit_status: None,
+                    last_cmds: vec![], cwd: None, ngram_boost: true, last_exit: None,
+                    ..Default::default()
+                }).unwrap();
+            }
+        }
+
+        for dir in dirs {
+            for (start, _) in dir.char_indices() {
+                let results = db.frecent_query(&crate::protocol::FrecentQueryParams {
+                    terms: vec![dir[start..].to_string()],
+                    limit: 10,
+                    ..Default::default()
+                }).unwrap();
+                assert!(!results.is_empty(), "term {:?}", &dir[start..]);
+            }
+        }
+
+        assert_eq!(truncate_at_char_boundary("aé", 2), "a");
+        assert_eq!(truncate_at_char_boundary("ab", 50), "ab");
+    }
+
+    #[test]
+    fn test_search_regex() {
+        let db = Database::open_in_memory().unwrap();
+        for cmd in ["git push origin", "git pull", "git status", "rm -rf ~/.cache", "echo git pushed"] {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/tmp".to_string(),
+                ..Default::default()
+            }).unwrap();
+        }
+
+        let search = |pattern: &str, regex: bool| {
+            db.search(&SearchParams { pattern: pattern.to_string(), regex, ..Default::default() })
+        };
+
+        let mut cmds: Vec<String> = search("^git (push|pull)", true).unwrap().into_iter().map(|r| r.cmd).collect();
+        cmds.sort();
+        assert_eq!(cmds, vec!["git pull", "git push origin"]);
+
+        let results = search(r"rm -rf .*cache", true).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].match_span, Some([0, 15]));
+
+        // Without the flag the pattern is a plain substring
+        assert!(search("^git (push|pull)", false).unwrap().is_empty());
+
+        let err = search("git (push", true).unwrap_err();
+        assert!(err.is::<regex::Error>());
+    }
+
+    #[test]
+    fn test_search_exit_status_filter() {
+        let db = Database::open_in_memory().unwrap();
+        let runs = [("make build", Some(0)), ("make test", Some(2)), ("make test", Some(0)), ("make lint", Some(1)), ("make docs", None)];
+        for (cmd, exit_status) in runs {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/project".to_string(),
+                exit_status,
+                ..Default::default()
+            }).unwrap();
+        }
+
+        let search = |exit_status: Option<i32>, dir: Option<&str>| {
+            let mut results = db.search(&SearchParams {
+                pattern: "make".to_string(),
+                exit_status,
+                dir: dir.map(String::from),
+                ..Default::default()
+            }).unwrap();
+            results.sort_by(|a, b| a.cmd.cmp(&b.cmd));
+            results.into_iter().map(|r| (r.cmd, r.exit_status)).collect::<Vec<_>>()
+        };
+
+        assert_eq!(search(None, None).len(), 4);
+        assert_eq!(search(Some(0), None), vec![
+            ("make build".to_string(), Some(0)),
+            ("make test".to_string(), Some(0)),
+        ]);
+        assert_eq!(search(Some(2), None), vec![("make test".to_string(), Some(2))]);
+        assert_eq!(search(Some(1), Some("/project")), vec![("make lint".to_string(), Some(1))]);
+        assert!(search(Some(127), None).is_empty());
+    }
+
+    #[test]
+    fn test_search_time_range() {
+        let db = Database::open_in_memory().unwrap();
+        for (cmd, start_time) in [("deploy staging", 1_000), ("deploy prod", 2_000), ("deploy prod", 5_000), ("deploy canary", 9_000)] {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/ops".to_string(),
+                start_time: Some(start_time),
+                ..Default::default()
+            }).unwrap();
+        }
+
+        let search = |since: Option<i64>, until: Option<i64>| {
+            let mut results = db.search(&SearchParams {
+                pattern: "deploy".to_string(),
+                since,
+                until,
+                ..Default::default()
+            }).unwrap();
+            results.sort_by(|a, b| a.cmd.cmp(&b.cmd));
+            results.into_iter().map(|r| (r.cmd, r.timestamp)).collect::<Vec<_>>()
+        };
+
+        assert_eq!(search(None, None).len(), 3);
+        // Bounds are inclusive, and last-run times reflect only runs inside the window
+        assert_eq!(search(Some(2_000), Some(4_000)), vec![("deploy prod".to_string(), 2_000)]);
+        assert_eq!(search(Some(5_000), None), vec![
+            ("deploy canary".to_string(), 9_000),
+            ("deploy prod".to_string(), 5_000),
+        ]);
+        assert_eq!(search(None, Some(1_000)), vec![("deploy staging".to_string(), 1_000)]);
+        assert!(search(Some(9_001), None).is_empty());
+    }
+
+    #[test]
+    fn test_search_pages_do_not_overlap() {
+        let db = Database::open_in_memory().unwrap();
+        for i in 0..7 {
+            db.store_command(&StoreParams {
+                cmd: format!("git checkout branch-{}", i),
+                cwd: "/repo".to_string(),
+                start_time: Some(1_700_000_000 + i),
+                ..Default::default()
+            }).unwrap();
+        }
+
+        let page = |offset: usize| -> Vec<String> {
+            db.search(&SearchParams { pattern: "git".to_string(), limit: 3, offset, ..Default::default() })
+                .unwrap()
+                .into_iter()
+                .map(|r| r.cmd)
+                .collect()
+        };
+
+        let all: Vec<String> = db.search(&SearchParams { pattern: "git".to_string(), ..Default::default() })
+            .unwrap()
+            .into_iter()
+            .map(|r| r.cmd)
+            .collect();
+        let pages = [page(0), page(3), page(6)];
+        assert_eq!(pages.iter().map(Vec::len).collect::<Vec<_>>(), vec![3, 3, 1]);
+        assert_eq!(pages.concat(), all);
+        assert!(page(7).is_empty());
+    }
+
+    #[test]
+    fn test_search_fts_matches_like() {
+        let db = Database::open_in_memory().unwrap();
+        let cmds = [
+            "git checkout main",
+            "GIT CHECKOUT -b topic",
+            "echo \"say \\\"hi\\\"\" > out.txt",
+            "grep -rn my_var src/",
+            "rm -rf ~/.cache/pip",
+            "ls",
+        ];
+        for cmd in cmds {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/tmp".to_string(),
+                ..Default::default()
+            }).unwrap();
+        }
+        db.delete_command("rm -rf ~/.cache/pip").unwrap();
+
+        for pattern in ["checkout", "Checkout -", "\"say", "out.txt", "my_var", "cache", "ls", "s", "zzz"] {
+            let search = |use_fts: bool| -> Vec<String> {
+                let mut cmds: Vec<String> = db.search(&SearchParams { pattern: pattern.to_string(), use_fts, ..Default::default() })
+                    .unwrap()
+                    .into_iter()
+                    .map(|r| r.cmd)
+                    .collect();
+                cmds.sort();
+                cmds
+            };
+            assert_eq!(search(true), search(false), "pattern {:?}", pattern);
+        }
+
+        let hits = db.search(&SearchParams { pattern: "checkout".to_string(), ..Default::default() }).unwrap();
+        assert_eq!(hits.len(), 2);
+        assert!(fts_searchable("checkout"));
+        assert!(!fts_searchable("ls"));
+        assert!(fts_searchable("my_var"));
+    }
+
+    #[test]
+    fn test_like_metacharacters_match_literally() {
+        let db = Database::open_in_memory().unwrap();
+        for cmd in ["echo a_b", "echo axb", "printf '%s' x", "printf 'xs' x", "cp a\\b c"] {
+            db.store_command(&StoreParams { cmd: cmd.to_string(), cwd: "/tmp".to_string(), ..Default::default() }).unwrap();
+        }
+
+        let search = |pattern: &str| -> Vec<String> {
+            db.search(&SearchParams { pattern: pattern.to_string(), ..Default::default() })
+                .unwrap()
+                .into_iter()
+                .map(|r| r.cmd)
+                .collect()
+        };
+        assert_eq!(search("a_b"), ["echo a_b"]);
+        assert_eq!(search("'%s'"), ["printf '%s' x"]);
+        assert_eq!(search("a\\b"), ["cp a\\b c"]);
+
+        let predict = |prefix: &str| -> Vec<String> {
+            db.predict(&PredictParams { prefix: prefix.to_string(), cwd: "/tmp".to_string(), ..Default::default() })
+                .unwrap()
+                .into_iter()
+                .map(|s| s.cmd)
+                .collect()
+        };
+        assert_eq!(predict("echo a_"), ["echo a_b"]);
+        assert_eq!(predict("printf '%"), ["printf '%s' x"]);
+    }
+
+    #[test]
+    fn test_match_span_fn() {
+        assert_eq!(match_span("git commit -m 'x'", "commit"), Some([4, 10]));
+        // LIKE is ASCII case-insensitive, so the span must be too
+        assert_eq!(match_span("GIT status", "git"), Some([0, 3]));
+        assert_eq!(match_span("écho grep", "grep"), Some([6, 10]));
+        assert_eq!(match_span("ls", ""), None);
+        assert_eq!(match_span("ls", "cat"), None);
+    }
+
+    #[test]
+    fn test_matches_fuzzy_fn() {
+        assert!(matches_fuzzy("/home/user/project", &["prj".to_string()]));
+        assert!(!matches_fuzzy("/home/user/project", &["xyz".to_string()]));
+    }
+
+    #[test]
+    fn test_predictions_with_ngrams() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Store git add followed by git commit multiple times
+        for i in 0..5 {
+            let params1 = StoreParams {
+                cmd: "git add -A".to_string(),
+                cwd: "/home/user/project".to_string(),
+                exit_status: Some(0),
+                duration_ms: Some(50),
+                start_time: Some(1700000000 + i * 10),
+                session_id: Some(12345),
+                prev_cmd: None,
+                prev2_cmd: None,
+                prev_exit: None,
+                ..Default::default()
+            };
+            db.store_command(&params1).unwrap();
+
+            let params2 = StoreParams {
+                cmd: "git commit -m 'test'".to_string(),
+                cwd: "/home/user/project".to_string(),
+                exit_status: Some(0),
+                duration_ms: Some(200),
+                start_time: Some(1700000001 + i * 10),
+                session_id: Some(12345),
+                prev_cmd: Some("git add -A".to_string()),
+                prev2_cmd: None,
+                prev_exit: None,
+                ..Default::default()
+            };
+            db.store_command(&params2).unwrap();
+        }
+
+        // Get predictions after git add
+        let predict_params = PredictParams {
+            prefix: "git".to_string(),
+            cwd: "/home/user/project".to_string(),
+            last_cmds: vec!["git add -A".to_string()],
+            limit: 5,
+            frecent_boost: true,
+            weights: None,
+            last_exit: None,
+            ..Default::default()
+        };
+
+        let suggestions = db.predict(&predict_params).unwrap();
+        assert!(!suggestions.is_empty());
+        // git commit should be highly ranked due to n-gram
+        assert!(suggestions.iter().any(|s| s.cmd.contains("commit")));
+    }
+
+    #[test]
+    fn test_ngram_ranks_successor_first() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Store "make build" followed by "make test" many times (strong bigram)
+        for i in 0..10 {
+            db.store_command(&StoreParams {
+                cmd: "make build".to_string(),
+                cwd: "/home/user/project".to_string(),
+                exit_status: Some(0),
+                duration_ms: Some(50),
+                start_time: Some(1700000000 + i * 10),
+                session_id: Some(1),
+                prev_cmd: None,
+                prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+
+            db.store_command(&StoreParams {
+                cmd: "make test".to_string(),
+                cwd: "/home/user/project".to_string(),
+                exit_status: Some(0),
+                duration_ms: Some(200),
+                start_time: Some(1700000005 + i * 10),
+                session_id: Some(1),
+                prev_cmd: Some("make build".to_string()),
+                prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+
+        // Also store "make clean" a few times (no bigram relationship with make build)
+        for i in 0..3 {
+            db.store_command(&StoreParams {
+                cmd: "make clean".to_string(),
+                cwd: "/home/user/project".to_string(),
+                exit_status: Some(0),
+                duration_ms: Some(30),
+                start_time: Some(1700000200 + i * 10),
+                session_id: Some(1),
+                prev_cmd: None,
+                prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+
+        // Predict with "make" prefix after "make build" — n-gram should push "make test" to top
+        let suggestions = db.predict(&PredictParams {
+            prefix: "make".to_string(),
+            cwd: "/home/user/project".to_string(),
+            last_cmds: vec!["make build".to_string()],
+            limit: 5,
+            frecent_boost: false,
+            weights: None,
+            last_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        assert!(suggestions.len() >= 2, "Expected at least 2 suggestions, got {}", suggestions.len());
+        assert_eq!(suggestions[0].cmd, "make test",
+            "Expected 'make test' first due to bigram, got: {:?}", suggestions);
+
+        // Verify n-gram score is meaningful (frequency=10, ln(10)/10 = 0.23)
+        assert!(suggestions[0].score > 0.1,
+            "N-gram score too low: {}", suggestions[0].score);
+
+        // Verify the n-gram weight is applied
+        let ngram_cmd = &suggestions[0];
+        let non_ngram_cmd = suggestions.iter().find(|s| s.cmd != "make test").unwrap();
+        assert!(ngram_cmd.score > non_ngram_cmd.score,
+            "N-gram command should outscore non-n-gram: {} vs {}", ngram_cmd.score, non_ngram_cmd.score);
+
+        // Without n-gram context, "make test" should NOT necessarily be first
+        let suggestions_no_ngram = db.predict(&PredictParams {
+            prefix: "make".to_string(),
+            cwd: "/home/user/project".to_string(),
+            last_cmds: vec![],
+            limit: 5,
+            frecent_boost: false,
+            weights: None,
+            last_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        // All three make commands should appear
+        let cmds: Vec<&str> = suggestions_no_ngram.iter().map(|s| s.cmd.as_str()).collect();
+        assert!(cmds.contains(&"make build"), "Missing 'make build' in {:?}", cmds);
+        assert!(cmds.contains(&"make test"), "Missing 'make test' in {:?}", cmds);
+        assert!(cmds.contains(&"make clean"), "Missing 'make clean' in {:?}", cmds);
+    }
+
+    #[test]
+    fn test_trigram_boosts_over_bigram() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Build a chain: git add → git commit → git push (repeated)
+        for i in 0..8 {
+            db.store_command(&StoreParams {
+                cmd: "git add -A".to_string(),
+                cwd: "/home/user/project".to_string(),
+                exit_status: Some(0), duration_ms: Some(50),
+                start_time: Some(1700000000 + i * 30),
+                session_id: Some(1),
+                prev_cmd: None, prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+
+            db.store_command(&StoreParams {
+                cmd: "git commit -m 'wip'".to_string(),
+                cwd: "/home/user/project".to_string(),
+                exit_status: Some(0), duration_ms: Some(100),
+                start_time: Some(1700000010 + i * 30),
+                session_id: Some(1),
+                prev_cmd: Some("git add -A".to_string()),
+                prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+
+            db.store_command(&StoreParams {
+                cmd: "git push".to_string(),
+                cwd: "/home/user/project".to_string(),
+                exit_status: Some(0), duration_ms: Some(200),
+                start_time: Some(1700000020 + i * 30),
+                session_id: Some(1),
+                prev_cmd: Some("git commit -m 'wip'".to_string()),
+                prev2_cmd: Some("git add -A".to_string()),
+                prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+
+        // Also store "git pull" after "git commit" a few times (bigram only, no trigram with add)
+        for i in 0..3 {
+            db.store_command(&StoreParams {
+                cmd: "git pull".to_string(),
+                cwd: "/home/user/project".to_string(),
+                exit_status: Some(0), duration_ms: Some(150),
+                start_time: Some(1700000300 + i * 10),
+                session_id: Some(1),
+                prev_cmd: Some("git commit -m 'wip'".to_string()),
+                prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+
+        // With trigram context (add → commit → ?), "git push" should rank highest
+        let suggestions = db.predict(&PredictParams {
+            prefix: "git".to_string(),
+            cwd: "/home/user/project".to_string(),
+            last_cmds: vec![
+                "git commit -m 'wip'".to_string(),
+                "git add -A".to_string(),
+            ],
+            limit: 5,
+            frecent_boost: false,
+            weights: None,
+            last_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        // git push should benefit from both the trigram (add→commit→push) and bigram (commit→push)
+        let push_entry = suggestions.iter().find(|s| s.cmd == "git push");
+        assert!(push_entry.is_some(), "git push should appear in suggestions: {:?}", suggestions);
+
+        let pull_entry = suggestions.iter().find(|s| s.cmd == "git pull");
+        assert!(pull_entry.is_some(), "git pull should appear in suggestions: {:?}", suggestions);
+
+        // Trigram-backed "git push" should outscore bigram-only "git pull"
+        assert!(push_entry.unwrap().score > pull_entry.unwrap().score,
+            "Trigram-backed 'git push' ({}) should outscore bigram-only 'git pull' ({})",
+            push_entry.unwrap().score, pull_entry.unwrap().score);
+    }
+
+    #[test]
+    fn test_fourgram_outranks_trigram_only_successor() {
+        let db = Database::open_in_memory().unwrap();
+        let mut t = 1700000000;
+        let mut store_chain = |chain: [&str; 4]| {
+            for (i, cmd) in chain.iter().enumerate() {
+                t += 10;
+                db.store_command(&StoreParams {
+                    cmd: cmd.to_string(),
+                    cwd: "/home/user/project".to_string(),
+                    exit_status: Some(0),
+                    start_time: Some(t),
+                    session_id: Some(1),
+                    prev_cmd: i.checked_sub(1).map(|j| chain[j].to_string()),
+                    prev2_cmd: i.checked_sub(2).map(|j| chain[j].to_string()),
+                    prev3_cmd: i.checked_sub(3).map(|j| chain[j].to_string()),
+                    ..Default::default()
+                }).unwrap();
+            }
+        };
+
+        // After pull → build → test comes deploy; after fmt → build → test, clean
+        for _ in 0..2 {
+            store_chain(["git pull", "make build", "make test", "make deploy"]);
+        }
+        for _ in 0..3 {
+            store_chain(["cargo fmt", "make build", "make test", "make clean"]);
+        }
+
+        let score_of = |last_cmds: &[&str]| {
+            let suggestions = db.predict(&PredictParams {
+                prefix: "make".to_string(),
+                cwd: "/home/user/project".to_string(),
+                last_cmds: last_cmds.iter().map(|c| c.to_string()).collect(),
+                limit: 10,
+                ..Default::default()
+            }).unwrap();
+            let score = |cmd: &str| suggestions.iter().find(|s| s.cmd == cmd).unwrap().score;
+            (score("make deploy"), score("make clean"))
+        };
+
+        // The trigram alone favours the more common "make clean"
+        let (deploy, clean) = score_of(&["make test", "make build"]);
+        assert!(clean > deploy, "trigram: clean {} should beat deploy {}", clean, deploy);
+
+        // The 4-gram knows this run started with git pull
+        let (deploy, clean) = score_of(&["make test", "make build", "git pull"]);
+        assert!(deploy > clean, "4-gram: deploy {} should beat clean {}", deploy, clean);
+    }
+
+    #[test]
+    fn test_predict_deadline_returns_best_so_far() {
+        let db = Database::open_in_memory().unwrap();
+        for i in 0..2000 {
+            db.store_command(&StoreParams {
+                cmd: format!("git log -n {}", i),
+                cwd: format!("/home/user/repo{}", i % 50),
+                start_time: Some(1700000000 + i),
+                ..Default::default()
+            }).unwrap();
+        }
+        db.store_command(&StoreParams {
+            cmd: "git commit -m wip".to_string(),
+            cwd: "/home/user/repo0".to_string(),
+            prev_cmd: Some("git add .".to_string()),
+            start_time: Some(1700003000),
+            ..Default::default()
+        }).unwrap();
+
+        let params = PredictParams {
+            prefix: "git".to_string(),
+            cwd: "/home/user/repo0".to_string(),
+            last_cmds: vec!["git add .".to_string()],
+            limit: 5,
+            deadline_ms: Some(0),
+            ..Default::default()
+        };
+
+        // With no time at all the history scan is cut off and the n-gram successor is all there is
+        let started = Instant::now();
+        let suggestions = db.predict(&params).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        let cmds: Vec<&str> = suggestions.iter().map(|s| s.cmd.as_str()).collect();
+        assert_eq!(cmds, ["git commit -m wip"]);
+
+        // The interrupted scan didn't leave a truncated hot index behind
+        let full = db.predict(&PredictParams { deadline_ms: None, ..params.clone() }).unwrap();
+        assert_eq!(full.len(), 5);
+        let generous = db.predict(&PredictParams { deadline_ms: Some(60_000), ..params.clone() }).unwrap();
+        let cmds = |suggestions: &[Suggestion]| suggestions.iter().map(|s| s.cmd.clone()).collect::<Vec<_>>();
+        assert_eq!(cmds(&generous), cmds(&full));
+    }
+
+    #[test]
+    fn test_predict_fallback_popular() {
+        let db = Database::open_in_memory().unwrap();
+        let mut t = 1700000000;
+        for (cmd, times) in [("git status", 5), ("git push", 3), ("git log", 1), ("ls -la", 9)] {
+            for _ in 0..times {
+                t += 10;
+                db.store_command(&StoreParams {
+                    cmd: cmd.to_string(),
+                    cwd: "/home/user/repo".to_string(),
+                    start_time: Some(t),
+                    ..Default::default()
+                }).unwrap();
+            }
+        }
+
+        let predict = |prefix: &str, fallback_popular: bool| {
+            db.predict(&PredictParams {
+                prefix: prefix.to_string(),
+                cwd: "/tmp".to_string(),
+                limit: 3,
+                fallback_popular,
+                ..Default::default()
+            }).unwrap()
+        };
+        let cmds = |suggestions: &[Suggestion]| suggestions.iter().map(|s| s.cmd.clone()).collect::<Vec<_>>();
+
+        // Nothing was ever rebased, but git itself is well used
+        assert!(predict("git rebase -i", false).is_empty());
+        assert_eq!(cmds(&predict("git rebase -i", true)), ["git status", "git push", "git log"]);
+
+        // Fallbacks only fill the remainder, ranked below the real match
+        let suggestions = predict("git l", true);
+        assert_eq!(cmds(&suggestions), ["git log", "git status", "git push"]);
+        assert!(suggestions[1].score < suggestions[0].score);
+        assert!(suggestions[2].score < suggestions[1].score);
+    }
+
+    #[test]
+    fn test_predict_fallback_popular_stops_at_deadline() {
+        let db = Database::open_in_memory().unwrap();
+        // Plenty of git history for the popular-command scan to work through
+        let cmds = (0..1500).map(|i| format!("git commit -m {}", i)).chain(["git log".to_string()]);
+        for (i, cmd) in cmds.enumerate() {
+            db.store_command(&StoreParams {
+                cmd,
+                cwd: "/home/user/repo".to_string(),
+                start_time: Some(1700000000 + i as i64),
+                ..Default::default()
+            }).unwrap();
+        }
+        let params = PredictParams {
+            prefix: "git l".to_string(),
+            cwd: "/home/user/repo".to_string(),
+            limit: 3,
+            fallback_popular: true,
+            ..Default::default()
+        };
+        // Rank once so the hot index answers from memory before the deadline
+        assert_eq!(db.predict(&params).unwrap().len(), 3);
+
+        // With no time left, filling is cut off and the real match still comes back
+        let suggestions = db.predict(&PredictParams { deadline_ms: Some(0), ..params }).unwrap();
+        let cmds: Vec<&str> = suggestions.iter().map(|s| s.cmd.as_str()).collect();
+        assert_eq!(cmds, ["git log"]);
+    }
+
+    #[test]
+    fn test_accepted_suggestion_ranks_higher() {
+        let db = Database::open_in_memory().unwrap();
+        // Equally frequent; "make test" was run last so it leads at first
+        for (i, cmd) in ["make lint", "make test", "make lint", "make test"].iter().enumerate() {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/home/user/project".to_string(),
+                start_time: Some(1700000000 + i as i64),
+                ..Default::default()
+            }).unwrap();
+        }
+        let ranked = || -> Vec<String> {
+            db.predict(&PredictParams {
+                prefix: "make".to_string(),
+                cwd: "/home/user/project".to_string(),
+                ..Default::default()
+            }).unwrap().into_iter().map(|s| s.cmd).collect()
+        };
+        assert_eq!(ranked(), ["make test", "make lint"]);
+
+        db.accept(&AcceptParams {
+            prefix: "make".to_string(),
+            cmd: "make lint".to_string(),
+            shown: vec!["make test".to_string(), "make lint".to_string(), "never stored".to_string()],
+        }).unwrap();
+        assert_eq!(ranked(), ["make lint", "make test"]);
+
+        let counts: Vec<(String, i64, i64)> = db.conn.lock().unwrap()
+            .prepare("SELECT c.argv, a.accepted, a.rejected FROM accepts a JOIN commands c ON c.id = a.command_id ORDER BY c.argv")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(counts, [("make lint".to_string(), 1, 0), ("make test".to_string(), 0, 1)]);
+    }
+
+    #[test]
+    fn test_newer_session_outranks_older_session() {
+        let db = Database::open_in_memory().unwrap();
+        let now = chrono_lite_timestamp();
+        let store = |cmd: &str, session_id: i64, start_time: i64| {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/home/user/project".to_string(),
+                session_id: Some(session_id),
+                start_time: Some(start_time),
+                ..Default::default()
+            }).unwrap();
+        };
+        // Session 1 has been open for two weeks, session 2 started an hour ago;
+        // each ran one make command, session 1's a little later
+        store("ls", 1, now - 14 * 86400);
+        store("make test", 2, now - 3600);
+        store("make lint", 1, now - 3500);
+
+        let started: i64 = db.conn.lock().unwrap()
+            .query_row("SELECT start_time FROM sessions WHERE pid = 1", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(started, now - 14 * 86400);
+
+        let ranked = |weights: crate::protocol::RankingWeights| -> Vec<String> {
+            db.predict(&PredictParams {
+                prefix: "make".to_string(),
+                cwd: "/home/user/project".to_string(),
+                weights: Some(weights),
+                ..Default::default()
+            }).unwrap().into_iter().map(|s| s.cmd).collect()
+        };
+        assert_eq!(ranked(Default::default()), ["make test", "make lint"]);
+        assert_eq!(ranked(crate::protocol::RankingWeights { session_recency: 0.0, ..Default::default() }), ["make lint", "make test"]);
+    }
+
+    #[test]
+    fn test_reused_pid_starts_a_new_session() {
+        let db = Database::open_in_memory().unwrap();
+        let now = chrono_lite_timestamp();
+        let store = |cmd: &str, session_start: i64, start_time: i64| {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/home/user/project".to_string(),
+                session_id: Some(7),
+                session_start: Some(session_start),
+                start_time: Some(start_time),
+                ..Default::default()
+            }).unwrap();
+        };
+        // A shell with PID 7 ran make build, exited, and a new shell got PID 7
+        store("make build", now - 7200, now - 7000);
+        store("make test", now - 60, now - 30);
+        // A late store from the first shell doesn't move the session back
+        store("make clean", now - 7200, now - 6900);
+
+        let conn = db.conn.lock().unwrap();
+        let (sessions, started): (i64, i64) = conn
+            .query_row("SELECT COUNT(*), MAX(start_time) FROM sessions WHERE pid = 7", [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!((sessions, started), (1, now - 60));
+
+        // Only the new shell's own command is credited to its session
+        let ids: Vec<i64> = ["make build", "make test", "make clean"]
+            .iter()
+            .map(|cmd| db.get_command_id(&conn, cmd).unwrap())
+            .collect();
+        let starts = db.session_starts(&conn, &ids, now).unwrap();
+        assert_eq!(starts, std::collections::HashMap::from([(ids[1], now - 60)]));
+    }
+
+    #[test]
+    fn test_predict_ranks_on_uncapped_scores() {
+        let db = Database::open_in_memory().unwrap();
+        // "make build" is run far more often, "make check" more recently
+        let mut t = 1700000000;
+        for cmd in std::iter::repeat_n("make build", 20).chain(std::iter::repeat_n("make check", 2)) {
+            t += 10;
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/home/user/project".to_string(),
+                start_time: Some(t),
+                ..Default::default()
+            }).unwrap();
+        }
+
+        // Heavy weights push both past 1.0 before capping
+        let suggestions = db.predict(&PredictParams {
+            prefix: "make".to_string(),
+            cwd: "/home/user/project".to_string(),
+            weights: Some(crate::protocol::RankingWeights {
+                frequency: 2.0,
+                recency: 2.0,
+                dir_exact: 1.0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }).unwrap();
+
+        let cmds: Vec<&str> = suggestions.iter().map(|s| s.cmd.as_str()).collect();
+        assert_eq!(cmds, ["make build", "make check"]);
+        assert!(suggestions.iter().all(|s| s.score <= 1.0));
+    }
+
+    #[test]
+    fn test_last_cmds_beyond_ngram_context_are_ignored() {
+        let db = Database::open_in_memory().unwrap();
+        let chain = ["cd api", "git pull", "make build", "make test", "make deploy"];
+        let mut t = 1700000000;
+        for _ in 0..2 {
+            for (i, cmd) in chain.iter().enumerate() {
+                t += 10;
+                db.store_command(&StoreParams {
+                    cmd: cmd.to_string(),
+                    cwd: "/home/user/project".to_string(),
+                    start_time: Some(t),
+                    session_id: Some(1),
+                    prev_cmd: i.checked_sub(1).map(|j| chain[j].to_string()),
+                    prev2_cmd: i.checked_sub(2).map(|j| chain[j].to_string()),
+                    prev3_cmd: i.checked_sub(3).map(|j| chain[j].to_string()),
+                    ..Default::default()
+                }).unwrap();
+            }
+        }
+
+        let predict = |last_cmds: Vec<String>| {
+            db.predict(&PredictParams {
+                prefix: "make".to_string(),
+                cwd: "/home/user/project".to_string(),
+                last_cmds,
+                ..Default::default()
+            }).unwrap().into_iter().map(|s| (s.cmd, s.score)).collect::<Vec<_>>()
+        };
+
+        let recent: Vec<String> = ["make test", "make build", "git pull", "cd api"].iter().map(|c| c.to_string()).collect();
+        let long: Vec<String> = recent.iter().cloned().cycle().take(10).collect();
+        let first_three = predict(recent[..NGRAM_CONTEXT].to_vec());
+        assert_eq!(predict(long), first_three);
+        assert_eq!(first_three[0].0, "make deploy");
+    }
+
+    #[test]
+    fn test_search_deduplication() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Store the same command 5 times
+        for i in 0..5 {
+            db.store_command(&StoreParams {
+                cmd: "ls -la".to_string(),
+                cwd: "/home/user".to_string(),
+                exit_status: Some(0), duration_ms: Some(10),
+                start_time: Some(1700000000 + i * 10),
+                session_id: Some(1),
+                prev_cmd: None, prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+
+        let results = db.search(&SearchParams {
+            pattern: "ls".to_string(),
+            limit: 10,
+            dir: None, exit_status: None,
+            last_cmds: vec![], cwd: None, ngram_boost: false, last_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        // Should return exactly 1 result, not 5
+        assert_eq!(results.len(), 1, "Search should deduplicate: {:?}", results);
+        assert_eq!(results[0].cmd, "ls -la");
+    }
+
+    #[test]
+    fn test_recency_half_life() {
+        let db = Database::open_in_memory().unwrap();
+        let now = chrono_lite_timestamp();
+        for (cmd, days_ago) in [("make fresh", 1), ("make stale", 60)] {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/home/user".to_string(),
+                start_time: Some(now - days_ago * 86400),
+                ..Default::default()
+            }).unwrap();
+        }
+
+        // Score on recency alone
+        let weights = |half_life: f64| crate::protocol::RankingWeights {
+            frequency: 0.0,
+            recency: 1.0,
+            recency_half_life_days: half_life,
+            dir_exact: 0.0,
+            dir_project: 0.0,
+            dir_hierarchy: 0.0,
+            use_ngram: false,
+            use_frecent: false,
+            ..Default::default()
+        };
+        let predict_ratio = |half_life: f64| {
+            let results = db.predict(&PredictParams {
+                prefix: "make".to_string(),
+                cwd: "/home/user".to_string(),
+                weights: Some(weights(half_life)),
+                ..Default::default()
+            }).unwrap();
+            let score = |cmd: &str| results.iter().find(|s| s.cmd == cmd).unwrap().score;
+            score("make stale") / score("make fresh")
+        };
+        let search_ratio = |half_life: f64| {
+            let results = db.search(&SearchParams {
+                pattern: "make".to_string(),
+                weights: Some(weights(half_life)),
+                ..Default::default()
+            }).unwrap();
+            let score = |cmd: &str| results.iter().find(|r| r.cmd == cmd).unwrap().score.unwrap();
+            score("make stale") / score("make fresh")
+        };
+
+        // A slower decay keeps the old command much closer to the fresh one
+        assert!(predict_ratio(30.0) < 0.2, "{}", predict_ratio(30.0));
+        assert!(predict_ratio(300.0) > 0.8, "{}", predict_ratio(300.0));
+        assert!((search_ratio(30.0) - predict_ratio(30.0)).abs() < 0.01);
+        assert!((search_ratio(300.0) - predict_ratio(300.0)).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_host_filter() {
+        let db = Database::open_in_memory().unwrap();
+        for (cmd, cwd) in [("deploy alpha", "/srv/alpha"), ("deploy beta", "/srv/beta"), ("deploy here", "/srv/here")] {
+            db.store_command(&StoreParams { cmd: cmd.to_string(), cwd: cwd.to_string(), ..Default::default() }).unwrap();
+        }
+        // Pretend two of the places were synced from other machines
+        {
+            let conn = db.conn.lock().unwrap();
+            conn.execute("UPDATE places SET host = 'alpha' WHERE dir = '/srv/alpha'", []).unwrap();
+            conn.execute("UPDATE places SET host = 'beta' WHERE dir = '/srv/beta'", []).unwrap();
+        }
+
+        let search = |host: Option<&str>| -> Vec<String> {
+            let mut cmds: Vec<String> = db.search(&SearchParams {
+                pattern: "deploy".to_string(),
+                host: host.map(str::to_string),
+                ..Default::default()
+            }).unwrap().into_iter().map(|r| r.cmd).collect();
+            cmds.sort();
+            cmds
+        };
+        assert_eq!(search(None), ["deploy here"]);
+        assert_eq!(search(Some("alpha")), ["deploy alpha"]);
+        assert_eq!(search(Some("*")), ["deploy alpha", "deploy beta", "deploy here"]);
+        assert!(search(Some("gamma")).is_empty());
+
+        let predict = |host: Option<&str>| -> Vec<String> {
+            let mut cmds: Vec<String> = db.predict(&PredictParams {
+                prefix: "dep".to_string(),
+                cwd: "/srv".to_string(),
+                host: host.map(str::to_string),
+                ..Default::default()
+            }).unwrap().into_iter().map(|s| s.cmd).collect();
+            cmds.sort();
+            cmds
+        };
+        assert_eq!(predict(None), ["deploy here"]);
+        assert_eq!(predict(Some("beta")), ["deploy beta"]);
+        assert_eq!(predict(Some("*")), ["deploy alpha", "deploy beta", "deploy here"]);
+    }
+
+    #[test]
+    fn test_predict_global() {
+        let db = Database::open_in_memory().unwrap();
+        let now = chrono_lite_timestamp();
+        let store = |cmd: &str, cwd: &str, ago: i64| {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: cwd.to_string(),
+                start_time: Some(now - ago),
+                ..Default::default()
+            }).unwrap();
+        };
+        // The overall favourite, a while ago; then a handful of one-offs since
+        for i in 0..20 {
+            store("make all", "/work/a", 7 * 86400 + i);
+        }
+        for i in 0..6 {
+            store(&format!("make target{}", i), "/work/b", 3600 + i);
+        }
+        store("make local", "/work/c", 60);
+
+        let top = |cwd: &str, global: bool| -> Vec<String> {
+            db.predict(&PredictParams {
+                prefix: "make".to_string(),
+                cwd: cwd.to_string(),
+                limit: 1,
+                global,
+                ..Default::default()
+            }).unwrap().into_iter().map(|s| s.cmd).collect()
+        };
+
+        assert_eq!(top("/work/c", false), ["make local"]);
+        assert_eq!(top("/work/c", true), ["make all"]);
+        assert_eq!(top("/unrelated", true), ["make all"]);
+    }
+
+    #[test]
+    fn test_search_score_ordering() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Store "rare" once and "common" many times
+        db.store_command(&StoreParams {
+            cmd: "rare-cmd".to_string(),
+            cwd: "/home/user".to_string(),
+            exit_status: Some(0), duration_ms: Some(10),
+            start_time: Some(1700000000),
+            session_id: Some(1),
+            prev_cmd: None, prev2_cmd: None, prev_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        for i in 0..20 {
+            db.store_command(&StoreParams {
+                cmd: "common-cmd".to_string(),
+                cwd: "/home/user".to_string(),
+                exit_status: Some(0), duration_ms: Some(10),
+                start_time: Some(1700000000 + i),
+                session_id: Some(1),
+                prev_cmd: None, prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+
+        let results = db.search(&SearchParams {
+            pattern: "cmd".to_string(),
+            limit: 10,
+            dir: None, exit_status: None,
+            last_cmds: vec![], cwd: None, ngram_boost: false, last_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        assert!(results.len() >= 2);
+        // Higher-scored result should come first
+        assert!(results[0].score >= results[1].score,
+            "Results should be sorted by score: {} >= {}", results[0].score.unwrap(), results[1].score.unwrap());
+        // common-cmd should outscore rare-cmd (higher frequency)
+        assert_eq!(results[0].cmd, "common-cmd");
+    }
+
+    #[test]
+    fn test_search_ngram_boost() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Build bigram: "cargo build" → "cargo test"
+        for i in 0..10 {
+            db.store_command(&StoreParams {
+                cmd: "cargo build".to_string(),
+                cwd: "/home/user".to_string(),
+                exit_status: Some(0), duration_ms: Some(100),
+                start_time: Some(1700000000 + i * 10),
+                session_id: Some(1),
+                prev_cmd: None, prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+            db.store_command(&StoreParams {
+                cmd: "cargo test".to_string(),
+                cwd: "/home/user".to_string(),
+                exit_status: Some(0), duration_ms: Some(200),
+                start_time: Some(1700000005 + i * 10),
+                session_id: Some(1),
+                prev_cmd: Some("cargo build".to_string()),
+                prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+
+        // Store "cargo doc" with same frequency but no bigram link
+        for i in 0..10 {
+            db.store_command(&StoreParams {
+                cmd: "cargo doc".to_string(),
+                cwd: "/home/user".to_string(),
+                exit_status: Some(0), duration_ms: Some(100),
+                start_time: Some(1700000000 + i * 10),
+                session_id: Some(1),
+                prev_cmd: None, prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+
+        // Search with ngram context: last cmd was "cargo build"
+        let with_ngram = db.search(&SearchParams {
+            pattern: "cargo".to_string(),
+            limit: 10,
+            dir: None, exit_status: None,
+            last_cmds: vec!["cargo build".to_string()],
+            cwd: None, ngram_boost: true, last_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        let test_entry = with_ngram.iter().find(|r| r.cmd == "cargo test").unwrap();
+        let doc_entry = with_ngram.iter().find(|r| r.cmd == "cargo doc").unwrap();
+
+        // "cargo test" should outscore "cargo doc" due to bigram bonus
+        assert!(test_entry.score > doc_entry.score,
+            "N-gram boosted 'cargo test' ({:?}) should outscore 'cargo doc' ({:?})",
+            test_entry.score, doc_entry.score);
+    }
+
+    #[test]
+    fn test_search_cwd_affinity() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Same frequency and recency, run from different directories
+        for (cmd, cwd) in [("make deploy", "/srv/app"), ("make docs", "/home/user")] {
+            for i in 0..5 {
+                db.store_command(&StoreParams {
+                    cmd: cmd.to_string(),
+                    cwd: cwd.to_string(),
+                    exit_status: Some(0),
+                    start_time: Some(1700000000 + i),
+                    session_id: Some(1),
+                    ..Default::default()
+                }).unwrap();
+            }
+        }
+
+        let search = |cwd: Option<&str>| {
+            db.search(&SearchParams {
+                pattern: "make".to_string(),
+                limit: 10,
+                cwd: cwd.map(str::to_string),
+                ..Default::default()
+            }).unwrap()
+        };
+        let score = |results: &[SearchResult], cmd: &str| results.iter().find(|r| r.cmd == cmd).unwrap().score.unwrap();
+
+        let plain = search(None);
+        assert_eq!(score(&plain, "make deploy"), score(&plain, "make docs"));
+
+        let in_app = search(Some("/srv/app"));
+        assert_eq!(in_app[0].cmd, "make deploy");
+        assert!(score(&in_app, "make deploy") > score(&in_app, "make docs"));
+        assert_eq!(search(Some("/home/user"))[0].cmd, "make docs");
+    }
+
+    #[test]
+    fn test_failure_penalty() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Store a command that always succeeds
+        for i in 0..10 {
+            db.store_command(&StoreParams {
+                cmd: "good-cmd".to_string(),
+                cwd: "/home/user".to_string(),
+                exit_status: Some(0), duration_ms: Some(10),
+                start_time: Some(1700000000 + i),
+                session_id: Some(1),
+                prev_cmd: None, prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+
+        // Store a command that always fails
+        for i in 0..10 {
+            db.store_command(&StoreParams {
+                cmd: "bad-cmd".to_string(),
+                cwd: "/home/user".to_string(),
+                exit_status: Some(1), duration_ms: Some(10),
+                start_time: Some(1700000000 + i),
+                session_id: Some(1),
+                prev_cmd: None, prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+
+        let results = db.predict(&PredictParams {
+            prefix: "".to_string(),
+            cwd: "/home/user".to_string(),
+            last_cmds: vec![],
+            limit: 10,
+            frecent_boost: false,
+            weights: None,
+            last_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        let good = results.iter().find(|s| s.cmd == "good-cmd");
+        let bad = results.iter().find(|s| s.cmd == "bad-cmd");
+        assert!(good.is_some() && bad.is_some(), "Both commands should appear");
+        assert!(good.unwrap().score > bad.unwrap().score,
+            "Failing command should score lower: good={} bad={}",
+            good.unwrap().score, bad.unwrap().score);
+    }
+
+    #[test]
+    fn test_interrupted_runs_are_not_failures() {
+        let store = |db: &Database| {
+            for (cmd, exit_status) in [("tail -f app.log", 130), ("make broken", 1), ("sleep 600", 143)] {
+                for i in 0..10 {
+                    db.store_command(&StoreParams {
+                        cmd: cmd.to_string(),
+                        cwd: "/home/user".to_string(),
+                        exit_status: Some(exit_status),
+                        start_time: Some(1700000000 + i),
+                        ..Default::default()
+                    }).unwrap();
+                }
+            }
+        };
+        let failure_rates = |db: &Database| -> Vec<(String, f64)> {
+            let mut results: Vec<(String, f64)> = db
+                .search(&SearchParams { pattern: String::new(), ..Default::default() })
+                .unwrap()
+                .into_iter()
+                .map(|r| (r.cmd, r.failure_rate.unwrap()))
+                .collect();
+            results.sort_by(|a, b| a.0.cmp(&b.0));
+            results
+        };
+        let predicted = |db: &Database, prefix: &str| -> f64 {
+            db.predict(&PredictParams {
+                prefix: prefix.to_string(),
+                cwd: "/home/user".to_string(),
+                limit: 5,
+                ..Default::default()
+            }).unwrap()[0].score
+        };
+
+        // Ctrl-C by default; other signals still count
+        let db = Database::open_in_memory().unwrap();
+        store(&db);
+        assert_eq!(failure_rates(&db), [
+            ("make broken".to_string(), 1.0),
+            ("sleep 600".to_string(), 1.0),
+            ("tail -f app.log".to_string(), 0.0),
+        ]);
+        // Run just as often, but only the real failure is penalized
+        assert!(predicted(&db, "tail") > predicted(&db, "make"));
+        let kept = db.predict(&PredictParams { prefix: "ta".to_string(), exclude_failed: true, limit: 5, ..Default::default() }).unwrap();
+        assert_eq!(kept.len(), 1);
+
+        let db = Database::open_in_memory().unwrap().with_ignored_exits(IgnoredExits { codes: vec![], signals: true });
+        store(&db);
+        let rates: Vec<f64> = failure_rates(&db).into_iter().map(|(_, rate)| rate).collect();
+        assert_eq!(rates, [1.0, 0.0, 0.0]);
+
+        let db = Database::open_in_memory().unwrap().with_ignored_exits(IgnoredExits { codes: vec![], signals: false });
+        store(&db);
+        assert!(failure_rates(&db).iter().all(|(_, rate)| *rate == 1.0));
+    }
+
+    #[test]
+    fn test_unknown_exit_status_is_neutral() {
+        let db = Database::open_in_memory().unwrap();
+        // Imported history has no exit status: it counts neither as success nor failure
+        for (cmd, exit_status) in [("make broken", Some(1)), ("make broken", None), ("make broken", None), ("make old", None)] {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/home/user".to_string(),
+                exit_status,
+                ..Default::default()
+            }).unwrap();
+        }
+
+        let mut rates: Vec<(String, f64)> = db
+            .search(&SearchParams { pattern: String::new(), ..Default::default() })
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.cmd, r.failure_rate.unwrap()))
+            .collect();
+        rates.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(rates, [("make broken".to_string(), 1.0), ("make old".to_string(), 0.0)]);
+
+        let kept = db.predict(&PredictParams { prefix: "make".to_string(), exclude_failed: true, limit: 5, ..Default::default() }).unwrap();
+        let kept: Vec<&str> = kept.iter().map(|s| s.cmd.as_str()).collect();
+        assert_eq!(kept, ["make old"]);
+    }
+
+    #[test]
+    fn test_search_failure_rate() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Fails 4 out of 5 runs, with the most recent run succeeding
+        for i in 0..5 {
+            db.store_command(&StoreParams {
+                cmd: "flaky-cmd".to_string(),
+                cwd: "/home/user".to_string(),
+                exit_status: Some(if i == 4 { 0 } else { 1 }), duration_ms: Some(10),
+                start_time: Some(1700000000 + i),
+                session_id: Some(1),
+                prev_cmd: None, prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+
+        db.store_command(&StoreParams {
+            cmd: "solid-cmd".to_string(),
+            cwd: "/home/user".to_string(),
+            exit_status: Some(0), duration_ms: Some(10),
+            start_time: Some(1700000000),
+            session_id: Some(1),
+            prev_cmd: None, prev2_cmd: None, prev_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        let results = db.search(&SearchParams {
+            pattern: "-cmd".to_string(),
+            limit: 10,
+            dir: None, exit_status: None,
+            last_cmds: vec![], cwd: None, ngram_boost: false, last_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        let flaky = results.iter().find(|r| r.cmd == "flaky-cmd").unwrap();
+        assert!((flaky.failure_rate.unwrap() - 0.8).abs() < 1e-9);
+        // exit_status reflects the latest run, not an arbitrary one
+        assert_eq!(flaky.exit_status, Some(0));
+
+        let solid = results.iter().find(|r| r.cmd == "solid-cmd").unwrap();
+        assert_eq!(solid.failure_rate, Some(0.0));
+    }
+
+    #[test]
+    fn test_search_reports_first_used() {
+        let db = Database::open_in_memory().unwrap();
+        for start_time in [1700000500, 1700000000, 1700000900] {
+            db.store_command(&StoreParams {
+                cmd: "cargo build".to_string(),
+                cwd: "/src".to_string(),
+                start_time: Some(start_time),
+                ..Default::default()
+            }).unwrap();
+        }
+
+        let results = db.search(&SearchParams { pattern: "cargo".to_string(), ..Default::default() }).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].first_used, Some(1700000000));
+        assert_eq!(results[0].timestamp, 1700000900);
+    }
+
+    #[test]
+    fn test_duration_ms_round_trips_exactly() {
+        let db = Database::open_in_memory().unwrap();
+        let id = db.store_command(&StoreParams {
+            cmd: "true".to_string(),
+            cwd: "/tmp".to_string(),
+            duration_ms: Some(1),
+            ..Default::default()
+        }).unwrap().id().unwrap();
+
+        assert_eq!(db.stored_command(id).unwrap().unwrap().duration_ms, Some(1));
+        let results = db.search(&SearchParams { pattern: "true".to_string(), ..Default::default() }).unwrap();
+        assert_eq!(results[0].duration_ms, Some(1));
+    }
+
+    #[test]
+    fn test_search_tty_filter() {
+        let db = Database::open_in_memory().unwrap();
+        for (i, (cmd, tty)) in [("make build", "/dev/pts/1"), ("make test", "/dev/pts/2"), ("make build", "/dev/pts/2")].iter().enumerate() {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/home/user/project".to_string(),
+                start_time: Some(1700000000 + i as i64),
+                tty: Some(tty.to_string()),
+                ..Default::default()
+            }).unwrap();
+        }
+
+        let search = |tty: Option<&str>| {
+            let mut cmds: Vec<String> = db.search(&SearchParams {
+                pattern: "make".to_string(),
+                tty: tty.map(String::from),
+                ..Default::default()
+            }).unwrap().into_iter().map(|r| r.cmd).collect();
+            cmds.sort();
+            cmds
+        };
+
+        assert_eq!(search(Some("/dev/pts/1")), ["make build"]);
+        assert_eq!(search(Some("/dev/pts/2")), ["make build", "make test"]);
+        assert_eq!(search(None), ["make build", "make test"]);
+        assert!(search(Some("/dev/pts/9")).is_empty());
+    }
+
+    #[test]
+    fn test_filter_by_project_type() {
+        let db = Database::open_in_memory().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let rust = dir.path().join("crate");
+        let node = dir.path().join("site");
+        std::fs::create_dir_all(rust.join("src")).unwrap();
+        std::fs::create_dir_all(&node).unwrap();
+        std::fs::write(rust.join("Cargo.toml"), "[package]\n").unwrap();
+        std::fs::write(node.join("package.json"), "{}").unwrap();
+
+        let runs = [(&rust.join("src"), "make check"), (&node, "make serve"), (&rust, "make all"), (&node, "make all")];
+        for (i, (cwd, cmd)) in runs.iter().enumerate() {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: cwd.to_string_lossy().to_string(),
+                start_time: Some(1700000000 + i as i64),
+                ..Default::default()
+            }).unwrap();
+        }
+
+        let search = |in_project: Option<&str>| {
+            let mut cmds: Vec<String> = db.search(&SearchParams {
+                pattern: "make".to_string(),
+                in_project: in_project.map(String::from),
+                ..Default::default()
+            }).unwrap().into_iter().map(|r| r.cmd).collect();
+            cmds.sort();
+            cmds
+        };
+        assert_eq!(search(Some("rust")), ["make all", "make check"]);
+        assert_eq!(search(Some("node")), ["make all", "make serve"]);
+        assert_eq!(search(None).len(), 3);
+        assert!(search(Some("go")).is_empty());
+
+        // Predictions from an unrelated directory keep to the requested ecosystem
+        let mut predicted: Vec<String> = db.predict(&PredictParams {
+            prefix: "make".to_string(),
+            cwd: "/tmp".to_string(),
+            in_project: Some("rust".to_string()),
+            ..Default::default()
+        }).unwrap().into_iter().map(|s| s.cmd).collect();
+        predicted.sort();
+        assert_eq!(predicted, ["make all", "make check"]);
+    }
+
+    #[test]
+    fn test_stored_context_records_git_remote() {
+        let db = Database::open_in_memory().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("nicehist");
+        std::fs::create_dir_all(repo.join(".git")).unwrap();
+        std::fs::write(repo.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        std::fs::write(
+            repo.join(".git/config"),
+            "[remote \"origin\"]\n\turl = git@github.com:akatrevorjay/nicehist.git\n",
+        ).unwrap();
+
+        db.store_command(&StoreParams {
+            cmd: "cargo test".to_string(),
+            cwd: repo.to_string_lossy().to_string(),
+            ..Default::default()
+        }).unwrap();
+
+        let (vcs_type, remote): (String, Option<String>) = db.conn.lock().unwrap().query_row(
+            "SELECT x.vcs_type, x.vcs_remote FROM history h JOIN contexts x ON x.id = h.context_id",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).unwrap();
+        assert_eq!(vcs_type, "git");
+        assert_eq!(remote.as_deref(), Some("git@github.com:akatrevorjay/nicehist.git"));
+    }
+
+    #[test]
+    fn test_stats_shell_breakdown() {
+        let db = Database::open_in_memory().unwrap();
+
+        for (i, shell) in ["zsh", "zsh", "zsh", "bash", "bash"].iter().enumerate() {
+            db.store_command(&StoreParams {
+                cmd: format!("cmd-{}", i),
+                cwd: "/home/user".to_string(),
+                start_time: Some(1700000000 + i as i64),
+                shell: Some(shell.to_string()),
+                terminal: Some("tmux".to_string()),
+                ..Default::default()
+            }).unwrap();
+        }
+        // Legacy row with no shell recorded
+        db.store_command(&StoreParams {
+            cmd: "old-cmd".to_string(),
+            cwd: "/home/user".to_string(),
+            ..Default::default()
+        }).unwrap();
+
+        let stored: (String, String) = db.conn.lock().unwrap().query_row(
+            "SELECT shell, terminal FROM history ORDER BY id LIMIT 1", [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).unwrap();
+        assert_eq!(stored, ("zsh".to_string(), "tmux".to_string()));
+
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.history, 6);
+        assert_eq!(stats.shells.len(), 2);
+        assert_eq!(stats.shells[0].shell, "zsh");
+        assert_eq!(stats.shells[0].count, 3);
+        assert!((stats.shells[0].fraction - 0.6).abs() < 1e-9);
+        assert_eq!(stats.shells[1].shell, "bash");
+        assert!((stats.shells[1].fraction - 0.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_stats_counts() {
+        let db = Database::open_in_memory().unwrap();
+
+        let session = [("git add .", "/repo"), ("git commit", "/repo"), ("git push", "/repo"), ("ls", "/tmp")];
+        for (i, (cmd, cwd)) in session.iter().enumerate() {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: cwd.to_string(),
+                prev_cmd: i.checked_sub(1).map(|j| session[j].0.to_string()),
+                prev2_cmd: i.checked_sub(2).map(|j| session[j].0.to_string()),
+                ..Default::default()
+            }).unwrap();
+        }
+        db.store_command(&StoreParams {
+            cmd: "ls".to_string(),
+            cwd: "/tmp".to_string(),
+            ..Default::default()
+        }).unwrap();
+        db.frecent_add(&crate::protocol::FrecentAddParams {
+            path: "/repo/notes.txt".to_string(),
+            path_type: "f".to_string(),
+            rank: None,
+            timestamp: None,
+        }).unwrap();
+
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.history, 5);
+        assert_eq!(stats.commands, 4);
+        assert_eq!(stats.places, 2);
+        assert_eq!(stats.bigrams, 3);
+        assert_eq!(stats.trigrams, 2);
+        assert_eq!(stats.frecent_dirs, 2);
+        assert_eq!(stats.frecent_files, 1);
+        assert!(stats.db_size_bytes > 0);
+    }
+
+    #[test]
+    fn test_maintenance_shrinks_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::open(&dir.path().join("history.db")).unwrap();
+
+        let batch: Vec<StoreParams> = (0..500)
+            .map(|i| StoreParams {
+                cmd: format!("echo {} {}", i, "x".repeat(200)),
+                cwd: "/home/user".to_string(),
+                ..Default::default()
+            })
+            .collect();
+        db.store_batch(&batch).unwrap();
+        for params in &batch[1..] {
+            db.delete_command(&params.cmd).unwrap();
+        }
+
+        let result = db.maintenance().unwrap();
+        assert!(result.size_after < result.size_before, "{:?}", result);
+        assert_eq!(result.size_after, std::fs::metadata(dir.path().join("history.db")).unwrap().len());
+        assert_eq!(db.stats().unwrap().history, 1);
+
+        // Also fine on an in-memory database
+        Database::open_in_memory().unwrap().maintenance().unwrap();
+    }
+
+    #[test]
+    fn test_open_recreates_dropped_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.db");
+        let db = Database::open(&path).unwrap();
+        db.store_command(&StoreParams {
+            cmd: "make test".to_string(),
+            cwd: "/project".to_string(),
+            ..Default::default()
+        }).unwrap();
+        drop(db);
+
+        Connection::open(&path).unwrap().execute_batch("DROP TABLE frecent_paths").unwrap();
+
+        let db = Database::open(&path).unwrap();
+        db.frecent_add(&crate::protocol::FrecentAddParams {
+            path: "/project".to_string(),
+            path_type: "d".to_string(),
+            rank: None,
+            timestamp: None,
+        }).unwrap();
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.frecent_dirs, 1);
+        assert_eq!(stats.history, 1);
+        assert_eq!(stats.commands, 1);
+    }
+
+    #[test]
+    fn test_reset_empties_data_tables_and_keeps_schema() {
+        let db = Database::open_in_memory().unwrap();
+        let cmds = ["git status", "git commit -m wip", "git push"];
+        for (i, cmd) in cmds.iter().enumerate() {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/repo".to_string(),
+                session_id: Some(1),
+                prev_cmd: i.checked_sub(1).map(|j| cmds[j].to_string()),
+                ..Default::default()
+            }).unwrap();
+        }
+        db.frecent_add(&crate::protocol::FrecentAddParams {
+            path: "/repo".to_string(),
+            path_type: "d".to_string(),
+            rank: None,
+            timestamp: None,
+        }).unwrap();
+        let schema_version = |db: &Database| -> i64 {
+            db.conn.lock().unwrap().query_row("SELECT MAX(version) FROM schema_version", [], |row| row.get(0)).unwrap()
+        };
+        let version = schema_version(&db);
+
+        let counted = db.reset(true).unwrap();
+        assert_eq!(counted.tables["history"], 3);
+        assert_eq!(counted.tables["frecent_paths"], 1);
+        assert_eq!(db.stats().unwrap().history, 3, "a dry run deletes nothing");
+
+        let deleted = db.reset(false).unwrap();
+        assert_eq!(deleted.rows, counted.rows);
+        assert_eq!(schema_version(&db), version);
+
+        let conn = db.conn.lock().unwrap();
+        for table in DATA_TABLES {
+            let rows: i64 = conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0)).unwrap();
+            assert_eq!(rows, 0, "{} not emptied", table);
+        }
+        let fts: i64 = conn.query_row("SELECT COUNT(*) FROM commands_fts WHERE commands_fts MATCH 'git'", [], |row| row.get(0)).unwrap();
+        assert_eq!(fts, 0);
+    }
+
+    #[test]
+    fn test_prune_history_removes_old_rows_and_orphaned_commands() {
+        let db = Database::open_in_memory().unwrap();
+        let (old, recent) = (1_600_000_000, 1_700_000_000);
+
+        // "make" is only old; "git status" is old and recent; "ls" is only recent
+        let rows = [("make", old), ("git status", old), ("make", old + 1), ("git status", recent), ("ls", recent + 1)];
+        for (i, (cmd, start_time)) in rows.iter().enumerate() {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/repo".to_string(),
+                start_time: Some(*start_time),
+                prev_cmd: i.checked_sub(1).map(|j| rows[j].0.to_string()),
+                prev2_cmd: i.checked_sub(2).map(|j| rows[j].0.to_string()),
+                prev3_cmd: i.checked_sub(3).map(|j| rows[j].0.to_string()),
+                ..Default::default()
+            }).unwrap();
+        }
+
+        let result = db.prune_history(recent).unwrap();
+        assert_eq!(result.history, 3);
+        assert_eq!(result.commands, 1);
+
+        let conn = db.conn.lock().unwrap();
+        let commands: Vec<String> = conn
+            .prepare("SELECT argv FROM commands ORDER BY argv").unwrap()
+            .query_map([], |row| row.get(0)).unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(commands, vec!["git status", "ls"]);
+        let count = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(count("SELECT COUNT(*) FROM history"), 2);
+        // Only git status -> ls survives; every other pair or triple involved make
+        assert_eq!(count("SELECT COUNT(*) FROM ngrams_2"), 1);
+        assert_eq!(count("SELECT COUNT(*) FROM ngrams_3"), 0);
+        assert_eq!(count("SELECT COUNT(*) FROM ngrams_4"), 0);
+        assert_eq!(count("SELECT COUNT(*) FROM parsed_commands WHERE command_id NOT IN (SELECT id FROM commands)"), 0);
+        assert_eq!(count("SELECT COUNT(*) FROM commands_fts WHERE commands_fts MATCH 'make'"), 0);
+        drop(conn);
+
+        // Nothing left to prune
+        let result = db.prune_history(recent).unwrap();
+        assert_eq!((result.history, result.commands), (0, 0));
+    }
+
+    #[test]
+    fn test_prune_history_keeps_segments_of_kept_lines() {
+        let db = Database::open_in_memory().unwrap();
+        let (old, recent) = (1_600_000_000, 1_700_000_000);
+        let store = |cmd: &str, start_time: i64| {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/project".to_string(),
+                start_time: Some(start_time),
+                split_pipelines: true,
+                ..Default::default()
+            }).unwrap();
+        };
+        store("make build && make test", recent);
+        store("cargo fmt; cargo clippy", old);
+
+        // Segments aren't counted as commands of their own
+        assert_eq!(db.stats().unwrap().commands, 2);
+
+        let result = db.prune_history(recent).unwrap();
+        assert_eq!(result.history, 1);
+        assert_eq!(result.commands, 3);
+
+        let conn = db.conn.lock().unwrap();
+        let commands: Vec<String> = conn
+            .prepare("SELECT argv FROM commands ORDER BY argv").unwrap()
+            .query_map([], |row| row.get(0)).unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(commands, vec!["make build", "make build && make test", "make test"]);
+        let count = |sql: &str| -> i64 { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+        // The kept line's sequence is still learned; the pruned one's is gone
+        assert_eq!(count("SELECT COUNT(*) FROM ngrams_2"), 1);
+        assert_eq!(count("SELECT COUNT(*) FROM command_segments"), 2);
+    }
+
+    #[test]
+    fn test_store_batch() {
+        let db = Database::open_in_memory().unwrap();
+
+        let batch: Vec<StoreParams> = ["ls", "pwd", "ls"]
+            .iter()
+            .map(|cmd| StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/home/user".to_string(),
+                ..Default::default()
+            })
+            .collect();
+        assert_eq!(db.store_batch(&batch).unwrap(), 3);
+
+        let conn = db.conn.lock().unwrap();
+        let history: i64 = conn.query_row("SELECT COUNT(*) FROM history", [], |row| row.get(0)).unwrap();
+        let commands: i64 = conn.query_row("SELECT COUNT(*) FROM commands", [], |row| row.get(0)).unwrap();
+        assert_eq!(history, 3);
+        assert_eq!(commands, 2);
+    }
+
+    #[test]
+    fn test_failed_store_batch_leaves_hot_index_alone() {
+        let db = Database::open_in_memory().unwrap();
+        let params = |cmd: &str| StoreParams {
+            cmd: cmd.to_string(),
+            cwd: "/repo".to_string(),
+            ..Default::default()
+        };
+        db.store_command(&params("git status")).unwrap();
+        // Build the hot index
+        db.predict(&PredictParams { prefix: "git".to_string(), cwd: "/repo".to_string(), ..Default::default() }).unwrap();
+
+        db.conn.lock().unwrap().execute_batch(
+            "CREATE TEMP TRIGGER fail_store BEFORE INSERT ON history
+             WHEN (SELECT argv FROM commands WHERE id = NEW.command_id) = 'boom'
+             BEGIN SELECT RAISE(ABORT, 'boom'); END;",
+        ).unwrap();
+        assert!(db.store_batch(&[params("git status"), params("boom")]).is_err());
+
+        // The rolled back run of git status was never counted
+        let history: i64 = db.conn.lock().unwrap().query_row("SELECT COUNT(*) FROM history", [], |row| row.get(0)).unwrap();
+        assert_eq!(history, 1);
+        let hits = db.hot.lock().unwrap().candidates("git", "/repo", &[], None, false, 10).unwrap();
+        assert_eq!(hits[0].freq, 1);
+    }
+
+    #[test]
+    fn test_diversity_spreads_programs() {
+        let db = Database::open_in_memory().unwrap();
+        let now = chrono_lite_timestamp();
+
+        let store = |cmd: &str, times: usize| {
+            for i in 0..times {
+                db.store_command(&StoreParams {
+                    cmd: cmd.to_string(),
+                    cwd: "/project".to_string(),
+                    start_time: Some(now - i as i64),
+                    ..Default::default()
+                }).unwrap();
+            }
+        };
+        store("docker compose up", 20);
+        store("docker compose down", 18);
+        store("docker compose logs", 16);
+        store("dig example.com", 5);
+
+        let predict = |diversity: f64| {
+            let params = PredictParams {
+                prefix: "d".to_string(),
+                cwd: "/project".to_string(),
+                limit: 2,
+                last_cmds: vec![],
+                frecent_boost: false,
+                last_exit: None,
+                weights: Some(crate::protocol::RankingWeights { diversity, ..Default::default() }),
+                ..Default::default()
+            };
+            db.predict(&params).unwrap()
+        };
+        let programs = |suggestions: &[Suggestion]| -> HashSet<String> {
+            suggestions.iter().map(|s| s.cmd.split_whitespace().next().unwrap().to_string()).collect()
+        };
+
+        let plain = predict(0.0);
+        assert_eq!(programs(&plain).len(), 1);
+
+        let diverse = predict(1.0);
+        assert_eq!(diverse[0].cmd, "docker compose up");
+        assert_eq!(programs(&diverse).len(), 2);
+    }
+
+    #[test]
+    fn test_token_similarity() {
+        assert_eq!(token_similarity("git status", "git status"), 1.0);
+        assert_eq!(token_similarity("git status", "ls -la"), 0.0);
+        assert_eq!(token_similarity("git push", "git pull"), 1.0 / 3.0);
+        assert_eq!(token_similarity("", ""), 0.0);
+    }
+
+    #[test]
+    fn test_predict_mid_line_keeps_trailing_text() {
+        let db = Database::open_in_memory().unwrap();
+
+        for cmd in ["git checkout -b feature", "git checkout main", "git cherry-pick abc123"] {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/project".to_string(),
+                ..Default::default()
+            }).unwrap();
+        }
+
+        // Editing "git ch| origin/main" with the cursor after "ch"
+        let suggestions = db.predict(&PredictParams {
+            prefix: "git ch".to_string(),
+            cwd: "/project".to_string(),
+            buffer: Some("git ch origin/main".to_string()),
+            cursor_pos: Some(6),
+            frecent_boost: false,
+            ..Default::default()
+        }).unwrap();
+
+        let cmds: Vec<&str> = suggestions.iter().map(|s| s.cmd.as_str()).collect();
+        assert_eq!(cmds.len(), 2);
+        assert!(cmds.contains(&"git checkout origin/main"));
+        assert!(cmds.contains(&"git cherry-pick origin/main"));
+    }
+
+    #[test]
+    fn test_splice_at_cursor() {
+        let suggestions = vec![
+            Suggestion { cmd: "git checkout -b feature".to_string(), score: 0.9 },
+            Suggestion { cmd: "git checkout main".to_string(), score: 0.8 },
+            Suggestion { cmd: "git checkout".to_string(), score: 0.7 },
+        ];
+
+        // Cursor inside "che|ckout": the rest of the token is replaced, the rest of the line kept
+        let spliced = splice_at_cursor(suggestions.clone(), "git che", "ckout main");
+        assert!(spliced.is_empty(), "completing to the existing token is a no-op");
+
+        let spliced = splice_at_cursor(suggestions, "git che", " main");
+        assert_eq!(spliced.len(), 1);
+        assert_eq!(spliced[0].cmd, "git checkout main");
+        assert_eq!(spliced[0].score, 0.9);
+    }
+
+    #[test]
+    fn test_frecent_add_batch_preserves_ranks() {
+        let db = Database::open_in_memory().unwrap();
+
+        // A large fasd file whose ranks total far more than the aging threshold
+        let entries: Vec<FrecentAddParams> = (1..=500)
+            .map(|i| FrecentAddParams {
+                path: format!("/data/dir{:03}", i),
+                path_type: "d".to_string(),
+                rank: Some(i as f64),
+                timestamp: Some(1700000000 + i),
+            })
+            .collect();
+        assert_eq!(db.frecent_add_batch(&entries).unwrap(), 500);
+
+        let exported = db.frecent_export().unwrap();
+        assert_eq!(exported.len(), 500);
+        for (r, expected) in exported.iter().zip((1..=500).rev()) {
+            assert_eq!(r.path, format!("/data/dir{:03}", expected));
+            assert_eq!(r.rank, Some(expected as f64));
+        }
+
+        // Ordinary bumps in a batch still age, but only once at the end
+        let bumps: Vec<FrecentAddParams> = (0..3)
+            .map(|_| FrecentAddParams {
+                path: "/data/dir001".to_string(),
+                path_type: "d".to_string(),
+                rank: None,
+                timestamp: None,
+            })
+            .collect();
+        db.frecent_add_batch(&bumps).unwrap();
+
+        let top = &db.frecent_export().unwrap()[0];
+        assert_eq!(top.path, "/data/dir500");
+        assert!((top.rank.unwrap() - 450.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_ngrams_not_linked_across_sessions() {
+        let bigram_count = |db: &Database, prev: &str, next: &str| -> i64 {
+            db.conn.lock().unwrap().query_row(
+                "SELECT COALESCE(SUM(b.frequency), 0) FROM ngrams_2 b
+                 JOIN commands p ON p.id = b.prev_command_id
+                 JOIN commands n ON n.id = b.command_id
+                 WHERE p.argv = ?1 AND n.argv = ?2",
+                [prev, next],
+                |row| row.get(0),
+            ).unwrap()
+        };
+
+        for cross_session_ngrams in [false, true] {
+            let db = Database::open_in_memory().unwrap();
+
+            // Session A ends with "exit-a"
+            db.store_command(&StoreParams {
+                cmd: "make build".to_string(),
+                cwd: "/project".to_string(),
+                session_id: Some(1),
+                ..Default::default()
+            }).unwrap();
+            db.store_command(&StoreParams {
+                cmd: "exit-a".to_string(),
+                cwd: "/project".to_string(),
+                session_id: Some(1),
+                prev_cmd: Some("make build".to_string()),
+                cross_session_ngrams,
+                ..Default::default()
+            }).unwrap();
+
+            // Session B starts, and the client still remembers session A's last command
+            db.store_command(&StoreParams {
+                cmd: "start-b".to_string(),
+                cwd: "/project".to_string(),
+                session_id: Some(2),
+                prev_cmd: Some("exit-a".to_string()),
+                cross_session_ngrams,
+                ..Default::default()
+            }).unwrap();
+
+            assert_eq!(bigram_count(&db, "make build", "exit-a"), 1);
+            let expected = if cross_session_ngrams { 1 } else { 0 };
+            assert_eq!(bigram_count(&db, "exit-a", "start-b"), expected);
+        }
+    }
+
+    #[test]
+    fn test_ngrams_only_link_the_sessions_own_commands() {
+        let db = Database::open_in_memory().unwrap();
+        let store = |cmd: &str, session_id: i64, prev: [Option<&str>; 2]| {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/project".to_string(),
+                session_id: Some(session_id),
+                prev_cmd: prev[0].map(str::to_string),
+                prev2_cmd: prev[1].map(str::to_string),
+                ..Default::default()
+            }).unwrap();
+        };
+        let count = |sql: &str| -> i64 {
+            db.conn.lock().unwrap().query_row(sql, [], |row| row.get(0)).unwrap()
+        };
+
+        // Two terminals interleave; session 2's command is handed to session 1 as prev_cmd
+        store("make build", 1, [None, None]);
+        store("tail -f log", 2, [None, None]);
+        store("make test", 1, [Some("tail -f log"), Some("make build")]);
+        assert_eq!(count("SELECT COUNT(*) FROM ngrams_2"), 0);
+        assert_eq!(count("SELECT COUNT(*) FROM ngrams_3"), 0);
+
+        // The session's real predecessor links, but an older command from elsewhere doesn't
+        store("make install", 1, [Some("make test"), Some("tail -f log")]);
+        assert_eq!(count(
+            "SELECT COUNT(*) FROM ngrams_2 n
+             JOIN commands p ON p.id = n.prev_command_id
+             JOIN commands c ON c.id = n.command_id
+             WHERE p.argv = 'make test' AND c.argv = 'make install'"
+        ), 1);
+        assert_eq!(count("SELECT COUNT(*) FROM ngrams_2"), 1);
+        assert_eq!(count("SELECT COUNT(*) FROM ngrams_3"), 0);
+    }
+
+    #[test]
+    fn test_ngrams_link_reordered_and_skipped_stores() {
+        let db = Database::open_in_memory().unwrap();
+        let store = |cmd: &str, prev: [Option<&str>; 3]| {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/project".to_string(),
+                session_id: Some(1),
+                prev_cmd: prev[0].map(str::to_string),
+                prev2_cmd: prev[1].map(str::to_string),
+                prev3_cmd: prev[2].map(str::to_string),
+                ..Default::default()
+            }).unwrap();
+        };
+        let ngram = |table: &str| -> Vec<String> {
+            let conn = db.conn.lock().unwrap();
+            let (prev, sql) = match table {
+                "ngrams_2" => ("p.argv", "JOIN commands p ON p.id = n.prev_command_id"),
+                _ => ("p2.argv || ' > ' || p1.argv",
+                      "JOIN commands p1 ON p1.id = n.prev1_command_id JOIN commands p2 ON p2.id = n.prev2_command_id"),
+            };
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} || ' > ' || c.argv FROM {} n {} JOIN commands c ON c.id = n.command_id ORDER BY 1",
+                prev, table, sql,
+            )).unwrap();
+            stmt.query_map([], |row| row.get(0)).unwrap().map(|r| r.unwrap()).collect()
+        };
+
+        store("make build", [None, None, None]);
+        // "make test" is stored after the command that followed it
+        store("make install", [Some("make test"), Some("make build"), None]);
+        store("make test", [Some("make build"), None, None]);
+        // "cd secret" was never stored; "make build" ran several commands ago
+        store("make clean", [Some("cd secret"), Some("make install"), Some("make build")]);
+
+        assert_eq!(ngram("ngrams_2"), [
+            "cd secret > make clean",
+            "make build > make test",
+            "make test > make install",
+        ]);
+        assert_eq!(ngram("ngrams_3"), [
+            "make build > make test > make install",
+            "make install > cd secret > make clean",
+        ]);
+    }
+
+    #[test]
+    fn test_dir_stats() {
+        let db = Database::open_in_memory().unwrap();
+
+        let runs = [("make test", 3), ("git status", 5), ("cargo build", 1), ("ls", 2)];
+        for (cmd, times) in runs {
+            for _ in 0..times {
+                db.store_command(&StoreParams {
+                    cmd: cmd.to_string(),
+                    cwd: "/project".to_string(),
+                    ..Default::default()
+                }).unwrap();
+            }
+        }
+        // Other directories don't count
+        db.store_command(&StoreParams {
+            cmd: "git status".to_string(),
+            cwd: "/project/src".to_string(),
+            ..Default::default()
+        }).unwrap();
+
+        let (total, top) = db.dir_stats("/project", 3).unwrap();
+        assert_eq!(total, 11);
+        let top: Vec<(&str, i64)> = top.iter().map(|c| (c.cmd.as_str(), c.count)).collect();
+        assert_eq!(top, vec![("git status", 5), ("make test", 3), ("ls", 2)]);
+
+        let (total, top) = db.dir_stats("/empty", 3).unwrap();
+        assert_eq!(total, 0);
+        assert!(top.is_empty());
+    }
+
+    #[test]
+    fn test_predict_project_root_boost() {
+        let db = Database::open_in_memory().unwrap();
+        let now = chrono_lite_timestamp();
+
+        // Same frequency and age: one run in a sibling dir of the repo, one elsewhere
+        for (cmd, cwd) in [("cargo test", "/repo/crates/core"), ("cargo fmt", "/elsewhere")] {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: cwd.to_string(),
+                start_time: Some(now - 60),
+                ..Default::default()
+            }).unwrap();
+        }
+        // Shares the "/repo" prefix but is a different directory
+        db.store_command(&StoreParams {
+            cmd: "cargo bench".to_string(),
+            cwd: "/repository".to_string(),
+            start_time: Some(now - 60),
+            ..Default::default()
+        }).unwrap();
+
+        let predict = |project_root: Option<&str>| {
+            db.predict(&PredictParams {
+                prefix: "cargo".to_string(),
+                cwd: "/repo/crates/cli".to_string(),
+                frecent_boost: false,
+                project_root: project_root.map(String::from),
+                ..Default::default()
+            }).unwrap()
+        };
+        let score = |suggestions: &[Suggestion], cmd: &str| {
+            suggestions.iter().find(|s| s.cmd == cmd).unwrap().score
+        };
+
+        let with_root = predict(Some("/repo"));
+        assert_eq!(with_root[0].cmd, "cargo test");
+        let boost = score(&with_root, "cargo test") - score(&with_root, "cargo fmt");
+        assert!((boost - crate::protocol::RankingWeights::default().dir_project).abs() < 1e-9);
+        assert_eq!(score(&with_root, "cargo bench"), score(&with_root, "cargo fmt"));
+
+        let without_root = predict(None);
+        assert_eq!(score(&without_root, "cargo test"), score(&without_root, "cargo fmt"));
+    }
+
+    #[test]
+    fn test_candidate_factor_widens_rerank_window() {
+        let db = Database::open_in_memory().unwrap();
+        let now = chrono_lite_timestamp();
+
+        // Plenty of commands in the current directory fill the SQL candidate window
+        for i in 0..10 {
+            db.store_command(&StoreParams {
+                cmd: format!("make target{}", i),
+                cwd: "/project".to_string(),
+                start_time: Some(now - 60),
+                ..Default::default()
+            }).unwrap();
+        }
+        // "make deploy" always follows "make build", but was run elsewhere
+        db.store_command(&StoreParams {
+            cmd: "make build".to_string(),
+            cwd: "/other".to_string(),
+            start_time: Some(now - 120),
+            ..Default::default()
+        }).unwrap();
+        db.store_command(&StoreParams {
+            cmd: "make deploy".to_string(),
+            cwd: "/other".to_string(),
+            start_time: Some(now - 60),
+            prev_cmd: Some("make build".to_string()),
+            ..Default::default()
+        }).unwrap();
+
+        let predict = |candidate_factor: usize| {
+            db.predict(&PredictParams {
+                prefix: "make".to_string(),
+                cwd: "/project".to_string(),
+                last_cmds: vec!["make build".to_string()],
+                limit: 1,
+                frecent_boost: false,
+                weights: Some(crate::protocol::RankingWeights {
+                    ngram: 1.0,
+                    candidate_factor,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }).unwrap()
+        };
+
+        assert_ne!(predict(5)[0].cmd, "make deploy");
+        assert_eq!(predict(20)[0].cmd, "make deploy");
+    }
+
+    #[test]
+    fn test_places() {
+        let db = Database::open_in_memory().unwrap();
+
+        for (cwd, times, start) in [("/busy", 5, 1700000000), ("/recent", 2, 1700009000), ("/quiet", 1, 1700000500)] {
+            for i in 0..times {
+                db.store_command(&StoreParams {
+                    cmd: format!("cmd{}", i),
+                    cwd: cwd.to_string(),
+                    start_time: Some(start + i as i64),
+                    ..Default::default()
+                }).unwrap();
+            }
+        }
+
+        let by_count = db.places(&PlacesParams { limit: 10, sort: PlaceSort::Count }).unwrap();
+        let rows: Vec<(&str, i64)> = by_count.iter().map(|p| (p.dir.as_str(), p.count)).collect();
+        assert_eq!(rows, vec![("/busy", 5), ("/recent", 2), ("/quiet", 1)]);
+        assert_eq!(by_count[0].last_used, 1700000004);
+
+        let by_recency = db.places(&PlacesParams { limit: 2, sort: PlaceSort::Recent }).unwrap();
+        let dirs: Vec<&str> = by_recency.iter().map(|p| p.dir.as_str()).collect();
+        assert_eq!(dirs, vec!["/recent", "/quiet"]);
+    }
+
+    #[test]
+    fn test_predict_since_last() {
+        let db = Database::open_in_memory().unwrap();
+        let now = chrono_lite_timestamp();
+
+        // Both are regulars here; the linter just hasn't been run for a couple of days
+        for i in 0..20 {
+            db.store_command(&StoreParams {
+                cmd: "make lint".to_string(),
+                cwd: "/project".to_string(),
+                start_time: Some(now - 2 * 86400 - i * 3600),
+                ..Default::default()
+            }).unwrap();
+            db.store_command(&StoreParams {
+                cmd: "make test".to_string(),
+                cwd: "/project".to_string(),
+                start_time: Some(now - 60 - i * 3600),
+                ..Default::default()
+            }).unwrap();
+        }
+
+        let predict = |since_last: bool| {
+            db.predict(&PredictParams {
+                prefix: "make".to_string(),
+                cwd: "/project".to_string(),
+                frecent_boost: false,
+                since_last,
+                ..Default::default()
+            }).unwrap()
+        };
+
+        assert_eq!(predict(false)[0].cmd, "make test");
+        assert_eq!(predict(true)[0].cmd, "make lint");
+    }
+
+    #[test]
+    fn test_predict_exclude_failed() {
+        let db = Database::open_in_memory().unwrap();
+        for (cmd, exit_status) in [("git pshu", 1), ("git pshu", 128), ("git push", 1), ("git push", 0)] {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/repo".to_string(),
+                exit_status: Some(exit_status),
+                ..Default::default()
+            }).unwrap();
+        }
+
+        for disabled in [false, true] {
+            db.hot.lock().unwrap().set_disabled(disabled);
+            let predict = |exclude_failed: bool| {
+                db.predict(&PredictParams {
+                    prefix: "git".to_string(),
+                    cwd: "/repo".to_string(),
+                    exclude_failed,
+                    ..Default::default()
+                }).unwrap().into_iter().map(|s| s.cmd).collect::<Vec<_>>()
+            };
+
+            assert!(predict(false).contains(&"git pshu".to_string()));
+            // Sometimes failing is fine; never succeeding is not
+            assert_eq!(predict(true), vec!["git push"]);
+        }
+    }
+
+    #[test]
+    fn test_predict_substring_mode() {
+        use crate::protocol::MatchMode;
+
+        let db = Database::open_in_memory().unwrap();
+        let store = |cmd: &str| {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/project".to_string(),
+                ..Default::default()
+            }).unwrap();
+        };
+        let predict = |prefix: &str, match_mode: MatchMode| {
+            db.predict(&PredictParams {
+                prefix: prefix.to_string(),
+                cwd: "/project".to_string(),
+                match_mode,
+                ..Default::default()
+            }).unwrap().into_iter().map(|s| s.cmd).collect::<Vec<_>>()
+        };
+
+        store("cargo build");
+        store("cargo build");
+        assert!(predict("buil", MatchMode::Prefix).is_empty());
+        assert_eq!(predict("buil", MatchMode::Substring), vec!["cargo build"]);
+
+        // A prefix hit ranks above a more frequent mid-string hit
+        store("build.sh --all");
+        assert_eq!(predict("buil", MatchMode::Substring), vec!["build.sh --all", "cargo build"]);
+    }
+
+    #[test]
+    fn test_hot_index_matches_sql_path() {
+        let db = Database::open_in_memory().unwrap();
+        let now = chrono_lite_timestamp();
+
+        let runs = [
+            ("git status", "/repo", Some(0)),
+            ("git status", "/repo/src", Some(0)),
+            ("git stash", "/repo", Some(1)),
+            ("git commit -m wip", "/repo/src", Some(0)),
+            ("Git log", "/other", None),
+            ("cargo test", "/repo", Some(101)),
+            ("cargo build", "/repo/src/bin", Some(0)),
+            ("vim foo.py", "/repo/src", Some(0)),
+        ];
+        for (i, (cmd, cwd, exit_status)) in runs.iter().enumerate() {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: cwd.to_string(),
+                exit_status: *exit_status,
+                start_time: Some(now - 3600 * i as i64),
+                ..Default::default()
+            }).unwrap();
+        }
+
+        let predict = |prefix: &str| {
+            db.predict(&PredictParams {
+                prefix: prefix.to_string(),
+                cwd: "/repo/src".to_string(),
+                project_root: Some("/repo".to_string()),
+                frecent_boost: false,
+                limit: 10,
+                ..Default::default()
+            }).unwrap()
+        };
+
+        for prefix in ["g", "git st", "GIT", "cargo", "v", "x", "git_"] {
+            let hot = predict(prefix);
+            db.hot.lock().unwrap().set_disabled(true);
+            let sql = predict(prefix);
+            db.hot.lock().unwrap().set_disabled(false);
+
+            assert_eq!(hot.len(), sql.len(), "prefix {:?}", prefix);
+            for (h, s) in hot.iter().zip(&sql) {
+                assert_eq!(h.cmd, s.cmd, "prefix {:?}", prefix);
+                assert!((h.score - s.score).abs() < 1e-9, "prefix {:?}", prefix);
+            }
+        }
+
+        // Stores after the build are folded in; deletes force a rebuild
+        predict("git");
+        db.store_command(&StoreParams {
+            cmd: "git push".to_string(),
+            cwd: "/repo/src".to_string(),
+            ..Default::default()
+        }).unwrap();
+        assert!(predict("git p").iter().any(|s| s.cmd == "git push"));
+        db.delete_command("git push").unwrap();
+        assert!(predict("git p").is_empty());
+    }
+
+    #[test]
+    fn test_store_in_excluded_dir_records_nothing() {
+        let db = Database::open_in_memory().unwrap();
+        let ignored = tempfile::tempdir().unwrap();
+        std::fs::create_dir(ignored.path().join("sub")).unwrap();
+        std::fs::write(ignored.path().join(".nicehistignore"), "").unwrap();
+
+        let store = |cwd: &str| {
+            db.store_command(&StoreParams {
+                cmd: "cat /etc/hosts".to_string(),
+                cwd: cwd.to_string(),
+                exclude_dirs: vec!["/secure/".to_string()],
+                ..Default::default()
+            }).unwrap()
+        };
+        assert_eq!(store("/secure"), StoreOutcome::Skipped("dir_excluded"));
+        assert_eq!(store("/secure/keys"), StoreOutcome::Skipped("dir_excluded"));
+        assert_eq!(store(&ignored.path().join("sub").to_string_lossy()), StoreOutcome::Skipped("dir_excluded"));
+
+        let conn = db.conn.lock().unwrap();
+        let count = |table: &str| -> i64 {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| row.get(0)).unwrap()
+        };
+        assert_eq!(count("history"), 0);
+        assert_eq!(count("frecent_paths"), 0);
+        drop(conn);
+
+        // A sibling that merely shares the prefix is still recorded
+        assert!(store("/secured").id().is_some());
+    }
+
+    #[test]
+    fn test_disabling_ngram_strategy_ignores_context() {
+        let db = Database::open_in_memory().unwrap();
+        let now = chrono_lite_timestamp();
+
+        // "make test" always follows "make build"; "make clean" is run more recently
+        for i in 0..5 {
+            db.store_command(&StoreParams {
+                cmd: "make build".to_string(),
+                cwd: "/project".to_string(),
+                start_time: Some(now - 7200 + i * 10),
+                ..Default::default()
+            }).unwrap();
+            db.store_command(&StoreParams {
+                cmd: "make test".to_string(),
+                cwd: "/project".to_string(),
+                start_time: Some(now - 7195 + i * 10),
+                prev_cmd: Some("make build".to_string()),
+                ..Default::default()
+            }).unwrap();
+            db.store_command(&StoreParams {
+                cmd: "make clean".to_string(),
+                cwd: "/project".to_string(),
+                start_time: Some(now - 60 + i),
+                ..Default::default()
+            }).unwrap();
+        }
+
+        let predict = |last_cmds: Vec<String>, use_ngram: bool| {
+            db.predict(&PredictParams {
+                prefix: "make".to_string(),
+                cwd: "/project".to_string(),
+                last_cmds,
+                frecent_boost: false,
+                weights: Some(crate::protocol::RankingWeights { use_ngram, ..Default::default() }),
+                ..Default::default()
+            }).unwrap()
+        };
+        let context = || vec!["make build".to_string()];
+
+        let no_context = predict(vec![], true);
+        assert_eq!(no_context[0].cmd, "make clean");
+        assert_eq!(predict(context(), true)[0].cmd, "make test");
+
+        let disabled = predict(context(), false);
+        assert_eq!(
+            disabled.iter().map(|s| (&s.cmd, s.score)).collect::<Vec<_>>(),
+            no_context.iter().map(|s| (&s.cmd, s.score)).collect::<Vec<_>>(),
+        );
+    }
+
+    #[test]
+    fn test_rank_order_tiebreak() {
+        use std::cmp::Ordering;
+
+        // Higher score wins regardless of recency
+        assert_eq!(rank_order(0.9, 0.5, 100, 200, "b", "a"), Ordering::Less);
+        // Equal scores: more recent first
+        assert_eq!(rank_order(0.5, 0.5, 200, 100, "b", "a"), Ordering::Less);
+        // Equal scores and recency: command text
+        assert_eq!(rank_order(0.5, 0.5, 100, 100, "a", "b"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_predict_equal_scores_sorted_deterministically() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Identical frequency, directory and timestamp, so scores tie exactly
+        for cmd in ["make test", "make build", "make check"] {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/project".to_string(),
+                start_time: Some(1700000000),
+                ..Default::default()
+            }).unwrap();
+        }
+
+        let params = PredictParams {
+            prefix: "make".to_string(),
+            cwd: "/project".to_string(),
+            limit: 5,
+            last_cmds: vec![],
+            frecent_boost: false,
+            last_exit: None,
+            weights: None,
+            ..Default::default()
+        };
+
+        for _ in 0..5 {
+            let cmds: Vec<String> = db.predict(&params).unwrap().into_iter().map(|s| s.cmd).collect();
+            assert_eq!(cmds, vec!["make build", "make check", "make test"]);
+        }
+    }
+
+    #[test]
+    fn test_delete_command() {
+        let db = Database::open_in_memory().unwrap();
+
+        db.store_command(&StoreParams {
+            cmd: "secret-cmd".to_string(),
+            cwd: "/home/user".to_string(),
+            exit_status: Some(0), duration_ms: Some(10),
+            start_time: Some(1700000000),
+            session_id: Some(1),
+            prev_cmd: None, prev2_cmd: None, prev_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        // Verify it exists
+        let results = db.search(&SearchParams {
+            pattern: "secret".to_string(),
+            limit: 10,
+            dir: None, exit_status: None,
+            last_cmds: vec![], cwd: None, ngram_boost: false, last_exit: None,
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(results.len(), 1);
+
+        // Delete it
+        let deleted = db.delete_command("secret-cmd").unwrap();
+        assert_eq!(deleted, 1);
+
+        // Verify it's gone
+        let results = db.search(&SearchParams {
+            pattern: "secret".to_string(),
+            limit: 10,
+            dir: None, exit_status: None,
+            last_cmds: vec![], cwd: None, ngram_boost: false, last_exit: None,
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(results.len(), 0);
+    }
+
+    #[test]
+    fn test_custom_ranking_weights() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Store commands with different characteristics
+        for i in 0..10 {
+            db.store_command(&StoreParams {
+                cmd: "frequent-cmd".to_string(),
+                cwd: "/home/user".to_string(),
+                exit_status: Some(0), duration_ms: Some(10),
+                start_time: Some(1700000000 + i),
+                session_id: Some(1),
+                prev_cmd: None, prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+        db.store_command(&StoreParams {
+            cmd: "recent-cmd".to_string(),
+            cwd: "/home/user".to_string(),
+            exit_status: Some(0), duration_ms: Some(10),
+            start_time: None, // defaults to now
+            session_id: Some(1),
+            prev_cmd: None, prev2_cmd: None, prev_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        // With high frequency weight, frequent-cmd should win
+        let freq_heavy = db.predict(&PredictParams {
+            prefix: "".to_string(),
+            cwd: "/home/user".to_string(),
+            last_cmds: vec![],
+            limit: 10,
+            frecent_boost: false,
+            weights: Some(crate::protocol::RankingWeights {
+                frequency: 1.0,
+                recency: 0.0,
+                ngram: 0.0,
+                dir_exact: 0.0,
+                dir_hierarchy: 0.0,
+                failure_penalty: 0.0,
+                frecent_boost_max: 0.0,
+                ngram_recency_halflife: 60.0,
+                ngram_trigram_boost: 1.5,
+                ngram_exit_boost: 1.2,
+                local_file_penalty: 0.0,
+                ..Default::default()
+            }),
+            last_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        assert!(!freq_heavy.is_empty());
+        assert_eq!(freq_heavy[0].cmd, "frequent-cmd",
+            "With frequency=1.0, recency=0.0, frequent-cmd should be first: {:?}", freq_heavy);
+    }
+
+    #[test]
+    fn test_frequency_cap() {
+        let capped_db = Database::open_in_memory().unwrap();
+        let baseline_db = Database::open_in_memory().unwrap();
+
+        // A tight build-fix loop: 1000 identical stores within a few minutes
+        for i in 0..1000 {
+            capped_db.store_command(&StoreParams {
+                cmd: "make".to_string(),
+                cwd: "/home/user".to_string(),
+                exit_status: Some(0), duration_ms: Some(10),
+                start_time: Some(1700000000 + i / 10),
+                session_id: Some(1),
+                prev_cmd: None, prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+        for i in 0..10 {
+            baseline_db.store_command(&StoreParams {
+                cmd: "make".to_string(),
+                cwd: "/home/user".to_string(),
+                exit_status: Some(0), duration_ms: Some(10),
+                start_time: Some(1700000000 + 99 - i),
+                session_id: Some(1),
+                prev_cmd: None, prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+
+        let predict = |db: &Database, cap: f64| {
+            db.predict(&PredictParams {
+                prefix: "make".to_string(),
+                cwd: "/home/user".to_string(),
+                last_cmds: vec![],
+                limit: 1,
+                frecent_boost: false,
+                weights: Some(crate::protocol::RankingWeights {
+                    frequency_cap: cap,
+                    ..Default::default()
+                }),
+                last_exit: None,
+                ..Default::default()
+            }).unwrap()[0].score
+        };
+
+        // Uncapped, the burst dominates
+        assert!(predict(&capped_db, 0.0) > predict(&baseline_db, 0.0));
+
+        // Capped at 10 runs, the burst scores no higher than 10 runs would
+        let capped = predict(&capped_db, 10.0);
+        let ceiling = predict(&baseline_db, 0.0);
+        assert!(capped <= ceiling + 1e-9, "capped={} ceiling={}", capped, ceiling);
+    }
+
+    #[test]
+    fn test_frequency_cap_favors_steady_use_over_a_burst() {
+        let db = Database::open_in_memory().unwrap();
+        let now = 1700000000;
+        // 50 runs within a minute, against one a day for ten days, last run at the same time
+        let runs = (0..50).map(|i| ("make burst", now - 49 + i)).chain((0..10).map(|day| ("make steady", now - day * 86400)));
+        for (cmd, start_time) in runs {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/home/user".to_string(),
+                exit_status: Some(0),
+                start_time: Some(start_time),
+                ..Default::default()
+            }).unwrap();
+        }
+
+        let top = |cap: f64, hot: bool| {
+            db.hot.lock().unwrap().set_disabled(!hot);
+            db.predict(&PredictParams {
+                prefix: "make".to_string(),
+                cwd: "/home/user".to_string(),
+                weights: Some(crate::protocol::RankingWeights { frequency_cap: cap, ..Default::default() }),
+                ..Default::default()
+            }).unwrap()[0].cmd.clone()
+        };
+        for hot in [true, false] {
+            assert_eq!(top(0.0, hot), "make burst");
+            assert_eq!(top(3.0, hot), "make steady");
+        }
+    }
+
+    #[test]
+    fn test_frecent_aging() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Add many paths with high ranks to trigger aging (total > 2000)
+        for i in 0..50 {
+            db.frecent_add(&FrecentAddParams {
+                path: format!("/path/{}", i),
+                path_type: "d".to_string(),
+                rank: Some(50.0), // 50 * 50 = 2500 total
+                timestamp: Some(1700000000),
+            }).unwrap();
+        }
+
+        // Add one more to trigger aging check
+        db.frecent_add(&FrecentAddParams {
+            path: "/path/trigger".to_string(),
+            path_type: "d".to_string(),
+            rank: None,
+            timestamp: None,
+        }).unwrap();
+
+        // After aging, ranks should be decayed (multiplied by 0.9)
+        let results = db.frecent_query(&FrecentQueryParams {
+            terms: vec!["path/0".to_string()],
+            path_types: vec!["d".to_string()],
+            limit: 1,
+            raw: true,
+            ..Default::default()
+        }).unwrap();
+
+        assert!(!results.is_empty());
+        // Original rank was 50.0, after 0.9x decay should be 45.0
+        assert!(results[0].rank.unwrap() < 50.0,
+            "Rank should be decayed after aging: {}", results[0].rank.unwrap());
+    }
+
+    #[test]
+    fn test_arg_suggestions() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Store git checkout with branch args multiple times
+        for i in 0..5 {
+            db.store_command(&StoreParams {
+                cmd: "git checkout main".to_string(),
+                cwd: "/home/user/project".to_string(),
+                exit_status: Some(0), duration_ms: Some(50),
+                start_time: Some(1700000000 + i * 10),
+                session_id: Some(1),
+                prev_cmd: None, prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+        for i in 0..3 {
+            db.store_command(&StoreParams {
+                cmd: "git checkout develop".to_string(),
+                cwd: "/home/user/project".to_string(),
+                exit_status: Some(0), duration_ms: Some(50),
+                start_time: Some(1700000100 + i * 10),
+                session_id: Some(1),
+                prev_cmd: None, prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+
+        // Ask for arg suggestions for "git checkout "
+        let suggestions = db.get_arg_suggestions(
+            "git checkout ", "/home/user/project", 5,
+        ).unwrap();
+
+        assert!(!suggestions.is_empty(), "Should suggest branch names");
+        // All suggestions should start with "git checkout "
+        assert!(suggestions.iter().all(|s| s.cmd.starts_with("git checkout ")),
+            "All suggestions should complete the command: {:?}", suggestions);
+    }
+
+    #[test]
+    fn test_expecting_args_without_learned_args_predicts_commands() {
+        let db = Database::open_in_memory().unwrap();
+        let store = |cmd: &str, prev_cmd: Option<&str>, t: i64| {
+            db.store_command(&StoreParams {
+                cmd: cmd.to_string(),
+                cwd: "/src".to_string(),
+                start_time: Some(1700000000 + t),
+                prev_cmd: prev_cmd.map(str::to_string),
+                session_id: Some(1),
+                ..Default::default()
+            }).unwrap();
+        };
+        // Only flags follow `cargo build`, so no argument is learned for it
+        for t in 0..5 {
+            store("cargo build --release", None, t * 100);
+        }
+        store("cargo fmt", None, 600);
+        store("cargo build --release --locked", Some("cargo fmt"), 601);
+        assert!(db.get_arg_suggestions("cargo build ", "/src", 5).unwrap().is_empty());
+
+        let predict = |last_cmds: Vec<String>| -> Vec<String> {
+            db.predict(&PredictParams {
+                prefix: "cargo build ".to_string(),
+                cwd: "/src".to_string(),
+                last_cmds,
+                limit: 5,
+                ..Default::default()
+            }).unwrap().into_iter().map(|s| s.cmd).collect()
+        };
+        assert_eq!(predict(vec![]), ["cargo build --release", "cargo build --release --locked"]);
+        // The fallback still follows what was just run
+        assert_eq!(predict(vec!["cargo fmt".to_string()])[0], "cargo build --release --locked");
+    }
+
+    #[test]
+    fn test_branch_suggestions_are_scoped_to_repo() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = |name: &str, branches: &[&str]| -> String {
+            let heads = dir.path().join(name).join(".git/refs/heads");
+            std::fs::create_dir_all(&heads).unwrap();
+            std::fs::write(heads.parent().unwrap().parent().unwrap().join("HEAD"), "ref: refs/heads/main\n").unwrap();
+            for branch in branches {
+                std::fs::write(heads.join(branch), "0123456789abcdef0123456789abcdef01234567\n").unwrap();
+            }
+            dir.path().join(name).to_string_lossy().to_string()
+        };
+        let api = repo("api", &["main", "fix-login"]);
+        let web = repo("web", &["main", "redesign"]);
+
+        let db = Database::open_in_memory().unwrap();
+        let checkout = |cwd: &str, branch: &str, times: usize| {
+            for _ in 0..times {
+                db.store_command(&StoreParams {
+                    cmd: format!("git checkout {}", branch),
+                    cwd: cwd.to_string(),
+                    ..Default::default()
+                }).unwrap();
+            }
+        };
+        checkout(&api, "fix-login", 1);
+        // Checked out often, but since merged and deleted
+        checkout(&api, "old-feature", 5);
+        checkout(&web, "redesign", 3);
+        // `git switch` shares the repository's branches
+        db.store_command(&StoreParams {
+            cmd: "git switch main".to_string(),
+            cwd: format!("{}/src", api),
+            ..Default::default()
+        }).unwrap();
+
+        let suggest = |prefix: &str, cwd: &str| -> Vec<String> {
+            db.get_arg_suggestions(prefix, cwd, 5).unwrap().into_iter().map(|s| s.cmd).collect()
+        };
+        assert_eq!(suggest("git checkout ", &web), ["git checkout redesign"]);
+        // The deleted branch goes last despite being checked out more
+        assert_eq!(suggest("git checkout ", &api), ["git checkout fix-login", "git checkout old-feature"]);
+        assert_eq!(suggest("git switch ", &web), Vec::<String>::new());
+        assert_eq!(suggest("git switch ", &api), ["git switch main"]);
+        // Outside a repository no branch is suggested
+        assert!(suggest("git checkout ", "/nonexistent/dir").is_empty());
+    }
+
+    #[test]
+    fn test_local_file_penalty_search() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Store a command with local file args from dir-a
+        db.store_command(&StoreParams {
+            cmd: "vim foo.py".to_string(),
+            cwd: "/home/user/dir-a".to_string(),
+            exit_status: Some(0), duration_ms: Some(50),
+            start_time: Some(1700000000),
+            session_id: Some(1),
+            prev_cmd: None, prev2_cmd: None, prev_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        // Store a generic command from dir-a
+        db.store_command(&StoreParams {
+            cmd: "vim --version".to_string(),
+            cwd: "/home/user/dir-a".to_string(),
+            exit_status: Some(0), duration_ms: Some(50),
+            start_time: Some(1700000000),
+            session_id: Some(1),
+            prev_cmd: None, prev2_cmd: None, prev_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        // Manually set has_local_file_args on the first command
+        {
+            let conn = db.conn.lock().unwrap();
+            conn.execute(
+                "UPDATE history SET has_local_file_args = 1 WHERE command_id = (SELECT id FROM commands WHERE argv = 'vim foo.py')",
+                [],
+            ).unwrap();
+        }
+
+        // Search from dir-b: command with local files should be penalized
+        let results = db.search(&SearchParams {
+            pattern: "vim".to_string(),
+            limit: 10,
+            dir: None,
+            exit_status: None,
+            last_cmds: vec![],
+            cwd: Some("/home/user/dir-b".to_string()),
+            ngram_boost: false,
+            last_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        assert_eq!(results.len(), 2);
+        let local_score = results.iter().find(|r| r.cmd == "vim foo.py").unwrap().score.unwrap();
+        let generic_score = results.iter().find(|r| r.cmd == "vim --version").unwrap().score.unwrap();
+        assert!(local_score < generic_score,
+            "Local file command should score lower from different dir: {:.4} vs {:.4}", local_score, generic_score);
+    }
+
+    #[test]
+    fn test_local_file_no_penalty_same_dir() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Store a command with local file args
+        db.store_command(&StoreParams {
+            cmd: "vim foo.py".to_string(),
+            cwd: "/home/user/dir-a".to_string(),
+            exit_status: Some(0), duration_ms: Some(50),
+            start_time: Some(1700000000),
+            session_id: Some(1),
+            prev_cmd: None, prev2_cmd: None, prev_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        // Store same command without local files for comparison
+        db.store_command(&StoreParams {
+            cmd: "vim --version".to_string(),
+            cwd: "/home/user/dir-a".to_string(),
+            exit_status: Some(0), duration_ms: Some(50),
+            start_time: Some(1700000000),
+            session_id: Some(1),
+            prev_cmd: None, prev2_cmd: None, prev_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        // Set has_local_file_args
+        {
+            let conn = db.conn.lock().unwrap();
+            conn.execute(
+                "UPDATE history SET has_local_file_args = 1 WHERE command_id = (SELECT id FROM commands WHERE argv = 'vim foo.py')",
+                [],
+            ).unwrap();
+        }
+
+        // Search from SAME dir: no penalty should apply
+        let results = db.search(&SearchParams {
+            pattern: "vim".to_string(),
+            limit: 10,
+            dir: None,
+            exit_status: None,
+            last_cmds: vec![],
+            cwd: Some("/home/user/dir-a".to_string()),
+            ngram_boost: false,
+            last_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        let local_score = results.iter().find(|r| r.cmd == "vim foo.py").unwrap().score.unwrap();
+        let generic_score = results.iter().find(|r| r.cmd == "vim --version").unwrap().score.unwrap();
+        // Same timestamps, same freq — scores should be equal (no penalty from same dir)
+        assert!((local_score - generic_score).abs() < 0.001,
+            "No penalty from same dir: {:.4} vs {:.4}", local_score, generic_score);
+    }
+
+    #[test]
+    fn test_local_file_penalty_predict() {
+        let db = Database::open_in_memory().unwrap();
+
+        // Store commands from dir-a
+        db.store_command(&StoreParams {
+            cmd: "vim foo.py".to_string(),
+            cwd: "/home/user/dir-a".to_string(),
+            exit_status: Some(0), duration_ms: Some(50),
+            start_time: Some(1700000000),
+            session_id: Some(1),
+            prev_cmd: None, prev2_cmd: None, prev_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        db.store_command(&StoreParams {
+            cmd: "vim --version".to_string(),
+            cwd: "/home/user/dir-a".to_string(),
+            exit_status: Some(0), duration_ms: Some(50),
+            start_time: Some(1700000000),
+            session_id: Some(1),
+            prev_cmd: None, prev2_cmd: None, prev_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        // Set has_local_file_args
+        {
+            let conn = db.conn.lock().unwrap();
+            conn.execute(
+                "UPDATE history SET has_local_file_args = 1 WHERE command_id = (SELECT id FROM commands WHERE argv = 'vim foo.py')",
+                [],
+            ).unwrap();
+        }
+
+        // Predict from dir-b: command with local files should be penalized
+        let suggestions = db.predict(&PredictParams {
+            prefix: "vim".to_string(),
+            cwd: "/home/user/dir-b".to_string(),
+            last_cmds: vec![],
+            limit: 10,
+            frecent_boost: false,
+            weights: None,
+            last_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        assert!(suggestions.len() >= 2);
+        let local_score = suggestions.iter().find(|s| s.cmd == "vim foo.py").unwrap().score;
+        let generic_score = suggestions.iter().find(|s| s.cmd == "vim --version").unwrap().score;
+        assert!(local_score < generic_score,
+            "Local file command should score lower in predict from different dir: {:.4} vs {:.4}", local_score, generic_score);
+    }
+
+    #[test]
+    fn test_conditional_probability_scoring() {
+        let db = Database::open_in_memory().unwrap();
+
+        // prev_A → cmd_target 8 times out of 10 total successors (80%)
+        for i in 0..8 {
+            db.store_command(&StoreParams {
+                cmd: "cmd_target".to_string(),
+                cwd: "/home/user".to_string(),
+                exit_status: Some(0), duration_ms: Some(10),
+                start_time: Some(1700000000 + i),
+                session_id: Some(1),
+                prev_cmd: Some("prev_A".to_string()),
+                prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+        for i in 0..2 {
+            db.store_command(&StoreParams {
+                cmd: "cmd_other_a".to_string(),
+                cwd: "/home/user".to_string(),
+                exit_status: Some(0), duration_ms: Some(10),
+                start_time: Some(1700000010 + i),
+                session_id: Some(1),
+                prev_cmd: Some("prev_A".to_string()),
+                prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+
+        // prev_B → cmd_target 8 times out of 100 total successors (8%)
+        for i in 0..8 {
+            db.store_command(&StoreParams {
+                cmd: "cmd_target".to_string(),
+                cwd: "/home/user".to_string(),
+                exit_status: Some(0), duration_ms: Some(10),
+                start_time: Some(1700000100 + i),
+                session_id: Some(1),
+                prev_cmd: Some("prev_B".to_string()),
+                prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+        for i in 0..92 {
+            db.store_command(&StoreParams {
+                cmd: format!("cmd_noise_{}", i),
+                cwd: "/home/user".to_string(),
+                exit_status: Some(0), duration_ms: Some(10),
+                start_time: Some(1700000200 + i as i64),
+                session_id: Some(1),
+                prev_cmd: Some("prev_B".to_string()),
+                prev2_cmd: None, prev_exit: None,
+                ..Default::default()
+            }).unwrap();
+        }
+
+        // Store prev_A and prev_B themselves
+        db.store_command(&StoreParams {
+            cmd: "prev_A".to_string(), cwd: "/home/user".to_string(),
+            exit_status: Some(0), duration_ms: Some(10),
+            start_time: Some(1700000000), session_id: Some(1),
+            prev_cmd: None, prev2_cmd: None, prev_exit: None,
+            ..Default::default()
+        }).unwrap();
+        db.store_command(&StoreParams {
+            cmd: "prev_B".to_string(), cwd: "/home/user".to_string(),
+            exit_status: Some(0), duration_ms: Some(10),
+            start_time: Some(1700000000), session_id: Some(1),
+            prev_cmd: None, prev2_cmd: None, prev_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        let w = crate::protocol::RankingWeights::default();
+        let conn = db.conn.lock().unwrap();
+
+        // After prev_A: cmd_target should have ~0.8 conditional probability
+        let bonus_a = db.compute_ngram_bonus(&conn, &["prev_A".to_string()], "cmd_target", 10, None, &w).unwrap();
+        let score_a = bonus_a.get("cmd_target").copied().unwrap_or(0.0);
+
+        // After prev_B: cmd_target should have ~0.08 conditional probability
+        let bonus_b = db.compute_ngram_bonus(&conn, &["prev_B".to_string()], "cmd_target", 10, None, &w).unwrap();
+        let score_b = bonus_b.get("cmd_target").copied().unwrap_or(0.0);
+
+        // 80% conditional probability should significantly outscore 8%
+        assert!(score_a > score_b * 2.0,
+            "Conditional prob: prev_A→target ({}) should be >> prev_B→target ({})",
+            score_a, score_b);
+    }
+
+    #[test]
+    fn test_ngram_recency_decay() {
+        let db = Database::open_in_memory().unwrap();
+        let now = chrono_lite_timestamp();
+
+        // Store prev_cmd
+        db.store_command(&StoreParams {
+            cmd: "prev_cmd".to_string(), cwd: "/home/user".to_string(),
+            exit_status: Some(0), duration_ms: Some(10),
+            start_time: Some(now), session_id: Some(1),
+            prev_cmd: None, prev2_cmd: None, prev_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        let conn = db.conn.lock().unwrap();
+        let prev_id = db.get_command_id(&conn, "prev_cmd").unwrap();
+
+        // Insert a recent bigram (today)
+        let recent_id = db.get_or_create_command(&conn, "recent_successor").unwrap();
+        conn.execute(
+            "INSERT INTO ngrams_2 (prev_command_id, command_id, frequency, last_used) VALUES (?1, ?2, 5, ?3)",
+            rusqlite::params![prev_id, recent_id, now],
+        ).unwrap();
+
+        // Insert a stale bigram (180 days ago, same frequency)
+        let stale_id = db.get_or_create_command(&conn, "stale_successor").unwrap();
+        let stale_time = now - 180 * 86400;
+        conn.execute(
+            "INSERT INTO ngrams_2 (prev_command_id, command_id, frequency, last_used) VALUES (?1, ?2, 5, ?3)",
+            rusqlite::params![prev_id, stale_id, stale_time],
+        ).unwrap();
+
+        let w = crate::protocol::RankingWeights::default();
+        let bonus = db.compute_ngram_bonus(&conn, &["prev_cmd".to_string()], "", 10, None, &w).unwrap();
+
+        let recent_score = bonus.get("recent_successor").copied().unwrap_or(0.0);
+        let stale_score = bonus.get("stale_successor").copied().unwrap_or(0.0);
+
+        assert!(recent_score > stale_score * 2.0,
+            "Recent bigram ({}) should significantly outscore stale bigram ({})",
+            recent_score, stale_score);
+    }
+
+    #[test]
+    fn test_exit_aware_bigram() {
+        let db = Database::open_in_memory().unwrap();
+
+        // After "make" fails → "make clean" (10 times)
+        for i in 0..10 {
+            db.store_command(&StoreParams {
+                cmd: "make clean".to_string(),
+                cwd: "/home/user".to_string(),
+                exit_status: Some(0), duration_ms: Some(10),
+                start_time: Some(1700000000 + i),
+                session_id: Some(1),
+                prev_cmd: Some("make".to_string()),
+                prev2_cmd: None,
+                prev_exit: Some(2), // make failed
+                ..Default::default()
+            }).unwrap();
+        }
+
+        // After "make" succeeds → "make install" (10 times)
+        for i in 0..10 {
+            db.store_command(&StoreParams {
+                cmd: "make install".to_string(),
+                cwd: "/home/user".to_string(),
+                exit_status: Some(0), duration_ms: Some(10),
+                start_time: Some(1700000100 + i),
+                session_id: Some(1),
+                prev_cmd: Some("make".to_string()),
+                prev2_cmd: None,
+                prev_exit: Some(0), // make succeeded
+                ..Default::default()
+            }).unwrap();
+        }
+
+        // Store "make" itself
+        db.store_command(&StoreParams {
+            cmd: "make".to_string(), cwd: "/home/user".to_string(),
+            exit_status: Some(0), duration_ms: Some(10),
+            start_time: Some(1700000000), session_id: Some(1),
+            prev_cmd: None, prev2_cmd: None, prev_exit: None,
+            ..Default::default()
+        }).unwrap();
+
+        let w = crate::protocol::RankingWeights::default();
+        let conn = db.conn.lock().unwrap();
+
+        // After "make" failed: "make clean" should be boosted
+        let bonus_fail = db.compute_ngram_bonus(
+            &conn, &["make".to_string()], "make", 10, Some(2), &w,
+        ).unwrap();
+        let clean_after_fail = bonus_fail.get("make clean").copied().unwrap_or(0.0);
+        let install_after_fail = bonus_fail.get("make install").copied().unwrap_or(0.0);
+
+        assert!(clean_after_fail > install_after_fail,
+            "After make fails, 'make clean' ({}) should outscore 'make install' ({})",
+            clean_after_fail, install_after_fail);
+
+        // After "make" succeeded: "make install" should be boosted
+        let bonus_ok = db.compute_ngram_bonus(
+            &conn, &["make".to_string()], "make", 10, Some(0), &w,
+        ).unwrap();
+        let install_after_ok = bonus_ok.get("make install").copied().unwrap_or(0.0);
+        let clean_after_ok = bonus_ok.get("make clean").copied().unwrap_or(0.0);
+
+        assert!(install_after_ok > clean_after_ok,
+            "After make succeeds, 'make install' ({}) should outscore 'make clean' ({})",
+            install_after_ok, clean_after_ok);
+    }
+}
@@ -19,10 +19,11 @@ CREATE TABLE IF NOT EXISTS places (
 -- VCS and project context
 CREATE TABLE IF NOT EXISTS contexts (
     id INTEGER PRIMARY KEY,
-    vcs_type TEXT,           -- 'git', 'hg', or NULL
+    vcs_type TEXT,           -- 'git', 'hg', 'jj', 'svn', or NULL
     vcs_root TEXT,           -- Repository root path
     vcs_branch TEXT,         -- Branch name
-    project_type TEXT        -- 'rust', 'node', 'python', etc.
+    project_type TEXT,       -- 'rust', 'node', 'python', etc.
+    vcs_remote TEXT          -- Origin remote URL, shared by every clone of a repository
 );
 
 -- Sessions (shell instances)
@@ -42,10 +43,14 @@ CREATE TABLE IF NOT EXISTS history (
     place_id INTEGER NOT NULL REFERENCES places(id),
     context_id INTEGER REFERENCES contexts(id),
     start_time INTEGER NOT NULL,
-    duration REAL,           -- Duration in seconds (float for sub-second precision)
+    duration REAL,           -- Duration in seconds; kept for older readers, see duration_ms
     exit_status INTEGER,
     time_bucket INTEGER,     -- Hour of day (0-23) for time-of-day patterns
-    has_local_file_args INTEGER NOT NULL DEFAULT 0  -- Command references local files (relative paths that exist)
+    has_local_file_args INTEGER NOT NULL DEFAULT 0,  -- Command references local files (relative paths that exist)
+    shell TEXT,              -- Shell the command ran in (zsh, bash, ...)
+    terminal TEXT,           -- Terminal or multiplexer ($TERM_PROGRAM, tmux, ...)
+    tty TEXT,                -- Terminal device the shell ran on (/dev/pts/3), one per pane
+    duration_ms INTEGER      -- Duration in milliseconds, exactly as reported
 );
 
 -- N-gram tables for prediction
@@ -69,6 +74,17 @@ CREATE TABLE IF NOT EXISTS ngrams_3 (
     PRIMARY KEY (prev2_command_id, prev1_command_id, command_id)
 );
 
+-- 4-gram: P(command | prev3_command, prev2_command, prev1_command)
+CREATE TABLE IF NOT EXISTS ngrams_4 (
+    prev3_command_id INTEGER NOT NULL REFERENCES commands(id),
+    prev2_command_id INTEGER NOT NULL REFERENCES commands(id),
+    prev1_command_id INTEGER NOT NULL REFERENCES commands(id),
+    command_id INTEGER NOT NULL REFERENCES commands(id),
+    frequency INTEGER NOT NULL DEFAULT 1,
+    last_used INTEGER NOT NULL,
+    PRIMARY KEY (prev3_command_id, prev2_command_id, prev1_command_id, command_id)
+);
+
 -- Exit-aware bigram: P(command | prev_command, prev_exit_ok)
 CREATE TABLE IF NOT EXISTS ngrams_2_exit (
     prev_command_id INTEGER NOT NULL REFERENCES commands(id),
@@ -88,6 +104,14 @@ CREATE TABLE IF NOT EXISTS dir_command_freq (
     PRIMARY KEY (place_id, command_id)
 );
 
+-- Prediction feedback: how often a shown suggestion was picked or passed over
+CREATE TABLE IF NOT EXISTS accepts (
+    command_id INTEGER PRIMARY KEY REFERENCES commands(id),
+    accepted INTEGER NOT NULL DEFAULT 0,  -- Times the user took this suggestion
+    rejected INTEGER NOT NULL DEFAULT 0,  -- Times it was shown and another one was taken
+    last_accepted INTEGER
+);
+
 -- Parsed commands for argument-aware suggestions
 -- e.g., "git commit -m 'fix'" -> program='git', subcommand='commit', args='-m fix'
 CREATE TABLE IF NOT EXISTS parsed_commands (
@@ -97,6 +121,14 @@ CREATE TABLE IF NOT EXISTS parsed_commands (
     args_hash TEXT                   -- Hash of remaining args for dedup
 );
 
+-- Commands learned from inside a compound line, linked to the line they came
+-- from so they live as long as its history does
+CREATE TABLE IF NOT EXISTS command_segments (
+    command_id INTEGER NOT NULL REFERENCES commands(id),
+    parent_id INTEGER NOT NULL REFERENCES commands(id),
+    PRIMARY KEY (command_id, parent_id)
+);
+
 -- Argument patterns: what arguments follow a given program+subcommand
 -- e.g., after "git checkout" -> "main", "develop", "feature/login"
 CREATE TABLE IF NOT EXISTS arg_patterns (
@@ -107,6 +139,7 @@ CREATE TABLE IF NOT EXISTS arg_patterns (
     frequency INTEGER NOT NULL DEFAULT 1,
     last_used INTEGER NOT NULL,
     place_id INTEGER REFERENCES places(id),  -- Optional: dir-specific args
+    vcs_root TEXT,                   -- Repository a branch name was checked out in
     UNIQUE(program, subcommand, arg_value, place_id)
 );
 
@@ -121,6 +154,26 @@ CREATE TABLE IF NOT EXISTS frecent_paths (
     UNIQUE(path, path_type)
 );
 
+-- Full-text index over command text; the trigram tokenizer answers substring queries
+CREATE VIRTUAL TABLE IF NOT EXISTS commands_fts USING fts5(
+    argv,
+    content='commands',
+    content_rowid='id',
+    tokenize='trigram'
+);
+
+-- Keep commands_fts in step with commands
+CREATE TRIGGER IF NOT EXISTS commands_fts_insert AFTER INSERT ON commands BEGIN
+    INSERT INTO commands_fts(rowid, argv) VALUES (new.id, new.argv);
+END;
+CREATE TRIGGER IF NOT EXISTS commands_fts_delete AFTER DELETE ON commands BEGIN
+    INSERT INTO commands_fts(commands_fts, rowid, argv) VALUES ('delete', old.id, old.argv);
+END;
+CREATE TRIGGER IF NOT EXISTS commands_fts_update AFTER UPDATE OF argv ON commands BEGIN
+    INSERT INTO commands_fts(commands_fts, rowid, argv) VALUES ('delete', old.id, old.argv);
+    INSERT INTO commands_fts(rowid, argv) VALUES (new.id, new.argv);
+END;
+
 -- Schema version tracking
 CREATE TABLE IF NOT EXISTS schema_version (
     version INTEGER PRIMARY KEY,
@@ -132,10 +185,13 @@ CREATE INDEX IF NOT EXISTS idx_history_command_id ON history(command_id);
 CREATE INDEX IF NOT EXISTS idx_history_place_id ON history(place_id);
 CREATE INDEX IF NOT EXISTS idx_history_start_time ON history(start_time DESC);
 CREATE INDEX IF NOT EXISTS idx_history_time_bucket ON history(time_bucket);
+CREATE INDEX IF NOT EXISTS idx_history_session_id ON history(session_id);
+CREATE UNIQUE INDEX IF NOT EXISTS idx_sessions_host_pid ON sessions(host, pid);
 CREATE INDEX IF NOT EXISTS idx_commands_argv ON commands(argv);
 CREATE INDEX IF NOT EXISTS idx_places_dir ON places(dir);
 CREATE INDEX IF NOT EXISTS idx_ngrams_2_prev ON ngrams_2(prev_command_id);
 CREATE INDEX IF NOT EXISTS idx_ngrams_3_prev ON ngrams_3(prev2_command_id, prev1_command_id);
+CREATE INDEX IF NOT EXISTS idx_ngrams_4_prev ON ngrams_4(prev3_command_id, prev2_command_id, prev1_command_id);
 CREATE INDEX IF NOT EXISTS idx_ngrams_2_exit_prev ON ngrams_2_exit(prev_command_id, prev_exit_ok);
 CREATE INDEX IF NOT EXISTS idx_parsed_commands_program ON parsed_commands(program);
 CREATE INDEX IF NOT EXISTS idx_parsed_commands_subcommand ON parsed_commands(program, subcommand);
@@ -172,7 +228,9 @@ mod tests {
         assert!(tables.contains(&"history".to_string()));
         assert!(tables.contains(&"ngrams_2".to_string()));
         assert!(tables.contains(&"ngrams_3".to_string()));
+        assert!(tables.contains(&"ngrams_4".to_string()));
         assert!(tables.contains(&"frecent_paths".to_string()));
+        assert!(tables.contains(&"accepts".to_string()));
     }
 
     #[test]
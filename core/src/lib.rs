@@ -0,0 +1,35 @@
+//! nicehist's history store and prediction engine, usable without the daemon.
+//!
+//! The daemon is a JSON-RPC shell over this crate; another tool can embed the
+//! same predictions by opening the database directly:
+//!
+//! ```
+//! use nicehist_core::Database;
+//! use nicehist_core::protocol::{PredictParams, StoreParams};
+//!
+//! let dir = tempfile::tempdir()?;
+//! let db = Database::open(&dir.path().join("history.db"))?;
+//!
+//! db.store_command(&StoreParams {
+//!     cmd: "cargo build".to_string(),
+//!     cwd: "/src".to_string(),
+//!     ..Default::default()
+//! })?;
+//!
+//! let suggestions = db.predict(&PredictParams {
+//!     prefix: "cargo".to_string(),
+//!     cwd: "/src".to_string(),
+//!     limit: 5,
+//!     ..Default::default()
+//! })?;
+//! assert_eq!(suggestions[0].cmd, "cargo build");
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+pub mod context;
+pub mod db;
+pub mod prediction;
+pub mod protocol;
+pub mod redact;
+
+pub use db::Database;
@@ -0,0 +1,419 @@
+//! Prediction engine for command suggestions.
+//!
+//! Combines n-gram models with context-aware ranking for fast (<10ms) predictions.
+
+mod ngram;
+pub mod parser;
+pub mod ranking;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::protocol::{CacheStats, MatchMode, PredictParams, RankingWeights, Suggestion};
+
+pub use ranking::ContextRanker;
+
+/// How long a cached prediction is served. Stores only invalidate the prefixes
+/// they can change directly; this bounds drift from indirect effects such as
+/// frecency bumps and recency decay.
+const CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// LRU cache for predictions
+#[allow(dead_code)]
+struct PredictionCache {
+    entries: HashMap<String, CacheEntry>,
+    max_size: usize,
+    ttl: Duration,
+    hits: u64,
+    misses: u64,
+    /// Bumped by every invalidation, so a prediction computed before one isn't cached after it
+    generation: u64,
+}
+
+#[allow(dead_code)]
+struct CacheEntry {
+    suggestions: Vec<Suggestion>,
+    timestamp: Instant,
+    /// Lowercased prefix the predictions complete; None if it can't be told from the request
+    prefix: Option<String>,
+}
+
+#[allow(dead_code)]
+impl PredictionCache {
+    fn new(max_size: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            max_size,
+            ttl: CACHE_TTL,
+            hits: 0,
+            misses: 0,
+            generation: 0,
+        }
+    }
+
+    fn get(&mut self, key: &str) -> Option<&Vec<Suggestion>> {
+        if self.entries.get(key).is_some_and(|e| e.timestamp.elapsed() >= self.ttl) {
+            self.entries.remove(key);
+        }
+        match self.entries.get(key) {
+            Some(e) => {
+                self.hits += 1;
+                Some(&e.suggestions)
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, key: String, suggestions: Vec<Suggestion>) {
+        self.insert_for_prefix(key, suggestions, None);
+    }
+
+    fn insert_for_prefix(&mut self, key: String, suggestions: Vec<Suggestion>, prefix: Option<String>) {
+        // Simple eviction: remove oldest entries if over capacity
+        if self.entries.len() >= self.max_size {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, v)| v.timestamp)
+                .map(|(k, _)| k.clone());
+
+            if let Some(k) = oldest {
+                self.entries.remove(&k);
+            }
+        }
+
+        self.entries.insert(
+            key,
+            CacheEntry {
+                suggestions,
+                timestamp: Instant::now(),
+                prefix,
+            },
+        );
+    }
+
+    fn invalidate_prefix(&mut self, prefix: &str) {
+        self.generation += 1;
+        self.entries.retain(|k, _| !k.starts_with(prefix));
+    }
+
+    /// Drop entries whose predictions could include `cmd` (prefix matching is
+    /// ASCII case-insensitive, like the prediction query)
+    fn invalidate_command(&mut self, cmd: &str) {
+        self.generation += 1;
+        let cmd = cmd.to_ascii_lowercase();
+        self.entries
+            .retain(|_, e| e.prefix.as_deref().is_some_and(|prefix| !cmd.starts_with(prefix)));
+    }
+
+    fn clear(&mut self) {
+        self.generation += 1;
+        self.entries.clear();
+    }
+
+    fn stats(&self) -> CacheStats {
+        // Approximate: key and suggestion text plus the score of each suggestion
+        let size_bytes = self
+            .entries
+            .iter()
+            .map(|(k, e)| {
+                k.len()
+                    + e.suggestions
+                        .iter()
+                        .map(|s| s.cmd.len() + std::mem::size_of::<f64>())
+                        .sum::<usize>()
+            })
+            .sum();
+
+        CacheStats {
+            entries: self.entries.len(),
+            capacity: self.max_size,
+            hits: self.hits,
+            misses: self.misses,
+            size_bytes,
+        }
+    }
+}
+
+/// Prediction engine combining n-gram model and context ranking
+#[allow(dead_code)]
+pub struct PredictionEngine {
+    cache: Arc<Mutex<PredictionCache>>,
+    /// Weights for requests that don't send their own
+    default_weights: RankingWeights,
+    /// Most results a predict or frecent request may ask for
+    max_limit: usize,
+    /// Most results a search request may ask for
+    max_search_limit: usize,
+}
+
+/// Default cap on a predict or frecent request's `limit`: far more than a
+/// prompt or picker shows, while stopping runaway requests
+pub const DEFAULT_MAX_LIMIT: usize = 1000;
+
+/// Default cap on a search request's `limit`: `export` reads whole histories
+/// through search, asking for up to 100000
+pub const DEFAULT_MAX_SEARCH_LIMIT: usize = 100_000;
+
+impl Default for PredictionEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[allow(dead_code)]
+impl PredictionEngine {
+    pub fn new() -> Self {
+        Self {
+            cache: Arc::new(Mutex::new(PredictionCache::new(1000))),
+            default_weights: RankingWeights::default(),
+            max_limit: DEFAULT_MAX_LIMIT,
+            max_search_limit: DEFAULT_MAX_SEARCH_LIMIT,
+        }
+    }
+
+    /// Serve cached predictions for `ttl` instead of the default
+    pub fn with_cache_ttl(self, ttl: Duration) -> Self {
+        self.cache.lock().unwrap().ttl = ttl;
+        self
+    }
+
+    /// Use `weights` for requests that don't send their own
+    pub fn with_default_weights(mut self, weights: RankingWeights) -> Self {
+        self.default_weights = weights;
+        self
+    }
+
+    pub fn default_weights(&self) -> &RankingWeights {
+        &self.default_weights
+    }
+
+    /// Cap the `limit` of predict and frecent requests at `max`
+    pub fn with_max_limit(mut self, max: usize) -> Self {
+        self.max_limit = max;
+        self
+    }
+
+    pub fn max_limit(&self) -> usize {
+        self.max_limit
+    }
+
+    /// Cap the `limit` of search requests at `max`
+    pub fn with_max_search_limit(mut self, max: usize) -> Self {
+        self.max_search_limit = max;
+        self
+    }
+
+    pub fn max_search_limit(&self) -> usize {
+        self.max_search_limit
+    }
+
+    /// Generate cache key from prediction parameters
+    fn cache_key(prefix: &str, cwd: &str, last_cmd: Option<&str>) -> String {
+        format!(
+            "{}:{}:{}",
+            prefix,
+            cwd,
+            last_cmd.unwrap_or("")
+        )
+    }
+
+    /// Check cache for existing predictions
+    pub fn get_cached(&self, prefix: &str, cwd: &str, last_cmd: Option<&str>) -> Option<Vec<Suggestion>> {
+        let key = Self::cache_key(prefix, cwd, last_cmd);
+        let mut cache = self.cache.lock().unwrap();
+        cache.get(&key).cloned()
+    }
+
+    /// Return cached predictions for these exact params, or compute and cache them
+    pub fn predict_cached(
+        &self,
+        params: &PredictParams,
+        compute: impl FnOnce() -> anyhow::Result<Vec<Suggestion>>,
+    ) -> anyhow::Result<Vec<Suggestion>> {
+        // Every field but the time budget can change the result, so the rest of the request is the key
+        let key = serde_json::to_string(&PredictParams { deadline_ms: None, ..params.clone() })?;
+        let generation = {
+            let mut cache = self.cache.lock().unwrap();
+            if let Some(cached) = cache.get(&key) {
+                return Ok(cached.clone());
+            }
+            cache.generation
+        };
+
+        let started = std::time::Instant::now();
+        let suggestions = compute()?;
+        // Results cut short by the deadline are partial; don't serve them again
+        if params.deadline_ms.is_some_and(|ms| started.elapsed().as_millis() >= u128::from(ms)) {
+            return Ok(suggestions);
+        }
+        // Mid-line, substring and popularity-fallback requests aren't keyed by a leading
+        // prefix, so any store may affect them
+        let by_prefix = params.buffer.is_none() && params.match_mode == MatchMode::Prefix && !params.fallback_popular;
+        let prefix = by_prefix.then(|| params.prefix.to_ascii_lowercase());
+        let mut cache = self.cache.lock().unwrap();
+        // A store invalidated the cache while these were computed, so they may be stale
+        if cache.generation == generation {
+            cache.insert_for_prefix(key, suggestions.clone(), prefix);
+        }
+        Ok(suggestions)
+    }
+
+    /// Store predictions in cache
+    pub fn cache_predictions(&self, prefix: &str, cwd: &str, last_cmd: Option<&str>, suggestions: Vec<Suggestion>) {
+        let key = Self::cache_key(prefix, cwd, last_cmd);
+        let mut cache = self.cache.lock().unwrap();
+        cache.insert(key, suggestions);
+    }
+
+    /// Invalidate cache entries matching a prefix pattern
+    pub fn invalidate_cache(&self, prefix: &str) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.invalidate_prefix(prefix);
+    }
+
+    /// Drop cached predictions that a newly stored command could change
+    pub fn invalidate_command(&self, cmd: &str) {
+        self.cache.lock().unwrap().invalidate_command(cmd);
+    }
+
+    /// Drop every cached prediction (hit/miss counters are kept)
+    pub fn invalidate_all(&self) {
+        self.cache.lock().unwrap().clear();
+    }
+
+    /// Current cache size and hit/miss counters
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.lock().unwrap().stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prediction_cache() {
+        let engine = PredictionEngine::new();
+
+        let suggestions = vec![
+            Suggestion {
+                cmd: "git status".to_string(),
+                score: 0.9,
+            },
+            Suggestion {
+                cmd: "git add".to_string(),
+                score: 0.8,
+            },
+        ];
+
+        // Cache miss
+        assert!(engine.get_cached("git", "/home/user", None).is_none());
+
+        // Cache hit after insert
+        engine.cache_predictions("git", "/home/user", None, suggestions.clone());
+        let cached = engine.get_cached("git", "/home/user", None);
+        assert!(cached.is_some());
+        assert_eq!(cached.unwrap().len(), 2);
+
+        // Different context = cache miss
+        assert!(engine.get_cached("git", "/other/dir", None).is_none());
+    }
+
+    #[test]
+    fn test_cache_eviction() {
+        let mut cache = PredictionCache::new(2);
+
+        cache.insert("a".to_string(), vec![]);
+        cache.insert("b".to_string(), vec![]);
+        assert_eq!(cache.entries.len(), 2);
+
+        // Third insert should evict oldest
+        cache.insert("c".to_string(), vec![]);
+        assert_eq!(cache.entries.len(), 2);
+        assert!(cache.entries.contains_key("c"));
+    }
+
+    #[test]
+    fn test_predict_cached_within_ttl_skips_compute() {
+        let engine = PredictionEngine::new();
+        let calls = std::cell::Cell::new(0);
+        let compute = || {
+            calls.set(calls.get() + 1);
+            Ok(vec![Suggestion { cmd: "git status".to_string(), score: 0.9 }])
+        };
+        let params = |prefix: &str| PredictParams {
+            prefix: prefix.to_string(),
+            cwd: "/repo".to_string(),
+            ..Default::default()
+        };
+
+        engine.predict_cached(&params("git"), compute).unwrap();
+        engine.predict_cached(&params("git"), compute).unwrap();
+        assert_eq!(calls.get(), 1);
+
+        // Storing an unrelated command keeps the entry; one it could predict drops it
+        engine.predict_cached(&params("ca"), compute).unwrap();
+        engine.invalidate_command("cargo build");
+        engine.predict_cached(&params("git"), compute).unwrap();
+        assert_eq!(calls.get(), 2);
+        engine.invalidate_command("GIT push");
+        engine.predict_cached(&params("git"), compute).unwrap();
+        assert_eq!(calls.get(), 3);
+
+        // Entries expire after the TTL
+        engine.cache.lock().unwrap().ttl = Duration::ZERO;
+        engine.predict_cached(&params("git"), compute).unwrap();
+        assert_eq!(calls.get(), 4);
+    }
+
+    #[test]
+    fn test_predict_cached_stats_and_clear() {
+        let engine = PredictionEngine::new();
+        let params = PredictParams {
+            prefix: "git".to_string(),
+            cwd: "/home/user".to_string(),
+            ..Default::default()
+        };
+        let compute = || Ok(vec![Suggestion { cmd: "git status".to_string(), score: 0.9 }]);
+
+        engine.predict_cached(&params, compute).unwrap();
+        engine.predict_cached(&params, || panic!("should be served from cache")).unwrap();
+        engine.predict_cached(&PredictParams { limit: 10, ..params.clone() }, compute).unwrap();
+
+        let stats = engine.cache_stats();
+        assert_eq!(stats.entries, 2);
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+        assert!(stats.size_bytes > 0);
+
+        engine.invalidate_all();
+        let stats = engine.cache_stats();
+        assert_eq!(stats.entries, 0);
+        assert_eq!(stats.size_bytes, 0);
+        assert_eq!(stats.hits, 1);
+    }
+
+    #[test]
+    fn test_predict_cached_drops_results_computed_across_an_invalidation() {
+        let engine = PredictionEngine::new();
+        let params = PredictParams {
+            prefix: "git".to_string(),
+            cwd: "/repo".to_string(),
+            ..Default::default()
+        };
+
+        // A store lands while the prediction is being computed
+        engine.predict_cached(&params, || {
+            engine.invalidate_command("git push");
+            Ok(vec![Suggestion { cmd: "git status".to_string(), score: 0.9 }])
+        }).unwrap();
+        assert_eq!(engine.cache_stats().entries, 0);
+
+        engine.predict_cached(&params, || Ok(vec![])).unwrap();
+        assert_eq!(engine.cache_stats().entries, 1);
+    }
+}
@@ -0,0 +1,545 @@
+//! Command parser for argument-aware suggestions.
+//!
+//! Parses commands into program, subcommand, and arguments. [`parse_command`]
+//! reads a line as one command with a simple quote-aware tokenizer;
+//! [`parse_statements`] is the opt-in shell-aware mode, which splits a compound
+//! line into its statements and parses the ones inside `$(...)` and backticks too.
+
+/// Parsed command structure
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedCommand {
+    /// The program (first token): git, docker, npm, cargo, etc.
+    pub program: String,
+    /// Subcommand for multi-level CLIs: commit, push, run, build, etc.
+    pub subcommand: Option<String>,
+    /// Remaining arguments after program and subcommand
+    pub args: Vec<String>,
+    /// Leading `NAME=value` assignments, including those after an `env` wrapper
+    pub env: Vec<String>,
+    /// Wrappers run before the program (`sudo`, `env`, `nice`), with their flags
+    pub wrappers: Vec<String>,
+    /// The original full command
+    pub full: String,
+}
+
+impl ParsedCommand {
+    /// Check if this is a partial command (ends with space, expecting more input)
+    pub fn is_partial(&self) -> bool {
+        self.full.ends_with(' ')
+    }
+
+    /// Get the prefix for argument lookup (program + subcommand)
+    #[allow(dead_code)]
+    pub fn arg_lookup_key(&self) -> String {
+        match &self.subcommand {
+            Some(sub) => format!("{} {}", self.program, sub),
+            None => self.program.clone(),
+        }
+    }
+}
+
+/// Programs known to have subcommands
+const SUBCOMMAND_PROGRAMS: &[&str] = &[
+    "git", "docker", "docker-compose", "kubectl", "npm", "yarn", "pnpm",
+    "cargo", "rustup", "go", "pip", "poetry", "conda", "brew", "apt",
+    "systemctl", "journalctl", "aws", "gcloud", "az", "terraform",
+    "make", "cmake", "gradle", "mvn", "dotnet", "mix", "bundle",
+];
+
+/// Programs that run another command given after their own flags, with
+/// those of their flags that take a value as the next token
+const WRAPPER_PROGRAMS: &[(&str, &[&str])] = &[
+    ("sudo", &["-u", "-g", "-C", "-h", "-p", "-r", "-t", "-U"]),
+    ("nice", &["-n"]),
+    ("env", &["-u", "-C", "-S"]),
+];
+
+/// Whether a token is a `NAME=value` environment assignment
+fn is_env_assignment(token: &str) -> bool {
+    let Some((name, _)) = token.split_once('=') else {
+        return false;
+    };
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parse a command string into structured components
+pub fn parse_command(cmd: &str) -> ParsedCommand {
+    parse_tokens(cmd, tokenize(cmd.trim()))
+}
+
+/// Parse each statement of a command line: those joined by `&&`, `||`, `;`
+/// and `|`, then those inside its `$(...)` and backtick substitutions (and
+/// theirs). A substitution stays one argument of the statement it's in, so
+/// `foo $(bar)` is `foo` with an argument, plus `bar` on its own.
+pub fn parse_statements(cmd: &str) -> Vec<ParsedCommand> {
+    let scan = scan_shell(cmd);
+    let mut statements: Vec<ParsedCommand> = split_top_level(cmd, &scan.top)
+        .into_iter()
+        .map(|statement| parse_tokens(statement, tokenize_top_level(statement)))
+        .collect();
+    // `$((...))` is arithmetic, not a command
+    for body in scan.substitutions.into_iter().filter(|body| !body.starts_with('(')) {
+        statements.extend(parse_statements(body));
+    }
+    statements.retain(|parsed| !parsed.program.is_empty());
+    statements
+}
+
+fn parse_tokens(original: &str, mut tokens: Vec<&str>) -> ParsedCommand {
+    if tokens.is_empty() {
+        return ParsedCommand {
+            program: String::new(),
+            subcommand: None,
+            args: vec![],
+            env: vec![],
+            wrappers: vec![],
+            full: original.to_string(),
+        };
+    }
+
+    // Peel off `FOO=bar` assignments and `sudo`/`env`/`nice` wrappers; if
+    // nothing follows them, the first token is the program after all
+    let (mut env, mut wrappers) = (vec![], vec![]);
+    let mut start = 0;
+    loop {
+        while start < tokens.len() && is_env_assignment(tokens[start]) {
+            env.push(tokens[start].to_string());
+            start += 1;
+        }
+        if let Some(&(wrapper, value_flags)) = tokens.get(start).and_then(|t| WRAPPER_PROGRAMS.iter().find(|(w, _)| w == t)) {
+            wrappers.push(wrapper.to_string());
+            start += 1;
+            while start < tokens.len() && tokens[start].starts_with('-') {
+                if value_flags.contains(&tokens[start]) && start + 1 < tokens.len() {
+                    wrappers.push(tokens[start].to_string());
+                    start += 1;
+                }
+                wrappers.push(tokens[start].to_string());
+                start += 1;
+            }
+            continue;
+        }
+        break;
+    }
+    if start < tokens.len() {
+        tokens.drain(..start);
+    } else {
+        env.clear();
+        wrappers.clear();
+    }
+
+    let program = tokens[0].to_string();
+
+    // Check if this program uses subcommands
+    let has_subcommand = SUBCOMMAND_PROGRAMS
+        .iter()
+        .any(|&p| p.eq_ignore_ascii_case(&program));
+
+    let (subcommand, args) = if has_subcommand && tokens.len() > 1 {
+        // Second token is subcommand if it doesn't start with - (flag)
+        let potential_sub = tokens[1];
+        if !potential_sub.starts_with('-') {
+            (
+                Some(potential_sub.to_string()),
+                tokens[2..].iter().map(|s| s.to_string()).collect(),
+            )
+        } else {
+            (None, tokens[1..].iter().map(|s| s.to_string()).collect())
+        }
+    } else {
+        (None, tokens[1..].iter().map(|s| s.to_string()).collect())
+    };
+
+    ParsedCommand {
+        program,
+        subcommand,
+        args,
+        env,
+        wrappers,
+        full: original.to_string(),
+    }
+}
+
+/// Quote tracking shared by the tokenizer and the pipeline splitter
+#[derive(Default)]
+struct QuoteState {
+    single: bool,
+    double: bool,
+}
+
+impl QuoteState {
+    /// Step over `c`; true if it is an ordinary character outside any quotes
+    fn advance(&mut self, c: char) -> bool {
+        match c {
+            '\'' if !self.double => {
+                self.single = !self.single;
+                false
+            }
+            '"' if !self.single => {
+                self.double = !self.double;
+                false
+            }
+            _ => !self.single && !self.double,
+        }
+    }
+}
+
+/// Simple tokenizer that handles basic quoting
+fn tokenize(cmd: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut quotes = QuoteState::default();
+    let mut token_start: Option<usize> = None;
+
+    for (i, c) in cmd.char_indices() {
+        if quotes.advance(c) && matches!(c, ' ' | '\t') {
+            if let Some(start) = token_start {
+                tokens.push(&cmd[start..i]);
+                token_start = None;
+            }
+        } else if token_start.is_none() {
+            token_start = Some(i);
+        }
+    }
+
+    // Push final token
+    if let Some(start) = token_start {
+        tokens.push(&cmd[start..]);
+    }
+
+    tokens
+}
+
+/// Split a command line into the commands joined by top-level `&&`, `||`, `;`
+/// and `|`, leaving quoted, backslash-escaped and substituted operators alone
+pub fn split_pipeline(cmd: &str) -> Vec<&str> {
+    split_top_level(cmd, &scan_shell(cmd).top)
+}
+
+/// Where a command line's own syntax is, for the shell-aware parsers
+struct ShellScan<'a> {
+    /// Each character, and whether it's outside quotes, escapes and substitutions
+    top: Vec<(usize, char, bool)>,
+    /// Bodies of the outermost `$(...)` and backtick substitutions
+    substitutions: Vec<&'a str>,
+}
+
+fn scan_shell(cmd: &str) -> ShellScan<'_> {
+    #[derive(PartialEq)]
+    enum Open {
+        Paren,
+        Backtick,
+    }
+    let mut top = Vec::new();
+    let mut substitutions = Vec::new();
+    // Open substitutions: their kind, where the body starts and the quotes around them
+    let mut open: Vec<(Open, usize, QuoteState)> = Vec::new();
+    let mut quotes = QuoteState::default();
+    let mut escaped = false;
+    let mut chars = cmd.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if escaped {
+            escaped = false;
+            top.push((i, c, false));
+            continue;
+        }
+        if c == '\\' && !quotes.single {
+            escaped = true;
+            top.push((i, c, false));
+            continue;
+        }
+        if !quotes.single {
+            let opens = match c {
+                '$' if chars.peek().is_some_and(|&(_, next)| next == '(') => Some(Open::Paren),
+                '`' if open.last().is_none_or(|(kind, ..)| *kind != Open::Backtick) => Some(Open::Backtick),
+                _ => None,
+            };
+            if let Some(kind) = opens {
+                top.push((i, c, false));
+                if kind == Open::Paren {
+                    let (j, paren) = chars.next().unwrap();
+                    top.push((j, paren, false));
+                }
+                let body = i + if kind == Open::Paren { 2 } else { 1 };
+                open.push((kind, body, std::mem::take(&mut quotes)));
+                continue;
+            }
+            let closes = match open.last() {
+                Some((Open::Paren, ..)) => c == ')' && !quotes.double,
+                Some((Open::Backtick, ..)) => c == '`',
+                None => false,
+            };
+            if closes {
+                let (_, body, outer) = open.pop().unwrap();
+                if open.is_empty() {
+                    substitutions.push(&cmd[body..i]);
+                }
+                quotes = outer;
+                top.push((i, c, false));
+                continue;
+            }
+        }
+        let unquoted = quotes.advance(c);
+        top.push((i, c, unquoted && open.is_empty()));
+    }
+
+    ShellScan { top, substitutions }
+}
+
+/// Split `cmd` at the `&&`, `||`, `;`, `|` and `|&` operators `top` marks as its own
+fn split_top_level<'a>(cmd: &'a str, top: &[(usize, char, bool)]) -> Vec<&'a str> {
+    let mut segments = vec![];
+    let mut start = 0;
+    let mut chars = top.iter().peekable();
+
+    while let Some(&(i, c, is_top)) = chars.next() {
+        if !is_top {
+            continue;
+        }
+        let end = match (c, chars.peek().filter(|&&&(_, _, next_top)| next_top).map(|&&(_, next, _)| next)) {
+            ('&', Some('&')) | ('|', Some('|')) | ('|', Some('&')) => {
+                chars.next();
+                i + 2
+            }
+            ('|' | ';', _) => i + 1,
+            _ => continue,
+        };
+        segments.push(cmd[start..i].trim());
+        start = end;
+    }
+    segments.push(cmd[start..].trim());
+
+    segments.retain(|s| !s.is_empty());
+    segments
+}
+
+/// Tokenize at top-level whitespace, keeping quoted strings and substitutions whole
+fn tokenize_top_level(cmd: &str) -> Vec<&str> {
+    let cmd = cmd.trim();
+    let mut tokens = vec![];
+    let mut token_start: Option<usize> = None;
+
+    for (i, c, is_top) in scan_shell(cmd).top {
+        if is_top && matches!(c, ' ' | '\t' | '\n') {
+            if let Some(start) = token_start.take() {
+                tokens.push(&cmd[start..i]);
+            }
+        } else if token_start.is_none() {
+            token_start = Some(i);
+        }
+    }
+    if let Some(start) = token_start {
+        tokens.push(&cmd[start..]);
+    }
+
+    tokens
+}
+
+/// Whether a command's arguments are branch names (`git checkout`, `git switch`)
+pub fn is_branch_command(parsed: &ParsedCommand) -> bool {
+    parsed.program == "git" && matches!(parsed.subcommand.as_deref(), Some("checkout" | "switch"))
+}
+
+/// Extract the "interesting" argument from a command for learning
+/// Filters out common flags and focuses on values like branch names, file paths, etc.
+pub fn extract_learnable_args(parsed: &ParsedCommand) -> Vec<String> {
+    let mut learnable = vec![];
+
+    for (i, arg) in parsed.args.iter().enumerate() {
+        // Skip common flags
+        if arg.starts_with('-') {
+            // But capture the value after flags like -m, -b, --message
+            // (next arg if this is a value-taking flag)
+            continue;
+        }
+
+        // Skip if previous arg was a flag that takes a value
+        if i > 0 {
+            let prev = &parsed.args[i - 1];
+            if matches!(prev.as_str(), "-m" | "-b" | "--message" | "--branch" | "-f" | "--file") {
+                // This is a flag value, might be interesting
+                // But skip commit messages (too unique)
+                if prev != "-m" && prev != "--message" {
+                    learnable.push(arg.clone());
+                }
+                continue;
+            }
+        }
+
+        // Skip very long args (likely paths or messages)
+        if arg.len() > 100 {
+            continue;
+        }
+
+        // Include branch names, file names, package names, etc.
+        learnable.push(arg.clone());
+    }
+
+    learnable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_simple_command() {
+        let parsed = parse_command("ls -la");
+        assert_eq!(parsed.program, "ls");
+        assert_eq!(parsed.subcommand, None);
+        assert_eq!(parsed.args, vec!["-la"]);
+    }
+
+    #[test]
+    fn test_parse_git_command() {
+        let parsed = parse_command("git commit -m 'test message'");
+        assert_eq!(parsed.program, "git");
+        assert_eq!(parsed.subcommand, Some("commit".to_string()));
+        assert_eq!(parsed.args, vec!["-m", "'test message'"]);
+    }
+
+    #[test]
+    fn test_parse_git_checkout() {
+        let parsed = parse_command("git checkout feature/login");
+        assert_eq!(parsed.program, "git");
+        assert_eq!(parsed.subcommand, Some("checkout".to_string()));
+        assert_eq!(parsed.args, vec!["feature/login"]);
+    }
+
+    #[test]
+    fn test_parse_docker_command() {
+        let parsed = parse_command("docker run -it ubuntu bash");
+        assert_eq!(parsed.program, "docker");
+        assert_eq!(parsed.subcommand, Some("run".to_string()));
+        assert_eq!(parsed.args, vec!["-it", "ubuntu", "bash"]);
+    }
+
+    #[test]
+    fn test_parse_with_flags_first() {
+        let parsed = parse_command("git -C /path status");
+        assert_eq!(parsed.program, "git");
+        // -C is a flag, so status should still be detected
+        // Actually our simple parser treats -C as not a subcommand
+        assert_eq!(parsed.subcommand, None);
+    }
+
+    #[test]
+    fn test_partial_command() {
+        let parsed = parse_command("git checkout ");
+        assert!(parsed.is_partial());
+        assert_eq!(parsed.arg_lookup_key(), "git checkout");
+    }
+
+    #[test]
+    fn test_tokenize_quoted() {
+        let tokens = tokenize("echo 'hello world' foo");
+        assert_eq!(tokens, vec!["echo", "'hello world'", "foo"]);
+    }
+
+    #[test]
+    fn test_split_pipeline() {
+        assert_eq!(split_pipeline("make build && make test"), vec!["make build", "make test"]);
+        assert_eq!(split_pipeline("a || b; c | d |& e"), vec!["a", "b", "c", "d", "e"]);
+        assert_eq!(split_pipeline("ls"), vec!["ls"]);
+        // Quoted and escaped operators, and redirections, stay put
+        assert_eq!(split_pipeline("echo 'a && b' \"c|d\""), vec!["echo 'a && b' \"c|d\""]);
+        assert_eq!(split_pipeline("find . -exec rm {} \\; && ls"), vec!["find . -exec rm {} \\;", "ls"]);
+        assert_eq!(split_pipeline("make 2>&1 | tee log"), vec!["make 2>&1", "tee log"]);
+        assert_eq!(split_pipeline("cd /tmp;"), vec!["cd /tmp"]);
+    }
+
+    #[test]
+    fn test_split_pipeline_leaves_substitutions_whole() {
+        assert_eq!(split_pipeline("echo $(a; b) && ls"), vec!["echo $(a; b)", "ls"]);
+        assert_eq!(split_pipeline("echo `a | b`; ls"), vec!["echo `a | b`", "ls"]);
+        assert_eq!(split_pipeline("echo \"$(date | cut -c1)\" | wc"), vec!["echo \"$(date | cut -c1)\"", "wc"]);
+    }
+
+    #[test]
+    fn test_parse_statements_splits_chained_commands() {
+        let statements = parse_statements("git checkout main; cargo build --release");
+        let programs: Vec<(&str, Option<&str>)> =
+            statements.iter().map(|p| (p.program.as_str(), p.subcommand.as_deref())).collect();
+        assert_eq!(programs, vec![("git", Some("checkout")), ("cargo", Some("build"))]);
+        assert_eq!(statements[0].args, vec!["main"]);
+        assert_eq!(statements[1].full, "cargo build --release");
+    }
+
+    #[test]
+    fn test_parse_statements_keeps_substitutions_nested() {
+        let statements = parse_statements("foo $(bar --baz qux) `date +%s`");
+        assert_eq!(statements[0].program, "foo");
+        assert_eq!(statements[0].args, vec!["$(bar --baz qux)", "`date +%s`"]);
+        // Each substitution is a statement of its own, not the line's program
+        let nested: Vec<&str> = statements[1..].iter().map(|p| p.program.as_str()).collect();
+        assert_eq!(nested, vec!["bar", "date"]);
+        assert_eq!(statements[1].args, vec!["--baz", "qux"]);
+
+        // Arithmetic isn't a command
+        assert_eq!(parse_statements("echo $((1 + 2))").len(), 1);
+        // The simple parser still sees the substitution's words
+        assert_eq!(parse_command("foo $(bar baz)").args, vec!["$(bar", "baz)"]);
+    }
+
+    #[test]
+    fn test_extract_learnable_args() {
+        let parsed = parse_command("git checkout -b feature/new-thing");
+        let learnable = extract_learnable_args(&parsed);
+        assert!(learnable.contains(&"feature/new-thing".to_string()));
+    }
+
+    #[test]
+    fn test_parse_env_assignments() {
+        let parsed = parse_command("FOO=bar RUST_LOG=debug git status");
+        assert_eq!(parsed.program, "git");
+        assert_eq!(parsed.subcommand, Some("status".to_string()));
+        assert_eq!(parsed.env, vec!["FOO=bar", "RUST_LOG=debug"]);
+        assert!(parsed.wrappers.is_empty());
+
+        // Not assignments: no name, or an invalid one
+        assert_eq!(parse_command("=x ls").program, "=x");
+        assert_eq!(parse_command("1A=x ls").program, "1A=x");
+        // Nothing but an assignment
+        assert_eq!(parse_command("FOO=bar").program, "FOO=bar");
+    }
+
+    #[test]
+    fn test_parse_wrappers() {
+        let parsed = parse_command("sudo apt install ripgrep");
+        assert_eq!(parsed.program, "apt");
+        assert_eq!(parsed.subcommand, Some("install".to_string()));
+        assert_eq!(parsed.args, vec!["ripgrep"]);
+        assert_eq!(parsed.wrappers, vec!["sudo"]);
+
+        let parsed = parse_command("sudo -u www-data env -i PATH=/bin nice -n 10 make build");
+        assert_eq!(parsed.program, "make");
+        assert_eq!(parsed.subcommand, Some("build".to_string()));
+        assert_eq!(parsed.env, vec!["PATH=/bin"]);
+        assert_eq!(parsed.wrappers, vec!["sudo", "-u", "www-data", "env", "-i", "nice", "-n", "10"]);
+
+        // Flags only take a value for the wrapper they belong to
+        let parsed = parse_command("sudo -n apt install ripgrep");
+        assert_eq!(parsed.program, "apt");
+        assert_eq!(parsed.subcommand, Some("install".to_string()));
+        assert_eq!(parsed.wrappers, vec!["sudo", "-n"]);
+
+        let parsed = parse_command("env -u HOME make test");
+        assert_eq!(parsed.program, "make");
+        assert_eq!(parsed.wrappers, vec!["env", "-u", "HOME"]);
+
+        // A bare wrapper is the program itself
+        let parsed = parse_command("sudo ");
+        assert_eq!(parsed.program, "sudo");
+        assert!(parsed.is_partial());
+    }
+
+    #[test]
+    fn test_cargo_command() {
+        let parsed = parse_command("cargo build --release");
+        assert_eq!(parsed.program, "cargo");
+        assert_eq!(parsed.subcommand, Some("build".to_string()));
+        assert_eq!(parsed.args, vec!["--release"]);
+    }
+}
@@ -75,24 +75,24 @@ impl ContextRanker {
         }
 
         // Project type match (0.0 - 0.20)
-        if let Some(ref project) = context.project_type {
-            if Self::matches_project_type(cmd, project) {
-                score += 0.20;
-            }
+        if let Some(ref project) = context.project_type
+            && Self::matches_project_type(cmd, project)
+        {
+            score += 0.20;
         }
 
         // VCS branch pattern (0.0 - 0.15)
-        if let Some(ref branch) = context.vcs_branch {
-            if Self::matches_branch_pattern(cmd, branch) {
-                score += 0.15;
-            }
+        if let Some(ref branch) = context.vcs_branch
+            && Self::matches_branch_pattern(cmd, branch)
+        {
+            score += 0.15;
         }
 
         score.min(1.0)
     }
 
     /// Check if command matches typical commands for a project type
-    fn matches_project_type(cmd: &str, project: &str) -> bool {
+    pub fn matches_project_type(cmd: &str, project: &str) -> bool {
         let cmd_lower = cmd.to_lowercase();
 
         match project {
@@ -129,6 +129,26 @@ impl ContextRanker {
                     || cmd_lower.starts_with("gradle ")
                     || cmd_lower.starts_with("java ")
             }
+            "nix" => {
+                cmd_lower.starts_with("nix ")
+                    || cmd_lower.starts_with("nix-")
+                    || cmd_lower.starts_with("home-manager ")
+            }
+            "bazel" => {
+                cmd_lower.starts_with("bazel ")
+                    || cmd_lower.starts_with("bazelisk ")
+                    || cmd_lower.starts_with("ibazel ")
+            }
+            "deno" => {
+                cmd_lower.starts_with("deno ")
+            }
+            "bun" => {
+                cmd_lower.starts_with("bun ")
+                    || cmd_lower.starts_with("bunx ")
+            }
+            "gleam" => {
+                cmd_lower.starts_with("gleam ")
+            }
             _ => false,
         }
     }
@@ -208,6 +228,18 @@ mod tests {
         assert!(!ContextRanker::matches_project_type("cargo build", "node"));
     }
 
+    #[test]
+    fn test_matches_project_type_new_ecosystems() {
+        assert!(ContextRanker::matches_project_type("nix build .#default", "nix"));
+        assert!(ContextRanker::matches_project_type("nix-shell -p jq", "nix"));
+        assert!(ContextRanker::matches_project_type("bazel build //...", "bazel"));
+        assert!(ContextRanker::matches_project_type("deno run main.ts", "deno"));
+        assert!(ContextRanker::matches_project_type("bun install", "bun"));
+        assert!(ContextRanker::matches_project_type("gleam test", "gleam"));
+        assert!(!ContextRanker::matches_project_type("npm install", "deno"));
+        assert!(!ContextRanker::matches_project_type("nixos-rebuild switch", "bazel"));
+    }
+
     #[test]
     fn test_recency_decay() {
         let now = SystemTime::now()
@@ -0,0 +1,1088 @@
+//! JSON-RPC 2.0 protocol types for nicehist daemon communication.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// JSON-RPC request
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Request {
+    pub jsonrpc: Option<String>,
+    pub id: Option<serde_json::Value>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Option<serde_json::Value>,
+}
+
+/// JSON-RPC response
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Response {
+    pub jsonrpc: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    pub fn success(id: Option<serde_json::Value>, result: serde_json::Value) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    pub fn error(id: Option<serde_json::Value>, code: i32, message: String) -> Self {
+        Self {
+            jsonrpc: "2.0".to_string(),
+            id,
+            result: None,
+            error: Some(RpcError {
+                code,
+                message,
+                data: None,
+            }),
+        }
+    }
+}
+
+/// JSON-RPC error object
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RpcError {
+    pub code: i32,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<serde_json::Value>,
+}
+
+/// Parameters for the "store" method
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct StoreParams {
+    /// The command that was executed
+    pub cmd: String,
+    /// Current working directory
+    pub cwd: String,
+    /// Exit status of the command (0 = success)
+    #[serde(default)]
+    pub exit_status: Option<i32>,
+    /// Duration in milliseconds
+    #[serde(default)]
+    pub duration_ms: Option<i64>,
+    /// Unix timestamp when command started
+    #[serde(default)]
+    pub start_time: Option<i64>,
+    /// Session ID (shell PID)
+    #[serde(default)]
+    pub session_id: Option<i64>,
+    /// Unix timestamp the session's shell started, telling a new shell that
+    /// reuses a PID apart from the one before it
+    #[serde(default)]
+    pub session_start: Option<i64>,
+    /// Previous command (for n-gram updates)
+    #[serde(default)]
+    pub prev_cmd: Option<String>,
+    /// Second previous command (for trigram updates)
+    #[serde(default)]
+    pub prev2_cmd: Option<String>,
+    /// Third previous command (for 4-gram updates)
+    #[serde(default)]
+    pub prev3_cmd: Option<String>,
+    /// Previous command's exit status (for exit-aware n-grams)
+    #[serde(default)]
+    pub prev_exit: Option<i32>,
+    /// Let `prev_cmd` form n-grams even when it came from another session
+    /// (by default one last run in another session isn't linked)
+    #[serde(default)]
+    pub cross_session_ngrams: bool,
+    /// Shell the command ran in (zsh, bash, ...)
+    #[serde(default)]
+    pub shell: Option<String>,
+    /// Terminal or multiplexer the shell ran in (e.g. $TERM_PROGRAM, tmux)
+    #[serde(default)]
+    pub terminal: Option<String>,
+    /// Terminal device the shell ran on (e.g. $TTY, /dev/pts/3)
+    #[serde(default)]
+    pub tty: Option<String>,
+    /// Directories (and everything below them) to never record history from
+    #[serde(default)]
+    pub exclude_dirs: Vec<String>,
+    /// Also learn n-grams between the commands of a compound line (`a && b | c`),
+    /// and argument patterns from each of them and from `$(...)` substitutions
+    #[serde(default)]
+    pub split_pipelines: bool,
+    /// Skip the command if it was already stored in the same directory within
+    /// this many seconds of `start_time`, e.g. when re-importing a history file.
+    /// Ignored without a `start_time`. A skipped duplicate teaches no n-grams either.
+    #[serde(default)]
+    pub dedup_window: Option<i64>,
+}
+
+/// Configurable ranking weights for prediction scoring
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RankingWeights {
+    /// Weight for command frequency score (default: 0.35)
+    #[serde(default = "default_freq_weight")]
+    pub frequency: f64,
+    /// Weight for recency score (default: 0.30)
+    #[serde(default = "default_recency_weight")]
+    pub recency: f64,
+    /// Days for command recency decay half-life (default: 30.0)
+    #[serde(default = "default_recency_half_life_days")]
+    pub recency_half_life_days: f64,
+    /// Score for exact directory match (default: 0.35)
+    #[serde(default = "default_dir_exact_weight")]
+    pub dir_exact: f64,
+    /// Score for commands run anywhere under the cwd's project (VCS) root (default: 0.25)
+    #[serde(default = "default_dir_project_weight")]
+    pub dir_project: f64,
+    /// Weight for parent directory hierarchy match (default: 0.15)
+    #[serde(default = "default_dir_hierarchy_weight")]
+    pub dir_hierarchy: f64,
+    /// Failure penalty factor: 100% fail rate → (1 - factor)x score (default: 0.5)
+    #[serde(default = "default_failure_penalty")]
+    pub failure_penalty: f64,
+    /// Maximum frecent directory boost (default: 0.1)
+    #[serde(default = "default_frecent_boost_max")]
+    pub frecent_boost_max: f64,
+    /// Bonus for commands typical of the cwd's project type, e.g. `cargo` in a Rust project (default: 0.15)
+    #[serde(default = "default_project_boost")]
+    pub project_boost: f64,
+    /// Weight for n-gram (bigram/trigram) sequence bonus (default: 0.40)
+    #[serde(default = "default_ngram_weight")]
+    pub ngram: f64,
+    /// Days for n-gram recency decay half-life (default: 60.0)
+    #[serde(default = "default_ngram_recency_halflife")]
+    pub ngram_recency_halflife: f64,
+    /// Multiplier for trigram bonus over bigram (default: 1.5)
+    #[serde(default = "default_ngram_trigram_boost")]
+    pub ngram_trigram_boost: f64,
+    /// Multiplier for 4-gram bonus over bigram (default: 2.0)
+    #[serde(default = "default_ngram_fourgram_boost")]
+    pub ngram_fourgram_boost: f64,
+    /// Multiplier when exit-aware data matches (default: 1.2)
+    #[serde(default = "default_ngram_exit_boost")]
+    pub ngram_exit_boost: f64,
+    /// Penalty factor for commands with local file args when searching from different dir (default: 0.3)
+    #[serde(default = "default_local_file_penalty")]
+    pub local_file_penalty: f64,
+    /// Most runs per hour fed into the frequency score, so a burst can't outweigh
+    /// steady use; 0 disables (default: 0)
+    #[serde(default)]
+    pub frequency_cap: f64,
+    /// Penalty for similarity to higher-ranked predictions, spreading the top-N
+    /// across different commands; 0 disables (default: 0)
+    #[serde(default)]
+    pub diversity: f64,
+    /// Candidates fetched per requested prediction before re-ranking (default: 5, 1 to 50).
+    /// Higher finds more commands promoted by n-gram/frecent bonuses, at some latency cost
+    #[serde(default = "default_candidate_factor", deserialize_with = "clamp_candidate_factor")]
+    pub candidate_factor: usize,
+    /// Use the n-gram (command sequence) strategy; off skips its lookups (default: true)
+    #[serde(default = "default_true")]
+    pub use_ngram: bool,
+    /// Use the frecent directory boost; off skips its lookup (default: true)
+    #[serde(default = "default_true")]
+    pub use_frecent: bool,
+    /// Use directory and project affinity (default: true)
+    #[serde(default = "default_true")]
+    pub use_directory: bool,
+    /// Bonus for suggestions you've picked when they were shown, scaled by how
+    /// often you picked them over the alternatives (default: 0.15)
+    #[serde(default = "default_accept_weight")]
+    pub accept: f64,
+    /// Bonus for commands run in a recently started shell session, fading over
+    /// about a day, so the current session's work beats older sessions' (default: 0.1)
+    #[serde(default = "default_session_recency_weight")]
+    pub session_recency: f64,
+}
+
+impl Default for RankingWeights {
+    fn default() -> Self {
+        Self {
+            frequency: 0.35,
+            recency: 0.30,
+            recency_half_life_days: 30.0,
+            dir_exact: 0.35,
+            dir_project: 0.25,
+            dir_hierarchy: 0.15,
+            failure_penalty: 0.5,
+            frecent_boost_max: 0.1,
+            project_boost: 0.15,
+            ngram: 0.40,
+            ngram_recency_halflife: 60.0,
+            ngram_trigram_boost: 1.5,
+            ngram_fourgram_boost: 2.0,
+            ngram_exit_boost: 1.2,
+            local_file_penalty: 0.3,
+            frequency_cap: 0.0,
+            diversity: 0.0,
+            candidate_factor: 5,
+            use_ngram: true,
+            use_frecent: true,
+            use_directory: true,
+            accept: 0.15,
+            session_recency: 0.1,
+        }
+    }
+}
+
+fn default_freq_weight() -> f64 { 0.35 }
+fn default_recency_weight() -> f64 { 0.30 }
+fn default_recency_half_life_days() -> f64 { 30.0 }
+fn default_dir_exact_weight() -> f64 { 0.35 }
+fn default_dir_project_weight() -> f64 { 0.25 }
+fn default_dir_hierarchy_weight() -> f64 { 0.15 }
+fn default_failure_penalty() -> f64 { 0.5 }
+fn default_frecent_boost_max() -> f64 { 0.1 }
+fn default_project_boost() -> f64 { 0.15 }
+fn default_ngram_weight() -> f64 { 0.40 }
+fn default_ngram_recency_halflife() -> f64 { 60.0 }
+fn default_ngram_trigram_boost() -> f64 { 1.5 }
+fn default_ngram_fourgram_boost() -> f64 { 2.0 }
+fn default_ngram_exit_boost() -> f64 { 1.2 }
+fn default_local_file_penalty() -> f64 { 0.3 }
+fn default_candidate_factor() -> usize { 5 }
+
+/// Largest `candidate_factor` accepted; bigger values are lowered to it
+pub const MAX_CANDIDATE_FACTOR: usize = 50;
+
+/// Keep `candidate_factor` within 1..=MAX_CANDIDATE_FACTOR, wherever the weights come from
+fn clamp_candidate_factor<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    Ok(usize::deserialize(deserializer)?.clamp(1, MAX_CANDIDATE_FACTOR))
+}
+fn default_accept_weight() -> f64 { 0.15 }
+fn default_session_recency_weight() -> f64 { 0.1 }
+
+/// Parameters for the "predict" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PredictParams {
+    /// The current input prefix to complete. Required: an empty string asks for
+    /// the best commands overall, while leaving it out is an invalid request
+    pub prefix: String,
+    /// Current working directory
+    pub cwd: String,
+    /// Recent commands for context (most recent first). Only the first three
+    /// are used, the context of the longest n-gram; any more are ignored
+    #[serde(default)]
+    pub last_cmds: Vec<String>,
+    /// Maximum number of suggestions to return
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+    /// Enable frecent directory boost (cross-pollination)
+    #[serde(default = "default_true")]
+    pub frecent_boost: bool,
+    /// Optional ranking weight overrides
+    #[serde(default)]
+    pub weights: Option<RankingWeights>,
+    /// Last command's exit status (for exit-aware n-gram scoring)
+    #[serde(default)]
+    pub last_exit: Option<i32>,
+    /// Full command line being edited, for completing mid-line (with `cursor_pos`)
+    #[serde(default)]
+    pub buffer: Option<String>,
+    /// Cursor position within `buffer`, in characters
+    #[serde(default)]
+    pub cursor_pos: Option<usize>,
+    /// Project (VCS) root containing `cwd`; the daemon detects it when not given
+    #[serde(default)]
+    pub project_root: Option<String>,
+    /// Project type of `cwd` (rust, node, ...); the daemon detects it when not given
+    #[serde(default)]
+    pub project_type: Option<String>,
+    /// Further project types of `cwd`, e.g. node in a Rust project with a
+    /// package.json; commands of any of them are boosted. The daemon detects
+    /// them along with `project_type`
+    #[serde(default)]
+    pub project_types: Vec<String>,
+    /// Invert recency: favour usual commands that haven't been run lately
+    /// ("did you forget to run the linter?")
+    #[serde(default)]
+    pub since_last: bool,
+    /// Match commands starting with `prefix` (default) or containing it anywhere
+    #[serde(default)]
+    pub match_mode: MatchMode,
+    /// Drop commands that have never once succeeded (runs without an exit status are ignored)
+    #[serde(default)]
+    pub exclude_failed: bool,
+    /// Only history recorded on this host; `*` for every host (default: this machine)
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Rank by frequency and recency alone, ignoring the directory and command context
+    #[serde(default)]
+    pub global: bool,
+    /// Time budget in milliseconds; once spent, the best results so far are returned
+    #[serde(default)]
+    pub deadline_ms: Option<u64>,
+    /// When fewer than `limit` predictions are found, fill the rest with the
+    /// most-used commands for the same program, scored below the others
+    #[serde(default)]
+    pub fallback_popular: bool,
+    /// Only commands run inside projects of this type (rust, node, ...), whatever the cwd is
+    #[serde(default)]
+    pub in_project: Option<String>,
+}
+
+/// How `predict` matches its prefix against commands
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchMode {
+    /// Commands starting with the prefix
+    #[default]
+    Prefix,
+    /// Commands containing the prefix anywhere; those starting with it still rank first
+    Substring,
+}
+
+impl Default for PredictParams {
+    fn default() -> Self {
+        Self {
+            prefix: String::new(),
+            cwd: String::new(),
+            last_cmds: Vec::new(),
+            limit: default_limit(),
+            frecent_boost: true,
+            weights: None,
+            last_exit: None,
+            buffer: None,
+            cursor_pos: None,
+            project_root: None,
+            project_type: None,
+            project_types: Vec::new(),
+            since_last: false,
+            match_mode: MatchMode::Prefix,
+            exclude_failed: false,
+            host: None,
+            global: false,
+            deadline_ms: None,
+            fallback_popular: false,
+            in_project: None,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_limit() -> usize {
+    5
+}
+
+/// A single prediction suggestion
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Suggestion {
+    /// The suggested command
+    pub cmd: String,
+    /// Confidence score (0.0 to 1.0)
+    pub score: f64,
+}
+
+/// Parameters for the "context" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ContextParams {
+    /// Directory to get context for
+    pub cwd: String,
+    /// Also report history stats for the directory (slower; off by default)
+    #[serde(default)]
+    pub stats: bool,
+}
+
+/// Context information for a directory
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ContextInfo {
+    /// VCS type (git, hg, jj, svn, or null)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vcs: Option<String>,
+    /// VCS branch name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// VCS repository root
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vcs_root: Option<String>,
+    /// URL of the repository's origin remote (git only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    /// Detected project type (rust, node, python, etc.)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+    /// Every project type found in the project root (`project` is the first)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub projects: Vec<String>,
+    /// Number of commands run in this exact directory (only with `stats`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir_commands: Option<i64>,
+    /// Most frequently run commands in this directory (only with `stats`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_commands: Option<Vec<CommandCount>>,
+}
+
+/// A command and how many times it was run
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CommandCount {
+    pub cmd: String,
+    pub count: i64,
+}
+
+/// Parameters for the "store_batch" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StoreBatchParams {
+    /// Commands to store, committed together in one transaction
+    pub commands: Vec<StoreParams>,
+}
+
+/// Parameters for the "places" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PlacesParams {
+    /// Maximum places to return
+    #[serde(default = "default_search_limit")]
+    pub limit: usize,
+    /// Order by "count" (default) or "recent"
+    #[serde(default)]
+    pub sort: PlaceSort,
+}
+
+/// Ordering for the "places" method
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaceSort {
+    /// Most commands first
+    #[default]
+    Count,
+    /// Most recently used first
+    Recent,
+}
+
+/// A directory commands have been run in
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PlaceInfo {
+    pub dir: String,
+    pub host: String,
+    /// Number of commands run there
+    pub count: i64,
+    /// When a command was last run there (Unix timestamp)
+    pub last_used: i64,
+}
+
+/// Parameters for the "delete" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DeleteParams {
+    /// The command string to delete
+    pub cmd: String,
+}
+
+/// Parameters for the "prune_history" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PruneHistoryParams {
+    /// Delete history recorded more than this many days ago
+    pub older_than_days: u32,
+}
+
+/// Result of the "prune_history" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PruneResult {
+    /// History rows deleted
+    pub history: usize,
+    /// Commands deleted because no history refers to them any more
+    pub commands: usize,
+}
+
+/// Parameters for the "accept" method: which shown suggestion the user took
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct AcceptParams {
+    /// What had been typed when the suggestions were shown
+    #[serde(default)]
+    pub prefix: String,
+    /// The suggestion that was accepted
+    pub cmd: String,
+    /// Every suggestion that was shown; the ones not taken count as rejected
+    #[serde(default)]
+    pub shown: Vec<String>,
+}
+
+/// Parameters for the "reset" method
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ResetParams {
+    /// Only count the rows that would be deleted
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Result of the "reset" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ResetResult {
+    /// Rows deleted (or that would be, for a dry run) across every table
+    pub rows: usize,
+    /// Rows per table
+    pub tables: std::collections::BTreeMap<String, usize>,
+}
+
+/// Parameters for the "search" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SearchParams {
+    /// Search pattern (substring match, or a regular expression with `regex`)
+    pub pattern: String,
+    /// Maximum results to return
+    #[serde(default = "default_search_limit")]
+    pub limit: usize,
+    /// Filter by directory (optional)
+    #[serde(default)]
+    pub dir: Option<String>,
+    /// Filter by exit status (optional, 0 = success only)
+    #[serde(default)]
+    pub exit_status: Option<i32>,
+    /// Recent commands for n-gram context scoring (most recent first; only the first three are used)
+    #[serde(default)]
+    pub last_cmds: Vec<String>,
+    /// Current working directory for directory affinity scoring
+    #[serde(default)]
+    pub cwd: Option<String>,
+    /// Enable n-gram context boost in scoring (default: false for backward compat)
+    #[serde(default)]
+    pub ngram_boost: bool,
+    /// Last command's exit status (for exit-aware n-gram scoring)
+    #[serde(default)]
+    pub last_exit: Option<i32>,
+    /// Treat `pattern` as a regular expression instead of a substring
+    #[serde(default)]
+    pub regex: bool,
+    /// Only runs started at or after this Unix timestamp
+    #[serde(default)]
+    pub since: Option<i64>,
+    /// Only runs started at or before this Unix timestamp
+    #[serde(default)]
+    pub until: Option<i64>,
+    /// Skip this many ranked results, for paging. Pages are cut from the score
+    /// order at query time, so commands stored between calls can shift entries
+    /// across page boundaries.
+    #[serde(default)]
+    pub offset: usize,
+    /// Use the full-text index when the pattern allows; off forces a full LIKE
+    /// scan (for benchmarking the two)
+    #[serde(default = "default_true")]
+    pub use_fts: bool,
+    /// Ranking weights (None = daemon defaults)
+    #[serde(default)]
+    pub weights: Option<RankingWeights>,
+    /// Only history recorded on this host; `*` for every host (default: this machine)
+    #[serde(default)]
+    pub host: Option<String>,
+    /// Only runs from this terminal device (optional)
+    #[serde(default)]
+    pub tty: Option<String>,
+    /// Only runs inside projects of this type, e.g. rust or node (optional)
+    #[serde(default)]
+    pub in_project: Option<String>,
+}
+
+impl Default for SearchParams {
+    fn default() -> Self {
+        Self {
+            pattern: String::new(),
+            limit: default_search_limit(),
+            dir: None,
+            exit_status: None,
+            last_cmds: Vec::new(),
+            cwd: None,
+            ngram_boost: false,
+            last_exit: None,
+            regex: false,
+            since: None,
+            until: None,
+            offset: 0,
+            use_fts: true,
+            weights: None,
+            host: None,
+            tty: None,
+            in_project: None,
+        }
+    }
+}
+
+fn default_search_limit() -> usize {
+    20
+}
+
+/// A search result entry
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SearchResult {
+    /// The command
+    pub cmd: String,
+    /// Directory where it was run
+    pub cwd: String,
+    /// When it was run (Unix timestamp)
+    pub timestamp: i64,
+    /// When it was first run (Unix timestamp), among the runs the search matched
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_used: Option<i64>,
+    /// Exit status of the most recent run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_status: Option<i32>,
+    /// Duration in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<i64>,
+    /// Relevance score (0.0 to 1.0) based on recency and exit status
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+    /// Fraction of runs with a known exit status that failed (0.0 to 1.0)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_rate: Option<f64>,
+    /// Byte offsets [start, end) of the pattern match within `cmd`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_span: Option<[usize; 2]>,
+}
+
+/// A newly stored command, as pushed to "subscribe" clients
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StoredCommand {
+    /// History entry id
+    pub id: i64,
+    /// The command, as stored (after redaction)
+    pub cmd: String,
+    /// Directory where it was run
+    pub cwd: String,
+    /// When it was run (Unix timestamp)
+    pub timestamp: i64,
+    /// Exit status
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_status: Option<i32>,
+    /// Duration in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<i64>,
+}
+
+/// Result of the "cache_stats" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CacheStats {
+    /// Cached prediction results
+    pub entries: usize,
+    /// Maximum number of entries before eviction
+    pub capacity: usize,
+    /// Predictions served from the cache since the daemon started
+    pub hits: u64,
+    /// Predictions that had to be computed since the daemon started
+    pub misses: u64,
+    /// Approximate memory held by cached keys and suggestions
+    pub size_bytes: usize,
+}
+
+/// Result of the "stats" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Stats {
+    /// Total history rows
+    pub history: i64,
+    /// Distinct commands
+    pub commands: i64,
+    /// Distinct places (host and directory)
+    pub places: i64,
+    /// Learned command pairs
+    pub bigrams: i64,
+    /// Learned command triples
+    pub trigrams: i64,
+    /// Frecent directories
+    pub frecent_dirs: i64,
+    /// Frecent files
+    pub frecent_files: i64,
+    /// Database size on disk, including the write-ahead log
+    pub db_size_bytes: u64,
+    /// Share of history per recording shell (rows with no shell recorded are excluded)
+    pub shells: Vec<ShellShare>,
+}
+
+/// Result of the "maintenance" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct MaintenanceResult {
+    /// Database size on disk (including the WAL) before maintenance
+    pub size_before: u64,
+    /// Database size on disk (including the WAL) afterwards
+    pub size_after: u64,
+}
+
+/// How many history rows were recorded from a given shell
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ShellShare {
+    /// Shell name
+    pub shell: String,
+    /// Number of history rows
+    pub count: i64,
+    /// Fraction of rows with a known shell (0.0 to 1.0)
+    pub fraction: f64,
+}
+
+/// Parameters for the "frecent_add" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FrecentAddParams {
+    /// The path to add/bump
+    pub path: String,
+    /// Path type: "d" = directory, "f" = file
+    #[serde(default = "default_path_type")]
+    pub path_type: String,
+    /// Override rank (for imports)
+    #[serde(default)]
+    pub rank: Option<f64>,
+    /// Override timestamp (for imports)
+    #[serde(default)]
+    pub timestamp: Option<i64>,
+}
+
+/// Parameters for the "frecent_delete" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FrecentDeleteParams {
+    /// The path to forget
+    pub path: String,
+    /// Path type: "d" = directory, "f" = file
+    #[serde(default = "default_path_type")]
+    pub path_type: String,
+}
+
+fn default_path_type() -> String {
+    "d".to_string()
+}
+
+/// Parameters for the "frecent_add_batch" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FrecentAddBatchParams {
+    /// Paths to add/bump, committed together in one transaction
+    pub entries: Vec<FrecentAddParams>,
+}
+
+/// Parameters for the "frecent_query" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FrecentQueryParams {
+    /// Search terms
+    #[serde(default)]
+    pub terms: Vec<String>,
+    /// Path types to include ("d" = dirs, "f" = files); empty = any.
+    /// The older single `path_type` field is still accepted
+    #[serde(default, alias = "path_type", deserialize_with = "one_or_many")]
+    pub path_types: Vec<String>,
+    /// Maximum results to return
+    #[serde(default = "default_frecent_limit")]
+    pub limit: usize,
+    /// Include raw rank/last_access in results
+    #[serde(default)]
+    pub raw: bool,
+    /// Skip paths that no longer exist on disk
+    #[serde(default)]
+    pub exists_only: bool,
+    /// Paths to leave out (exact match), e.g. the directory you're already in
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Score multipliers by path type, e.g. to favor directories for `cd`;
+    /// unset weighs both types the same
+    #[serde(default)]
+    pub type_weights: Option<FrecentTypeWeights>,
+}
+
+impl Default for FrecentQueryParams {
+    fn default() -> Self {
+        Self {
+            terms: Vec::new(),
+            path_types: Vec::new(),
+            limit: default_frecent_limit(),
+            raw: false,
+            exists_only: false,
+            exclude: Vec::new(),
+            type_weights: None,
+        }
+    }
+}
+
+fn default_frecent_limit() -> usize {
+    20
+}
+
+/// Frecency score multipliers for each path type
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FrecentTypeWeights {
+    /// Directories (default: 1.0)
+    #[serde(default = "default_type_weight")]
+    pub d: f64,
+    /// Files (default: 1.0)
+    #[serde(default = "default_type_weight")]
+    pub f: f64,
+}
+
+impl FrecentTypeWeights {
+    /// Multiplier for a path of `path_type`; other types keep their score
+    pub fn weight(&self, path_type: &str) -> f64 {
+        match path_type {
+            "d" => self.d,
+            "f" => self.f,
+            _ => 1.0,
+        }
+    }
+}
+
+fn default_type_weight() -> f64 { 1.0 }
+
+/// Accept a single string (or null) where a list of strings is expected
+fn one_or_many<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+        Some(OneOrMany::One(value)) => vec![value],
+        Some(OneOrMany::Many(values)) => values,
+        None => Vec::new(),
+    })
+}
+
+/// A frecency result entry
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FrecencyResult {
+    /// The path
+    pub path: String,
+    /// Path type ("d" or "f")
+    pub path_type: String,
+    /// Frecency score
+    pub score: f64,
+    /// Raw rank value (for export)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rank: Option<f64>,
+    /// Last access timestamp (for export)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_access: Option<i64>,
+}
+
+/// Result of the "store" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StoreResult {
+    /// Whether the command was stored
+    pub stored: bool,
+    /// Id of the stored command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    /// Why it was skipped: "dir_excluded", "redacted" or "duplicate"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// Result of the "store_batch" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct StoreBatchResult {
+    /// Commands stored
+    pub stored: usize,
+    /// Commands skipped as duplicates, in excluded directories or redacted
+    pub skipped: usize,
+}
+
+/// Result of the "frecent_add_batch" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CountResult {
+    /// Entries added
+    pub added: usize,
+}
+
+/// Result of the "predict" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PredictResult {
+    pub suggestions: Vec<Suggestion>,
+}
+
+/// Result of the "search" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SearchResults {
+    pub results: Vec<SearchResult>,
+}
+
+/// Result of the "frecent_query" and "frecent_export" methods
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FrecencyResults {
+    pub results: Vec<FrecencyResult>,
+}
+
+/// Result of the "places" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PlacesResult {
+    pub places: Vec<PlaceInfo>,
+}
+
+/// Result of the "delete" and "frecent_delete" methods
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DeleteResult {
+    /// Whether anything was deleted
+    pub deleted: bool,
+}
+
+/// Result of methods that only acknowledge the request
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct OkResult {
+    pub ok: bool,
+}
+
+/// Result of the "ping" method
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PingResult {
+    pub pong: bool,
+}
+
+/// Acknowledgement of the "subscribe" method, before the stream of stored commands
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SubscribeResult {
+    pub subscribed: bool,
+}
+
+/// JSON Schemas for each RPC method's params and result, derived from the
+/// types above, so clients in other languages can validate what they send.
+/// Types used by several methods are referenced from `$defs`.
+pub fn rpc_schema() -> serde_json::Value {
+    use serde_json::{json, Value};
+
+    fn schema<T: JsonSchema>(generator: &mut schemars::SchemaGenerator) -> Value {
+        generator.subschema_for::<T>().to_value()
+    }
+
+    let mut generator = schemars::generate::SchemaSettings::draft2020_12().into_generator();
+    let ok = schema::<OkResult>(&mut generator);
+
+    let methods: Vec<(&str, Option<Value>, Value)> = vec![
+        ("store", Some(schema::<StoreParams>(&mut generator)), schema::<StoreResult>(&mut generator)),
+        ("store_batch", Some(schema::<StoreBatchParams>(&mut generator)), schema::<StoreBatchResult>(&mut generator)),
+        ("predict", Some(schema::<PredictParams>(&mut generator)), schema::<PredictResult>(&mut generator)),
+        ("search", Some(schema::<SearchParams>(&mut generator)), schema::<SearchResults>(&mut generator)),
+        ("context", Some(schema::<ContextParams>(&mut generator)), schema::<ContextInfo>(&mut generator)),
+        ("delete", Some(schema::<DeleteParams>(&mut generator)), schema::<DeleteResult>(&mut generator)),
+        ("accept", Some(schema::<AcceptParams>(&mut generator)), ok.clone()),
+        ("frecent_add", Some(schema::<FrecentAddParams>(&mut generator)), ok.clone()),
+        ("frecent_add_batch", Some(schema::<FrecentAddBatchParams>(&mut generator)), schema::<CountResult>(&mut generator)),
+        ("frecent_delete", Some(schema::<FrecentDeleteParams>(&mut generator)), schema::<DeleteResult>(&mut generator)),
+        ("frecent_query", Some(schema::<FrecentQueryParams>(&mut generator)), schema::<FrecencyResults>(&mut generator)),
+        ("frecent_export", None, schema::<FrecencyResults>(&mut generator)),
+        ("places", Some(schema::<PlacesParams>(&mut generator)), schema::<PlacesResult>(&mut generator)),
+        ("stats", None, schema::<Stats>(&mut generator)),
+        ("prune_history", Some(schema::<PruneHistoryParams>(&mut generator)), schema::<PruneResult>(&mut generator)),
+        ("reset", Some(schema::<ResetParams>(&mut generator)), schema::<ResetResult>(&mut generator)),
+        ("maintenance", None, schema::<MaintenanceResult>(&mut generator)),
+        ("cache_stats", None, schema::<CacheStats>(&mut generator)),
+        ("cache_clear", None, ok.clone()),
+        ("ping", None, schema::<PingResult>(&mut generator)),
+        ("shutdown", None, ok),
+        ("schema", None, json!({"type": "object"})),
+        // Acknowledged with `result`, then each stored command follows as a line of its own
+        ("subscribe", None, schema::<SubscribeResult>(&mut generator)),
+    ];
+
+    let stored = schema::<StoredCommand>(&mut generator);
+    let methods: serde_json::Map<String, Value> = methods
+        .into_iter()
+        .map(|(name, params, result)| {
+            let mut method = json!({"result": result});
+            if let Some(params) = params {
+                method["params"] = params;
+            }
+            if name == "subscribe" {
+                method["stream"] = stored.clone();
+            }
+            (name.to_string(), method)
+        })
+        .collect();
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$defs": generator.take_definitions(true),
+        "methods": methods,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_parse() {
+        let json = r#"{"method": "store", "params": {"cmd": "git status", "cwd": "/home/user"}}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        assert_eq!(req.method, "store");
+        assert!(req.params.is_some());
+    }
+
+    #[test]
+    fn test_store_params_parse() {
+        let json = r#"{"cmd": "git commit -m 'test'", "cwd": "/home/user/project", "exit_status": 0, "duration_ms": 1234}"#;
+        let params: StoreParams = serde_json::from_str(json).unwrap();
+        assert_eq!(params.cmd, "git commit -m 'test'");
+        assert_eq!(params.cwd, "/home/user/project");
+        assert_eq!(params.exit_status, Some(0));
+        assert_eq!(params.duration_ms, Some(1234));
+    }
+
+    #[test]
+    fn test_predict_params_defaults() {
+        let json = r#"{"prefix": "git c", "cwd": "/home/user"}"#;
+        let params: PredictParams = serde_json::from_str(json).unwrap();
+        assert_eq!(params.prefix, "git c");
+        assert_eq!(params.limit, 5); // default
+        assert!(params.last_cmds.is_empty()); // default
+    }
+
+    #[test]
+    fn test_ranking_weights_clamp_candidate_factor() {
+        let factor = |json: &str| serde_json::from_str::<RankingWeights>(json).unwrap().candidate_factor;
+        assert_eq!(factor("{}"), 5);
+        assert_eq!(factor(r#"{"candidate_factor": 0}"#), 1);
+        assert_eq!(factor(r#"{"candidate_factor": 18446744073709551615}"#), MAX_CANDIDATE_FACTOR);
+    }
+
+    #[test]
+    fn test_response_success() {
+        let resp = Response::success(Some(serde_json::json!(1)), serde_json::json!({"id": 42}));
+        assert_eq!(resp.jsonrpc, "2.0");
+        assert!(resp.result.is_some());
+        assert!(resp.error.is_none());
+    }
+
+    #[test]
+    fn test_response_error() {
+        let resp = Response::error(Some(serde_json::json!(7)), -32600, "Invalid Request".to_string());
+        assert_eq!(resp.jsonrpc, "2.0");
+        assert_eq!(resp.id, Some(serde_json::json!(7)));
+        assert!(resp.result.is_none());
+        assert!(resp.error.is_some());
+        assert_eq!(resp.error.unwrap().code, -32600);
+    }
+
+    #[test]
+    fn test_frecent_query_params_path_types() {
+        let parse = |json: &str| serde_json::from_str::<FrecentQueryParams>(json).unwrap().path_types;
+        assert_eq!(parse(r#"{"path_types": ["d", "f"]}"#), ["d", "f"]);
+        assert_eq!(parse(r#"{"path_type": "d"}"#), ["d"]);
+        assert!(parse(r#"{"path_type": null}"#).is_empty());
+        assert!(parse("{}").is_empty());
+    }
+
+    #[test]
+    fn test_rpc_schema_store_params() {
+        let schema = rpc_schema();
+        assert_eq!(schema["methods"]["store"]["params"]["$ref"], "#/$defs/StoreParams");
+
+        let store = &schema["$defs"]["StoreParams"];
+        assert_eq!(store["required"], serde_json::json!(["cmd", "cwd"]));
+        for field in ["exit_status", "duration_ms", "session_id", "exclude_dirs", "split_pipelines"] {
+            assert!(store["properties"].get(field).is_some(), "missing {}", field);
+        }
+        // Doc comments become descriptions
+        assert!(store["properties"]["cmd"]["description"].as_str().is_some());
+
+        // Methods without params have none
+        assert!(schema["methods"]["ping"].get("params").is_none());
+        assert_eq!(schema["methods"]["predict"]["result"]["$ref"], "#/$defs/PredictResult");
+        assert_eq!(schema["$defs"]["PredictResult"]["required"], serde_json::json!(["suggestions"]));
+    }
+
+    #[test]
+    fn test_suggestion_serialize() {
+        let suggestion = Suggestion {
+            cmd: "git commit".to_string(),
+            score: 0.85,
+        };
+        let json = serde_json::to_string(&suggestion).unwrap();
+        assert!(json.contains("git commit"));
+        assert!(json.contains("0.85"));
+    }
+}
@@ -0,0 +1,36 @@
+//! `NICEHIST_HOSTNAME` is process-wide, so it's tested in a binary of its own
+//! rather than alongside tests that store and search under the real hostname.
+
+use nicehist_core::Database;
+use nicehist_core::db::resolve_hostname;
+use nicehist_core::protocol::{SearchParams, StoreParams};
+
+#[test]
+fn test_hostname_override_pins_store_and_search() {
+    // Safety: the only test in this binary, so no other thread reads the environment
+    unsafe { std::env::set_var("NICEHIST_HOSTNAME", "ci-runner") };
+    assert_eq!(resolve_hostname(), "ci-runner");
+
+    let db = Database::open_in_memory().unwrap();
+    db.store_command(&StoreParams {
+        cmd: "cargo test".to_string(),
+        cwd: "/src".to_string(),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let search = |host: Option<&str>| -> Vec<String> {
+        db.search(&SearchParams {
+            pattern: "cargo".to_string(),
+            host: host.map(str::to_string),
+            ..Default::default()
+        })
+        .unwrap()
+        .into_iter()
+        .map(|r| r.cmd)
+        .collect()
+    };
+    assert_eq!(search(None), ["cargo test"]);
+    assert_eq!(search(Some("ci-runner")), ["cargo test"]);
+    assert!(search(Some("elsewhere")).is_empty());
+}
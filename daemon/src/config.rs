@@ -0,0 +1,129 @@
+//! Daemon configuration file.
+//!
+//! Read once at startup from `config.toml` in the config directory, or the
+//! file given with `--config`. Every key is optional:
+//!
+//! ```toml
+//! # Override the socket and database locations
+//! socket = "/run/user/1000/nicehist.sock"
+//! db = "~/history/nicehist.db"
+//!
+//! # Seconds a cached prediction is served
+//! cache_ttl_secs = 10
+//!
+//! # Exit after this many seconds without a request (0 = never)
+//! idle_timeout_secs = 600
+//!
+//! # Store commands verbatim, terminal escape sequences and all
+//! raw_commands = false
+//!
+//! # Most results a predict or frecent request can get, and a search request
+//! max_limit = 1000
+//! max_search_limit = 100000
+//!
+//! # Exit statuses that don't count as failures (Ctrl-C), and whether every
+//! # 128+n status of a command killed by a signal is ignored too
+//! ignored_exit_codes = [130]
+//! ignore_signal_exits = false
+//!
+//! # Ranking weights for predict requests that don't send their own
+//! [weights]
+//! ngram = 0.6
+//! recency = 0.2
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use nicehist_core::db::IgnoredExits;
+use nicehist_core::protocol::RankingWeights;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Socket path, instead of `$XDG_RUNTIME_DIR/nicehist.sock`
+    pub socket: Option<PathBuf>,
+    /// Database path, instead of the data directory's `history.db`
+    pub db: Option<PathBuf>,
+    /// Seconds a cached prediction is served
+    pub cache_ttl_secs: Option<u64>,
+    /// Seconds without a request before the daemon exits (0 = never)
+    pub idle_timeout_secs: Option<u64>,
+    /// Keep control characters in stored commands instead of stripping them
+    pub raw_commands: bool,
+    /// Cap on the `limit` of predict and frecent requests
+    pub max_limit: Option<usize>,
+    /// Cap on the `limit` of search requests
+    pub max_search_limit: Option<usize>,
+    /// Exit statuses that don't count toward a command's failure rate, instead of 130
+    pub ignored_exit_codes: Option<Vec<i32>>,
+    /// Don't count runs killed by a signal (exit status 129-192) as failures
+    pub ignore_signal_exits: bool,
+    /// Default ranking weights; keys left out keep their built-in values
+    pub weights: RankingWeights,
+}
+
+impl Config {
+    /// Read and parse a config file
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid config in {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut config: Self = toml::from_str(text)?;
+        config.socket = config.socket.map(|p| expand_home(&p));
+        config.db = config.db.map(|p| expand_home(&p));
+        Ok(config)
+    }
+
+    pub fn cache_ttl(&self) -> Option<Duration> {
+        self.cache_ttl_secs.map(Duration::from_secs)
+    }
+
+    pub fn ignored_exits(&self) -> IgnoredExits {
+        IgnoredExits {
+            codes: self.ignored_exit_codes.clone().unwrap_or_else(|| IgnoredExits::default().codes),
+            signals: self.ignore_signal_exits,
+        }
+    }
+}
+
+/// Expand a leading `~/` to `$HOME`
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), std::env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse("socket = \"/tmp/x.sock\"\ncache_ttl_secs = 30\n\n[weights]\nngram = 0.9\n").unwrap();
+        assert_eq!(config.socket.as_deref(), Some(Path::new("/tmp/x.sock")));
+        assert_eq!(config.db, None);
+        assert_eq!(config.cache_ttl(), Some(Duration::from_secs(30)));
+        assert!(!config.raw_commands);
+        assert_eq!(config.ignored_exits(), IgnoredExits::default());
+        assert_eq!(config.weights.ngram, 0.9);
+        // Weights not given keep their defaults
+        assert_eq!(config.weights.recency, RankingWeights::default().recency);
+
+        let empty = Config::parse("").unwrap();
+        assert_eq!(empty.cache_ttl(), None);
+        assert_eq!(empty.weights.ngram, RankingWeights::default().ngram);
+
+        let exits = Config::parse("ignored_exit_codes = [130, 141]\nignore_signal_exits = true\n").unwrap().ignored_exits();
+        assert_eq!(exits, IgnoredExits { codes: vec![130, 141], signals: true });
+
+        assert!(Config::parse("sokcet = \"/tmp/x.sock\"").is_err());
+        assert!(Config::parse("cache_ttl_secs = \"soon\"").is_err());
+    }
+}
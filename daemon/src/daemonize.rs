@@ -0,0 +1,177 @@
+//! Process management for running the daemon detached from a shell.
+//!
+//! Provides a pidfile lock so only one daemon serves a socket, and a classic
+//! fork/setsid daemonization that only returns control to the caller once the
+//! new daemon answers a ping.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+/// How long the parent waits for the detached daemon to answer a ping
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pidfile path for a given socket (e.g. nicehist.sock -> nicehist.pid)
+pub fn pid_path(socket: &Path) -> PathBuf {
+    socket.with_extension("pid")
+}
+
+/// Take an exclusive lock on the pidfile and record our pid in it.
+///
+/// Returns `None` if another process already holds the lock. The lock is
+/// released when the returned file is dropped (or the process exits).
+pub fn acquire_lock(path: &Path) -> Result<Option<File>> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .with_context(|| format!("Failed to open pidfile: {}", path.display()))?;
+
+    let rc = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if rc != 0 {
+        let err = std::io::Error::last_os_error();
+        if err.kind() == std::io::ErrorKind::WouldBlock {
+            return Ok(None);
+        }
+        return Err(err).with_context(|| format!("Failed to lock pidfile: {}", path.display()));
+    }
+
+    file.set_len(0)?;
+    file.seek(SeekFrom::Start(0))?;
+    writeln!(file, "{}", std::process::id())?;
+    file.flush()?;
+
+    Ok(Some(file))
+}
+
+/// Read the pid recorded in a pidfile
+pub fn read_pid(path: &Path) -> Option<i32> {
+    let mut content = String::new();
+    File::open(path).ok()?.read_to_string(&mut content).ok()?;
+    content.trim().parse().ok()
+}
+
+/// Format " (pid N)" for messages, or nothing if the pid is unknown
+pub fn pid_suffix(path: &Path) -> String {
+    read_pid(path)
+        .map(|pid| format!(" (pid {})", pid))
+        .unwrap_or_default()
+}
+
+/// Detach from the controlling terminal.
+///
+/// Forks; the parent waits until the child answers a ping on `socket` and then
+/// exits (0 if healthy, 1 otherwise). Only the child returns from this function,
+/// as a session leader with stdio redirected to /dev/null.
+///
+/// Must be called before any threads (e.g. the tokio runtime) are started.
+pub fn daemonize(socket: &Path) -> Result<()> {
+    match unsafe { libc::fork() } {
+        -1 => return Err(std::io::Error::last_os_error()).context("fork failed"),
+        0 => {}
+        child => {
+            if wait_for_health(socket, HEALTH_TIMEOUT) {
+                println!("nicehist-daemon started (pid {})", child);
+                std::process::exit(0);
+            }
+            eprintln!("nicehist-daemon (pid {}) did not become healthy", child);
+            std::process::exit(1);
+        }
+    }
+
+    if unsafe { libc::setsid() } == -1 {
+        return Err(std::io::Error::last_os_error()).context("setsid failed");
+    }
+
+    std::env::set_current_dir("/").ok();
+
+    let devnull = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .context("Failed to open /dev/null")?;
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        if unsafe { libc::dup2(devnull.as_raw_fd(), fd) } == -1 {
+            return Err(std::io::Error::last_os_error()).context("Failed to redirect stdio");
+        }
+    }
+
+    Ok(())
+}
+
+/// Poll the socket until the daemon answers a ping or the timeout expires
+fn wait_for_health(socket: &Path, timeout: Duration) -> bool {
+    let step = Duration::from_millis(50);
+    let mut waited = Duration::ZERO;
+
+    while waited < timeout {
+        if ping(socket) {
+            return true;
+        }
+        std::thread::sleep(step);
+        waited += step;
+    }
+
+    false
+}
+
+/// Send a single ping request and check for a pong
+fn ping(socket: &Path) -> bool {
+    let Ok(mut stream) = UnixStream::connect(socket) else {
+        return false;
+    };
+    stream.set_read_timeout(Some(Duration::from_millis(500))).ok();
+
+    if writeln!(stream, r#"{{"method": "ping"}}"#).is_err() {
+        return false;
+    }
+
+    let mut line = String::new();
+    if BufReader::new(stream).read_line(&mut line).is_err() {
+        return false;
+    }
+
+    serde_json::from_str::<serde_json::Value>(&line)
+        .map(|resp| resp["result"]["pong"] == true)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pid_path() {
+        assert_eq!(
+            pid_path(Path::new("/run/user/1000/nicehist.sock")),
+            PathBuf::from("/run/user/1000/nicehist.pid")
+        );
+    }
+
+    #[test]
+    fn test_lock_prevents_second_instance() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("nicehist.pid");
+
+        let first = acquire_lock(&pid_file).unwrap();
+        assert!(first.is_some());
+        assert!(acquire_lock(&pid_file).unwrap().is_none());
+        assert_eq!(read_pid(&pid_file), Some(std::process::id() as i32));
+
+        drop(first);
+        assert!(acquire_lock(&pid_file).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_ping_without_daemon() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!ping(&dir.path().join("missing.sock")));
+    }
+}
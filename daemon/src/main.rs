@@ -1,19 +1,41 @@
-mod context;
-mod db;
-mod prediction;
-mod protocol;
+mod config;
+mod daemonize;
 
-use std::path::PathBuf;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::{UnixListener, UnixStream};
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::{Notify, broadcast};
+use tokio::task::JoinSet;
 use tracing::{debug, error, info, warn};
 
-use crate::context::ContextCollector;
-use crate::db::Database;
-use crate::protocol::{Request, Response};
+use nicehist_core::context::ContextCollector;
+use nicehist_core::db::{self, Database, StoreOutcome};
+use nicehist_core::prediction::PredictionEngine;
+use nicehist_core::protocol::{self, Request, Response, StoredCommand};
+use nicehist_core::redact::Redactor;
+
+use crate::config::Config;
+use crate::daemonize::{acquire_lock, daemonize, pid_path, pid_suffix};
+
+/// Methods that change stored data, after which cached predictions are stale
+/// ("store" invalidates only the predictions the new command can change)
+const WRITE_METHODS: &[&str] =
+    &["store_batch", "delete", "prune_history", "reset", "accept", "frecent_add", "frecent_add_batch", "frecent_delete"];
+
+/// How long a shutdown waits for in-flight requests before dropping them
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+/// Stored commands buffered per subscriber before a slow one starts missing them
+const SUBSCRIBER_BACKLOG: usize = 256;
 
 /// Get the socket path for the daemon
 fn socket_path() -> PathBuf {
@@ -38,113 +60,360 @@ fn db_path() -> PathBuf {
     }
 }
 
-async fn handle_client(stream: UnixStream, db: Database, ctx_collector: Arc<ContextCollector>) {
+/// Get the config directory
+fn config_dir() -> PathBuf {
+    if let Some(proj_dirs) = directories::ProjectDirs::from("", "", "nicehist") {
+        proj_dirs.config_dir().to_path_buf()
+    } else {
+        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+        PathBuf::from(home).join(".config/nicehist")
+    }
+}
+
+/// Get the redaction rules path (see `redact`)
+fn redact_config_path() -> PathBuf {
+    config_dir().join("redact.conf")
+}
+
+/// Get the default config file path (see `config`)
+fn config_path() -> PathBuf {
+    config_dir().join("config.toml")
+}
+
+const USAGE: &str = "Usage: nicehist-daemon [--foreground | --detach] [--config <path>] [--idle-timeout <secs>] [--in-memory]
+
+Options:
+  --foreground           Run in the foreground (default)
+  --detach               Fork into the background, returning once the daemon answers ping
+  --config <path>        Read settings from this file instead of config.toml in the config directory
+  --idle-timeout <secs>  Exit after this many seconds without a request (0 = never, the default)
+  --in-memory            Keep history in memory only; nothing is written to disk
+  -h, --help             Show this help";
+
+/// Command-line options for the daemon binary
+#[derive(Debug, Default)]
+struct Options {
+    /// Fork into the background instead of running in the foreground
+    detach: bool,
+    /// Print usage and exit
+    help: bool,
+    /// Config file to use instead of the default one
+    config: Option<PathBuf>,
+    /// Seconds without a request before exiting, overriding the config file
+    idle_timeout: Option<u64>,
+    /// Use a throwaway in-memory database instead of the database file
+    in_memory: bool,
+}
+
+/// Parse daemon command-line arguments
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options> {
+    let mut options = Options::default();
+    let mut foreground = false;
+
+    while let Some(arg) = args.next() {
+        // Options taking a value accept both `--opt value` and `--opt=value`
+        let (name, inline) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || inline.clone().or_else(|| args.next()).with_context(|| format!("{} requires a value", name));
+        // Flags take none, so `--foreground=no` is an error rather than a silent yes
+        let flag = || inline.is_none().then_some(true).with_context(|| format!("{} takes no value", name));
+        match name {
+            "--foreground" => foreground = flag()?,
+            "--detach" => options.detach = flag()?,
+            "--in-memory" => options.in_memory = flag()?,
+            "-h" | "--help" => options.help = flag()?,
+            "--config" => options.config = Some(PathBuf::from(value()?)),
+            "--idle-timeout" => {
+                let secs = value()?;
+                options.idle_timeout = Some(secs.parse().with_context(|| format!("invalid --idle-timeout: {}", secs))?);
+            }
+            other => bail!("unknown argument: {}", other),
+        }
+    }
+
+    if foreground && options.detach {
+        bail!("--foreground and --detach are mutually exclusive");
+    }
+
+    Ok(options)
+}
+
+/// When the daemon last handled a request, shared by client handlers and the
+/// idle watchdog
+struct Activity {
+    started: Instant,
+    /// Milliseconds after `started`
+    last: AtomicU64,
+    /// Open subscriptions, which keep the daemon busy however quiet they are
+    subscriptions: AtomicUsize,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            last: AtomicU64::new(0),
+            subscriptions: AtomicUsize::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        self.last.store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn idle_for(&self) -> Duration {
+        if self.subscriptions.load(Ordering::Relaxed) > 0 {
+            return Duration::ZERO;
+        }
+        self.started
+            .elapsed()
+            .saturating_sub(Duration::from_millis(self.last.load(Ordering::Relaxed)))
+    }
+}
+
+async fn handle_client(
+    stream: UnixStream,
+    db: Database,
+    ctx_collector: Arc<ContextCollector>,
+    engine: Arc<PredictionEngine>,
+    shutdown: Arc<Notify>,
+    activity: Arc<Activity>,
+    events: broadcast::Sender<StoredCommand>,
+) {
     let (reader, mut writer) = stream.into_split();
     let mut reader = BufReader::new(reader);
     let mut line = String::new();
 
-    loop {
-        line.clear();
-        match reader.read_line(&mut line).await {
-            Ok(0) => break, // EOF
-            Ok(_) => {
-                let response = match serde_json::from_str::<Request>(&line) {
-                    Ok(request) => handle_request(request, &db, &ctx_collector).await,
-                    Err(e) => Response::error(-32700, format!("Parse error: {}", e)),
-                };
-
-                let response_json = serde_json::to_string(&response).unwrap_or_else(|e| {
-                    serde_json::to_string(&Response::error(-32603, format!("Serialize error: {}", e)))
-                        .unwrap()
+    match reader.read_line(&mut line).await {
+        Ok(0) => return, // EOF
+        Ok(_) => {}
+        Err(e) => {
+            error!("Failed to read from client: {}", e);
+            return;
+        }
+    }
+    // Touched again once answered, so a slow request doesn't count as idle time
+    activity.touch();
+
+    if let Ok(request) = serde_json::from_str::<Request>(&line)
+        && request.method == "subscribe"
+    {
+        // Holding only a receiver lets the channel close when serve stops
+        let receiver = events.subscribe();
+        drop(events);
+        activity.subscriptions.fetch_add(1, Ordering::Relaxed);
+        stream_stored(request, reader, writer, receiver).await;
+        activity.subscriptions.fetch_sub(1, Ordering::Relaxed);
+        activity.touch();
+        return;
+    }
+
+    // A JSON array is a batch: each request is handled in order and the
+    // responses come back as an array in the same order
+    let response_json = match serde_json::from_str::<serde_json::Value>(&line) {
+        Ok(serde_json::Value::Array(requests)) if !requests.is_empty() => {
+            let mut responses = Vec::with_capacity(requests.len());
+            for request in requests {
+                responses.push(match Request::deserialize(&request) {
+                    Ok(request) => handle_request(request, &db, &ctx_collector, &engine, &shutdown, &events).await,
+                    Err(e) => Response::error(request.get("id").cloned(), -32600, format!("Invalid request: {}", e)),
                 });
+            }
+            serde_json::to_string(&responses)
+        }
+        Ok(serde_json::Value::Array(_)) => {
+            serde_json::to_string(&Response::error(None, -32600, "Invalid request: empty batch".to_string()))
+        }
+        Ok(value) => {
+            let response = match Request::deserialize(&value) {
+                Ok(request) => handle_request(request, &db, &ctx_collector, &engine, &shutdown, &events).await,
+                Err(e) => Response::error(value.get("id").cloned(), -32600, format!("Invalid request: {}", e)),
+            };
+            serde_json::to_string(&response)
+        }
+        Err(e) => serde_json::to_string(&Response::error(None, -32700, format!("Parse error: {}", e))),
+    };
+    let response_json = response_json.unwrap_or_else(|e| {
+        serde_json::to_string(&Response::error(None, -32603, format!("Serialize error: {}", e)))
+            .unwrap()
+    });
 
-                if let Err(e) = writer.write_all(response_json.as_bytes()).await {
-                    error!("Failed to write response: {}", e);
-                    break;
+    if let Err(e) = writer.write_all(response_json.as_bytes()).await {
+        error!("Failed to write response: {}", e);
+        return;
+    }
+    if let Err(e) = writer.write_all(b"\n").await {
+        error!("Failed to write newline: {}", e);
+        return;
+    }
+    if let Err(e) = writer.shutdown().await {
+        debug!("Failed to shutdown writer: {}", e);
+    }
+    activity.touch();
+}
+
+/// Acknowledge a "subscribe" request, then write each newly stored command as
+/// a JSON line until the client hangs up or the daemon shuts down
+async fn stream_stored(
+    request: Request,
+    mut reader: BufReader<OwnedReadHalf>,
+    mut writer: OwnedWriteHalf,
+    mut receiver: broadcast::Receiver<StoredCommand>,
+) {
+    let ack = Response::success(request.id, to_result(protocol::SubscribeResult { subscribed: true }));
+    if let Err(e) = write_json_line(&mut writer, &ack).await {
+        debug!("Subscriber went away: {}", e);
+        return;
+    }
+    let mut discard = String::new();
+    loop {
+        tokio::select! {
+            stored = receiver.recv() => match stored {
+                Ok(stored) => {
+                    if let Err(e) = write_json_line(&mut writer, &stored).await {
+                        debug!("Subscriber went away: {}", e);
+                        return;
+                    }
                 }
-                if let Err(e) = writer.write_all(b"\n").await {
-                    error!("Failed to write newline: {}", e);
-                    break;
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    warn!("Subscriber fell behind, dropped {} command(s)", missed);
                 }
-                if let Err(e) = writer.shutdown().await {
-                    debug!("Failed to shutdown writer: {}", e);
+                Err(broadcast::error::RecvError::Closed) => {
+                    writer.shutdown().await.ok();
+                    return;
                 }
-                break;
-            }
-            Err(e) => {
-                error!("Failed to read from client: {}", e);
-                break;
-            }
+            },
+            // Subscribers send nothing more, so EOF (or anything else) ends the stream
+            _ = reader.read_line(&mut discard) => return,
         }
     }
 }
 
-async fn handle_request(request: Request, db: &Database, ctx_collector: &ContextCollector) -> Response {
+async fn write_json_line(writer: &mut OwnedWriteHalf, value: &impl serde::Serialize) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await
+}
+
+async fn handle_request(
+    request: Request,
+    db: &Database,
+    ctx_collector: &ContextCollector,
+    engine: &PredictionEngine,
+    shutdown: &Notify,
+    events: &broadcast::Sender<StoredCommand>,
+) -> Response {
     debug!("Handling request: {:?}", request.method);
 
-    match request.method.as_str() {
+    let writes = WRITE_METHODS.contains(&request.method.as_str());
+
+    let response = match request.method.as_str() {
         "store" => {
             if let Some(params) = request.params {
                 match serde_json::from_value::<protocol::StoreParams>(params) {
                     Ok(store_params) => {
                         match db.store_command(&store_params) {
-                            Ok(id) => Response::success(request.id, serde_json::json!({"id": id})),
-                            Err(e) => Response::error(-32000, format!("Store failed: {}", e)),
+                            Ok(outcome) => {
+                                if let StoreOutcome::Stored(id) = outcome {
+                                    engine.invalidate_command(&store_params.cmd);
+                                    // Publish the stored (redacted) text, not what was sent
+                                    if events.receiver_count() > 0
+                                        && let Ok(Some(stored)) = db.stored_command(id)
+                                    {
+                                        events.send(stored).ok();
+                                    }
+                                }
+                                Response::success(request.id, to_result(protocol::StoreResult::from(outcome)))
+                            }
+                            Err(e) => Response::error(request.id, -32000, format!("Store failed: {}", e)),
                         }
                     }
-                    Err(e) => Response::error(-32602, format!("Invalid params: {}", e)),
+                    Err(e) => Response::error(request.id, -32602, format!("Invalid params: {}", e)),
                 }
             } else {
-                Response::error(-32602, "Missing params".to_string())
+                Response::error(request.id, -32602, "Missing params".to_string())
             }
         }
         "predict" => {
             if let Some(params) = request.params {
                 match serde_json::from_value::<protocol::PredictParams>(params) {
-                    Ok(predict_params) => {
-                        match db.predict(&predict_params) {
-                            Ok(suggestions) => Response::success(
-                                request.id,
-                                serde_json::json!({"suggestions": suggestions}),
-                            ),
-                            Err(e) => Response::error(-32000, format!("Predict failed: {}", e)),
+                    Ok(mut predict_params) => {
+                        clamp_limit("predict", &mut predict_params.limit, engine.max_limit());
+                        // Unused context would only split the prediction cache
+                        predict_params.last_cmds.truncate(db::NGRAM_CONTEXT);
+                        if predict_params.weights.is_none() {
+                            predict_params.weights = Some(engine.default_weights().clone());
+                        }
+                        if !predict_params.global
+                            && (predict_params.project_root.is_none() || predict_params.project_type.is_none())
+                        {
+                            let ctx = ctx_collector.get_context(&predict_params.cwd);
+                            predict_params.project_root = predict_params.project_root.or(ctx.vcs_root);
+                            if predict_params.project_type.is_none() {
+                                predict_params.project_type = ctx.project;
+                                predict_params.project_types = ctx.projects;
+                            }
+                        }
+                        match engine.predict_cached(&predict_params, || db.predict(&predict_params)) {
+                            Ok(suggestions) => Response::success(request.id, to_result(protocol::PredictResult { suggestions })),
+                            Err(e) => Response::error(request.id, -32000, format!("Predict failed: {}", e)),
                         }
                     }
-                    Err(e) => Response::error(-32602, format!("Invalid params: {}", e)),
+                    Err(e) => Response::error(request.id, -32602, format!("Invalid params: {}", e)),
                 }
             } else {
-                Response::error(-32602, "Missing params".to_string())
+                Response::error(request.id, -32602, "Missing params".to_string())
             }
         }
         "context" => {
             if let Some(params) = request.params {
                 match serde_json::from_value::<protocol::ContextParams>(params) {
                     Ok(context_params) => {
-                        let ctx = ctx_collector.get_context(&context_params.cwd);
-                        Response::success(request.id, serde_json::to_value(ctx).unwrap())
+                        let mut ctx = ctx_collector.get_context(&context_params.cwd);
+                        let stats = if context_params.stats {
+                            db.dir_stats(&context_params.cwd, 3).map(Some)
+                        } else {
+                            Ok(None)
+                        };
+                        match stats {
+                            Ok(stats) => {
+                                if let Some((total, top)) = stats {
+                                    ctx.dir_commands = Some(total);
+                                    ctx.top_commands = Some(top);
+                                }
+                                Response::success(request.id, to_result(ctx))
+                            }
+                            Err(e) => Response::error(request.id, -32000, format!("Context stats failed: {}", e)),
+                        }
                     }
-                    Err(e) => Response::error(-32602, format!("Invalid params: {}", e)),
+                    Err(e) => Response::error(request.id, -32602, format!("Invalid params: {}", e)),
                 }
             } else {
-                Response::error(-32602, "Missing params".to_string())
+                Response::error(request.id, -32602, "Missing params".to_string())
             }
         }
         "search" => {
             if let Some(params) = request.params {
                 match serde_json::from_value::<protocol::SearchParams>(params) {
-                    Ok(search_params) => {
+                    Ok(mut search_params) => {
+                        clamp_limit("search", &mut search_params.limit, engine.max_search_limit());
+                        if search_params.weights.is_none() {
+                            search_params.weights = Some(engine.default_weights().clone());
+                        }
                         match db.search(&search_params) {
-                            Ok(results) => Response::success(
-                                request.id,
-                                serde_json::json!({"results": results}),
-                            ),
-                            Err(e) => Response::error(-32000, format!("Search failed: {}", e)),
+                            Ok(results) => Response::success(request.id, to_result(protocol::SearchResults { results })),
+                            Err(e) if e.is::<regex::Error>() => {
+                                Response::error(request.id, -32602, format!("Invalid regex: {}", e))
+                            }
+                            Err(e) => Response::error(request.id, -32000, format!("Search failed: {}", e)),
                         }
                     }
-                    Err(e) => Response::error(-32602, format!("Invalid params: {}", e)),
+                    Err(e) => Response::error(request.id, -32602, format!("Invalid params: {}", e)),
                 }
             } else {
-                Response::error(-32602, "Missing params".to_string())
+                Response::error(request.id, -32602, "Missing params".to_string())
             }
         }
         "delete" => {
@@ -152,17 +421,29 @@ async fn handle_request(request: Request, db: &Database, ctx_collector: &Context
                 match serde_json::from_value::<protocol::DeleteParams>(params) {
                     Ok(delete_params) => {
                         match db.delete_command(&delete_params.cmd) {
-                            Ok(_) => Response::success(
-                                request.id,
-                                serde_json::json!({"deleted": true}),
-                            ),
-                            Err(e) => Response::error(-32000, format!("Delete failed: {}", e)),
+                            Ok(_) => Response::success(request.id, to_result(protocol::DeleteResult { deleted: true })),
+                            Err(e) => Response::error(request.id, -32000, format!("Delete failed: {}", e)),
                         }
                     }
-                    Err(e) => Response::error(-32602, format!("Invalid params: {}", e)),
+                    Err(e) => Response::error(request.id, -32602, format!("Invalid params: {}", e)),
                 }
             } else {
-                Response::error(-32602, "Missing params".to_string())
+                Response::error(request.id, -32602, "Missing params".to_string())
+            }
+        }
+        "accept" => {
+            if let Some(params) = request.params {
+                match serde_json::from_value::<protocol::AcceptParams>(params) {
+                    Ok(accept_params) => {
+                        match db.accept(&accept_params) {
+                            Ok(()) => Response::success(request.id, to_result(protocol::OkResult { ok: true })),
+                            Err(e) => Response::error(request.id, -32000, format!("accept failed: {}", e)),
+                        }
+                    }
+                    Err(e) => Response::error(request.id, -32602, format!("Invalid params: {}", e)),
+                }
+            } else {
+                Response::error(request.id, -32602, "Missing params".to_string())
             }
         }
         "frecent_add" => {
@@ -170,90 +451,974 @@ async fn handle_request(request: Request, db: &Database, ctx_collector: &Context
                 match serde_json::from_value::<protocol::FrecentAddParams>(params) {
                     Ok(frecent_params) => {
                         match db.frecent_add(&frecent_params) {
-                            Ok(()) => Response::success(request.id, serde_json::json!({"ok": true})),
-                            Err(e) => Response::error(-32000, format!("frecent_add failed: {}", e)),
+                            Ok(()) => Response::success(request.id, to_result(protocol::OkResult { ok: true })),
+                            Err(e) => Response::error(request.id, -32000, format!("frecent_add failed: {}", e)),
+                        }
+                    }
+                    Err(e) => Response::error(request.id, -32602, format!("Invalid params: {}", e)),
+                }
+            } else {
+                Response::error(request.id, -32602, "Missing params".to_string())
+            }
+        }
+        "frecent_delete" => {
+            if let Some(params) = request.params {
+                match serde_json::from_value::<protocol::FrecentDeleteParams>(params) {
+                    Ok(delete_params) => {
+                        match db.frecent_delete(&delete_params.path, &delete_params.path_type) {
+                            Ok(deleted) => Response::success(request.id, to_result(protocol::DeleteResult { deleted })),
+                            Err(e) => Response::error(request.id, -32000, format!("frecent_delete failed: {}", e)),
+                        }
+                    }
+                    Err(e) => Response::error(request.id, -32602, format!("Invalid params: {}", e)),
+                }
+            } else {
+                Response::error(request.id, -32602, "Missing params".to_string())
+            }
+        }
+        "frecent_add_batch" => {
+            if let Some(params) = request.params {
+                match serde_json::from_value::<protocol::FrecentAddBatchParams>(params) {
+                    Ok(batch) => {
+                        match db.frecent_add_batch(&batch.entries) {
+                            Ok(added) => Response::success(request.id, to_result(protocol::CountResult { added })),
+                            Err(e) => Response::error(request.id, -32000, format!("frecent_add_batch failed: {}", e)),
                         }
                     }
-                    Err(e) => Response::error(-32602, format!("Invalid params: {}", e)),
+                    Err(e) => Response::error(request.id, -32602, format!("Invalid params: {}", e)),
                 }
             } else {
-                Response::error(-32602, "Missing params".to_string())
+                Response::error(request.id, -32602, "Missing params".to_string())
             }
         }
+        "frecent_export" => match db.frecent_export() {
+            Ok(results) => Response::success(request.id, to_result(protocol::FrecencyResults { results })),
+            Err(e) => Response::error(request.id, -32000, format!("frecent_export failed: {}", e)),
+        },
         "frecent_query" => {
             if let Some(params) = request.params {
                 match serde_json::from_value::<protocol::FrecentQueryParams>(params) {
-                    Ok(query_params) => {
+                    Ok(mut query_params) => {
+                        clamp_limit("frecent_query", &mut query_params.limit, engine.max_limit());
                         match db.frecent_query(&query_params) {
-                            Ok(results) => Response::success(
+                            Ok(results) => Response::success(request.id, to_result(protocol::FrecencyResults { results })),
+                            Err(e) => Response::error(request.id, -32000, format!("frecent_query failed: {}", e)),
+                        }
+                    }
+                    Err(e) => Response::error(request.id, -32602, format!("Invalid params: {}", e)),
+                }
+            } else {
+                Response::error(request.id, -32602, "Missing params".to_string())
+            }
+        }
+        "store_batch" => {
+            if let Some(params) = request.params {
+                match serde_json::from_value::<protocol::StoreBatchParams>(params) {
+                    Ok(batch) => {
+                        match db.store_batch(&batch.commands) {
+                            Ok(stored) => Response::success(
                                 request.id,
-                                serde_json::json!({"results": results}),
+                                to_result(protocol::StoreBatchResult { stored, skipped: batch.commands.len() - stored }),
                             ),
-                            Err(e) => Response::error(-32000, format!("frecent_query failed: {}", e)),
+                            Err(e) => Response::error(request.id, -32000, format!("Store failed: {}", e)),
                         }
                     }
-                    Err(e) => Response::error(-32602, format!("Invalid params: {}", e)),
+                    Err(e) => Response::error(request.id, -32602, format!("Invalid params: {}", e)),
                 }
             } else {
-                Response::error(-32602, "Missing params".to_string())
+                Response::error(request.id, -32602, "Missing params".to_string())
+            }
+        }
+        "places" => {
+            let params = request.params.unwrap_or_else(|| serde_json::json!({}));
+            match serde_json::from_value::<protocol::PlacesParams>(params) {
+                Ok(places_params) => match db.places(&places_params) {
+                    Ok(places) => Response::success(request.id, to_result(protocol::PlacesResult { places })),
+                    Err(e) => Response::error(request.id, -32000, format!("Places failed: {}", e)),
+                },
+                Err(e) => Response::error(request.id, -32602, format!("Invalid params: {}", e)),
+            }
+        }
+        "stats" => match db.stats() {
+            Ok(stats) => Response::success(request.id, to_result(stats)),
+            Err(e) => Response::error(request.id, -32000, format!("Stats failed: {}", e)),
+        },
+        "prune_history" => {
+            if let Some(params) = request.params {
+                match serde_json::from_value::<protocol::PruneHistoryParams>(params) {
+                    Ok(prune_params) => {
+                        let cutoff = db::chrono_lite_timestamp() - i64::from(prune_params.older_than_days) * 86400;
+                        match db.prune_history(cutoff) {
+                            Ok(result) => Response::success(request.id, to_result(result)),
+                            Err(e) => Response::error(request.id, -32000, format!("Prune failed: {}", e)),
+                        }
+                    }
+                    Err(e) => Response::error(request.id, -32602, format!("Invalid params: {}", e)),
+                }
+            } else {
+                Response::error(request.id, -32602, "Missing params".to_string())
+            }
+        }
+        "reset" => {
+            let params = request.params.unwrap_or_else(|| serde_json::json!({}));
+            match serde_json::from_value::<protocol::ResetParams>(params) {
+                Ok(reset_params) => match db.reset(reset_params.dry_run) {
+                    Ok(result) => Response::success(request.id, to_result(result)),
+                    Err(e) => Response::error(request.id, -32000, format!("Reset failed: {}", e)),
+                },
+                Err(e) => Response::error(request.id, -32602, format!("Invalid params: {}", e)),
+            }
+        }
+        "maintenance" => match db.maintenance() {
+            Ok(result) => Response::success(request.id, to_result(result)),
+            Err(e) => Response::error(request.id, -32000, format!("Maintenance failed: {}", e)),
+        },
+        "cache_stats" => Response::success(request.id, to_result(engine.cache_stats())),
+        "cache_clear" => {
+            engine.invalidate_all();
+            Response::success(request.id, to_result(protocol::OkResult { ok: true }))
+        }
+        "ping" => Response::success(request.id, to_result(protocol::PingResult { pong: true })),
+        "schema" => Response::success(request.id, protocol::rpc_schema()),
+        "shutdown" => {
+            info!("Shutdown requested");
+            shutdown.notify_one();
+            Response::success(request.id, to_result(protocol::OkResult { ok: true }))
+        }
+        _ => Response::error(request.id, -32601, format!("Method not found: {}", request.method)),
+    };
+
+    if writes {
+        engine.invalidate_all();
+    }
+
+    response
+}
+
+/// A method's result as JSON. The result types are plain data, so this can't fail
+fn to_result(result: impl serde::Serialize) -> serde_json::Value {
+    serde_json::to_value(result).expect("result types serialize to JSON")
+}
+
+/// Lower a request's result `limit` to the configured maximum, so a runaway
+/// client can't make the daemon build and serialize a huge response
+fn clamp_limit(method: &str, limit: &mut usize, max: usize) {
+    if *limit > max {
+        warn!("Clamping {} limit {} to {}", method, limit, max);
+        *limit = max;
+    }
+}
+
+/// Accept clients until a shutdown is requested, then give in-flight requests
+/// up to `SHUTDOWN_GRACE` to finish
+async fn serve(
+    listener: UnixListener,
+    db: Database,
+    ctx_collector: Arc<ContextCollector>,
+    engine: Arc<PredictionEngine>,
+    shutdown: Arc<Notify>,
+    activity: Arc<Activity>,
+) {
+    let (events, _) = broadcast::channel(SUBSCRIBER_BACKLOG);
+    let mut clients = JoinSet::new();
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _addr)) => {
+                    debug!("New client connected");
+                    let db = db.clone();
+                    let ctx = Arc::clone(&ctx_collector);
+                    let engine = Arc::clone(&engine);
+                    let shutdown = Arc::clone(&shutdown);
+                    let activity = Arc::clone(&activity);
+                    let events = events.clone();
+                    clients.spawn(async move {
+                        handle_client(stream, db, ctx, engine, shutdown, activity, events).await;
+                    });
+                }
+                Err(e) => {
+                    warn!("Failed to accept connection: {}", e);
+                }
+            },
+            // Reap finished clients so the set doesn't grow
+            Some(_) = clients.join_next(), if !clients.is_empty() => {}
+            _ = shutdown.notified() => break,
+        }
+    }
+    drop(listener);
+    // Ends subscriptions once in-flight requests release their senders
+    drop(events);
+
+    let pending = clients.len();
+    if pending > 0 {
+        debug!("Waiting for {} in-flight request(s)", pending);
+        let drained = tokio::time::timeout(SHUTDOWN_GRACE, async {
+            while clients.join_next().await.is_some() {}
+        });
+        if drained.await.is_err() {
+            warn!("Dropping {} request(s) still running after {:?}", clients.len(), SHUTDOWN_GRACE);
+        }
+    }
+}
+
+/// Request a shutdown on SIGTERM or SIGINT, the same way the `shutdown` RPC does
+async fn forward_signals(shutdown: Arc<Notify>) -> Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = sigterm.recv() => info!("Received SIGTERM, shutting down"),
+        _ = sigint.recv() => info!("Received SIGINT, shutting down"),
+    }
+    shutdown.notify_one();
+    Ok(())
+}
+
+/// First file descriptor passed by systemd socket activation (`SD_LISTEN_FDS_START`)
+const LISTEN_FDS_START: RawFd = 3;
+
+/// The listening fd passed by systemd, if the daemon was socket-activated.
+///
+/// `LISTEN_PID` must name this process (otherwise the variables were meant for
+/// a parent) and `LISTEN_FDS` must pass exactly one socket.
+fn activated_fd(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> Result<Option<RawFd>> {
+    let (Some(listen_pid), Some(listen_fds)) = (listen_pid, listen_fds) else {
+        return Ok(None);
+    };
+    if listen_pid.parse::<u32>().ok() != Some(pid) {
+        return Ok(None);
+    }
+    match listen_fds.parse::<u32>() {
+        Ok(1) => Ok(Some(LISTEN_FDS_START)),
+        _ => bail!("expected a single socket from systemd, got LISTEN_FDS={}", listen_fds),
+    }
+}
+
+/// Adopt the socket systemd bound for us, if socket-activated.
+///
+/// A matching user unit pair, `nicehist.socket`:
+///
+/// ```ini
+/// [Socket]
+/// ListenStream=%t/nicehist.sock
+/// SocketMode=0600
+///
+/// [Install]
+/// WantedBy=sockets.target
+/// ```
+///
+/// and `nicehist.service`:
+///
+/// ```ini
+/// [Service]
+/// ExecStart=/usr/bin/nicehist-daemon --foreground
+/// ```
+///
+/// `%t` is `$XDG_RUNTIME_DIR`, where the CLI looks for the socket. The unit
+/// must not set `Accept=yes`: the daemon takes the listening socket, not
+/// single connections.
+fn systemd_listener() -> Result<Option<UnixListener>> {
+    let listen_pid = std::env::var("LISTEN_PID").ok();
+    let listen_fds = std::env::var("LISTEN_FDS").ok();
+    let Some(fd) = activated_fd(listen_pid.as_deref(), listen_fds.as_deref(), std::process::id())? else {
+        return Ok(None);
+    };
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut stat) } != 0 || stat.st_mode & libc::S_IFMT != libc::S_IFSOCK {
+        bail!("LISTEN_FDS is set but fd {} is not a socket", fd);
+    }
+    // Safety: systemd hands us this fd and nothing else in the process owns it
+    let listener = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+    listener.set_nonblocking(true)?;
+    Ok(Some(UnixListener::from_std(listener)?))
+}
+
+/// Request a shutdown once no request has been handled for `timeout`
+async fn watch_idle(activity: Arc<Activity>, timeout: Duration, shutdown: Arc<Notify>) {
+    loop {
+        let idle = activity.idle_for();
+        if idle >= timeout {
+            info!("Idle for {:?}, shutting down", idle);
+            shutdown.notify_one();
+            return;
+        }
+        tokio::time::sleep(timeout - idle).await;
+    }
+}
+
+/// Bind the socket (or adopt systemd's) and serve requests in the foreground
+/// until `shutdown` is notified (by the `shutdown` RPC, a signal or the idle
+/// timeout, unless it is zero), then remove the socket we bound
+async fn run(
+    socket: &Path,
+    db: Database,
+    engine: PredictionEngine,
+    shutdown: Arc<Notify>,
+    idle_timeout: Duration,
+) -> Result<()> {
+    // systemd owns an activated socket's file and keeps listening after we exit
+    let (listener, bound) = match systemd_listener()? {
+        Some(listener) => {
+            info!("Listening on socket passed by systemd");
+            (listener, false)
+        }
+        None => {
+            // Remove existing socket if present
+            if socket.exists() {
+                std::fs::remove_file(socket)?;
             }
+            let listener = UnixListener::bind(socket)?;
+            info!("Listening on {}", socket.display());
+            (listener, true)
         }
-        "ping" => Response::success(request.id, serde_json::json!({"pong": true})),
-        _ => Response::error(-32601, format!("Method not found: {}", request.method)),
+    };
+
+    // Initialize context collector
+    let ctx_collector = Arc::new(ContextCollector::new());
+    let engine = Arc::new(engine);
+
+    let activity = Arc::new(Activity::new());
+    let watchdog = (!idle_timeout.is_zero())
+        .then(|| tokio::spawn(watch_idle(Arc::clone(&activity), idle_timeout, Arc::clone(&shutdown))));
+
+    serve(listener, db, ctx_collector, engine, shutdown, activity).await;
+
+    if let Some(watchdog) = watchdog {
+        watchdog.abort();
     }
+
+    if bound {
+        std::fs::remove_file(socket).ok();
+    }
+    info!("Daemon stopped");
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+/// Open the database file, or an empty in-memory database with --in-memory
+fn open_database(options: &Options, db_file: &Path) -> Result<Database> {
+    if options.in_memory {
+        info!("Database: in memory (nothing is saved)");
+        Database::open_in_memory()
+    } else {
+        info!("Database: {}", db_file.display());
+        Database::open(db_file)
+    }
+}
+
+fn main() -> Result<()> {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("nicehist-daemon: {}", e);
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return Ok(());
+    }
+
+    // An explicit --config must exist; the default file is optional
+    let config = match &options.config {
+        Some(path) => Config::load(path)?,
+        None => {
+            let path = config_path();
+            if path.exists() { Config::load(&path)? } else { Config::default() }
+        }
+    };
+
+    // NICEHIST_SOCKET wins over the config file, as it does for the CLI
+    let socket = std::env::var_os("NICEHIST_SOCKET")
+        .map(PathBuf::from)
+        .or_else(|| config.socket.clone())
+        .unwrap_or_else(socket_path);
+    let db_file = config.db.clone().unwrap_or_else(db_path);
+    let pid_file = pid_path(&socket);
+
+    // Ensure parent directories exist
+    let db_parent = db_file.parent().filter(|_| !options.in_memory);
+    for parent in [socket.parent(), db_parent].into_iter().flatten() {
+        std::fs::create_dir_all(parent).ok();
+    }
+
+    if options.detach {
+        // Check the lock before forking so the caller gets a useful answer
+        if acquire_lock(&pid_file)?.is_none() {
+            println!("nicehist-daemon already running{}", pid_suffix(&pid_file));
+            return Ok(());
+        }
+        daemonize(&socket)?;
+    }
+
     // Initialize logging
     tracing_subscriber::fmt()
         .with_env_filter(
             tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive("nicehist_daemon=info".parse().unwrap()),
+                .add_directive("nicehist_daemon=info".parse().unwrap())
+                .add_directive("nicehist_core=info".parse().unwrap()),
         )
         .init();
 
-    let socket = socket_path();
-    let db_file = db_path();
+    // Held for the lifetime of the process
+    let Some(_lock) = acquire_lock(&pid_file)? else {
+        warn!("Daemon already running{}", pid_suffix(&pid_file));
+        return Ok(());
+    };
 
     info!("Starting nicehist daemon");
     info!("Socket: {}", socket.display());
-    info!("Database: {}", db_file.display());
 
-    // Remove existing socket if present
-    if socket.exists() {
-        std::fs::remove_file(&socket)?;
+    // Unreadable rules shouldn't stop history from being recorded; fall back to the built-in set
+    let redact_file = redact_config_path();
+    let redactor = Redactor::load(&redact_file).unwrap_or_else(|e| {
+        error!("{:#}; using built-in redaction rules", e);
+        Redactor::default()
+    });
+
+    // Initialize database
+    let db = open_database(&options, &db_file)?
+        .with_redactor(redactor)
+        .with_raw_commands(config.raw_commands)
+        .with_ignored_exits(config.ignored_exits());
+    info!("Database initialized");
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    let mut engine = PredictionEngine::new();
+    if let Some(ttl) = config.cache_ttl() {
+        engine = engine.with_cache_ttl(ttl);
+    }
+    if let Some(max) = config.max_limit {
+        engine = engine.with_max_limit(max);
+    }
+    if let Some(max) = config.max_search_limit {
+        engine = engine.with_max_search_limit(max);
     }
+    let engine = engine.with_default_weights(config.weights);
+    let idle_timeout = Duration::from_secs(options.idle_timeout.or(config.idle_timeout_secs).unwrap_or(0));
+    let result = runtime.block_on(async {
+        let shutdown = Arc::new(Notify::new());
+        let signals = {
+            let shutdown = Arc::clone(&shutdown);
+            tokio::spawn(async move {
+                if let Err(e) = forward_signals(shutdown).await {
+                    warn!("Failed to install signal handlers: {}", e);
+                }
+            })
+        };
+        let result = run(&socket, db, engine, shutdown, idle_timeout).await;
+        signals.abort();
+        result
+    });
 
-    // Ensure parent directory exists
-    if let Some(parent) = socket.parent() {
-        std::fs::create_dir_all(parent).ok();
+    std::fs::remove_file(&pid_file).ok();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::task::JoinHandle;
+
+    async fn rpc(socket: &Path, request: &str) -> Response {
+        let mut stream = UnixStream::connect(socket).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        stream.write_all(b"\n").await.unwrap();
+
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).await.unwrap();
+        serde_json::from_str(&line).unwrap()
     }
 
-    // Initialize database
-    let db = Database::open(&db_file)?;
-    info!("Database initialized");
+    /// Run the daemon on `socket`, returning once the socket is up
+    async fn spawn_server(
+        socket: &Path,
+        db: Database,
+        engine: PredictionEngine,
+        shutdown: Arc<Notify>,
+        idle_timeout: Duration,
+    ) -> JoinHandle<Result<()>> {
+        let server = {
+            let socket = socket.to_path_buf();
+            tokio::spawn(async move { run(&socket, db, engine, shutdown, idle_timeout).await })
+        };
+        for _ in 0..100 {
+            if socket.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        server
+    }
 
-    // Initialize context collector
-    let ctx_collector = Arc::new(ContextCollector::new());
+    /// Run the daemon with default settings on a socket in a new temp dir
+    async fn start_server(db: Database) -> (tempfile::TempDir, PathBuf, JoinHandle<Result<()>>) {
+        start_server_with(db, PredictionEngine::new()).await
+    }
 
-    // Bind to socket
-    let listener = UnixListener::bind(&socket)?;
-    info!("Listening on {}", socket.display());
+    async fn start_server_with(
+        db: Database,
+        engine: PredictionEngine,
+    ) -> (tempfile::TempDir, PathBuf, JoinHandle<Result<()>>) {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("nicehist.sock");
+        let server = spawn_server(&socket, db, engine, Arc::new(Notify::new()), Duration::ZERO).await;
+        (dir, socket, server)
+    }
 
-    loop {
-        match listener.accept().await {
-            Ok((stream, _addr)) => {
-                debug!("New client connected");
-                let db = db.clone();
-                let ctx = Arc::clone(&ctx_collector);
-                tokio::spawn(async move {
-                    handle_client(stream, db, ctx).await;
-                });
+    /// Ask the daemon to shut down and wait for it to exit cleanly
+    async fn stop_server(socket: &Path, server: JoinHandle<Result<()>>) {
+        rpc(socket, r#"{"method": "shutdown"}"#).await;
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("daemon did not shut down")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_foreground_ping_and_shutdown() {
+        let (_dir, socket, server) = start_server(Database::open_in_memory().unwrap()).await;
+
+        let pong = rpc(&socket, r#"{"method": "ping"}"#).await;
+        assert_eq!(pong.result.unwrap()["pong"], true);
+
+        let ack = rpc(&socket, r#"{"method": "shutdown"}"#).await;
+        assert_eq!(ack.result.unwrap()["ok"], true);
+
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("daemon did not shut down")
+            .unwrap()
+            .unwrap();
+        assert!(!socket.exists());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_finishes_in_flight_requests_and_removes_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("nicehist.sock");
+        let db = Database::open_in_memory().unwrap();
+        let shutdown = Arc::new(Notify::new());
+        let server = spawn_server(&socket, db, PredictionEngine::new(), Arc::clone(&shutdown), Duration::ZERO).await;
+
+        // A client that connected before the shutdown (as a signal would send it)
+        // still gets its answer
+        let mut stream = UnixStream::connect(&socket).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        shutdown.notify_one();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!server.is_finished());
+
+        stream.write_all(b"{\"method\": \"ping\"}\n").await.unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).await.unwrap();
+        let pong: Response = serde_json::from_str(&line).unwrap();
+        assert_eq!(pong.result.unwrap()["pong"], true);
+
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("daemon did not shut down")
+            .unwrap()
+            .unwrap();
+        assert!(!socket.exists());
+        assert!(UnixStream::connect(&socket).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout_exits_after_inactivity() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("nicehist.sock");
+        let db = Database::open_in_memory().unwrap();
+        let server = spawn_server(&socket, db, PredictionEngine::new(), Arc::new(Notify::new()), Duration::from_millis(300)).await;
+
+        // Requests keep it alive past the timeout
+        for _ in 0..4 {
+            tokio::time::sleep(Duration::from_millis(150)).await;
+            let pong = rpc(&socket, r#"{"method": "ping"}"#).await;
+            assert_eq!(pong.result.unwrap()["pong"], true);
+        }
+        assert!(!server.is_finished());
+
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("daemon did not exit when idle")
+            .unwrap()
+            .unwrap();
+        assert!(!socket.exists());
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout_waits_for_open_subscriptions() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("nicehist.sock");
+        let db = Database::open_in_memory().unwrap();
+        let server = spawn_server(&socket, db, PredictionEngine::new(), Arc::new(Notify::new()), Duration::from_millis(300)).await;
+
+        let mut stream = UnixStream::connect(&socket).await.unwrap();
+        stream.write_all(b"{\"method\": \"subscribe\"}\n").await.unwrap();
+        let mut subscription = BufReader::new(stream);
+        let mut line = String::new();
+        subscription.read_line(&mut line).await.unwrap();
+
+        // Nothing is stored, but the subscriber is still listening
+        tokio::time::sleep(Duration::from_millis(900)).await;
+        assert!(!server.is_finished());
+
+        drop(subscription);
+        tokio::time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("daemon did not exit when idle")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_streams_stored_commands() {
+        let (_dir, socket, server) = start_server(Database::open_in_memory().unwrap()).await;
+
+        let mut stream = UnixStream::connect(&socket).await.unwrap();
+        stream.write_all(b"{\"id\": 1, \"method\": \"subscribe\"}\n").await.unwrap();
+        let mut subscription = BufReader::new(stream);
+        let mut line = String::new();
+        subscription.read_line(&mut line).await.unwrap();
+        let ack: Response = serde_json::from_str(&line).unwrap();
+        assert_eq!(ack.result.unwrap()["subscribed"], true);
+
+        let stored = rpc(&socket, r#"{"method": "store", "params": {"cmd": "make test", "cwd": "/src", "exit_status": 0}}"#).await;
+        let id = stored.result.unwrap()["id"].as_i64().unwrap();
+
+        line.clear();
+        tokio::time::timeout(Duration::from_secs(5), subscription.read_line(&mut line))
+            .await
+            .expect("no event for the stored command")
+            .unwrap();
+        let event: StoredCommand = serde_json::from_str(&line).unwrap();
+        assert_eq!(event.id, id);
+        assert_eq!(event.cmd, "make test");
+        assert_eq!(event.cwd, "/src");
+        assert_eq!(event.exit_status, Some(0));
+
+        // Shutting down closes the stream rather than waiting out the grace period
+        rpc(&socket, r#"{"method": "shutdown"}"#).await;
+        line.clear();
+        assert_eq!(subscription.read_line(&mut line).await.unwrap(), 0);
+        tokio::time::timeout(Duration::from_secs(1), server)
+            .await
+            .expect("daemon did not shut down")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_predict_requires_prefix_but_allows_empty() {
+        let (_dir, socket, server) = start_server(Database::open_in_memory().unwrap()).await;
+
+        rpc(&socket, r#"{"method": "store", "params": {"cmd": "make test", "cwd": "/src"}}"#).await;
+
+        let missing = rpc(&socket, r#"{"id": 1, "method": "predict", "params": {"cwd": "/src"}}"#).await;
+        let error = missing.error.expect("predict without a prefix should fail");
+        assert_eq!(error.code, -32602);
+        assert!(error.message.contains("prefix"), "{}", error.message);
+
+        let empty = rpc(&socket, r#"{"id": 2, "method": "predict", "params": {"prefix": "", "cwd": "/src"}}"#).await;
+        assert!(empty.error.is_none(), "{:?}", empty.error);
+        assert_eq!(empty.result.unwrap()["suggestions"][0]["cmd"], "make test");
+
+        stop_server(&socket, server).await;
+    }
+
+    #[tokio::test]
+    async fn test_limit_is_clamped_to_max_limit() {
+        let engine = PredictionEngine::new().with_max_limit(2).with_max_search_limit(1);
+        let (_dir, socket, server) = start_server_with(Database::open_in_memory().unwrap(), engine).await;
+
+        for cmd in ["make a", "make b", "make c"] {
+            let store = format!(r#"{{"method": "store", "params": {{"cmd": "{}", "cwd": "/src"}}}}"#, cmd);
+            rpc(&socket, &store).await;
+        }
+
+        let search = rpc(&socket, r#"{"method": "search", "params": {"pattern": "make", "limit": 1000000}}"#).await;
+        assert_eq!(search.result.unwrap()["results"].as_array().unwrap().len(), 1);
+        let predict = rpc(&socket, r#"{"method": "predict", "params": {"prefix": "make", "cwd": "/src", "limit": 1000000}}"#).await;
+        assert_eq!(predict.result.unwrap()["suggestions"].as_array().unwrap().len(), 2);
+
+        stop_server(&socket, server).await;
+    }
+
+    #[tokio::test]
+    async fn test_cache_stats_and_clear_rpcs() {
+        let (_dir, socket, server) = start_server(Database::open_in_memory().unwrap()).await;
+
+        rpc(&socket, r#"{"method": "store", "params": {"cmd": "git status", "cwd": "/tmp"}}"#).await;
+        for _ in 0..3 {
+            rpc(&socket, r#"{"method": "predict", "params": {"prefix": "git", "cwd": "/tmp"}}"#).await;
+        }
+
+        let stats = rpc(&socket, r#"{"method": "cache_stats"}"#).await.result.unwrap();
+        assert_eq!(stats["entries"], 1);
+        assert_eq!(stats["hits"], 2);
+        assert_eq!(stats["misses"], 1);
+
+        rpc(&socket, r#"{"method": "cache_clear"}"#).await;
+        let stats = rpc(&socket, r#"{"method": "cache_stats"}"#).await.result.unwrap();
+        assert_eq!(stats["entries"], 0);
+
+        // Storing a command invalidates cached predictions
+        rpc(&socket, r#"{"method": "predict", "params": {"prefix": "git", "cwd": "/tmp"}}"#).await;
+        rpc(&socket, r#"{"method": "store", "params": {"cmd": "git push", "cwd": "/tmp"}}"#).await;
+        let stats = rpc(&socket, r#"{"method": "cache_stats"}"#).await.result.unwrap();
+        assert_eq!(stats["entries"], 0);
+
+        stop_server(&socket, server).await;
+    }
+
+    #[tokio::test]
+    async fn test_batch_requests_answered_in_order() {
+        let (_dir, socket, server) = start_server(Database::open_in_memory().unwrap()).await;
+
+        let mut stream = UnixStream::connect(&socket).await.unwrap();
+        stream.write_all(r#"[
+            {"id": 1, "method": "store", "params": {"cmd": "git status", "cwd": "/tmp"}},
+            {"id": 2, "method": "bogus"},
+            {"id": 3, "method": "search", "params": {"pattern": "git"}}
+        ]"#.replace('\n', " ").as_bytes()).await.unwrap();
+        stream.write_all(b"\n").await.unwrap();
+        let mut line = String::new();
+        BufReader::new(stream).read_line(&mut line).await.unwrap();
+        let responses: Vec<Response> = serde_json::from_str(&line).unwrap();
+
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0].result.as_ref().unwrap()["stored"], true);
+        assert_eq!(responses[1].error.as_ref().unwrap().code, -32601);
+        // Later requests in a batch see the earlier ones' writes
+        assert_eq!(responses[2].result.as_ref().unwrap()["results"][0]["cmd"], "git status");
+
+        // A plain request on its own still gets a plain response
+        let pong = rpc(&socket, r#"{"method": "ping"}"#).await;
+        assert_eq!(pong.result.unwrap()["pong"], true);
+
+        stop_server(&socket, server).await;
+    }
+
+    #[tokio::test]
+    async fn test_schema_covers_served_methods() {
+        let (_dir, socket, server) = start_server(Database::open_in_memory().unwrap()).await;
+
+        let schema = rpc(&socket, r#"{"method": "schema"}"#).await.result.unwrap();
+        assert_eq!(schema["$defs"]["StoreParams"]["required"], serde_json::json!(["cmd", "cwd"]));
+        let methods = schema["methods"].as_object().unwrap();
+        assert!(methods.len() > 20);
+        // Every documented method is one the daemon answers
+        for method in methods.keys().filter(|m| !matches!(m.as_str(), "shutdown" | "subscribe")) {
+            let response = rpc(&socket, &serde_json::json!({"method": method, "params": {}}).to_string()).await;
+            assert!(response.error.is_none_or(|e| e.code != -32601), "{} is not served", method);
+        }
+
+        stop_server(&socket, server).await;
+    }
+
+    #[tokio::test]
+    async fn test_responses_match_served_schema() {
+        let (dir, socket, server) = start_server(Database::open_in_memory().unwrap()).await;
+        let schema = rpc(&socket, r#"{"method": "schema"}"#).await.result.unwrap();
+
+        let stored = r#"{"cmd": "make test", "cwd": "/src", "exit_status": 0, "start_time": 1700000000, "dedup_window": 0}"#;
+        let requests = [
+            ("store", stored.to_string()),
+            // The same run again is skipped, with a reason
+            ("store", stored.to_string()),
+            ("store_batch", format!(r#"{{"commands": [{}, {{"cmd": "make lint", "cwd": "/src"}}]}}"#, stored)),
+            ("predict", r#"{"prefix": "make", "cwd": "/src"}"#.to_string()),
+            ("search", r#"{"pattern": "make"}"#.to_string()),
+            ("context", serde_json::json!({"cwd": dir.path(), "stats": true}).to_string()),
+            ("accept", r#"{"prefix": "make", "cmd": "make test", "shown": ["make test"]}"#.to_string()),
+            ("frecent_add", r#"{"path": "/src"}"#.to_string()),
+            ("frecent_add_batch", r#"{"entries": [{"path": "/tmp", "rank": 2.0, "timestamp": 1700000000}]}"#.to_string()),
+            ("frecent_query", "{}".to_string()),
+            ("frecent_export", "{}".to_string()),
+            ("frecent_delete", r#"{"path": "/tmp"}"#.to_string()),
+            ("places", "{}".to_string()),
+            ("delete", r#"{"cmd": "make lint"}"#.to_string()),
+            ("stats", "{}".to_string()),
+            ("prune_history", r#"{"older_than_days": 36500}"#.to_string()),
+            ("reset", r#"{"dry_run": true}"#.to_string()),
+            ("maintenance", "{}".to_string()),
+            ("cache_stats", "{}".to_string()),
+            ("cache_clear", "{}".to_string()),
+            ("ping", "{}".to_string()),
+        ];
+        for (method, params) in requests {
+            let request = format!(r#"{{"method": "{}", "params": {}}}"#, method, params);
+            let response = rpc(&socket, &request).await;
+            assert!(response.error.is_none(), "{}: {:?}", method, response.error);
+
+            let mut result_schema = schema["methods"][method]["result"].clone();
+            result_schema["$defs"] = schema["$defs"].clone();
+            let validator = jsonschema::validator_for(&result_schema).unwrap();
+            let result = response.result.unwrap();
+            if let Err(e) = validator.validate(&result) {
+                panic!("{} result {} does not match its schema: {}", method, result, e);
             }
-            Err(e) => {
-                warn!("Failed to accept connection: {}", e);
+        }
+
+        stop_server(&socket, server).await;
+    }
+
+    #[tokio::test]
+    async fn test_error_responses_echo_request_id() {
+        let (_dir, socket, server) = start_server(Database::open_in_memory().unwrap()).await;
+
+        let response = rpc(&socket, r#"{"jsonrpc": "2.0", "id": 42, "method": "bogus"}"#).await;
+        assert_eq!(response.error.unwrap().code, -32601);
+        assert_eq!(response.id, Some(serde_json::json!(42)));
+
+        let response = rpc(&socket, r#"{"jsonrpc": "2.0", "id": "abc", "method": "search"}"#).await;
+        assert_eq!(response.error.unwrap().code, -32602);
+        assert_eq!(response.id, Some(serde_json::json!("abc")));
+
+        // Even a request that isn't valid keeps its id when it has one
+        let response = rpc(&socket, r#"{"jsonrpc": "2.0", "id": 7, "method": 5}"#).await;
+        assert_eq!(response.error.unwrap().code, -32600);
+        assert_eq!(response.id, Some(serde_json::json!(7)));
+
+        let response = rpc(&socket, "{not json").await;
+        assert_eq!(response.error.unwrap().code, -32700);
+        assert_eq!(response.id, None);
+
+        stop_server(&socket, server).await;
+    }
+
+    #[tokio::test]
+    async fn test_search_invalid_regex_is_invalid_params() {
+        let (_dir, socket, server) = start_server(Database::open_in_memory().unwrap()).await;
+
+        let response = rpc(&socket, r#"{"method": "search", "params": {"pattern": "git (push", "regex": true}}"#).await;
+        let error = response.error.unwrap();
+        assert_eq!(error.code, -32602);
+        assert!(error.message.starts_with("Invalid regex"), "{}", error.message);
+
+        // The daemon keeps serving afterwards
+        let pong = rpc(&socket, r#"{"method": "ping"}"#).await;
+        assert_eq!(pong.result.unwrap()["pong"], true);
+
+        stop_server(&socket, server).await;
+    }
+
+    #[tokio::test]
+    async fn test_predict_boosts_commands_for_detected_project_type() {
+        let (dir, socket, server) = start_server(Database::open_in_memory().unwrap()).await;
+        let project = dir.path().join("project");
+        std::fs::create_dir(&project).unwrap();
+        std::fs::write(project.join("Cargo.toml"), "").unwrap();
+        // Rust first, with a Node frontend
+        let polyglot = dir.path().join("polyglot");
+        std::fs::create_dir(&polyglot).unwrap();
+        std::fs::write(polyglot.join("Cargo.toml"), "").unwrap();
+        std::fs::write(polyglot.join("package.json"), "{}").unwrap();
+
+        // make all is run most often and npm install more than cargo build, all from an unrelated directory
+        for (cmd, runs) in [("cargo build", 1), ("npm install", 2), ("make all", 3)] {
+            let request = serde_json::json!({"method": "store", "params": {"cmd": cmd, "cwd": "/elsewhere"}}).to_string();
+            for _ in 0..runs {
+                rpc(&socket, &request).await;
             }
         }
+
+        let top = |cwd: &Path| {
+            let request = serde_json::json!({"method": "predict", "params": {"prefix": "", "cwd": cwd}}).to_string();
+            let socket = socket.clone();
+            async move { rpc(&socket, &request).await.result.unwrap()["suggestions"][0]["cmd"].clone() }
+        };
+        assert_eq!(top(dir.path()).await, "make all");
+        assert_eq!(top(&project).await, "cargo build");
+        assert_eq!(top(&polyglot).await, "npm install");
+
+        stop_server(&socket, server).await;
+    }
+
+    #[tokio::test]
+    async fn test_predict_uses_config_weights_unless_overridden() {
+        let config = Config::parse("[weights]\nngram = 0.0\n").unwrap();
+        let engine = PredictionEngine::new().with_default_weights(config.weights);
+        let (_dir, socket, server) = start_server_with(Database::open_in_memory().unwrap(), engine).await;
+
+        rpc(&socket, r#"{"method": "store", "params": {"cmd": "git add .", "cwd": "/repo"}}"#).await;
+        rpc(&socket, r#"{"method": "store", "params": {"cmd": "git commit", "cwd": "/repo", "prev_cmd": "git add ."}}"#).await;
+
+        let score = |weights: Option<serde_json::Value>| {
+            let mut params = serde_json::json!({"prefix": "git c", "cwd": "/repo", "last_cmds": ["git add ."]});
+            if let Some(weights) = weights {
+                params["weights"] = weights;
+            }
+            let request = serde_json::json!({"method": "predict", "params": params}).to_string();
+            let socket = socket.clone();
+            async move { rpc(&socket, &request).await.result.unwrap()["suggestions"][0]["score"].as_f64().unwrap() }
+        };
+        let configured = score(None).await;
+        assert_eq!(configured, score(Some(serde_json::json!({"ngram": 0.0}))).await);
+        // Weights sent with the request still win, here the built-in ngram weight
+        assert!(score(Some(serde_json::json!({}))).await > configured);
+
+        stop_server(&socket, server).await;
+    }
+
+    #[test]
+    fn test_activated_fd() {
+        assert_eq!(activated_fd(None, None, 42).unwrap(), None);
+        assert_eq!(activated_fd(Some("42"), Some("1"), 42).unwrap(), Some(3));
+        // Meant for another process
+        assert_eq!(activated_fd(Some("41"), Some("1"), 42).unwrap(), None);
+        assert_eq!(activated_fd(Some("42"), None, 42).unwrap(), None);
+        assert!(activated_fd(Some("42"), Some("2"), 42).is_err());
+        assert!(activated_fd(Some("42"), Some("x"), 42).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_database_is_empty_after_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("nicehist.sock");
+        let db_file = dir.path().join("history.db");
+        let options = Options { in_memory: true, ..Default::default() };
+
+        let mut histories = Vec::new();
+        for _ in 0..2 {
+            let db = open_database(&options, &db_file).unwrap();
+            let server = spawn_server(&socket, db, PredictionEngine::new(), Arc::new(Notify::new()), Duration::ZERO).await;
+
+            let stats = rpc(&socket, r#"{"method": "stats"}"#).await;
+            histories.push(stats.result.unwrap()["history"].as_i64().unwrap());
+            let stored = rpc(&socket, r#"{"method": "store", "params": {"cmd": "make test", "cwd": "/tmp"}}"#).await;
+            assert!(stored.error.is_none(), "{:?}", stored.error);
+
+            rpc(&socket, r#"{"method": "shutdown"}"#).await;
+            tokio::time::timeout(Duration::from_secs(5), server).await.unwrap().unwrap().unwrap();
+        }
+
+        assert_eq!(histories, [0, 0]);
+        assert!(!db_file.exists());
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let opts = parse_args(["--detach".to_string()].into_iter()).unwrap();
+        assert!(opts.detach);
+        let opts = parse_args(["--foreground".to_string()].into_iter()).unwrap();
+        assert!(!opts.detach);
+        assert!(parse_args(["--foreground".to_string(), "--detach".to_string()].into_iter()).is_err());
+        assert!(parse_args(["--foreground=x".to_string()].into_iter()).is_err());
+        assert!(parse_args(["--detach=false".to_string()].into_iter()).is_err());
+        assert!(parse_args(["--bogus".to_string()].into_iter()).is_err());
+        let opts = parse_args(["--config".to_string(), "/etc/nicehist.toml".to_string()].into_iter()).unwrap();
+        assert_eq!(opts.config.as_deref(), Some(Path::new("/etc/nicehist.toml")));
+        let opts = parse_args(["--config=/etc/nicehist.toml".to_string()].into_iter()).unwrap();
+        assert_eq!(opts.config.as_deref(), Some(Path::new("/etc/nicehist.toml")));
+        assert!(parse_args(["--config".to_string()].into_iter()).is_err());
+        let opts = parse_args(["--idle-timeout".to_string(), "600".to_string()].into_iter()).unwrap();
+        assert_eq!(opts.idle_timeout, Some(600));
+        assert_eq!(parse_args(std::iter::empty()).unwrap().idle_timeout, None);
+        assert!(parse_args(["--idle-timeout=soon".to_string()].into_iter()).is_err());
+        assert!(parse_args(["--in-memory".to_string()].into_iter()).unwrap().in_memory);
     }
 }